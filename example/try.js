@@ -0,0 +1,39 @@
+try {
+	throw 'boom';
+} catch (e) {
+	console.log(e);
+}
+
+try {
+	throw { code: 42 };
+} catch (e) {
+	console.log(e.code);
+}
+
+try {
+	missing;
+} catch (e) {
+	console.log(e.name);
+	console.log(e.message);
+}
+
+const check = function (n) {
+	if (n < 0) {
+		throw 'negative';
+	}
+	return n;
+};
+try {
+	check(-1);
+} catch (e) {
+	console.log('caught ' + e);
+}
+
+const f = function () {
+	try {
+		return 1;
+	} finally {
+		console.log('finally');
+	}
+};
+console.log(f());
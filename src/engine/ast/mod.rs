@@ -5,11 +5,14 @@ use std::fmt::Debug;
 #[derive(Default, Debug)]
 pub struct Program {
     pub statements: Vec<Statement>,
+    /// the line each statement starts on
+    pub lines: Vec<u32>,
 }
 impl Program {
     pub fn new() -> Program {
         Program {
             statements: Vec::new(),
+            lines: Vec::new(),
         }
     }
 }
@@ -28,15 +31,32 @@ pub enum Statement {
     // TODO: label
     Break,
     // while
+    Throw(Expression),
+    Try(TryStatement),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub struct BlockStatement {
     pub statements: Vec<Statement>,
+    /// the line each statement starts on, empty for blocks that are not in the source
+    pub lines: Vec<u32>,
 }
 impl BlockStatement {
     pub fn new(statements: Vec<Statement>) -> BlockStatement {
-        BlockStatement { statements }
+        BlockStatement {
+            statements,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn with_lines(statements: Vec<Statement>, lines: Vec<u32>) -> BlockStatement {
+        BlockStatement { statements, lines }
+    }
+}
+/// blocks are equal whatever lines they were parsed from.
+impl PartialEq for BlockStatement {
+    fn eq(&self, other: &Self) -> bool {
+        self.statements == other.statements
     }
 }
 
@@ -130,6 +150,30 @@ impl ForStatement {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct TryStatement {
+    pub block: BlockStatement,
+    /// the binding of the exception, `catch (param)`
+    pub param: Option<String>,
+    pub handler: Option<BlockStatement>,
+    pub finalizer: Option<BlockStatement>,
+}
+impl TryStatement {
+    pub fn new(
+        block: BlockStatement,
+        param: Option<String>,
+        handler: Option<BlockStatement>,
+        finalizer: Option<BlockStatement>,
+    ) -> TryStatement {
+        TryStatement {
+            block,
+            param,
+            handler,
+            finalizer,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ForInit {
     Statement(Box<Statement>), // variable declaration // TODO: struct Declaration
@@ -0,0 +1,90 @@
+use std::{
+    fmt::Display,
+    io::{Error, ErrorKind},
+};
+
+use super::value::Value;
+
+/// a value thrown out of a native function.
+/// a `try`/`catch` of the script catches it like a `throw`, otherwise it aborts the running
+/// script.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Exception {
+    message: String,
+    value: Option<Value>,
+    /// `ErrorKind::Other` for a thrown value, the kind of the error for a limit the script
+    /// ran into.
+    kind: ErrorKind,
+}
+
+impl Exception {
+    /// throw an arbitrary JavaScript value.
+    pub fn new(value: Value) -> Self {
+        Exception {
+            message: format!("{}", value.clone().into_raw()),
+            value: Some(value),
+            kind: ErrorKind::Other,
+        }
+    }
+
+    pub fn error(message: &str) -> Self {
+        Self::with_kind("Error", message)
+    }
+
+    pub fn type_error(message: &str) -> Self {
+        Self::with_kind("TypeError", message)
+    }
+
+    pub fn range_error(message: &str) -> Self {
+        Self::with_kind("RangeError", message)
+    }
+
+    pub fn reference_error(message: &str) -> Self {
+        Self::with_kind("ReferenceError", message)
+    }
+
+    fn with_kind(kind: &str, message: &str) -> Self {
+        Exception {
+            message: format!("{}: {}", kind, message),
+            value: None,
+            kind: ErrorKind::Other,
+        }
+    }
+
+    /// the thrown value, if the exception was created from one.
+    pub fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ErrorKind::Other => write!(f, "Uncaught {}", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<Exception> for Error {
+    fn from(e: Exception) -> Self {
+        Error::new(e.kind, e.to_string())
+    }
+}
+
+/// errors coming back from the interpreter already carry their "Uncaught ..." message.
+/// a terminated script keeps its error, so it stays uncatchable past a native function.
+impl From<Error> for Exception {
+    fn from(e: Error) -> Self {
+        let message = e.to_string();
+        let (message, kind) = match message.strip_prefix("Uncaught ") {
+            Some(thrown) => (thrown.to_string(), ErrorKind::Other),
+            None => (message.clone(), e.kind()),
+        };
+        Exception {
+            message,
+            value: None,
+            kind,
+        }
+    }
+}
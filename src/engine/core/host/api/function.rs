@@ -0,0 +1,179 @@
+use std::io::Error;
+
+use super::{exception::Exception, value::Value, Context};
+use crate::engine::{
+    ast::Program,
+    core::host::{
+        handles::{HandleScope, ModuleScope},
+        objects::{JSBuiltinFunction, RuntimeObject},
+        HostInterpreter,
+    },
+};
+
+/// a handle to a callable JavaScript value (script or native function).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function(RuntimeObject);
+
+impl Function {
+    /// create a native function.
+    /// the closure may capture rust state and receives the call context, `this` and the arguments.
+    pub fn new(
+        name: &str,
+        func: impl Fn(&mut CallContext, Value, Vec<Value>) -> Result<Value, Exception> + 'static,
+    ) -> Self {
+        Function(RuntimeObject::BuiltinFunction(JSBuiltinFunction::new(
+            name, func,
+        )))
+    }
+
+    pub(super) fn from_raw(o: RuntimeObject) -> Self {
+        Function(o)
+    }
+
+    /// call the function with `this` as receiver.
+    pub fn call(
+        &self,
+        context: &mut Context,
+        this: &Value,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let mut ev = HostInterpreter::new(context);
+        CallContext::new(&mut ev)
+            .call(self, this, args)
+            .map_err(Error::from)
+    }
+}
+
+impl From<Function> for Value {
+    fn from(f: Function) -> Self {
+        Value::from(f.0)
+    }
+}
+
+/// the interpreter state visible to a native function while it runs.
+pub struct CallContext<'a, 'b> {
+    interpreter: &'a mut HostInterpreter<'b>,
+}
+
+impl<'a, 'b> CallContext<'a, 'b> {
+    pub(crate) fn new(interpreter: &'a mut HostInterpreter<'b>) -> Self {
+        CallContext { interpreter }
+    }
+
+    /// call back into JavaScript.
+    pub fn call(
+        &mut self,
+        function: &Function,
+        this: &Value,
+        args: Vec<Value>,
+    ) -> Result<Value, Exception> {
+        let saved_this = self.interpreter.exec_ctx_this.clone();
+        let args = args.into_iter().map(Value::into_raw).collect();
+        let result = self
+            .interpreter
+            .call(function.0.clone(), this.clone().into_raw(), args);
+        self.interpreter.exec_ctx_this = saved_this;
+
+        result.map(Value::from).map_err(Exception::from)
+    }
+
+    pub fn context(&mut self) -> &mut Context {
+        self.interpreter.ctx
+    }
+
+    /// run `program` as a CommonJS module: in the context and on the budget of the running
+    /// script, with the bindings of `module` in place of the caller's.
+    pub fn run_module(
+        &mut self,
+        program: &Program,
+        module: ModuleScope,
+    ) -> Result<Value, Exception> {
+        let saved_this = self.interpreter.exec_ctx_this.clone();
+        let scope = HandleScope::for_module(module);
+        let result = self.interpreter.in_scope(scope, |ev| ev.eval(program));
+        self.interpreter.exec_ctx_this = saved_this;
+
+        result.map(Value::from).map_err(Exception::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::engine::{
+        core::host::{
+            api::{Object, Script},
+            handles::HandleScope,
+            objects::JSNumber,
+        },
+        parsing::{BuiltinParser, Parser},
+    };
+
+    fn run(context: &mut Context, source: &str) -> Result<Value, Error> {
+        let mut parser: Box<dyn Parser> = Box::new(BuiltinParser);
+        let mut script = Script::compile(source.to_string(), context, &mut parser);
+        script.run().map(Value::from)
+    }
+
+    #[test]
+    fn test_native_function_captures_state() {
+        let mut context = Context::new(HandleScope::new());
+        let counter = Rc::new(Cell::new(0));
+        let c = counter.clone();
+        let increment = Function::new("increment", move |_, _, _| {
+            c.set(c.get() + 1);
+            Ok(Value::from(RuntimeObject::Number(JSNumber::new(
+                c.get() as f64
+            ))))
+        });
+        context
+            .global()
+            .set("increment", Value::from(increment).into_raw());
+
+        let v = run(&mut context, "increment(); increment(); increment();").unwrap();
+        assert_eq!(v.to::<f64>().unwrap(), 3.0);
+        assert_eq!(counter.get(), 3);
+    }
+
+    #[test]
+    fn test_native_function_calls_back() {
+        let mut context = Context::new(HandleScope::new());
+        let apply = Function::new("apply", |cx, _, args| {
+            let callback = args[0].to::<Function>()?;
+            cx.call(&callback, &Value::undefined(), vec![args[1].clone()])
+        });
+        context.global().set("apply", Value::from(apply).into_raw());
+
+        let v = run(&mut context, "apply(function(n) { return n * 2; }, 21);").unwrap();
+        assert_eq!(v.to::<f64>().unwrap(), 42.0);
+    }
+
+    #[test]
+    fn test_native_function_this_and_throw() {
+        let mut context = Context::new(HandleScope::new());
+        let get_name = Function::new("getName", |_, this, _| {
+            let this = this.to::<Object>()?;
+            let name = this.get("name");
+            if name.is_undefined() {
+                Err(Exception::type_error("name is not set"))
+            } else {
+                Ok(name)
+            }
+        });
+        context
+            .global()
+            .set("getName", Value::from(get_name).into_raw());
+
+        let v = run(
+            &mut context,
+            "const o = { name: 'glasper', getName: getName }; o.getName();",
+        )
+        .unwrap();
+        assert_eq!(v.to::<String>().unwrap(), "glasper");
+
+        let e = run(&mut context, "const p = { getName: getName }; p.getName();").unwrap_err();
+        assert_eq!(e.to_string(), "Uncaught TypeError: name is not set");
+    }
+}
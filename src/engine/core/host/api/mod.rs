@@ -2,12 +2,26 @@ use crate::engine::{
     ast::Program,
     core::host::{
         handles::HandleScope,
+        heap::{Heap, HeapStatistics},
         objects::{JSObject, RuntimeObject},
         HostInterpreter,
     },
+    core::limits::Budget,
     parsing::Parser,
 };
-use std::{cell::RefCell, collections::HashMap, io::Error, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, io::Error, path::PathBuf, rc::Rc};
+
+mod exception;
+mod function;
+mod value;
+#[allow(unused_imports)]
+pub use crate::engine::core::limits::{InterruptHandle, Limits};
+#[allow(unused_imports)]
+pub use exception::Exception;
+#[allow(unused_imports)]
+pub use function::{CallContext, Function};
+#[allow(unused_imports)]
+pub use value::{FromJs, IntoJs, Object, Value};
 
 pub struct Isolate {
     pub context: Context,
@@ -18,6 +32,25 @@ impl Isolate {
         Isolate { context, parser }
     }
 
+    /// limits applied to every script run in this isolate.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.context.set_limits(limits);
+    }
+
+    /// a handle that can terminate the running script from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.context.interrupt_handle()
+    }
+
+    pub fn heap_statistics(&mut self) -> HeapStatistics {
+        self.context.heap_statistics()
+    }
+
+    /// free objects only kept alive by reference cycles.
+    pub fn collect_garbage(&mut self) {
+        self.context.collect_garbage();
+    }
+
     pub fn install_functions(&mut self, paths: Vec<&str>) {
         for path in paths {
             match std::fs::read_to_string(path) {
@@ -41,18 +74,87 @@ impl Isolate {
 pub struct Context {
     pub scope: HandleScope,
     global_scope: Global,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    heap: Heap,
+    /// the value of the last `throw` with the message of its error, for the `catch` that
+    /// catches the error.
+    thrown: Option<(String, RuntimeObject)>,
+    /// the CommonJS modules loaded in this context by their file, for `require`
+    modules: HashMap<PathBuf, RuntimeObject>,
 }
 impl Context {
     pub fn new(scope: HandleScope) -> Self {
         Self {
             scope,
             global_scope: Global::new(),
+            limits: Limits::default(),
+            interrupt: InterruptHandle::new(),
+            heap: Heap::new(),
+            thrown: None,
+            modules: HashMap::new(),
         }
     }
 
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// allocate an object on the accounted heap of this context.
+    pub fn alloc(&mut self, object: JSObject) -> Result<Rc<RefCell<JSObject>>, Error> {
+        self.heap.alloc(object, &self.limits)
+    }
+
+    pub fn heap_statistics(&mut self) -> HeapStatistics {
+        self.heap.statistics()
+    }
+
+    pub fn collect_garbage(&mut self) {
+        self.heap.collect();
+    }
+
+    /// keep `value`, thrown with the error `message`.
+    pub(crate) fn set_thrown(&mut self, message: String, value: RuntimeObject) {
+        self.thrown = Some((message, value));
+    }
+
+    /// the value thrown with the error `message`, `None` for an error of the interpreter.
+    pub(crate) fn take_thrown(&mut self, message: &str) -> Option<RuntimeObject> {
+        match self.thrown.take() {
+            Some((m, value)) if m == message => Some(value),
+            _ => None,
+        }
+    }
+
+    /// the `module` objects of the files required in this context, cached by their path.
+    pub fn modules(&mut self) -> &mut HashMap<PathBuf, RuntimeObject> {
+        &mut self.modules
+    }
+
+    /// a fresh budget for one run, the deadline starts now.
+    pub(crate) fn budget(&self) -> Budget {
+        Budget::new(self.limits.clone(), self.interrupt.clone())
+    }
+
     pub fn global(&mut self) -> &mut Global {
         &mut self.global_scope
     }
+
+    /// `Array.prototype`, linked from every array created in this context
+    pub fn array_prototype(&self) -> Option<RuntimeObject> {
+        match self.global_scope.get("Array") {
+            Some(RuntimeObject::Object(o)) => o.borrow().properties.get("prototype").cloned(),
+            _ => None,
+        }
+    }
 }
 
 pub struct Global {
@@ -90,6 +192,10 @@ impl Global {
     pub fn set(&mut self, name: &str, ob: RuntimeObject) {
         self.scope.insert(name.to_string(), ob);
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &RuntimeObject)> {
+        self.scope.iter()
+    }
 }
 
 pub struct Script<'a> {
@@ -0,0 +1,564 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    rc::Rc,
+};
+
+use super::function::Function;
+use crate::engine::core::host::{
+    api::Context,
+    objects::{JSBoolean, JSNull, JSNumber, JSObject, JSString, JSUndefined, RuntimeObject},
+    HostInterpreter,
+};
+
+const PROTOTYPE_KEY_NAME: &str = "__proto__";
+
+/// a handle to any JavaScript value.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Value(RuntimeObject);
+
+impl Value {
+    pub fn undefined() -> Self {
+        Value(RuntimeObject::Undefined(JSUndefined))
+    }
+
+    pub fn null() -> Self {
+        Value(RuntimeObject::Null(JSNull))
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        matches!(self.0, RuntimeObject::Undefined(_))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.0, RuntimeObject::Null(_))
+    }
+
+    pub fn is_boolean(&self) -> bool {
+        matches!(self.0, RuntimeObject::Boolean(_))
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self.0, RuntimeObject::Number(_))
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(self.0, RuntimeObject::String(_))
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(self.0, RuntimeObject::Object(_))
+    }
+
+    pub fn is_function(&self) -> bool {
+        matches!(
+            self.0,
+            RuntimeObject::Function(_) | RuntimeObject::BuiltinFunction(_)
+        )
+    }
+
+    /// the result of `typeof value`.
+    pub fn type_of(&self) -> String {
+        self.0.get_type()
+    }
+
+    pub fn as_object(&self) -> Option<Object> {
+        match &self.0 {
+            RuntimeObject::Object(o) => Some(Object(o.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<Function> {
+        if self.is_function() {
+            Some(Function::from_raw(self.0.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// convert into a rust value.
+    pub fn to<T: FromJs>(&self) -> Result<T, Error> {
+        T::from_js(self)
+    }
+
+    pub fn into_raw(self) -> RuntimeObject {
+        self.0
+    }
+}
+
+impl From<RuntimeObject> for Value {
+    fn from(o: RuntimeObject) -> Self {
+        Value(o)
+    }
+}
+
+/// a handle to a JavaScript object (arrays included).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Object(Rc<RefCell<JSObject>>);
+
+impl Object {
+    /// create an empty object on the heap of `context`.
+    pub fn new(context: &mut Context) -> Result<Self, Error> {
+        let o = context.alloc(JSObject {
+            properties: HashMap::new(),
+        })?;
+        Ok(Object(o))
+    }
+
+    /// create an array linked to `Array.prototype` of `context`, on its heap.
+    pub fn new_array(context: &mut Context, elements: Vec<Value>) -> Result<Self, Error> {
+        let elements = elements.into_iter().map(|e| e.0).collect();
+        let prototype = context.array_prototype();
+        let o = context.alloc(JSObject::array(elements, prototype))?;
+        Ok(Object(o))
+    }
+
+    /// get a property, following the prototype chain.
+    pub fn get(&self, key: &str) -> Value {
+        match HostInterpreter::eval_property(self.0.clone(), key) {
+            Ok(v) => Value(v),
+            Err(_) => Value::undefined(),
+        }
+    }
+
+    pub fn set(&self, key: &str, value: Value) {
+        self.0
+            .borrow_mut()
+            .properties
+            .insert(key.to_string(), value.0);
+    }
+
+    pub fn has(&self, key: &str) -> bool {
+        self.0.borrow().properties.contains_key(key)
+    }
+
+    /// own property names, without the prototype link.
+    pub fn keys(&self) -> Vec<String> {
+        self.0
+            .borrow()
+            .properties
+            .keys()
+            .filter(|k| k.as_str() != PROTOTYPE_KEY_NAME)
+            .cloned()
+            .collect()
+    }
+}
+
+impl From<Object> for Value {
+    fn from(o: Object) -> Self {
+        Value(RuntimeObject::Object(o.0))
+    }
+}
+
+/// conversion from a rust value into a JavaScript value. objects are allocated on the heap of
+/// `context`, so the conversion fails when it is full.
+pub trait IntoJs {
+    fn into_js(self, context: &mut Context) -> Result<Value, Error>;
+}
+
+/// conversion from a JavaScript value into a rust value.
+pub trait FromJs: Sized {
+    fn from_js(value: &Value) -> Result<Self, Error>;
+}
+
+fn type_error(expected: &str, value: &Value) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "Uncaught TypeError: expected {}, got {}",
+            expected,
+            value.type_of()
+        ),
+    )
+}
+
+impl IntoJs for Value {
+    fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+        Ok(self)
+    }
+}
+impl FromJs for Value {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoJs for Object {
+    fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+        Ok(self.into())
+    }
+}
+impl FromJs for Object {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        value.as_object().ok_or_else(|| type_error("object", value))
+    }
+}
+
+impl FromJs for Function {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        value
+            .as_function()
+            .ok_or_else(|| type_error("function", value))
+    }
+}
+
+impl IntoJs for () {
+    fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+        Ok(Value::undefined())
+    }
+}
+
+impl IntoJs for bool {
+    fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+        Ok(Value(RuntimeObject::Boolean(JSBoolean::new(self))))
+    }
+}
+impl FromJs for bool {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        match value.0 {
+            RuntimeObject::Boolean(JSBoolean { value }) => Ok(value),
+            _ => Err(type_error("boolean", value)),
+        }
+    }
+}
+
+macro_rules! impl_number {
+    ($($t:ty),*) => {
+        $(
+            impl IntoJs for $t {
+                fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+                    Ok(Value(RuntimeObject::Number(JSNumber::new(self as f64))))
+                }
+            }
+            impl FromJs for $t {
+                fn from_js(value: &Value) -> Result<Self, Error> {
+                    match value.0 {
+                        RuntimeObject::Number(JSNumber { value }) => Ok(value as $t),
+                        _ => Err(type_error("number", value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+impl_number!(f64, f32);
+
+/// integers take numbers that are integral and in their range, where `as` would truncate
+/// or saturate.
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl IntoJs for $t {
+                fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+                    Ok(Value(RuntimeObject::Number(JSNumber::new(self as f64))))
+                }
+            }
+            impl FromJs for $t {
+                fn from_js(value: &Value) -> Result<Self, Error> {
+                    // 2^bits or 2^(bits - 1), exact where `MAX as f64` rounds up
+                    let end = (<$t>::MAX / 2 + 1) as f64 * 2.0;
+                    let n = integer(value, <$t>::MIN as f64, end, stringify!($t))?;
+                    Ok(n as $t)
+                }
+            }
+        )*
+    };
+}
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// the number `value` holds, if it is an integer in `start..end`.
+fn integer(value: &Value, start: f64, end: f64, name: &str) -> Result<f64, Error> {
+    let n = match value.0 {
+        RuntimeObject::Number(JSNumber { value }) => value,
+        _ => return Err(type_error("number", value)),
+    };
+    if n.is_nan() || (n.is_finite() && n.fract() != 0.0) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Uncaught TypeError: {} is not an integer", n),
+        ));
+    }
+    if n < start || n >= end {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Uncaught RangeError: {} is out of the range of {}", n, name),
+        ));
+    }
+    Ok(n)
+}
+
+impl IntoJs for String {
+    fn into_js(self, _: &mut Context) -> Result<Value, Error> {
+        Ok(Value(RuntimeObject::String(JSString { value: self })))
+    }
+}
+impl IntoJs for &str {
+    fn into_js(self, context: &mut Context) -> Result<Value, Error> {
+        self.to_string().into_js(context)
+    }
+}
+impl FromJs for String {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        match &value.0 {
+            RuntimeObject::String(JSString { value }) => Ok(value.clone()),
+            _ => Err(type_error("string", value)),
+        }
+    }
+}
+
+impl<T: IntoJs> IntoJs for Option<T> {
+    fn into_js(self, context: &mut Context) -> Result<Value, Error> {
+        match self {
+            Some(v) => v.into_js(context),
+            None => Ok(Value::null()),
+        }
+    }
+}
+impl<T: FromJs> FromJs for Option<T> {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        if value.is_null() || value.is_undefined() {
+            Ok(None)
+        } else {
+            T::from_js(value).map(Some)
+        }
+    }
+}
+
+impl<T: IntoJs> IntoJs for Vec<T> {
+    fn into_js(self, context: &mut Context) -> Result<Value, Error> {
+        let elements = self
+            .into_iter()
+            .map(|e| e.into_js(context))
+            .collect::<Result<_, _>>()?;
+        Object::new_array(context, elements).map(Value::from)
+    }
+}
+/// the `length` of the array is trusted only as far as the object has the properties for it.
+impl<T: FromJs> FromJs for Vec<T> {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        let o = value
+            .as_object()
+            .ok_or_else(|| type_error("array", value))?;
+        let len = match o.get("length").0 {
+            RuntimeObject::Number(JSNumber { value }) if value >= 0.0 && value.fract() == 0.0 => {
+                value
+            }
+            _ => return Err(type_error("array", value)),
+        };
+        if len > o.0.borrow().properties.len() as f64 {
+            return Err(type_error("array", value));
+        }
+        (0..len as usize)
+            .map(|i| T::from_js(&o.get(&i.to_string())))
+            .collect()
+    }
+}
+
+impl<T: IntoJs> IntoJs for HashMap<String, T> {
+    fn into_js(self, context: &mut Context) -> Result<Value, Error> {
+        let o = Object::new(context)?;
+        for (k, v) in self {
+            o.set(&k, v.into_js(context)?);
+        }
+        Ok(o.into())
+    }
+}
+impl<T: FromJs> FromJs for HashMap<String, T> {
+    fn from_js(value: &Value) -> Result<Self, Error> {
+        let o = value
+            .as_object()
+            .ok_or_else(|| type_error("object", value))?;
+        o.keys()
+            .into_iter()
+            .map(|k| {
+                let v = T::from_js(&o.get(&k))?;
+                Ok((k, v))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        core::{
+            host::{api::Script, handles::HandleScope},
+            limits::Limits,
+        },
+        parsing::{BuiltinParser, Parser},
+    };
+
+    fn run(context: &mut Context, source: &str) -> Value {
+        let mut parser: Box<dyn Parser> = Box::new(BuiltinParser);
+        let mut script = Script::compile(source.to_string(), context, &mut parser);
+        Value::from(script.run().unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut context = Context::new(HandleScope::new());
+
+        let v = 1.5.into_js(&mut context).unwrap();
+        assert_eq!(v.to::<f64>().unwrap(), 1.5);
+        let v = "glasper".into_js(&mut context).unwrap();
+        assert_eq!(v.to::<String>().unwrap(), "glasper");
+        let v = Some(true).into_js(&mut context).unwrap();
+        assert_eq!(v.to::<Option<bool>>().unwrap(), Some(true));
+        let v = None::<bool>.into_js(&mut context).unwrap();
+        assert!(v.is_null());
+        assert_eq!(v.to::<Option<bool>>().unwrap(), None);
+
+        let v = vec![1, 2, 3].into_js(&mut context).unwrap();
+        assert_eq!(v.to::<Vec<i32>>().unwrap(), vec![1, 2, 3]);
+
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), vec!["x".to_string()]);
+        let v = map.clone().into_js(&mut context).unwrap();
+        assert_eq!(v.to::<HashMap<String, Vec<String>>>().unwrap(), map);
+
+        assert!(1.into_js(&mut context).unwrap().to::<String>().is_err());
+    }
+
+    #[test]
+    fn test_to_integer() {
+        let mut context = Context::new(HandleScope::new());
+        let error = |v: Value, to: fn(&Value) -> Result<(), Error>| to(&v).unwrap_err().to_string();
+
+        assert_eq!(
+            (-128.0).into_js(&mut context).unwrap().to::<i8>().unwrap(),
+            -128
+        );
+        assert_eq!(
+            255.0.into_js(&mut context).unwrap().to::<u8>().unwrap(),
+            255
+        );
+        assert_eq!((-0.0).into_js(&mut context).unwrap().to::<u8>().unwrap(), 0);
+        let v = 9007199254740992.0.into_js(&mut context).unwrap();
+        assert_eq!(v.to::<u64>().unwrap(), 1 << 53);
+
+        // no truncation
+        assert_eq!(
+            error(1.5.into_js(&mut context).unwrap(), |v| v
+                .to::<i32>()
+                .map(drop)),
+            "Uncaught TypeError: 1.5 is not an integer"
+        );
+        assert_eq!(
+            error(f64::NAN.into_js(&mut context).unwrap(), |v| v
+                .to::<i32>()
+                .map(drop)),
+            "Uncaught TypeError: NaN is not an integer"
+        );
+        // no saturation
+        assert_eq!(
+            error((-1.0).into_js(&mut context).unwrap(), |v| v
+                .to::<u8>()
+                .map(drop)),
+            "Uncaught RangeError: -1 is out of the range of u8"
+        );
+        assert_eq!(
+            error(300.0.into_js(&mut context).unwrap(), |v| v
+                .to::<u8>()
+                .map(drop)),
+            "Uncaught RangeError: 300 is out of the range of u8"
+        );
+        assert_eq!(
+            error(f64::INFINITY.into_js(&mut context).unwrap(), |v| v
+                .to::<i64>()
+                .map(drop)),
+            "Uncaught RangeError: inf is out of the range of i64"
+        );
+        // i64::MAX as f64 rounds up to 2^63, which is out of range
+        assert_eq!(
+            error((i64::MAX as f64).into_js(&mut context).unwrap(), |v| v
+                .to::<i64>()
+                .map(drop)),
+            "Uncaught RangeError: 9223372036854776000 is out of the range of i64"
+        );
+        assert!(vec![1.0, 2.5]
+            .into_js(&mut context)
+            .unwrap()
+            .to::<Vec<u32>>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_from_js_length() {
+        let mut context = Context::new(HandleScope::new());
+        // the `length` is not backed by properties
+        let v = run(&mut context, "const o = { length: 1e15 }; o;");
+        assert_eq!(
+            v.to::<Vec<f64>>().unwrap_err().to_string(),
+            "Uncaught TypeError: expected array, got object"
+        );
+        let v = run(&mut context, "const p = { length: -1 }; p;");
+        assert!(v.to::<Vec<f64>>().is_err());
+        let v = run(&mut context, "const q = { length: 1 }; q[0] = 'a'; q;");
+        assert_eq!(v.to::<Vec<String>>().unwrap(), vec!["a"]);
+    }
+
+    #[test]
+    fn test_heap() {
+        let mut context = Context::new(HandleScope::new());
+        let objects = context.heap_statistics().objects;
+        let _o = Object::new(&mut context).unwrap();
+        let _v = vec![1, 2].into_js(&mut context).unwrap();
+        let _m = HashMap::<String, i32>::new().into_js(&mut context).unwrap();
+        assert_eq!(context.heap_statistics().objects, objects + 3);
+
+        context.set_limits(Limits::new().max_heap_objects(objects + 3));
+        assert_eq!(
+            Object::new(&mut context).unwrap_err().to_string(),
+            "Uncaught RangeError: Out of memory: heap limit exceeded"
+        );
+        assert!(vec![1].into_js(&mut context).is_err());
+        // scalars need no heap
+        assert!(1.into_js(&mut context).is_ok());
+    }
+
+    #[test]
+    fn test_read_script_result() {
+        let mut context = Context::new(HandleScope::new());
+        let v = run(&mut context, "const o = { a: 1, b: [1, 2], c: 'str' }; o;");
+        let o = v.as_object().unwrap();
+        assert_eq!(o.get("a").to::<u8>().unwrap(), 1);
+        assert_eq!(o.get("b").to::<Vec<f64>>().unwrap(), vec![1.0, 2.0]);
+        assert_eq!(o.get("c").to::<String>().unwrap(), "str");
+        assert!(o.get("d").is_undefined());
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut context = Context::new(HandleScope::new());
+        let add = run(
+            &mut context,
+            "const add = function(a, b) { return a + b + this.c; }; add;",
+        );
+        let add = add.as_function().unwrap();
+
+        let this = Object::new(&mut context).unwrap();
+        this.set("c", 10.into_js(&mut context).unwrap());
+        let args = vec![
+            1.into_js(&mut context).unwrap(),
+            2.into_js(&mut context).unwrap(),
+        ];
+        let ret = add.call(&mut context, &this.into(), args).unwrap();
+        assert_eq!(ret.to::<f64>().unwrap(), 13.0);
+    }
+
+    #[test]
+    fn test_set_global_object() {
+        let mut context = Context::new(HandleScope::new());
+        let config = Object::new(&mut context).unwrap();
+        config.set("name", "glasper".into_js(&mut context).unwrap());
+        context
+            .global()
+            .set("config", Value::from(config).into_raw());
+
+        let v = run(&mut context, "config.name;");
+        assert_eq!(v.to::<String>().unwrap(), "glasper");
+    }
+}
@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
 
 use super::objects::RuntimeObject;
 
 pub struct HandleScope {
     pub scopes: Vec<HashMap<String, Variable>>,
+    /// the top-level bindings of the module being evaluated, below `scopes`
+    module: Option<ModuleScope>,
 }
 impl Default for HandleScope {
     fn default() -> Self {
@@ -16,38 +18,60 @@ impl HandleScope {
             scopes: vec![
                 HashMap::new(), // default scope
             ],
+            module: None,
         }
     }
 
-    pub fn get(&self, name: &str) -> Option<&Variable> {
+    /// the scope of code evaluated as the module `module`, its top-level bindings go there.
+    pub fn for_module(module: ModuleScope) -> HandleScope {
+        HandleScope {
+            scopes: vec![],
+            module: Some(module),
+        }
+    }
+
+    /// the module being evaluated, which functions created now keep.
+    pub fn module(&self) -> Option<ModuleScope> {
+        self.module.clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Variable> {
         for scope in self.scopes.iter().rev() {
             if let Some(variable) = scope.get(name) {
-                return Some(variable);
+                return Some(variable.clone());
             }
         }
 
-        None
+        let module = self.module.as_ref()?;
+        let bindings = module.0.borrow();
+        bindings.get(name).cloned()
     }
 
     pub fn set(&mut self, name: &str, var: Variable) {
-        let last_idx = self.scopes.len() - 1;
-        self.scopes[last_idx].insert(name.to_string(), var);
+        match (self.scopes.last_mut(), &self.module) {
+            (Some(scope), _) => {
+                scope.insert(name.to_string(), var);
+            }
+            (None, Some(module)) => {
+                module.0.borrow_mut().insert(name.to_string(), var);
+            }
+            // there is always a scope or a module
+            (None, None) => {}
+        }
     }
 
     pub fn assign(&mut self, name: &str, var: Variable) {
-        let target_scope_idx = {
-            let mut i = self.scopes.len() - 1;
-            loop {
-                if self.scopes[i].get(name).is_some() {
-                    break i;
-                }
-                if i == 0 {
-                    break self.scopes.len() - 1;
-                }
-                i -= 1;
+        if let Some(scope) = self.scopes.iter_mut().rev().find(|s| s.contains_key(name)) {
+            scope.insert(name.to_string(), var);
+            return;
+        }
+        match &self.module {
+            Some(module) if module.0.borrow().contains_key(name) => {
+                module.0.borrow_mut().insert(name.to_string(), var);
             }
-        };
-        self.scopes[target_scope_idx].insert(name.to_string(), var);
+            // an undeclared name goes to the innermost scope
+            _ => self.set(name, var),
+        }
     }
 
     pub fn scope_in(&mut self) {
@@ -59,7 +83,34 @@ impl HandleScope {
     }
 }
 
-#[derive(PartialEq, Debug)]
+/// the top-level bindings of a CommonJS module. functions created in the module keep them
+/// and see them wherever they are called, after the module is done too.
+#[derive(Clone, Default)]
+pub struct ModuleScope(pub(crate) Rc<RefCell<HashMap<String, Variable>>>);
+
+impl ModuleScope {
+    /// bind `name` at the top level of the module, like a parameter of the function node
+    /// wraps a module in.
+    pub fn declare(&self, name: &str, value: RuntimeObject) {
+        let var = Variable::new(VariableKind::Var, value);
+        self.0.borrow_mut().insert(name.to_string(), var);
+    }
+}
+
+impl PartialEq for ModuleScope {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// the bindings reach the functions that keep them
+impl Debug for ModuleScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ModuleScope")
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub struct Variable {
     pub kind: VariableKind,
     pub value: RuntimeObject,
@@ -94,7 +145,7 @@ mod test {
         );
         assert_eq!(
             env.get("a"),
-            Some(&Variable::new(
+            Some(Variable::new(
                 VariableKind::Const,
                 RuntimeObject::Number(JSNumber::new(1.0))
             ))
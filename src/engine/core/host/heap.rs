@@ -0,0 +1,373 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    mem::size_of,
+    rc::{Rc, Weak},
+};
+
+use super::{
+    handles::ModuleScope,
+    objects::{JSFunction, JSObject, RuntimeObject},
+};
+use crate::engine::core::limits::Limits;
+
+/// number of tracked objects before the first collection.
+const INITIAL_COLLECTION_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStatistics {
+    /// live objects allocated by scripts
+    pub objects: usize,
+    /// estimated size of those objects in bytes
+    pub bytes: usize,
+    /// number of cycle collections run so far
+    pub collections: usize,
+}
+
+/// accounting for the objects allocated by the interpreter,
+/// with a cycle collector for the `Rc` graph.
+///
+/// `Rc` frees acyclic garbage on its own. cycles such as `o.self = o` are found by trial
+/// deletion: an object whose strong count is larger than the number of references held by
+/// tracked objects is referenced from outside (a variable, the global scope, rust code) and
+/// is live along with everything it reaches. the rest is only kept alive by cycles, and its
+/// properties are cleared to break them. a function keeps the bindings of its module, which are
+/// walked like the properties of an object.
+pub struct Heap {
+    objects: Vec<Weak<RefCell<JSObject>>>,
+    /// estimated bytes of the tracked objects, exact right after a collection
+    bytes: usize,
+    threshold: usize,
+    collections: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            bytes: 0,
+            threshold: INITIAL_COLLECTION_THRESHOLD,
+            collections: 0,
+        }
+    }
+
+    /// allocate `object`, collecting cycles when the heap has grown since the last collection.
+    /// throws `RangeError` when the limits are still exceeded after a collection.
+    pub fn alloc(
+        &mut self,
+        object: JSObject,
+        limits: &Limits,
+    ) -> Result<Rc<RefCell<JSObject>>, Error> {
+        let size = object_size(&object);
+
+        if self.objects.len() >= self.threshold || self.exceeds(limits, size) {
+            self.collect();
+            if self.exceeds(limits, size) {
+                return Err(Error::new(
+                    ErrorKind::OutOfMemory,
+                    "Uncaught RangeError: Out of memory: heap limit exceeded",
+                ));
+            }
+        }
+
+        let o = Rc::new(RefCell::new(object));
+        self.objects.push(Rc::downgrade(&o));
+        self.bytes += size;
+        Ok(o)
+    }
+
+    fn exceeds(&self, limits: &Limits, size: usize) -> bool {
+        matches!(limits.max_heap_objects, Some(max) if self.objects.len() + 1 > max)
+            || matches!(limits.max_heap_bytes, Some(max) if self.bytes + size > max)
+    }
+
+    pub fn statistics(&mut self) -> HeapStatistics {
+        self.objects.retain(|o| o.strong_count() > 0);
+        self.bytes = self.live_bytes();
+        HeapStatistics {
+            objects: self.objects.len(),
+            bytes: self.bytes,
+            collections: self.collections,
+        }
+    }
+
+    /// free unreachable cycles and recompute the accounting.
+    pub fn collect(&mut self) {
+        let mut nodes: Vec<Node> = self
+            .objects
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Node::Object)
+            .collect();
+        let mut index: HashMap<*const (), usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.as_ptr(), i))
+            .collect();
+
+        // references held by tracked objects and by the module scopes they reach. a node that
+        // is borrowed right now can not be inspected and is treated as live.
+        let mut internal = vec![0; nodes.len()];
+        let mut edges = vec![Vec::new(); nodes.len()];
+        let mut live = vec![false; nodes.len()];
+        let mut i = 0;
+        while i < nodes.len() {
+            match nodes[i].children() {
+                Some(children) => {
+                    for child in children {
+                        let j = match (index.get(&child.as_ptr()), child) {
+                            (Some(&j), _) => j,
+                            // the scopes are found on the way, untracked objects are external
+                            (None, child @ Node::Module(_)) => {
+                                index.insert(child.as_ptr(), nodes.len());
+                                nodes.push(child);
+                                internal.push(0);
+                                edges.push(Vec::new());
+                                live.push(false);
+                                nodes.len() - 1
+                            }
+                            (None, Node::Object(_)) => continue,
+                        };
+                        internal[j] += 1;
+                        edges[i].push(j);
+                    }
+                }
+                None => live[i] = true,
+            }
+            i += 1;
+        }
+
+        // `nodes` itself holds one reference to each
+        let mut stack: Vec<usize> = (0..nodes.len())
+            .filter(|&i| live[i] || nodes[i].strong_count() - 1 > internal[i])
+            .collect();
+        for &i in &stack {
+            live[i] = true;
+        }
+        while let Some(i) = stack.pop() {
+            for &j in &edges[i] {
+                if !live[j] {
+                    live[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+
+        for (i, node) in nodes.iter().enumerate() {
+            if !live[i] {
+                node.clear();
+            }
+        }
+        drop(nodes);
+
+        self.collections += 1;
+        self.objects.retain(|o| o.strong_count() > 0);
+        self.bytes = self.live_bytes();
+        self.threshold = INITIAL_COLLECTION_THRESHOLD.max(self.objects.len() * 2);
+    }
+
+    fn live_bytes(&self) -> usize {
+        self.objects
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|o| match o.try_borrow() {
+                Ok(o) => object_size(&o),
+                Err(_) => size_of::<RefCell<JSObject>>(),
+            })
+            .sum()
+    }
+}
+
+/// a node of the graph the collector walks: a tracked object, or the bindings of a module,
+/// which the functions created in the module keep.
+enum Node {
+    Object(Rc<RefCell<JSObject>>),
+    Module(ModuleScope),
+}
+
+impl Node {
+    fn as_ptr(&self) -> *const () {
+        match self {
+            Node::Object(o) => Rc::as_ptr(o) as *const (),
+            Node::Module(m) => Rc::as_ptr(&m.0) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Object(o) => Rc::strong_count(o),
+            Node::Module(m) => Rc::strong_count(&m.0),
+        }
+    }
+
+    /// the nodes directly referenced by the properties of an object or the variables of a
+    /// module, `None` while it is borrowed.
+    fn children(&self) -> Option<Vec<Node>> {
+        let mut out = Vec::new();
+        match self {
+            Node::Object(o) => {
+                let o = o.try_borrow().ok()?;
+                o.properties.values().for_each(|v| visit(v, &mut out));
+            }
+            Node::Module(m) => {
+                let variables = m.0.try_borrow().ok()?;
+                variables.values().for_each(|v| visit(&v.value, &mut out));
+            }
+        }
+        Some(out)
+    }
+
+    /// drop the references of garbage, which breaks its cycles.
+    fn clear(&self) {
+        match self {
+            Node::Object(o) => {
+                if let Ok(mut o) = o.try_borrow_mut() {
+                    o.properties.clear();
+                }
+            }
+            Node::Module(m) => {
+                if let Ok(mut variables) = m.0.try_borrow_mut() {
+                    variables.clear();
+                }
+            }
+        }
+    }
+}
+
+fn visit(value: &RuntimeObject, out: &mut Vec<Node>) {
+    match value {
+        RuntimeObject::Object(o) => out.push(Node::Object(o.clone())),
+        RuntimeObject::Array(a) => a.elements.iter().for_each(|e| visit(e, out)),
+        RuntimeObject::Function(JSFunction {
+            module: Some(m), ..
+        }) => out.push(Node::Module(m.clone())),
+        RuntimeObject::Return(v) => visit(v, out),
+        _ => {}
+    }
+}
+
+/// an estimate of the memory held by `object`, excluding the objects it references.
+fn object_size(object: &JSObject) -> usize {
+    fn value_size(value: &RuntimeObject) -> usize {
+        match value {
+            RuntimeObject::String(s) => s.value.capacity(),
+            RuntimeObject::Array(a) => a.elements.iter().map(value_size).sum::<usize>(),
+            _ => 0,
+        }
+    }
+
+    // the two reference counts of the `Rc`
+    let header = size_of::<RefCell<JSObject>>() + 2 * size_of::<usize>();
+    let properties: usize = object
+        .properties
+        .iter()
+        .map(|(k, v)| size_of::<(String, RuntimeObject)>() + k.capacity() + value_size(v))
+        .sum();
+    header + properties
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ast::BlockStatement;
+
+    fn object() -> JSObject {
+        JSObject {
+            properties: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let limits = Limits::new();
+        let mut heap = Heap::new();
+
+        // a <-> b, unreachable once dropped
+        let a = heap.alloc(object(), &limits).unwrap();
+        let b = heap.alloc(object(), &limits).unwrap();
+        a.borrow_mut()
+            .properties
+            .insert("b".to_string(), RuntimeObject::Object(b.clone()));
+        b.borrow_mut()
+            .properties
+            .insert("a".to_string(), RuntimeObject::Object(a.clone()));
+        let weak = Rc::downgrade(&a);
+        drop((a, b));
+
+        // c -> d -> c, kept alive by `c`
+        let c = heap.alloc(object(), &limits).unwrap();
+        let d = heap.alloc(object(), &limits).unwrap();
+        c.borrow_mut()
+            .properties
+            .insert("d".to_string(), RuntimeObject::Object(d.clone()));
+        d.borrow_mut()
+            .properties
+            .insert("c".to_string(), RuntimeObject::Object(c.clone()));
+        drop(d);
+
+        assert_eq!(heap.statistics().objects, 4);
+        heap.collect();
+        assert!(weak.upgrade().is_none());
+        assert_eq!(heap.statistics().objects, 2);
+        assert!(c.borrow().properties.contains_key("d"));
+    }
+
+    #[test]
+    fn test_collect_module_cycles() {
+        let limits = Limits::new();
+        let mut heap = Heap::new();
+        let function = |module: &ModuleScope| {
+            RuntimeObject::Function(JSFunction::new(
+                vec![],
+                BlockStatement::new(vec![]),
+                Some(module.clone()),
+            ))
+        };
+
+        // `const o = {}; o.f = function () {};` at the top of a module that is done
+        let module = ModuleScope::default();
+        let o = heap.alloc(object(), &limits).unwrap();
+        module.declare("o", RuntimeObject::Object(o.clone()));
+        o.borrow_mut()
+            .properties
+            .insert("f".to_string(), function(&module));
+        let weak = Rc::downgrade(&o);
+        drop((module, o));
+
+        // the same, with the module still running
+        let running = ModuleScope::default();
+        let p = heap.alloc(object(), &limits).unwrap();
+        running.declare("p", RuntimeObject::Object(p.clone()));
+        p.borrow_mut()
+            .properties
+            .insert("f".to_string(), function(&running));
+        drop(p);
+
+        assert_eq!(heap.statistics().objects, 2);
+        heap.collect();
+        assert!(weak.upgrade().is_none());
+        assert_eq!(heap.statistics().objects, 1);
+        assert!(running.0.borrow().contains_key("p"));
+    }
+
+    #[test]
+    fn test_heap_limit() {
+        let limits = Limits::new().max_heap_objects(2);
+        let mut heap = Heap::new();
+        let _a = heap.alloc(object(), &limits).unwrap();
+        let _b = heap.alloc(object(), &limits).unwrap();
+        let e = heap.alloc(object(), &limits).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::OutOfMemory);
+
+        // a dropped object makes room again
+        drop(_b);
+        assert!(heap.alloc(object(), &limits).is_ok());
+    }
+}
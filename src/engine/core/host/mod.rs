@@ -6,28 +6,38 @@ use crate::engine::{
     ast::{
         ArrayExpression, BlockStatement, CallExpression, ConstStatement, Expression, ForInit,
         ForStatement, IfStatement, LetStatement, MemberExpression, ObjectExpression, Program,
-        Statement, SwitchStatement, UpdateExpression,
+        Statement, SwitchStatement, TryStatement, UpdateExpression,
     },
-    core::host::{
-        api::Context,
-        handles::{Variable, VariableKind},
-        objects::{
-            JSBoolean, JSFunction, JSNull, JSNumber, JSObject, JSString, JSUndefined, RuntimeObject,
+    core::{
+        host::{
+            api::{CallContext, Context, Value},
+            handles::{HandleScope, Variable, VariableKind},
+            objects::{
+                JSBoolean, JSFunction, JSNull, JSNumber, JSObject, JSString, JSUndefined,
+                RuntimeObject,
+            },
         },
+        limits::Budget,
     },
     parsing::Parser,
 };
 
 pub mod api;
 pub mod handles;
+pub mod heap;
 pub mod objects;
+#[cfg(feature = "serde")]
+pub mod serde;
 
 pub struct HostInterpreter<'a> {
     ctx: &'a mut Context,
     exec_ctx_this: Rc<RefCell<JSObject>>,
+    budget: Budget,
 }
 impl<'a> HostInterpreter<'a> {
     pub fn new(ctx: &'a mut Context) -> Self {
+        let budget = ctx.budget();
+
         // TODO: bind global object
         let global_obj = Rc::new(RefCell::new(JSObject {
             properties: HashMap::new(),
@@ -36,6 +46,7 @@ impl<'a> HostInterpreter<'a> {
         HostInterpreter {
             ctx,
             exec_ctx_this: global_obj,
+            budget,
         }
     }
 }
@@ -66,6 +77,7 @@ impl<'a> HostInterpreter<'a> {
         statement: &Statement,
         scope_type: ScopeType,
     ) -> Result<RuntimeObject, Error> {
+        self.budget.step()?;
         match statement {
             Statement::Expression(expr) => self.eval_expression(expr),
             Statement::Let(stmt) => self.eval_let_statement(stmt),
@@ -77,10 +89,13 @@ impl<'a> HostInterpreter<'a> {
             Statement::Return(expr) => self.eval_return_statement(expr, scope_type),
             Statement::Break => Ok(RuntimeObject::Break),
             Statement::Continue => Ok(RuntimeObject::Continue),
+            Statement::Throw(expr) => self.eval_throw_statement(expr),
+            Statement::Try(stmt) => self.eval_try_statement(stmt, scope_type),
         }
     }
 
     fn eval_expression(&mut self, expr: &Expression) -> Result<RuntimeObject, Error> {
+        self.budget.step()?;
         match expr {
             // literals
             Expression::Boolean(b) => Ok(RuntimeObject::Boolean(JSBoolean { value: *b })),
@@ -89,6 +104,7 @@ impl<'a> HostInterpreter<'a> {
             Expression::Function(f) => Ok(RuntimeObject::Function(JSFunction::new(
                 f.clone().parameters,
                 f.clone().body,
+                self.ctx.scope.module(),
             ))),
             Expression::Null => Ok(RuntimeObject::Null(JSNull)),
             Expression::Undefined => Ok(RuntimeObject::Undefined(JSUndefined)),
@@ -538,9 +554,8 @@ impl<'a> HostInterpreter<'a> {
             let value = self.eval_expression(&prop.value)?;
             properties.insert(key, value);
         }
-        Ok(RuntimeObject::Object(Rc::new(RefCell::new(JSObject {
-            properties,
-        }))))
+        let o = self.ctx.alloc(JSObject { properties })?;
+        Ok(RuntimeObject::Object(o))
     }
 
     fn eval_member_expression(&mut self, m: &MemberExpression) -> Result<RuntimeObject, Error> {
@@ -596,30 +611,14 @@ impl<'a> HostInterpreter<'a> {
     }
 
     fn eval_array_expression(&mut self, arr: &ArrayExpression) -> Result<RuntimeObject, Error> {
-        let mut properties = HashMap::new();
-        for (i, e) in arr.elements.iter().enumerate() {
-            let element = self.eval_expression(e)?;
-            properties.insert(i.to_string(), element);
+        let mut elements = Vec::new();
+        for e in arr.elements.iter() {
+            elements.push(self.eval_expression(e)?);
         }
 
-        // set length
-        properties.insert(
-            "length".to_string(),
-            RuntimeObject::Number(JSNumber {
-                value: arr.elements.len() as f64,
-            }),
-        );
-
-        // set prototype
-        let prototype = match self.ctx.global().get("Array").unwrap() {
-            RuntimeObject::Object(o) => o.borrow().properties.get("prototype").unwrap().clone(),
-            _ => unreachable!("unreachable"),
-        };
-        properties.insert("__proto__".to_string(), prototype);
-
-        Ok(RuntimeObject::Object(Rc::new(RefCell::new(JSObject {
-            properties,
-        }))))
+        let prototype = self.ctx.array_prototype();
+        let o = self.ctx.alloc(JSObject::array(elements, prototype))?;
+        Ok(RuntimeObject::Object(o))
     }
 
     fn eval_assign_expression(
@@ -666,83 +665,22 @@ impl<'a> HostInterpreter<'a> {
                 let prop = self.eval_expression(&m.property)?;
                 let new_value = self.eval_expression(right)?;
 
-                match prop {
-                    RuntimeObject::String(s) => match obj {
-                        RuntimeObject::Object(o) => {
-                            let o_name = if let Expression::Identifier(name) = &m.object.as_ref() {
-                                name.clone()
-                            } else {
-                                return Err(Error::new(
-                                    std::io::ErrorKind::Other,
-                                    "Uncaught SyntaxError: Invalid or unexpected token",
-                                ));
-                            };
-
-                            let v = self.ctx.scope.get(&o_name);
-                            match v {
-                                Some(Variable { value, .. }) => {
-                                    if let RuntimeObject::Object(o) = value.clone() {
-                                        o.borrow_mut()
-                                            .properties
-                                            .insert(s.value.clone(), new_value.clone());
-                                    }
-                                }
-                                None => {
-                                    let v = self.ctx.global().get(&o_name);
-                                    if let Some(RuntimeObject::Object(o)) = v {
-                                        o.borrow_mut()
-                                            .properties
-                                            .insert(s.value.clone(), new_value.clone());
-                                    }
-                                }
-                            }
-                            o.borrow_mut().properties.insert(s.value, new_value.clone());
-                            Ok(new_value)
-                        }
-                        _ => Err(Error::new(
+                let key = match prop {
+                    RuntimeObject::String(s) => s.value,
+                    RuntimeObject::Number(n) => n.value.to_string(),
+                    _ => {
+                        return Err(Error::new(
                             std::io::ErrorKind::Other,
                             "Uncaught SyntaxError: Invalid or unexpected token",
-                        )),
-                    },
-                    RuntimeObject::Number(n) => match obj {
-                        RuntimeObject::Object(o) => {
-                            let o_name = if let Expression::Identifier(name) = &m.object.as_ref() {
-                                name.clone()
-                            } else {
-                                return Err(Error::new(
-                                    std::io::ErrorKind::Other,
-                                    "Uncaught SyntaxError: Invalid or unexpected token",
-                                ));
-                            };
+                        ))
+                    }
+                };
 
-                            let v = self.ctx.scope.get(&o_name);
-                            match v {
-                                Some(Variable { value, .. }) => {
-                                    if let RuntimeObject::Object(o) = value.clone() {
-                                        o.borrow_mut()
-                                            .properties
-                                            .insert(n.value.clone().to_string(), new_value.clone());
-                                    }
-                                }
-                                None => {
-                                    let v = self.ctx.global().get(&o_name);
-                                    if let Some(RuntimeObject::Object(o)) = v {
-                                        o.borrow_mut()
-                                            .properties
-                                            .insert(n.value.clone().to_string(), new_value.clone());
-                                    }
-                                }
-                            }
-                            o.borrow_mut()
-                                .properties
-                                .insert(n.value.to_string(), new_value.clone());
-                            Ok(new_value)
-                        }
-                        _ => Err(Error::new(
-                            std::io::ErrorKind::Other,
-                            "Uncaught SyntaxError: Invalid or unexpected token",
-                        )),
-                    },
+                match obj {
+                    RuntimeObject::Object(o) => {
+                        o.borrow_mut().properties.insert(key, new_value.clone());
+                        Ok(new_value)
+                    }
                     _ => Err(Error::new(
                         std::io::ErrorKind::Other,
                         "Uncaught SyntaxError: Invalid or unexpected token",
@@ -820,7 +758,16 @@ impl<'a> HostInterpreter<'a> {
         scope_type: ScopeType,
     ) -> Result<RuntimeObject, Error> {
         self.ctx.scope.scope_in();
+        let result = self.eval_for_loop(statement, scope_type);
+        self.ctx.scope.scope_out();
+        result
+    }
 
+    fn eval_for_loop(
+        &mut self,
+        statement: &ForStatement,
+        scope_type: ScopeType,
+    ) -> Result<RuntimeObject, Error> {
         if let Some(ref init) = statement.init {
             match init {
                 ForInit::Expression(e) => {
@@ -894,47 +841,109 @@ impl<'a> HostInterpreter<'a> {
             args.push(self.eval_expression(arg)?);
         }
 
-        match function {
-            RuntimeObject::BuiltinFunction(func) => {
-                let func = func.func;
-                Ok(func(args))
-            }
-            RuntimeObject::Function(func) => {
-                self.ctx.scope.scope_in();
-                for (i, param) in func.parameters.iter().enumerate() {
-                    let name = param.clone().name;
-                    let var = match param.default.clone() {
-                        Some(v) => {
-                            let value = self.eval_expression(&v)?;
-                            Variable::new(VariableKind::Var, value)
-                        }
-                        None => {
-                            Variable::new(VariableKind::Var, RuntimeObject::Undefined(JSUndefined))
-                        }
-                    };
-
-                    // bind args
-                    if let Some(a) = args.get(i) {
-                        self.ctx
-                            .scope
-                            .set(&name, Variable::new(VariableKind::Var, a.clone()));
-                    } else {
-                        self.ctx.scope.set(&name, var);
-                    }
-                }
+        self.call_function(function, args)
+    }
 
-                let result = self.eval_block_statement(&func.body, ScopeType::Function)?;
-                self.ctx.scope.scope_in();
+    /// call `function` with `this` bound to `this` (when it is an object).
+    pub fn call(
+        &mut self,
+        function: RuntimeObject,
+        this: RuntimeObject,
+        args: Vec<RuntimeObject>,
+    ) -> Result<RuntimeObject, Error> {
+        if let RuntimeObject::Object(o) = this {
+            self.exec_ctx_this = o;
+        }
+        self.call_function(function, args)
+    }
 
-                match result {
-                    RuntimeObject::Return(ret) => Ok(*ret),
-                    _ => Ok(RuntimeObject::Undefined(JSUndefined)),
-                }
+    fn call_function(
+        &mut self,
+        function: RuntimeObject,
+        args: Vec<RuntimeObject>,
+    ) -> Result<RuntimeObject, Error> {
+        self.budget.enter_call()?;
+        let result = match function {
+            RuntimeObject::BuiltinFunction(func) => {
+                let this = RuntimeObject::Object(self.exec_ctx_this.clone());
+                let args = args.into_iter().map(Value::from).collect();
+                let mut cx = CallContext::new(self);
+                (func.func)(&mut cx, Value::from(this), args)
+                    .map(Value::into_raw)
+                    .map_err(|e| {
+                        if let Some(value) = e.value() {
+                            let value = value.clone().into_raw();
+                            self.ctx.set_thrown(e.to_string(), value);
+                        }
+                        Error::from(e)
+                    })
             }
+            // a function of a module sees the bindings of the module, not of its caller
+            RuntimeObject::Function(func) => match func.module.clone() {
+                Some(module) => {
+                    let scope = HandleScope::for_module(module);
+                    self.in_scope(scope, |ev| ev.call_in_scope(&func, args))
+                }
+                None => self.call_in_scope(&func, args),
+            },
             _ => Err(Error::new(
                 std::io::ErrorKind::Other,
                 "Uncaught TypeError: not a function",
             )),
+        };
+        self.budget.exit_call();
+        result
+    }
+
+    fn call_in_scope(
+        &mut self,
+        func: &JSFunction,
+        args: Vec<RuntimeObject>,
+    ) -> Result<RuntimeObject, Error> {
+        self.ctx.scope.scope_in();
+        let result = self.eval_function_body(func, args);
+        self.ctx.scope.scope_out();
+        result
+    }
+
+    /// run `f` with `scope` in place of the scope of the running code.
+    fn in_scope<T>(&mut self, scope: HandleScope, f: impl FnOnce(&mut Self) -> T) -> T {
+        let saved = std::mem::replace(&mut self.ctx.scope, scope);
+        let result = f(self);
+        self.ctx.scope = saved;
+        result
+    }
+
+    fn eval_function_body(
+        &mut self,
+        func: &JSFunction,
+        args: Vec<RuntimeObject>,
+    ) -> Result<RuntimeObject, Error> {
+        for (i, param) in func.parameters.iter().enumerate() {
+            let name = param.clone().name;
+            let var = match param.default.clone() {
+                Some(v) => {
+                    let value = self.eval_expression(&v)?;
+                    Variable::new(VariableKind::Var, value)
+                }
+                None => Variable::new(VariableKind::Var, RuntimeObject::Undefined(JSUndefined)),
+            };
+
+            // bind args
+            if let Some(a) = args.get(i) {
+                self.ctx
+                    .scope
+                    .set(&name, Variable::new(VariableKind::Var, a.clone()));
+            } else {
+                self.ctx.scope.set(&name, var);
+            }
+        }
+
+        let result = self.eval_block_statement(&func.body, ScopeType::Function)?;
+
+        match result {
+            RuntimeObject::Return(ret) => Ok(*ret),
+            _ => Ok(RuntimeObject::Undefined(JSUndefined)),
         }
     }
 
@@ -997,6 +1006,80 @@ impl<'a> HostInterpreter<'a> {
         Ok(RuntimeObject::Return(Box::new(value)))
     }
 
+    fn eval_throw_statement(&mut self, expr: &Expression) -> Result<RuntimeObject, Error> {
+        let value = self.eval_expression(expr)?;
+        let message = match JSString::into(value.clone()) {
+            RuntimeObject::String(s) => format!("Uncaught {}", s.value),
+            _ => unreachable!(),
+        };
+        self.ctx.set_thrown(message.clone(), value);
+        Err(Error::new(std::io::ErrorKind::Other, message))
+    }
+
+    /// `catch` binds the thrown value, or an error object with the `name` and `message` of an
+    /// error of the interpreter. the limits can not be caught.
+    fn eval_try_statement(
+        &mut self,
+        stmt: &TryStatement,
+        scope_type: ScopeType,
+    ) -> Result<RuntimeObject, Error> {
+        let depth = self.ctx.scope.scopes.len();
+        let mut result = self.eval_block_statement(&stmt.block, scope_type);
+
+        if let (Err(e), Some(handler)) = (&result, &stmt.handler) {
+            let message = e.to_string();
+            if let Some(description) = message.strip_prefix("Uncaught ") {
+                let value = match self.ctx.take_thrown(&message) {
+                    Some(value) => value,
+                    None => self.error_object(description)?,
+                };
+                // an error leaves the scopes it entered
+                self.ctx.scope.scopes.truncate(depth);
+                self.ctx.scope.scope_in();
+                if let Some(param) = &stmt.param {
+                    self.ctx
+                        .scope
+                        .set(param, Variable::new(VariableKind::Let, value));
+                }
+                result = self.eval_block_statement(handler, scope_type);
+                self.ctx.scope.scope_out();
+            }
+        }
+
+        if let Some(finalizer) = &stmt.finalizer {
+            if result.is_err() {
+                self.ctx.scope.scopes.truncate(depth);
+            }
+            let completion = self.eval_block_statement(finalizer, scope_type)?;
+            if let RuntimeObject::Return(_) | RuntimeObject::Break | RuntimeObject::Continue =
+                completion
+            {
+                return Ok(completion);
+            }
+        }
+        result
+    }
+
+    /// the object of an error the interpreter raised with `description`, `TypeError: message`
+    /// or a message alone for an `Error`.
+    fn error_object(&mut self, description: &str) -> Result<RuntimeObject, Error> {
+        let (name, message) = match description.split_once(": ") {
+            Some((name, message)) if name.ends_with("Error") && !name.contains(' ') => {
+                (name, message)
+            }
+            _ => ("Error", description),
+        };
+        let mut properties = HashMap::new();
+        for (key, value) in [("name", name), ("message", message)] {
+            let value = RuntimeObject::String(JSString {
+                value: value.to_string(),
+            });
+            properties.insert(key.to_string(), value);
+        }
+        let o = self.ctx.alloc(JSObject { properties })?;
+        Ok(RuntimeObject::Object(o))
+    }
+
     fn is_truthy(&self, obj: RuntimeObject) -> bool {
         match obj {
             RuntimeObject::Boolean(b) => b.value,
@@ -1573,6 +1656,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn eval_try_statement() {
+        use crate::engine::core::host::api::{Exception, Function, Limits};
+
+        let eval = |context: &mut Context, source: &str| {
+            let program = BuiltinParser.parse(source.to_string());
+            let mut ev = HostInterpreter::new(context);
+            ev.eval(&program).map(|v| format!("{}", v))
+        };
+        let mut context = Context::new(HandleScope::new());
+        let fail = Function::new("fail", |_, _, args| {
+            Err(Exception::new(args.into_iter().next().unwrap()))
+        });
+        context.global().set("fail", Value::from(fail).into_raw());
+
+        let case = vec![
+            // the thrown value itself
+            (
+                "const o = { code: 1 }; let c; try { throw o; } catch (e) { c = e; } c === o;",
+                "\x1b[33mtrue\x1b[0m",
+            ),
+            (
+                "let c; try { fail(2); } catch (e) { c = e; } c;",
+                "\x1b[33m2\x1b[0m",
+            ),
+            // an error object for the errors of the interpreter
+            (
+                "let c; try { missing; } catch (e) { c = e.name + ': ' + e.message; } c;",
+                "\x1b[32m'ReferenceError: missing is not defined'\x1b[0m",
+            ),
+        ];
+        for (input, expected) in case {
+            assert_eq!(eval(&mut context, input).unwrap(), expected, "{}", input);
+        }
+
+        // the limits are not caught
+        context.set_limits(Limits::new().max_steps(100));
+        let e = eval(
+            &mut context,
+            "try { for (let i = 0; true; i++) {} } catch (e) {}",
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Interrupted);
+    }
+
     #[test]
     fn eval_function_this() {
         let case = vec![(
@@ -1643,4 +1771,119 @@ mod tests {
             assert_eq!(format!("{}", ev.eval(&program).unwrap()), expected);
         }
     }
+
+    #[test]
+    fn eval_with_limits() {
+        use crate::engine::core::limits::Limits;
+        use std::time::Duration;
+
+        let case = vec![
+            (
+                Limits::new().max_call_depth(200),
+                r#"
+                    const f = function(n) {
+                        if (n == 0) {
+                            return 0;
+                        } else {
+                            return 1 + f(n - 1);
+                        }
+                    };
+                    f(100000);
+                "#,
+                "Uncaught RangeError: Maximum call stack size exceeded",
+            ),
+            (
+                Limits::new().max_call_depth(3),
+                "const f = function(n) { return f(n + 1); }; f(0);",
+                "Uncaught RangeError: Maximum call stack size exceeded",
+            ),
+            (
+                Limits::new().max_steps(1000),
+                "for (let i = 0; true; i++) {}",
+                "Execution terminated: step limit exceeded",
+            ),
+            (
+                Limits::new().timeout(Duration::from_millis(10)),
+                "for (let i = 0; true; i++) {}",
+                "Execution terminated: timed out",
+            ),
+        ];
+
+        for (limits, input, expected) in case {
+            let program = BuiltinParser.parse(input.to_string());
+            let handle_scope = HandleScope::new();
+            let mut context = Context::new(handle_scope);
+            context.set_limits(limits);
+            let mut ev = HostInterpreter::new(&mut context);
+            assert_eq!(ev.eval(&program).unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn eval_terminate() {
+        let program = BuiltinParser.parse("for (let i = 0; true; i++) {}".to_string());
+        let mut context = Context::new(HandleScope::new());
+        let handle = context.interrupt_handle();
+
+        let terminator = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.terminate();
+        });
+        let mut ev = HostInterpreter::new(&mut context);
+        let e = ev.eval(&program).unwrap_err();
+        terminator.join().unwrap();
+
+        assert_eq!(e.kind(), std::io::ErrorKind::Interrupted);
+        assert_eq!(e.to_string(), "Execution terminated");
+
+        // the context can run again after termination
+        let program = BuiltinParser.parse("1 + 1;".to_string());
+        let mut ev = HostInterpreter::new(&mut context);
+        assert_eq!(
+            format!("{}", ev.eval(&program).unwrap()),
+            "\x1b[33m2\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn eval_heap_limits() {
+        use crate::engine::core::limits::Limits;
+
+        let mut context = Context::new(HandleScope::new());
+        context.set_limits(Limits::new().max_heap_objects(100));
+
+        // cycles are collected instead of hitting the limit
+        let program = BuiltinParser.parse(
+            r#"
+                for (let i = 0; i < 1000; i++) {
+                    const o = { i: i };
+                    o.self = o;
+                }
+            "#
+            .to_string(),
+        );
+        let mut ev = HostInterpreter::new(&mut context);
+        assert!(ev.eval(&program).is_ok());
+        let statistics = context.heap_statistics();
+        assert!(statistics.collections > 0);
+        assert!(statistics.objects <= 100);
+
+        // reachable objects are not
+        let program = BuiltinParser.parse(
+            r#"
+                let list = {};
+                for (let i = 0; i < 1000; i++) {
+                    list = { next: list };
+                }
+            "#
+            .to_string(),
+        );
+        let mut ev = HostInterpreter::new(&mut context);
+        let e = ev.eval(&program).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::OutOfMemory);
+        assert_eq!(
+            e.to_string(),
+            "Uncaught RangeError: Out of memory: heap limit exceeded"
+        );
+    }
 }
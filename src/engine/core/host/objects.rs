@@ -1,8 +1,19 @@
 #![allow(dead_code)]
 
-use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
 
-use crate::engine::ast::{BlockStatement, FunctionParameter};
+use crate::engine::{
+    ast::{BlockStatement, FunctionParameter},
+    core::host::{
+        api::{CallContext, Exception, Value},
+        handles::ModuleScope,
+    },
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum RuntimeObject {
@@ -164,6 +175,24 @@ impl JSBoolean {
 pub struct JSObject {
     pub properties: HashMap<String, RuntimeObject>,
 }
+impl JSObject {
+    /// array-like object: indexed elements, `length` and the link to `Array.prototype`
+    pub fn array(elements: Vec<RuntimeObject>, prototype: Option<RuntimeObject>) -> JSObject {
+        let mut properties = HashMap::new();
+        let len = elements.len();
+        for (i, element) in elements.into_iter().enumerate() {
+            properties.insert(i.to_string(), element);
+        }
+        properties.insert(
+            "length".to_string(),
+            RuntimeObject::Number(JSNumber { value: len as f64 }),
+        );
+        if let Some(prototype) = prototype {
+            properties.insert("__proto__".to_string(), prototype);
+        }
+        JSObject { properties }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct JSArray {
@@ -173,11 +202,22 @@ pub struct JSArray {
 #[derive(Debug, PartialEq, Clone)]
 pub struct JSFunction {
     pub parameters: Vec<FunctionParameter>,
-    pub body: BlockStatement,
+    /// shared, so that a function value stays small on the stack of calls
+    pub body: Rc<BlockStatement>,
+    /// the module the function was created in, whose bindings its body sees
+    pub module: Option<ModuleScope>,
 }
 impl JSFunction {
-    pub fn new(parameters: Vec<FunctionParameter>, body: BlockStatement) -> JSFunction {
-        JSFunction { parameters, body }
+    pub fn new(
+        parameters: Vec<FunctionParameter>,
+        body: BlockStatement,
+        module: Option<ModuleScope>,
+    ) -> JSFunction {
+        JSFunction {
+            parameters,
+            body: Rc::new(body),
+            module,
+        }
     }
 }
 
@@ -187,19 +227,40 @@ pub struct JSNull;
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct JSUndefined;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// native function body: `(call context, this, arguments) -> return value or thrown exception`
+pub type NativeFunction =
+    Rc<dyn Fn(&mut CallContext, Value, Vec<Value>) -> Result<Value, Exception>>;
+
+#[derive(Clone)]
 pub struct JSBuiltinFunction {
     name: String,
-    pub func: fn(Vec<RuntimeObject>) -> RuntimeObject,
+    pub func: NativeFunction,
 }
 impl JSBuiltinFunction {
-    pub fn new(name: &str, func: fn(Vec<RuntimeObject>) -> RuntimeObject) -> JSBuiltinFunction {
+    pub fn new(
+        name: &str,
+        func: impl Fn(&mut CallContext, Value, Vec<Value>) -> Result<Value, Exception> + 'static,
+    ) -> JSBuiltinFunction {
         JSBuiltinFunction {
             name: name.to_string(),
-            func,
+            func: Rc::new(func),
         }
     }
 }
+impl PartialEq for JSBuiltinFunction {
+    fn eq(&self, other: &Self) -> bool {
+        // compare data pointers only; vtable pointers of the same closure may differ
+        std::ptr::eq(
+            Rc::as_ptr(&self.func) as *const u8,
+            Rc::as_ptr(&other.func) as *const u8,
+        )
+    }
+}
+impl Debug for JSBuiltinFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JSBuiltinFunction({})", self.name)
+    }
+}
 
 #[cfg(test)]
 mod test {
@@ -0,0 +1,309 @@
+use std::{rc::Rc, vec::IntoIter};
+
+use ::serde::{
+    de::{self, DeserializeSeed, IntoDeserializer, Visitor},
+    forward_to_deserialize_any,
+};
+
+use super::Error;
+use crate::engine::core::host::objects::{
+    JSArray, JSBoolean, JSNumber, JSObject, JSString, JSUndefined, RuntimeObject,
+};
+
+const PROTOTYPE_KEY_NAME: &str = "__proto__";
+
+/// how deep values may nest. a cycle such as `o.self = o` nests without end, the limit turns it
+/// into an error before the native stack overflows.
+const MAX_DEPTH: usize = 128;
+
+/// deserializes `RuntimeObject`s into rust values.
+/// objects linked to `Array.prototype` are sequences, the other objects maps.
+pub struct Deserializer {
+    value: RuntimeObject,
+    array_prototype: Option<RuntimeObject>,
+    depth: usize,
+}
+
+impl Deserializer {
+    pub fn new(value: RuntimeObject, array_prototype: Option<RuntimeObject>) -> Self {
+        Deserializer {
+            value,
+            array_prototype,
+            depth: 0,
+        }
+    }
+
+    /// a deserializer for a value nested in this one.
+    fn nested(&self, value: RuntimeObject) -> Deserializer {
+        Deserializer {
+            value,
+            array_prototype: self.array_prototype.clone(),
+            depth: self.depth + 1,
+        }
+    }
+
+    fn check_depth(&self) -> Result<(), Error> {
+        if self.depth > MAX_DEPTH {
+            return Err(Error(format!(
+                "the value is nested deeper than {} levels, or has a cycle",
+                MAX_DEPTH
+            )));
+        }
+        Ok(())
+    }
+
+    /// the elements of `o` if it is an array: an entry per index below its `length`, holes are
+    /// `undefined`. the `length` is trusted only as far as `o` has the properties for it.
+    fn array_elements(&self, o: &JSObject) -> Result<Option<Vec<RuntimeObject>>, Error> {
+        let is_array = match (o.properties.get(PROTOTYPE_KEY_NAME), &self.array_prototype) {
+            (Some(RuntimeObject::Object(p)), Some(RuntimeObject::Object(array))) => {
+                Rc::ptr_eq(p, array)
+            }
+            _ => false,
+        };
+        let len = match o.properties.get("length") {
+            Some(RuntimeObject::Number(JSNumber { value })) if is_array => *value,
+            _ => return Ok(None),
+        };
+        if len < 0.0 || len.fract() != 0.0 || len > o.properties.len() as f64 {
+            return Err(Error(format!("invalid array length {}", len)));
+        }
+        let elements = (0..len as usize).map(|i| match o.properties.get(&i.to_string()) {
+            Some(element) => element.clone(),
+            None => RuntimeObject::Undefined(JSUndefined),
+        });
+        Ok(Some(elements.collect()))
+    }
+
+    fn visit_seq<'de, V: Visitor<'de>>(
+        &self,
+        elements: Vec<RuntimeObject>,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(SeqAccess {
+            elements: elements.into_iter(),
+            parent: self,
+        })
+    }
+
+    /// the own properties of `o` as map entries.
+    fn visit_map<'de, V: Visitor<'de>>(&self, o: &JSObject, visitor: V) -> Result<V::Value, Error> {
+        let entries: Vec<(String, RuntimeObject)> = o
+            .properties
+            .iter()
+            .filter(|(k, _)| k.as_str() != PROTOTYPE_KEY_NAME)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        visitor.visit_map(MapAccess {
+            entries: entries.into_iter(),
+            value: None,
+            parent: self,
+        })
+    }
+}
+
+fn unexpected(o: &RuntimeObject) -> Error {
+    Error(format!(
+        "cannot deserialize a value of type {}",
+        o.get_type()
+    ))
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        match &self.value {
+            RuntimeObject::Boolean(JSBoolean { value }) => visitor.visit_bool(*value),
+            RuntimeObject::Number(JSNumber { value }) => {
+                if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+                    visitor.visit_i64(*value as i64)
+                } else {
+                    visitor.visit_f64(*value)
+                }
+            }
+            RuntimeObject::String(JSString { value }) => visitor.visit_string(value.clone()),
+            RuntimeObject::Null(_) | RuntimeObject::Undefined(_) => visitor.visit_unit(),
+            RuntimeObject::Array(JSArray { elements }) => self.visit_seq(elements.clone(), visitor),
+            RuntimeObject::Object(o) => {
+                let o = o.borrow();
+                match self.array_elements(&o)? {
+                    Some(elements) => self.visit_seq(elements, visitor),
+                    None => self.visit_map(&o, visitor),
+                }
+            }
+            o => Err(unexpected(o)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            RuntimeObject::Null(_) | RuntimeObject::Undefined(_) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// any object has the keys of a map or a struct, an array too.
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        match &self.value {
+            RuntimeObject::Object(o) => self.visit_map(&o.borrow(), visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    /// unit variants are strings, the others objects with the variant name as their only key.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        match &self.value {
+            RuntimeObject::String(JSString { value }) => {
+                visitor.visit_enum(value.clone().into_deserializer())
+            }
+            RuntimeObject::Object(o) => {
+                let o = o.borrow();
+                let mut entries = o
+                    .properties
+                    .iter()
+                    .filter(|(k, _)| k.as_str() != PROTOTYPE_KEY_NAME);
+                match (entries.next(), entries.next()) {
+                    (Some((variant, value)), None) => visitor.visit_enum(EnumAccess {
+                        variant: variant.clone(),
+                        value: self.nested(value.clone()),
+                    }),
+                    _ => Err(Error(
+                        "expected an object with a single key for an enum".to_string(),
+                    )),
+                }
+            }
+            o => Err(unexpected(o)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct
+        identifier ignored_any
+    }
+}
+
+struct SeqAccess<'a> {
+    elements: IntoIter<RuntimeObject>,
+    parent: &'a Deserializer,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.elements.next() {
+            Some(o) => seed.deserialize(self.parent.nested(o)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+struct MapAccess<'a> {
+    entries: IntoIter<(String, RuntimeObject)>,
+    value: Option<RuntimeObject>,
+    parent: &'a Deserializer,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(self.parent.nested(value)),
+            None => Err(Error("next_value called before next_key".to_string())),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: Deserializer,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer), Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,234 @@
+//! serde integration: any `Serialize` type can be handed to scripts and any `Deserialize` type
+//! can be read back from a script result.
+
+use std::fmt::Display;
+
+use ::serde::{de::DeserializeOwned, Serialize};
+
+use super::{api::Context, objects::RuntimeObject};
+
+mod de;
+mod ser;
+
+pub use de::Deserializer;
+pub use ser::Serializer;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ::serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl ::serde::de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e.0)
+    }
+}
+
+/// convert `value` into a JavaScript value on the heap of `context`. sequences are linked to
+/// `Array.prototype` of `context`.
+pub fn to_runtime_object<T: Serialize + ?Sized>(
+    value: &T,
+    context: &mut Context,
+) -> Result<RuntimeObject, Error> {
+    value.serialize(Serializer::new(context))
+}
+
+/// convert a JavaScript value into `T`. objects linked to `Array.prototype` of `context` are
+/// sequences.
+pub fn from_runtime_object<T: DeserializeOwned>(
+    value: RuntimeObject,
+    context: &Context,
+) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(value, context.array_prototype()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ::serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::engine::{
+        core::{
+            host::{api::Script, handles::HandleScope},
+            limits::Limits,
+        },
+        parsing::{BuiltinParser, Parser},
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Fast,
+        Limited { steps: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        retries: u8,
+        ratio: f64,
+        verbose: bool,
+        tags: Vec<String>,
+        limits: HashMap<String, i64>,
+        parent: Option<Box<Config>>,
+        mode: Mode,
+    }
+
+    fn run(context: &mut Context, source: &str) -> RuntimeObject {
+        let mut parser: Box<dyn Parser> = Box::new(BuiltinParser);
+        let mut script = Script::compile(source.to_string(), context, &mut parser);
+        script.run().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut context = Context::new(HandleScope::new());
+        let mut limits = HashMap::new();
+        limits.insert("depth".to_string(), -3);
+        let config = Config {
+            name: "glasper".to_string(),
+            retries: 3,
+            ratio: 0.5,
+            verbose: false,
+            tags: vec!["a".to_string(), "b".to_string()],
+            limits,
+            parent: None,
+            mode: Mode::Limited { steps: 10 },
+        };
+
+        let o = to_runtime_object(&config, &mut context).unwrap();
+        assert_eq!(from_runtime_object::<Config>(o, &context).unwrap(), config);
+    }
+
+    #[test]
+    fn test_script_reads_serialized_object() {
+        let mut context = Context::new(HandleScope::new());
+        let tags = to_runtime_object(&vec!["x", "y", "z"], &mut context).unwrap();
+        context.global().set("tags", tags);
+
+        let o = run(&mut context, "tags.length + tags[1];");
+        assert_eq!(from_runtime_object::<String>(o, &context).unwrap(), "3y");
+    }
+
+    #[test]
+    fn test_deserialize_script_result() {
+        let mut context = Context::new(HandleScope::new());
+        let o = run(
+            &mut context,
+            r#"
+                const c = {
+                    name: "from script",
+                    retries: 1 + 1,
+                    ratio: 1 / 4,
+                    verbose: true,
+                    tags: ["t"],
+                    limits: { steps: 100 },
+                    parent: null,
+                    mode: "Fast",
+                };
+                c;
+            "#,
+        );
+
+        let config = from_runtime_object::<Config>(o, &context).unwrap();
+        assert_eq!(config.name, "from script");
+        assert_eq!(config.retries, 2);
+        assert_eq!(config.ratio, 0.25);
+        assert_eq!(config.tags, vec!["t".to_string()]);
+        assert_eq!(config.limits.get("steps"), Some(&100));
+        assert_eq!(config.mode, Mode::Fast);
+
+        let o = run(&mut context, "1.5;");
+        let e = from_runtime_object::<u8>(o, &context).unwrap_err();
+        assert!(e.to_string().contains("invalid type"), "{}", e);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Segment {
+        length: u32,
+        name: String,
+    }
+
+    #[test]
+    fn test_deserialize_array_like_object() {
+        let mut context = Context::new(HandleScope::new());
+
+        // an object with a `length` is not an array
+        let o = run(&mut context, "const s = { length: 0, name: 'x' }; s;");
+        let segment = from_runtime_object::<Segment>(o, &context).unwrap();
+        assert_eq!(
+            segment,
+            Segment {
+                length: 0,
+                name: "x".to_string()
+            }
+        );
+        let o = run(&mut context, "const l = { length: 1 }; l;");
+        let e = from_runtime_object::<Vec<String>>(o, &context).unwrap_err();
+        assert!(e.to_string().contains("invalid type: map"), "{}", e);
+
+        // an array is
+        let o = run(&mut context, "const a = [1, null, 3]; a;");
+        let a = from_runtime_object::<Vec<Option<u8>>>(o, &context).unwrap();
+        assert_eq!(a, vec![Some(1), None, Some(3)]);
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Node {
+        #[allow(dead_code)]
+        next: Option<Box<Node>>,
+    }
+
+    #[test]
+    fn test_deserialize_cycle() {
+        let mut context = Context::new(HandleScope::new());
+        let o = run(&mut context, "const o = {}; o.next = o; o;");
+        let e = from_runtime_object::<Node>(o, &context).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "the value is nested deeper than 128 levels, or has a cycle"
+        );
+        let o = run(&mut context, "const p = {}; p.self = p; p;");
+        assert!(from_runtime_object::<::serde::de::IgnoredAny>(o, &context).is_err());
+
+        // the `length` of an array is bounded by its properties
+        let o = run(&mut context, "const a = [1]; a.length = 1e15; a;");
+        let e = from_runtime_object::<Vec<Option<u8>>>(o, &context).unwrap_err();
+        assert_eq!(e.to_string(), "invalid array length 1000000000000000");
+    }
+
+    #[test]
+    fn test_serialize_on_heap() {
+        let mut context = Context::new(HandleScope::new());
+        let objects = context.heap_statistics().objects;
+        let o = to_runtime_object(&vec![vec![1], vec![2]], &mut context).unwrap();
+        assert_eq!(context.heap_statistics().objects, objects + 3);
+        drop(o);
+
+        context.set_limits(Limits::new().max_heap_objects(objects + 1));
+        let e = to_runtime_object(&vec![vec![1], vec![2]], &mut context).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Uncaught RangeError: Out of memory: heap limit exceeded"
+        );
+    }
+}
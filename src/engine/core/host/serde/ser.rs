@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+
+use ::serde::ser::{self, Serialize};
+
+use super::Error;
+use crate::engine::core::host::{
+    api::Context,
+    objects::{JSBoolean, JSNull, JSNumber, JSObject, JSString, RuntimeObject},
+};
+
+/// serializes rust values into `RuntimeObject`s on the heap of a context.
+/// structs and maps become objects, sequences and tuples become arrays.
+pub struct Serializer<'a> {
+    context: &'a mut Context,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(context: &'a mut Context) -> Self {
+        Serializer { context }
+    }
+
+    /// a serializer for a value nested in this one.
+    fn nested(&mut self) -> Serializer<'_> {
+        Serializer::new(self.context)
+    }
+
+    fn object(&mut self, object: JSObject) -> Result<RuntimeObject, Error> {
+        self.context
+            .alloc(object)
+            .map(RuntimeObject::Object)
+            .map_err(|e| Error(e.to_string()))
+    }
+
+    fn array(&mut self, elements: Vec<RuntimeObject>) -> Result<RuntimeObject, Error> {
+        let prototype = self.context.array_prototype();
+        self.object(JSObject::array(elements, prototype))
+    }
+
+    /// `{ variant: value }`, the externally tagged enum representation
+    fn tagged(&mut self, variant: &str, value: RuntimeObject) -> Result<RuntimeObject, Error> {
+        let mut properties = HashMap::new();
+        properties.insert(variant.to_string(), value);
+        self.object(JSObject { properties })
+    }
+}
+
+fn number(n: f64) -> Result<RuntimeObject, Error> {
+    Ok(RuntimeObject::Number(JSNumber::new(n)))
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    type SerializeSeq = SerializeVec<'a>;
+    type SerializeTuple = SerializeVec<'a>;
+    type SerializeTupleStruct = SerializeVec<'a>;
+    type SerializeTupleVariant = SerializeVec<'a>;
+    type SerializeMap = SerializeMap<'a>;
+    type SerializeStruct = SerializeMap<'a>;
+    type SerializeStructVariant = SerializeMap<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<RuntimeObject, Error> {
+        Ok(RuntimeObject::Boolean(JSBoolean::new(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<RuntimeObject, Error> {
+        number(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<RuntimeObject, Error> {
+        number(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<RuntimeObject, Error> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<RuntimeObject, Error> {
+        Ok(RuntimeObject::String(JSString {
+            value: v.to_string(),
+        }))
+    }
+
+    fn serialize_bytes(mut self, v: &[u8]) -> Result<RuntimeObject, Error> {
+        let elements = v
+            .iter()
+            .map(|b| RuntimeObject::Number(JSNumber::new(*b as f64)))
+            .collect();
+        self.array(elements)
+    }
+
+    fn serialize_none(self) -> Result<RuntimeObject, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<RuntimeObject, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<RuntimeObject, Error> {
+        Ok(RuntimeObject::Null(JSNull))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<RuntimeObject, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<RuntimeObject, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<RuntimeObject, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<RuntimeObject, Error> {
+        let value = value.serialize(self.nested())?;
+        self.tagged(variant, value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec<'a>, Error> {
+        Ok(SerializeVec {
+            serializer: self,
+            elements: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec<'a>, Error> {
+        let mut seq = self.serialize_seq(Some(len))?;
+        seq.variant = Some(variant);
+        Ok(seq)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap<'a>, Error> {
+        Ok(SerializeMap {
+            serializer: self,
+            properties: HashMap::new(),
+            next_key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap<'a>, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeMap<'a>, Error> {
+        let mut map = self.serialize_map(Some(len))?;
+        map.variant = Some(variant);
+        Ok(map)
+    }
+}
+
+pub struct SerializeVec<'a> {
+    serializer: Serializer<'a>,
+    elements: Vec<RuntimeObject>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeVec<'_> {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.elements
+            .push(value.serialize(self.serializer.nested())?);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<RuntimeObject, Error> {
+        let array = self.serializer.array(self.elements)?;
+        match self.variant {
+            Some(variant) => self.serializer.tagged(variant, array),
+            None => Ok(array),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
+
+pub struct SerializeMap<'a> {
+    serializer: Serializer<'a>,
+    properties: HashMap<String, RuntimeObject>,
+    next_key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeMap<'_> {
+    fn insert<T: ?Sized + Serialize>(&mut self, key: String, value: &T) -> Result<(), Error> {
+        let value = value.serialize(self.serializer.nested())?;
+        self.properties.insert(key, value);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<RuntimeObject, Error> {
+        let o = self.serializer.object(JSObject {
+            properties: self.properties,
+        })?;
+        match self.variant {
+            Some(variant) => self.serializer.tagged(variant, o),
+            None => Ok(o),
+        }
+    }
+}
+
+impl ser::SerializeMap for SerializeMap<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        // property keys are strings, numbers are stringified like `o[1]` does
+        let key = match key.serialize(self.serializer.nested())? {
+            RuntimeObject::String(JSString { value }) => value,
+            RuntimeObject::Number(JSNumber { value }) => value.to_string(),
+            RuntimeObject::Boolean(JSBoolean { value }) => value.to_string(),
+            o => {
+                return Err(Error(format!(
+                    "object keys must be strings, got {}",
+                    o.get_type()
+                )))
+            }
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error("serialize_value called before serialize_key".to_string()))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap<'_> {
+    type Ok = RuntimeObject;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<RuntimeObject, Error> {
+        self.finish()
+    }
+}
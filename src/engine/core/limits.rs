@@ -0,0 +1,268 @@
+use std::{
+    fmt::Display,
+    io::{Error, ErrorKind},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// the deadline is checked once every this many steps, a power of two.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// execution limits applied to every script run. none is set by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_call_depth: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// caps on the objects allocated by scripts. the vm heap only grows up to `max_heap_bytes`
+    pub max_heap_bytes: Option<usize>,
+    pub max_heap_objects: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Limits {
+            max_steps: None,
+            max_call_depth: None,
+            timeout: None,
+            max_heap_bytes: None,
+            max_heap_objects: None,
+        }
+    }
+
+    /// abort after `steps` evaluated statements/expressions (host) or instructions (vm).
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    /// throw `RangeError` when calls nest deeper than `depth`.
+    ///
+    /// without it, deep recursion in the host interpreter overflows the native stack and aborts
+    /// the process. a call takes several KiB of native stack there in debug builds, so a depth
+    /// of 200 fits a 2 MiB thread stack; scale it with the stack of the thread running scripts.
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    /// abort when a run takes longer than `timeout` of wall-clock time.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// throw `RangeError` when live objects would take more than `bytes` (estimated).
+    pub fn max_heap_bytes(mut self, bytes: usize) -> Self {
+        self.max_heap_bytes = Some(bytes);
+        self
+    }
+
+    /// throw `RangeError` when there would be more than `objects` live objects.
+    pub fn max_heap_objects(mut self, objects: usize) -> Self {
+        self.max_heap_objects = Some(objects);
+        self
+    }
+}
+
+/// aborts the running script from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// request termination. the script stops at its next step,
+    /// a request made while nothing runs terminates the next run.
+    pub fn terminate(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_terminating(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// consume a pending request so that the next run starts clean.
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+
+    /// the address of the flag, for machine code that polls it.
+    pub(crate) fn as_ptr(&self) -> *const AtomicBool {
+        Arc::as_ptr(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Steps,
+    CallDepth,
+    Timeout,
+    Terminated,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Steps => write!(f, "Execution terminated: step limit exceeded"),
+            LimitExceeded::CallDepth => write!(f, "RangeError: Maximum call stack size exceeded"),
+            LimitExceeded::Timeout => write!(f, "Execution terminated: timed out"),
+            LimitExceeded::Terminated => write!(f, "Execution terminated"),
+        }
+    }
+}
+
+impl From<LimitExceeded> for Error {
+    fn from(e: LimitExceeded) -> Self {
+        match e {
+            // a RangeError is an ordinary exception, the others can not be caught
+            LimitExceeded::CallDepth => Error::new(ErrorKind::Other, format!("Uncaught {}", e)),
+            LimitExceeded::Timeout => Error::new(ErrorKind::TimedOut, e.to_string()),
+            LimitExceeded::Steps | LimitExceeded::Terminated => {
+                Error::new(ErrorKind::Interrupted, e.to_string())
+            }
+        }
+    }
+}
+
+/// the state of the limits during one run.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    limits: Limits,
+    interrupt: InterruptHandle,
+    steps: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: Limits, interrupt: InterruptHandle) -> Self {
+        let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+        Budget {
+            limits,
+            interrupt,
+            steps: 0,
+            depth: 0,
+            deadline,
+        }
+    }
+
+    /// count one step of execution.
+    pub(crate) fn step(&mut self) -> Result<(), LimitExceeded> {
+        self.steps += 1;
+
+        if self.interrupt.is_terminating() && self.interrupt.take() {
+            return Err(LimitExceeded::Terminated);
+        }
+        if matches!(self.limits.max_steps, Some(max) if self.steps > max) {
+            return Err(LimitExceeded::Steps);
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps & (DEADLINE_CHECK_INTERVAL - 1) == 0 && Instant::now() >= deadline {
+                return Err(LimitExceeded::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// the loop iterations machine code may run before it hands the call back to the
+    /// interpreter, which checks the limits. a deadline is checked as often as in the
+    /// interpreter.
+    pub(crate) fn jit_fuel(&self) -> u64 {
+        let steps = match self.limits.max_steps {
+            Some(max) => max.saturating_sub(self.steps).min(i64::MAX as u64),
+            None => i64::MAX as u64,
+        };
+        match self.deadline {
+            Some(_) => steps.min(DEADLINE_CHECK_INTERVAL),
+            None => steps,
+        }
+    }
+
+    /// count the steps of machine code.
+    pub(crate) fn charge(&mut self, steps: u64) {
+        self.steps += steps;
+    }
+
+    pub(crate) fn interrupt(&self) -> &InterruptHandle {
+        &self.interrupt
+    }
+
+    pub(crate) fn enter_call(&mut self) -> Result<(), LimitExceeded> {
+        if matches!(self.limits.max_call_depth, Some(max) if self.depth >= max) {
+            return Err(LimitExceeded::CallDepth);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn exit_call(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let mut budget = Budget::new(Limits::new().max_steps(2), InterruptHandle::new());
+        assert_eq!(budget.step(), Ok(()));
+        assert_eq!(budget.step(), Ok(()));
+        assert_eq!(budget.step(), Err(LimitExceeded::Steps));
+
+        // calls nest without limit unless one is set
+        let mut budget = Budget::new(Limits::new(), InterruptHandle::new());
+        assert!((0..100_000).all(|_| budget.enter_call().is_ok()));
+
+        let mut budget = Budget::new(Limits::new().max_call_depth(1), InterruptHandle::new());
+        assert_eq!(budget.enter_call(), Ok(()));
+        assert_eq!(budget.enter_call(), Err(LimitExceeded::CallDepth));
+        budget.exit_call();
+        assert_eq!(budget.enter_call(), Ok(()));
+
+        let mut budget = Budget::new(
+            Limits::new().timeout(Duration::from_millis(0)),
+            InterruptHandle::new(),
+        );
+        let result = (0..DEADLINE_CHECK_INTERVAL).try_for_each(|_| budget.step());
+        assert_eq!(result, Err(LimitExceeded::Timeout));
+        assert_eq!(budget.jit_fuel(), DEADLINE_CHECK_INTERVAL);
+
+        // machine code runs on the steps that are left
+        let mut budget = Budget::new(Limits::new().max_steps(10), InterruptHandle::new());
+        assert_eq!(budget.step(), Ok(()));
+        budget.charge(4);
+        assert_eq!(budget.jit_fuel(), 5);
+        assert_eq!(
+            Budget::new(Limits::new(), InterruptHandle::new()).jit_fuel(),
+            i64::MAX as u64
+        );
+    }
+
+    #[test]
+    fn test_interrupt_handle() {
+        let handle = InterruptHandle::new();
+        let mut budget = Budget::new(Limits::new(), handle.clone());
+        assert_eq!(budget.step(), Ok(()));
+
+        std::thread::spawn(move || handle.terminate())
+            .join()
+            .unwrap();
+        assert_eq!(budget.step(), Err(LimitExceeded::Terminated));
+        // the request is consumed
+        assert_eq!(budget.step(), Ok(()));
+    }
+}
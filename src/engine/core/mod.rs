@@ -1,2 +1,3 @@
 pub mod host;
+pub mod limits;
 pub mod vm;
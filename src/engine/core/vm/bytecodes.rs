@@ -14,16 +14,17 @@ pub(crate) mod Bytecodes {
 
     /* - [Loading the accumulator] */
     pub(crate) const Ldar: u8 = 0x10;
-    pub(crate) const LdaZero: u8 = 0x11;
     pub(crate) const LdaSmi: u8 = 0x12;
     pub(crate) const LdaUndefined: u8 = 0x13;
     pub(crate) const LdaNull: u8 = 0x14;
-    pub(crate) const LdaTheHole: u8 = 0x15;
     pub(crate) const LdaTrue: u8 = 0x16;
     pub(crate) const LdaFalse: u8 = 0x17;
     pub(crate) const LdaConstant: u8 = 0x18;
     pub(crate) const LdaContextSlot: u8 = 0x19; // implicit store to r0
 
+    /* Property stores (StoreIC) operations */
+    pub(crate) const SetNamedProperty: u8 = 0x1b;
+
     /* Property loads (LoadIC) operations */
     pub(crate) const GetNamedProperty: u8 = 0x1c;
 
@@ -32,11 +33,6 @@ pub(crate) mod Bytecodes {
     pub(crate) const Push: u8 = 0x1e;
     pub(crate) const Pop: u8 = 0x1f;
 
-    /* - [Test Operations ] */
-    pub(crate) const TestReferenceEqual: u8 = 0x20;
-    pub(crate) const TestNull: u8 = 0x21;
-    pub(crate) const TestUndefined: u8 = 0x22;
-
     /* Binary Operators */
     pub(crate) const Add: u8 = 0x23;
     pub(crate) const Sub: u8 = 0x24;
@@ -77,27 +73,14 @@ pub(crate) mod Bytecodes {
     pub(crate) const TestGreaterThan: u8 = 0x42;
     pub(crate) const TestLessThanOrEqual: u8 = 0x43;
     pub(crate) const TestGreaterThanOrEqual: u8 = 0x44;
-    pub(crate) const TestInstanceOf: u8 = 0x45;
-    pub(crate) const TestIn: u8 = 0x46;
 
     /* - [Unconditional jumps] */
     pub(crate) const JumpLoop: u8 = 0x47;
     /* - [Forward jumps] */
     pub(crate) const Jump: u8 = 0x48;
     /* - [Conditional jumps] */
-    /* - [Conditional constant jumps] */
-    pub(crate) const JumpIfNullConstant: u8 = 0x49;
-    pub(crate) const JumpIfNotNullConstant: u8 = 0x4a;
-    pub(crate) const JumpIfUndefinedConstant: u8 = 0x4b;
-    pub(crate) const JumpIfNotUndefinedConstant: u8 = 0x4c;
-    pub(crate) const JumpIfUndefinedOrNullConstant: u8 = 0x4d;
-    pub(crate) const JumpIfTrueConstant: u8 = 0x4e;
-    pub(crate) const JumpIfFalseConstant: u8 = 0x4f;
     pub(crate) const JumpIfTrue: u8 = 0x50;
     pub(crate) const JumpIfFalse: u8 = 0x51;
-    pub(crate) const JumpIfNull: u8 = 0x52;
-    pub(crate) const JumpIfNotNull: u8 = 0x53;
-    pub(crate) const JumpIfUndefined: u8 = 0x54;
     pub(crate) const JumpIfNotUndefined: u8 = 0x55;
     pub(crate) const JumpIfUndefinedOrNull: u8 = 0x56;
 
@@ -108,8 +91,34 @@ pub(crate) mod Bytecodes {
     pub(crate) const Return: u8 = 0x59;
     pub(crate) const Hlt: u8 = 0x5a;
 
-    pub(crate) const Construct: u8 = 0x5b; // implicit store to r0
     pub(crate) const StaContextSlot: u8 = 0x5c; // implicit load r0 and store to context slot
+    pub(crate) const StaConstContextSlot: u8 = 0x5d; // same as StaContextSlot, but not reassignable
+
+    /* Context operations */
+    pub(crate) const PushContext: u8 = 0x5e;
+    pub(crate) const PopContext: u8 = 0x5f;
+
+    /* Closure operations */
+    pub(crate) const CreateClosure: u8 = 0x60; // implicit store to r0
+
+    /* Operators missing above */
+    pub(crate) const LogicalNot: u8 = 0x61;
+    pub(crate) const Exp: u8 = 0x62;
+    pub(crate) const ToNumeric: u8 = 0x63;
+
+    /* Stores to the nearest declaration of a name */
+    pub(crate) const StaLookupSlot: u8 = 0x64; // implicit load r0
+
+    /* Keyed property loads and stores */
+    pub(crate) const GetKeyedProperty: u8 = 0x65; // implicit store to r0
+    pub(crate) const SetKeyedProperty: u8 = 0x66; // implicit load r0
+
+    /* Literals */
+    pub(crate) const CreateEmptyObjectLiteral: u8 = 0x67; // implicit store to r0
+    pub(crate) const CreateArrayLiteral: u8 = 0x68; // implicit store to r0
+
+    /* Exceptions */
+    pub(crate) const Throw: u8 = 0x69; // implicit load r0
 }
 
 #[allow(non_snake_case)]
@@ -123,3 +132,294 @@ pub(crate) mod RName {
     pub(crate) const R6: u8 = 0x06;
     pub(crate) const R7: u8 = 0x07;
 }
+
+/// an operand of an instruction. operands follow the opcode in this order, a register is one
+/// byte, the others are little endian i64s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operand {
+    Register,
+    /// the bits of a `Value`
+    Value,
+    /// an integer
+    Immediate,
+    /// an index into the constant table
+    Constant,
+    /// an index into the load or the store slots of the feedback vector
+    LoadSlot,
+    StoreSlot,
+    /// an offset from the end of the instruction, forward for `Jump*` and backward for
+    /// `JumpLoop`
+    Offset,
+    /// the number of values taken off the stack
+    Count,
+    /// the length of a name, followed by its bytes
+    Name,
+}
+
+/// the operands of `opcode`, `None` for a byte that is no opcode.
+pub(crate) fn operands(opcode: u8) -> Option<&'static [Operand]> {
+    use Bytecodes::*;
+    use Operand::*;
+
+    Some(match opcode {
+        Star0 | Star1 | Star2 | Star3 | Star4 | Star5 | Star6 | Star7 => &[],
+        Hlt
+        | Return
+        | Throw
+        | LdaUndefined
+        | LdaNull
+        | LdaTrue
+        | LdaFalse
+        | PushContext
+        | PopContext
+        | CreateEmptyObjectLiteral => &[],
+        Ldar | Push | Pop | Negate | BitwiseNot | Inc | Dec | ToNumeric | LogicalNot | TypeOf => {
+            &[Register]
+        }
+        Add
+        | Sub
+        | Mul
+        | Div
+        | Mod
+        | Exp
+        | BitwiseOr
+        | BitwiseXor
+        | BitwiseAnd
+        | ShiftLeft
+        | ShiftRight
+        | ShiftRightLogical
+        | TestEqual
+        | TestEqualStrict
+        | TestLessThan
+        | TestGreaterThan
+        | TestLessThanOrEqual
+        | TestGreaterThanOrEqual
+        | GetKeyedProperty
+        | SetKeyedProperty => &[Register, Register],
+        AddSmi | SubSmi | MulSmi | DivSmi | ModSmi | ExpSmi | BitwiseOrSmi | BitwiseXorSmi
+        | BitwiseAndSmi | ShiftLeftSmi | ShiftRightSmi | ShiftRightLogicalSmi => {
+            &[Register, Immediate]
+        }
+        Mov => &[Register, Value],
+        LdaSmi => &[Immediate],
+        LdaConstant | CreateClosure => &[Constant],
+        LdaContextSlot | StaContextSlot | StaConstContextSlot | StaLookupSlot => &[Name],
+        GetNamedProperty => &[Register, Constant, LoadSlot],
+        SetNamedProperty => &[Register, Constant, StoreSlot],
+        Jump | JumpLoop | JumpIfTrue | JumpIfFalse | JumpIfNotUndefined | JumpIfUndefinedOrNull => {
+            &[Offset]
+        }
+        CreateArrayLiteral | CallProperty | CallAnyReceiver => &[Count],
+        _ => return None,
+    })
+}
+
+/// the `*Smi` form of a binary opcode, which takes its right operand as an immediate.
+pub(crate) fn smi_form(opcode: u8) -> Option<u8> {
+    use Bytecodes::*;
+
+    Some(match opcode {
+        Add => AddSmi,
+        Sub => SubSmi,
+        Mul => MulSmi,
+        Div => DivSmi,
+        Mod => ModSmi,
+        Exp => ExpSmi,
+        BitwiseOr => BitwiseOrSmi,
+        BitwiseXor => BitwiseXorSmi,
+        BitwiseAnd => BitwiseAndSmi,
+        ShiftLeft => ShiftLeftSmi,
+        ShiftRight => ShiftRightSmi,
+        ShiftRightLogical => ShiftRightLogicalSmi,
+        _ => return None,
+    })
+}
+
+/// the name of `opcode`, `None` for a byte that is no opcode.
+pub(crate) fn name(opcode: u8) -> Option<&'static str> {
+    use Bytecodes::*;
+
+    Some(match opcode {
+        Star0 => "Star0",
+        Star1 => "Star1",
+        Star2 => "Star2",
+        Star3 => "Star3",
+        Star4 => "Star4",
+        Star5 => "Star5",
+        Star6 => "Star6",
+        Star7 => "Star7",
+        Ldar => "Ldar",
+        LdaSmi => "LdaSmi",
+        LdaUndefined => "LdaUndefined",
+        LdaNull => "LdaNull",
+        LdaTrue => "LdaTrue",
+        LdaFalse => "LdaFalse",
+        LdaConstant => "LdaConstant",
+        LdaContextSlot => "LdaContextSlot",
+        SetNamedProperty => "SetNamedProperty",
+        GetNamedProperty => "GetNamedProperty",
+        Mov => "Mov",
+        Push => "Push",
+        Pop => "Pop",
+        Add => "Add",
+        Sub => "Sub",
+        Mul => "Mul",
+        Div => "Div",
+        Mod => "Mod",
+        BitwiseOr => "BitwiseOr",
+        BitwiseXor => "BitwiseXor",
+        BitwiseAnd => "BitwiseAnd",
+        ShiftLeft => "ShiftLeft",
+        ShiftRight => "ShiftRight",
+        ShiftRightLogical => "ShiftRightLogical",
+        AddSmi => "AddSmi",
+        SubSmi => "SubSmi",
+        MulSmi => "MulSmi",
+        DivSmi => "DivSmi",
+        ModSmi => "ModSmi",
+        ExpSmi => "ExpSmi",
+        BitwiseOrSmi => "BitwiseOrSmi",
+        BitwiseXorSmi => "BitwiseXorSmi",
+        BitwiseAndSmi => "BitwiseAndSmi",
+        ShiftLeftSmi => "ShiftLeftSmi",
+        ShiftRightSmi => "ShiftRightSmi",
+        ShiftRightLogicalSmi => "ShiftRightLogicalSmi",
+        Inc => "Inc",
+        Dec => "Dec",
+        Negate => "Negate",
+        BitwiseNot => "BitwiseNot",
+        TypeOf => "TypeOf",
+        TestEqual => "TestEqual",
+        TestEqualStrict => "TestEqualStrict",
+        TestLessThan => "TestLessThan",
+        TestGreaterThan => "TestGreaterThan",
+        TestLessThanOrEqual => "TestLessThanOrEqual",
+        TestGreaterThanOrEqual => "TestGreaterThanOrEqual",
+        JumpLoop => "JumpLoop",
+        Jump => "Jump",
+        JumpIfTrue => "JumpIfTrue",
+        JumpIfFalse => "JumpIfFalse",
+        JumpIfNotUndefined => "JumpIfNotUndefined",
+        JumpIfUndefinedOrNull => "JumpIfUndefinedOrNull",
+        CallAnyReceiver => "CallAnyReceiver",
+        CallProperty => "CallProperty",
+        Return => "Return",
+        Hlt => "Hlt",
+        StaContextSlot => "StaContextSlot",
+        StaConstContextSlot => "StaConstContextSlot",
+        PushContext => "PushContext",
+        PopContext => "PopContext",
+        CreateClosure => "CreateClosure",
+        LogicalNot => "LogicalNot",
+        Exp => "Exp",
+        ToNumeric => "ToNumeric",
+        StaLookupSlot => "StaLookupSlot",
+        GetKeyedProperty => "GetKeyedProperty",
+        SetKeyedProperty => "SetKeyedProperty",
+        CreateEmptyObjectLiteral => "CreateEmptyObjectLiteral",
+        CreateArrayLiteral => "CreateArrayLiteral",
+        Throw => "Throw",
+        _ => return None,
+    })
+}
+
+/// an instruction of a code. the operand of a name is its length, the bytes of the name end
+/// the instruction.
+pub(crate) struct Instruction {
+    pub(crate) opcode: u8,
+    pub(crate) operands: Vec<i64>,
+    pub(crate) len: usize,
+}
+
+pub(crate) enum DecodeError {
+    UnknownOpcode,
+    /// the operands run past the end of the code
+    Truncated,
+}
+
+/// the instruction at `pc` of `code`.
+pub(crate) fn decode(code: &[u8], pc: usize) -> Result<Instruction, DecodeError> {
+    let opcode = code[pc];
+    let kinds = operands(opcode).ok_or(DecodeError::UnknownOpcode)?;
+
+    let mut at = pc + 1;
+    let mut values = vec![];
+    for &kind in kinds {
+        if kind == Operand::Register {
+            values.push(*code.get(at).ok_or(DecodeError::Truncated)? as i64);
+            at += 1;
+            continue;
+        }
+
+        let bytes = code.get(at..at + 8).ok_or(DecodeError::Truncated)?;
+        let n = i64::from_le_bytes(bytes.try_into().unwrap());
+        at += 8;
+        if kind == Operand::Name {
+            at = usize::try_from(n)
+                .ok()
+                .and_then(|len| at.checked_add(len))
+                .filter(|&end| end <= code.len())
+                .ok_or(DecodeError::Truncated)?;
+        }
+        values.push(n);
+    }
+
+    Ok(Instruction {
+        opcode,
+        operands: values,
+        len: at - pc,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcodes() {
+        for opcode in 0..=u8::MAX {
+            assert_eq!(operands(opcode).is_some(), name(opcode).is_some());
+        }
+        assert_eq!(name(Bytecodes::Throw), Some("Throw"));
+        assert!(operands(0xff).is_none());
+        // `Exp` is not next to the other binary operators, its smi form is
+        assert_eq!(smi_form(Bytecodes::Exp), Some(Bytecodes::ExpSmi));
+        assert_eq!(
+            smi_form(Bytecodes::ShiftRightLogical),
+            Some(Bytecodes::ShiftRightLogicalSmi)
+        );
+        assert_eq!(smi_form(Bytecodes::AddSmi), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let code = [
+            Bytecodes::GetNamedProperty,
+            RName::R1,
+            2,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            3,
+        ];
+        assert!(matches!(decode(&code, 0), Err(DecodeError::Truncated)));
+        let mut code = code.to_vec();
+        code.extend_from_slice(&[0; 7]);
+        code.extend_from_slice(&[Bytecodes::StaLookupSlot, 1, 0, 0, 0, 0, 0, 0, 0, b'a']);
+
+        let instruction = decode(&code, 0).ok().unwrap();
+        assert_eq!(instruction.operands, [1, 2, 3]);
+        assert_eq!(instruction.len, 18);
+        let instruction = decode(&code, 18).ok().unwrap();
+        assert_eq!(instruction.operands, [1]);
+        assert_eq!(instruction.len, 10);
+        assert!(matches!(
+            decode(&[0xff], 0),
+            Err(DecodeError::UnknownOpcode)
+        ));
+    }
+}
@@ -0,0 +1,57 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::serializer::{source_hash, Script};
+
+/// compiled scripts on disk, a `<source hash>.glsc` file for each source. a file that can not
+/// be read is a miss and is written again.
+pub(crate) struct CodeCache {
+    dir: PathBuf,
+}
+
+impl CodeCache {
+    /// `$GLASPER_CACHE_DIR`, otherwise `glasper` in the cache directory of the user.
+    pub(crate) fn new() -> Self {
+        let dir = match std::env::var_os("GLASPER_CACHE_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+                .unwrap_or_else(std::env::temp_dir)
+                .join("glasper"),
+        };
+        CodeCache::with_dir(dir)
+    }
+
+    pub(crate) fn with_dir(dir: PathBuf) -> Self {
+        CodeCache { dir }
+    }
+
+    /// the script compiled from `source`, if it is cached.
+    pub(crate) fn get(&self, source: &str) -> Option<Script> {
+        let hash = source_hash(source);
+        let bytes = fs::read(self.path(hash)).ok()?;
+        Script::deserialize(&bytes)
+            .ok()
+            .filter(|script| script.source_hash == hash)
+    }
+
+    /// store `script`. the cache is only an optimization, so failures are ignored.
+    pub(crate) fn put(&self, script: &Script) {
+        let path = self.path(script.source_hash);
+        // written aside and renamed, so no one reads half a file
+        let partial = path.with_extension(format!("glsc.{}", std::process::id()));
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&partial, script.serialize()))
+            .and_then(|_| fs::rename(&partial, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.glsc", hash))
+    }
+}
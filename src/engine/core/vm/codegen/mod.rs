@@ -1,190 +1,1022 @@
 #![allow(dead_code)]
 
-use crate::engine::ast::{Expression, Program, Statement};
+use std::{cell::RefCell, rc::Rc};
+
+use crate::engine::ast::{
+    BlockStatement, Expression, ForInit, ForStatement, FunctionExpression, IfStatement,
+    MemberExpression, Program, Statement, SwitchStatement, TryStatement,
+};
 
 use super::{
     bytecodes::{
         Bytecodes::{
-            Add, CallProperty, Div, GetNamedProperty, LdaConstant, LdaContextSlot, LdaSmi,
-            LdaUndefined, Mod, Mul, Pop, Push, Return, StaContextSlot, Sub,
+            Add, BitwiseAnd, BitwiseNot, BitwiseOr, BitwiseXor, CallAnyReceiver, CallProperty,
+            CreateArrayLiteral, CreateClosure, CreateEmptyObjectLiteral, Dec, Div, Exp,
+            GetKeyedProperty, GetNamedProperty, Inc, Jump, JumpIfFalse, JumpIfNotUndefined,
+            JumpIfTrue, JumpIfUndefinedOrNull, JumpLoop, LdaConstant, LdaContextSlot, LdaFalse,
+            LdaNull, LdaSmi, LdaTrue, LdaUndefined, Ldar, LogicalNot, Mod, Mov, Mul, Negate, Pop,
+            PopContext, Push, PushContext, Return, SetKeyedProperty, SetNamedProperty, ShiftLeft,
+            ShiftRight, ShiftRightLogical, StaConstContextSlot, StaContextSlot, StaLookupSlot,
+            Star0, Sub, TestEqual, TestEqualStrict, TestGreaterThan, TestGreaterThanOrEqual,
+            TestLessThan, TestLessThanOrEqual, Throw, ToNumeric, TypeOf,
         },
-        RName::{R0, R1, R2},
+        RName::{R0, R6, R7},
     },
     constant_table::ConstantTable,
+    feedback::FeedbackVector,
+    function::FunctionCode,
+    handler_table::{HandlerTable, HandlerTableEntry},
+    jit::JitState,
+    source_positions::SourcePositionTable,
+    value::Value,
+    VMError, VMErrorKind,
 };
 
+/// the operand of a forward jump, patched by `bind` once the target is known.
+struct Label(usize);
+
+/// temporaries live in r1 to r5, the ones past them are spilled to the stack. expressions
+/// leave their value in r0, the accumulator.
+const TEMP_REGISTERS: usize = 5;
+
+/// a value held while other code runs. temporaries are released in the reverse order of
+/// their allocation, so the spilled ones are always on top of the stack.
+#[derive(Clone, Copy)]
+enum Temp {
+    Register(u8),
+    Stack,
+}
+
+/// a statement `break` (and for loops `continue`) can leave.
+struct JumpTarget {
+    is_loop: bool,
+    breaks: Vec<Label>,
+    continues: Vec<Label>,
+    /// contexts pushed and stack values held when the statement was entered
+    context_depth: usize,
+    stack_depth: usize,
+}
+
+/// how a `try` block was left, the token the finally block dispatches on after it ran.
+/// `break`s and `continue`s to a target outside of the statement get a token each.
+const COMPLETION_NORMAL: i64 = 0;
+const COMPLETION_THROW: i64 = 1;
+const COMPLETION_RETURN: i64 = 2;
+
+enum Completion {
+    Break(usize),
+    Continue(usize),
+}
+
+/// a `try` statement with a finally block. `return`, `break` and `continue` leaving it keep
+/// their value and completion token in the next two temporaries and jump into the finally
+/// block.
+struct FinallyScope {
+    /// the jump targets outside of the statement
+    jump_targets: usize,
+    context_depth: usize,
+    stack_depth: usize,
+    temps: usize,
+    entries: Vec<Label>,
+    has_return: bool,
+    /// the `break`s and `continue`s by their token
+    jumps: Vec<Completion>,
+}
+
 pub struct CodeGenerator<'a> {
     code: Vec<u8>,
     constant_table: &'a mut ConstantTable,
+    feedback: &'a mut FeedbackVector,
+    handler_table: &'a mut HandlerTable,
+    source_positions: &'a mut SourcePositionTable,
+    jump_targets: Vec<JumpTarget>,
+    finally_scopes: Vec<FinallyScope>,
+    context_depth: usize,
+    /// temporaries spilled to the stack
+    stack_depth: usize,
+    /// temporaries alive, such as the discriminant of a switch
+    temps: usize,
+    /// whether the code is a function body, where `return` is allowed
+    in_function: bool,
 }
 
 impl<'a> CodeGenerator<'a> {
-    pub(super) fn new(constant_table: &'a mut ConstantTable) -> Self {
+    pub(super) fn new(
+        constant_table: &'a mut ConstantTable,
+        feedback: &'a mut FeedbackVector,
+        handler_table: &'a mut HandlerTable,
+        source_positions: &'a mut SourcePositionTable,
+    ) -> Self {
         CodeGenerator {
             code: Vec::new(),
             constant_table,
+            feedback,
+            handler_table,
+            source_positions,
+            jump_targets: Vec::new(),
+            finally_scopes: Vec::new(),
+            context_depth: 0,
+            stack_depth: 0,
+            temps: 0,
+            in_function: false,
         }
     }
 
-    pub(super) fn gen(&mut self, program: &Program) -> Vec<u8> {
-        for statement in program.statements.iter() {
-            self.gen_statement(statement);
+    pub(super) fn gen(&mut self, program: &Program) -> Result<Vec<u8>, VMError> {
+        self.gen_statements(&program.statements, &program.lines)?;
+        Ok(self.code.clone())
+    }
+
+    /// compile a script into its own code, constants and feedback vector instead of those of
+    /// the vm. the completion value is left in r0.
+    pub(super) fn gen_script(program: &Program) -> Result<FunctionCode, VMError> {
+        let mut constant_table = ConstantTable::new();
+        let mut feedback = FeedbackVector::new();
+        let mut handler_table = HandlerTable::new();
+        let mut source_positions = SourcePositionTable::new();
+        let mut codegen = CodeGenerator::new(
+            &mut constant_table,
+            &mut feedback,
+            &mut handler_table,
+            &mut source_positions,
+        );
+        let code = codegen.gen(program)?;
+
+        Ok(FunctionCode {
+            parameters: vec![],
+            code,
+            constant_table,
+            feedback: RefCell::new(feedback),
+            handler_table,
+            source_positions,
+            jit: RefCell::new(JitState::default()),
+        })
+    }
+
+    /// compile a function literal into its own code, constants and feedback vector.
+    /// the call puts the arguments into the context of the function before it starts.
+    fn gen_function(function: &FunctionExpression) -> Result<FunctionCode, VMError> {
+        let mut constant_table = ConstantTable::new();
+        let mut feedback = FeedbackVector::new();
+        let mut handler_table = HandlerTable::new();
+        let mut source_positions = SourcePositionTable::new();
+        let mut codegen = CodeGenerator::new(
+            &mut constant_table,
+            &mut feedback,
+            &mut handler_table,
+            &mut source_positions,
+        );
+        codegen.in_function = true;
+
+        for parameter in function.parameters.iter() {
+            if let Some(default) = &parameter.default {
+                // the default replaces a missing (undefined) argument
+                codegen.gen_expression(&Expression::Identifier(parameter.name.clone()))?;
+                let label = codegen.gen_jump(JumpIfNotUndefined);
+                codegen.gen_declaration(StaContextSlot, &parameter.name, default)?;
+                codegen.bind(label);
+            }
+        }
+        codegen.gen_statements(&function.body.statements, &function.body.lines)?;
+        codegen.code.extend_from_slice(&[LdaUndefined, Return]);
+        let code = std::mem::take(&mut codegen.code);
+
+        Ok(FunctionCode {
+            parameters: function.parameters.iter().map(|p| p.name.clone()).collect(),
+            code,
+            constant_table,
+            feedback: RefCell::new(feedback),
+            handler_table,
+            source_positions,
+            jit: RefCell::new(JitState::default()),
+        })
+    }
+
+    /// the statements with the lines they start on, if they come from the source.
+    fn gen_statements(&mut self, statements: &[Statement], lines: &[u32]) -> Result<(), VMError> {
+        for (i, statement) in statements.iter().enumerate() {
+            if let Some(&line) = lines.get(i) {
+                self.source_positions.add(self.code.len(), line);
+            }
+            self.gen_statement(statement)?;
         }
-        self.code.clone()
+        Ok(())
     }
 
-    fn gen_statement(&mut self, statement: &Statement) {
+    fn gen_statement(&mut self, statement: &Statement) -> Result<(), VMError> {
         match statement {
             Statement::Expression(expr) => {
-                self.gen_expression(expr);
+                // the value stays in r0 as the completion value
+                self.gen_expression(expr)?;
             }
 
             Statement::Let(stmt) => {
-                let name = stmt.name.as_bytes();
-                let len_bytes = (name.len() as i64).to_le_bytes();
+                self.gen_declaration(StaContextSlot, &stmt.name, &stmt.value)?
+            }
+            Statement::Const(stmt) => {
+                self.gen_declaration(StaConstContextSlot, &stmt.name, &stmt.value)?
+            }
 
-                self.gen_expression(&stmt.value);
-                self.code
-                    .extend_from_slice(&[&[StaContextSlot], &len_bytes[0..], name].concat());
-                self.code.extend(&[LdaUndefined]);
-                self.code.extend(&[Return]);
+            Statement::Block(block) => self.gen_block(block)?,
+
+            Statement::If(stmt) => self.gen_if_statement(stmt)?,
+            Statement::For(stmt) => self.gen_for_statement(stmt)?,
+            Statement::Switch(stmt) => self.gen_switch_statement(stmt)?,
+            Statement::Try(stmt) => self.gen_try_statement(stmt)?,
+
+            Statement::Break => {
+                let index = match self.jump_targets.len() {
+                    0 => return Err(Self::syntax_error("Illegal break statement")),
+                    n => n - 1,
+                };
+                self.gen_jump_out(Completion::Break(index));
+            }
+            Statement::Continue => {
+                let index = match self.jump_targets.iter().rposition(|t| t.is_loop) {
+                    Some(index) => index,
+                    None => {
+                        return Err(Self::syntax_error(
+                            "Illegal continue statement: no surrounding iteration statement",
+                        ))
+                    }
+                };
+                self.gen_jump_out(Completion::Continue(index));
+            }
+
+            Statement::Return(expr) => {
+                if !self.in_function {
+                    return Err(Self::syntax_error("Illegal return statement"));
+                }
+                self.gen_expression(expr)?;
+                self.gen_return();
+            }
+            Statement::Throw(expr) => {
+                self.gen_expression(expr)?;
+                self.code.push(Throw);
             }
-            _ => todo!(),
         }
+        Ok(())
     }
 
-    fn gen_expression(&mut self, expr: &Expression) {
-        match expr {
-            Expression::Undefined => {
-                self.code.extend(&[LdaUndefined]);
+    fn gen_block(&mut self, block: &BlockStatement) -> Result<(), VMError> {
+        self.code.push(PushContext);
+        self.context_depth += 1;
+        self.gen_statements(&block.statements, &block.lines)?;
+        self.context_depth -= 1;
+        self.code.push(PopContext);
+        Ok(())
+    }
+
+    /// return r0. the frame restores the context and the stack of the caller.
+    fn gen_return(&mut self) {
+        match self.finally_scopes.last_mut() {
+            Some(scope) => {
+                scope.has_return = true;
+                self.gen_enter_finally(COMPLETION_RETURN);
             }
-            Expression::Number(literal) => {
-                self.code
-                    .extend_from_slice(&[&[LdaSmi], &Self::into_bytes(*literal)[0..]].concat());
-                self.code.extend_from_slice(&[Push, R0]);
+            None => {
+                // the verifier holds a function to the contexts it pushed
+                for _ in 0..self.context_depth {
+                    self.code.push(PopContext);
+                }
+                self.code.push(Return);
             }
-            Expression::String(literal) => {
-                let id = self.constant_table.add(literal.clone());
-                let id_bytes = &Self::into_bytes(id as f64)[0..];
+        }
+    }
 
-                self.code
-                    .extend_from_slice(&[&[LdaConstant], id_bytes].concat());
+    /// `break` or `continue`, through the finally block of a `try` between here and the
+    /// target.
+    fn gen_jump_out(&mut self, completion: Completion) {
+        let index = match completion {
+            Completion::Break(index) | Completion::Continue(index) => index,
+        };
+        if let Some(scope) = self.finally_scopes.last_mut() {
+            if index < scope.jump_targets {
+                let token = COMPLETION_RETURN + 1 + scope.jumps.len() as i64;
+                scope.jumps.push(completion);
+                self.code.push(LdaUndefined);
+                self.gen_enter_finally(token);
+                return;
+            }
+        }
+
+        self.gen_exit(index);
+        let label = self.gen_jump(Jump);
+        match completion {
+            Completion::Break(_) => self.jump_targets[index].breaks.push(label),
+            Completion::Continue(_) => self.jump_targets[index].continues.push(label),
+        }
+    }
 
+    /// leave the innermost `try` with the value in r0 and run its finally block.
+    fn gen_enter_finally(&mut self, token: i64) {
+        let scope = self.finally_scopes.last().unwrap();
+        let temps = scope.temps;
+        for _ in scope.context_depth..self.context_depth {
+            self.code.push(PopContext);
+        }
+        for _ in scope.stack_depth..self.stack_depth {
+            self.code.extend_from_slice(&[Pop, R7]);
+        }
+        self.gen_store_completion(temps, token);
+        let label = self.gen_jump(Jump);
+        self.finally_scopes.last_mut().unwrap().entries.push(label);
+    }
+
+    /// keep r0 and `token` where the finally block takes them from: the two temporaries after
+    /// the `temps` alive at the `try`.
+    fn gen_store_completion(&mut self, temps: usize, token: i64) {
+        match Self::temp_at(temps) {
+            Temp::Register(r) => self.gen_star(r),
+            Temp::Stack => self.code.extend_from_slice(&[Push, R0]),
+        }
+        match Self::temp_at(temps + 1) {
+            Temp::Register(r) => self.gen_mov(r, Value::number(token as f64)),
+            Temp::Stack => {
+                self.code.push(LdaSmi);
+                self.code.extend_from_slice(&Self::into_bytes(token));
                 self.code.extend_from_slice(&[Push, R0]);
             }
-            Expression::Member(expr) => {
-                self.gen_expression(&expr.object);
-                self.code.extend_from_slice(&[Pop, R1]);
-                match expr.property.as_ref() {
-                    Expression::String(s) => {
-                        let id = self.constant_table.add(s.clone());
-                        self.code.extend_from_slice(&[GetNamedProperty, R1]);
-                        self.code
-                            .extend_from_slice(&Self::into_bytes(id as f64)[0..]);
-                    }
-                    _ => todo!(),
-                }
+        }
+    }
 
-                self.code.extend_from_slice(&[Push, R0]);
+    ///   <block>               // region of the catch handler
+    ///   Jump end
+    /// catch:                  // the exception is in r0
+    ///   PushContext
+    ///   StaContextSlot param
+    ///   <handler>
+    ///   PopContext
+    /// end:
+    ///
+    /// with a finally block, both are a region whose handler runs it with the exception.
+    fn gen_try_statement(&mut self, stmt: &TryStatement) -> Result<(), VMError> {
+        let start = self.code.len();
+        let finalizer = match &stmt.finalizer {
+            Some(finalizer) => finalizer,
+            None => return self.gen_try_catch(stmt),
+        };
+
+        self.finally_scopes.push(FinallyScope {
+            jump_targets: self.jump_targets.len(),
+            context_depth: self.context_depth,
+            stack_depth: self.stack_depth,
+            temps: self.temps,
+            entries: Vec::new(),
+            has_return: false,
+            jumps: Vec::new(),
+        });
+        self.gen_try_catch(stmt)?;
+        let end = self.code.len();
+        let scope = self.finally_scopes.pop().unwrap();
+
+        // fall through with undefined
+        self.code.push(LdaUndefined);
+        self.gen_store_completion(self.temps, COMPLETION_NORMAL);
+        let normal = self.gen_jump(Jump);
+
+        self.add_handler(start, end);
+        self.gen_store_completion(self.temps, COMPLETION_THROW);
+
+        // the value and the token are held while the finally block runs
+        for label in scope.entries.into_iter().chain([normal]) {
+            self.bind(label);
+        }
+        let value = self.alloc_temp();
+        let token = self.alloc_temp();
+        self.gen_block(finalizer)?;
+        let token = self.release_temp(token, R7);
+
+        let mut completions = vec![(COMPLETION_THROW, None)];
+        if scope.has_return {
+            completions.push((COMPLETION_RETURN, None));
+        }
+        for (i, jump) in scope.jumps.into_iter().enumerate() {
+            completions.push((COMPLETION_RETURN + 1 + i as i64, Some(jump)));
+        }
+        for (token_value, jump) in completions {
+            self.code.push(LdaSmi);
+            self.code.extend_from_slice(&Self::into_bytes(token_value));
+            self.code.extend_from_slice(&[TestEqualStrict, token, R0]);
+            let next = self.gen_jump(JumpIfFalse);
+            // the completion leaves the statement, the value is released on its path only
+            let temps = self.temps;
+            let stack_depth = self.stack_depth;
+            self.gen_load_temp(value);
+            match jump {
+                Some(jump) => self.gen_jump_out(jump),
+                None if token_value == COMPLETION_THROW => self.code.push(Throw),
+                None => self.gen_return(),
             }
+            self.temps = temps;
+            self.stack_depth = stack_depth;
+            self.bind(next);
+        }
+        self.release_temp(value, R7);
+        self.code.push(LdaUndefined);
+        Ok(())
+    }
 
-            Expression::Call(call_expr) => {
-                match call_expr.callee.as_ref() {
-                    Expression::Member(member_expr) => {
-                        // gen callee
-                        self.gen_expression(&call_expr.callee);
+    fn gen_try_catch(&mut self, stmt: &TryStatement) -> Result<(), VMError> {
+        let start = self.code.len();
+        self.gen_block(&stmt.block)?;
+        let handler = match &stmt.handler {
+            Some(handler) => handler,
+            None => return Ok(()),
+        };
+        let end = self.code.len();
+        let skip = self.gen_jump(Jump);
+
+        self.add_handler(start, end);
+        self.code.push(PushContext);
+        self.context_depth += 1;
+        if let Some(param) = &stmt.param {
+            self.gen_name_op(StaContextSlot, param);
+        }
+        self.gen_block(handler)?;
+        self.context_depth -= 1;
+        self.code.push(PopContext);
+        self.bind(skip);
+        self.code.push(LdaUndefined);
+        Ok(())
+    }
 
-                        // TODO: arguments (gen array)
-                        // self.gen_expression(expr.arguments);
+    /// the code from `start` to `end` goes on at the current position on an exception.
+    fn add_handler(&mut self, start: usize, end: usize) {
+        self.handler_table.add(HandlerTableEntry {
+            start,
+            end,
+            handler: self.code.len(),
+            stack_depth: self.stack_depth,
+            context_depth: self.context_depth,
+        });
+    }
 
-                        self.gen_expression(&member_expr.object);
+    fn gen_declaration(&mut self, op: u8, name: &str, value: &Expression) -> Result<(), VMError> {
+        self.gen_expression(value)?;
+        self.gen_name_op(op, name);
+        self.code.push(LdaUndefined);
+        Ok(())
+    }
 
-                        self.code.extend_from_slice(&[Pop, R2]);
-                        self.code.extend_from_slice(&[Pop, R1]);
+    fn gen_if_statement(&mut self, stmt: &IfStatement) -> Result<(), VMError> {
+        self.gen_expression(&stmt.test)?;
+        let else_label = self.gen_jump(JumpIfFalse);
+        self.gen_statement(&stmt.consequence)?;
 
-                        // gen call instruction (signature: `[CallProperty, callee_pointer, argument_pointer, parent_obj_pointer]`)
-                        self.code.extend_from_slice(&[CallProperty, R1, R2]);
-                        self.code.extend_from_slice(&[Push, R0]);
-                    }
-                    _ => todo!(),
-                }
+        match stmt.alternate.as_ref() {
+            Some(alternate) => {
+                let end_label = self.gen_jump(Jump);
+                self.bind(else_label);
+                self.gen_statement(alternate)?;
+                self.bind(end_label);
+            }
+            None => self.bind(else_label),
+        }
+        Ok(())
+    }
 
-                // self.gen_expression(&expr.callee);
-                // self.code.extend_from_slice(&[Pop, R1]);
+    ///   PushContext
+    ///   <init>
+    /// loop:
+    ///   <test> JumpIfFalse end
+    ///   <body>
+    /// continue:
+    ///   <update>
+    ///   JumpLoop loop
+    /// end:
+    ///   LdaUndefined
+    ///   PopContext
+    fn gen_for_statement(&mut self, stmt: &ForStatement) -> Result<(), VMError> {
+        self.code.push(PushContext);
+        self.context_depth += 1;
 
-                // TODO: arguments (gen array)
-                // for arg in expr.arguments.iter() {
-                //     self.gen_expression(arg);
-                // }
+        match &stmt.init {
+            Some(ForInit::Statement(init)) => self.gen_statement(init)?,
+            Some(ForInit::Expression(init)) => self.gen_expression(init)?,
+            None => {}
+        }
 
-                self.code.extend_from_slice(&[Pop, R1]);
+        let loop_start = self.code.len();
+        let end_label = match &stmt.test {
+            Some(test) => {
+                self.gen_expression(test)?;
+                Some(self.gen_jump(JumpIfFalse))
             }
+            None => None,
+        };
 
-            Expression::Binary(expr) => match expr.operator.as_str() {
-                "+" => {
-                    self.gen_expression(&expr.left);
-                    self.gen_expression(&expr.right);
-                    self.code.extend_from_slice(&[Pop, R0]);
-                    self.code.extend_from_slice(&[Pop, R1]);
-                    self.code.extend_from_slice(&[Add, R0, R1]);
-                    self.code.extend_from_slice(&[Push, R0]);
+        let target = self.gen_breakable_body(true, &stmt.body)?;
+        for label in target.continues {
+            self.bind(label);
+        }
+        if let Some(update) = &stmt.update {
+            self.gen_expression(update)?;
+        }
+        self.gen_jump_loop(loop_start);
+
+        for label in end_label.into_iter().chain(target.breaks) {
+            self.bind(label);
+        }
+        // the test left its result in r0
+        self.code.push(LdaUndefined);
+        self.context_depth -= 1;
+        self.code.push(PopContext);
+        Ok(())
+    }
+
+    /// the discriminant is held while the cases are tested, then control jumps to the first
+    /// matching case body and falls through the following ones.
+    fn gen_switch_statement(&mut self, stmt: &SwitchStatement) -> Result<(), VMError> {
+        self.gen_expression(&stmt.discriminant)?;
+        let discriminant = self.gen_store_temp();
+
+        let mut case_labels = Vec::new();
+        for case in stmt.cases.iter() {
+            if let Some(test) = &case.test {
+                self.gen_expression(test)?;
+                let r = match discriminant {
+                    Temp::Register(r) => r,
+                    Temp::Stack => {
+                        self.code.extend_from_slice(&[Pop, R7, Push, R7]);
+                        R7
+                    }
+                };
+                self.code.extend_from_slice(&[TestEqualStrict, r, R0]);
+                case_labels.push(Some(self.gen_jump(JumpIfTrue)));
+            } else {
+                case_labels.push(None);
+            }
+        }
+        let default_label = self.gen_jump(Jump);
+
+        self.jump_targets.push(self.jump_target(false));
+        let mut default_label = Some(default_label);
+        for (case, label) in stmt.cases.iter().zip(case_labels) {
+            match label {
+                Some(label) => self.bind(label),
+                None => self.bind(default_label.take().unwrap()),
+            }
+            for statement in case.consequent.iter() {
+                self.gen_statement(statement)?;
+            }
+        }
+        let target = self.jump_targets.pop().unwrap();
+
+        // no default: a miss skips every case
+        for label in default_label.into_iter().chain(target.breaks) {
+            self.bind(label);
+        }
+        self.release_temp(discriminant, R7);
+        Ok(())
+    }
+
+    fn gen_breakable_body(
+        &mut self,
+        is_loop: bool,
+        body: &Statement,
+    ) -> Result<JumpTarget, VMError> {
+        self.jump_targets.push(self.jump_target(is_loop));
+        let result = self.gen_statement(body);
+        let target = self.jump_targets.pop().unwrap();
+        result.map(|_| target)
+    }
+
+    fn jump_target(&self, is_loop: bool) -> JumpTarget {
+        JumpTarget {
+            is_loop,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+            context_depth: self.context_depth,
+            stack_depth: self.stack_depth,
+        }
+    }
+
+    /// unwind the contexts and stack values entered since `jump_targets[index]`.
+    fn gen_exit(&mut self, index: usize) {
+        let target = &self.jump_targets[index];
+        for _ in target.context_depth..self.context_depth {
+            self.code.push(PopContext);
+        }
+        for _ in target.stack_depth..self.stack_depth {
+            self.code.extend_from_slice(&[Pop, R7]);
+        }
+    }
+
+    /// a jump with a relative operand to patch later.
+    fn gen_jump(&mut self, op: u8) -> Label {
+        self.code.push(op);
+        self.code.extend_from_slice(&[0; 8]);
+        Label(self.code.len() - 8)
+    }
+
+    /// make `label` jump to the current position.
+    fn bind(&mut self, label: Label) {
+        let offset = (self.code.len() - (label.0 + 8)) as i64;
+        self.code[label.0..label.0 + 8].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    /// a backward jump to `target`. the operand counts from the end of the instruction.
+    fn gen_jump_loop(&mut self, target: usize) {
+        let offset = (self.code.len() + 9 - target) as i64;
+        self.code.push(JumpLoop);
+        self.code.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    fn syntax_error(message: &str) -> VMError {
+        VMError::new(VMErrorKind::Syntax, message.to_string())
+    }
+
+    /// generate `expr`, leaving its value in r0.
+    fn gen_expression(&mut self, expr: &Expression) -> Result<(), VMError> {
+        match expr {
+            Expression::Undefined => self.code.push(LdaUndefined),
+            Expression::Null => self.code.push(LdaNull),
+            Expression::Boolean(true) => self.code.push(LdaTrue),
+            Expression::Boolean(false) => self.code.push(LdaFalse),
+            Expression::Number(literal) => match Value::number(*literal).as_smi() {
+                Some(n) => {
+                    self.code.push(LdaSmi);
+                    self.code.extend_from_slice(&Self::into_bytes(n as i64));
                 }
-                "-" => {
-                    self.gen_expression(&expr.left);
-                    self.gen_expression(&expr.right);
-                    self.code.extend_from_slice(&[Pop, R0]);
-                    self.code.extend_from_slice(&[Pop, R1]);
-                    self.code.extend_from_slice(&[Sub, R0, R1]);
-                    self.code.extend_from_slice(&[Push, R0]);
+                // doubles are no immediate operands
+                None => {
+                    let id = self.constant_table.add_number(*literal);
+                    self.code.push(LdaConstant);
+                    self.code.extend_from_slice(&Self::into_bytes(id as i64));
                 }
-                "*" => {
-                    self.gen_expression(&expr.left);
-                    self.gen_expression(&expr.right);
-                    self.code.extend_from_slice(&[Pop, R0]);
-                    self.code.extend_from_slice(&[Pop, R1]);
-                    self.code.extend_from_slice(&[Mul, R0, R1]);
-                    self.code.extend_from_slice(&[Push, R0]);
+            },
+            Expression::String(literal) => {
+                let id = self.constant_table.add(literal.clone());
+                self.code.push(LdaConstant);
+                self.code.extend_from_slice(&Self::into_bytes(id as i64));
+            }
+
+            Expression::Object(object) => {
+                // the object is held while the values are generated
+                self.code.push(CreateEmptyObjectLiteral);
+                let temp = self.gen_store_temp();
+                for property in object.properties.iter() {
+                    self.gen_expression(&property.value)?;
+                    match temp {
+                        Temp::Register(r) => self.gen_set_named_property(r, &property.key),
+                        Temp::Stack => {
+                            self.code.extend_from_slice(&[Pop, R7]);
+                            self.gen_set_named_property(R7, &property.key);
+                            self.code.extend_from_slice(&[Push, R7]);
+                        }
+                    }
                 }
-                "/" => {
-                    self.gen_expression(&expr.left);
-                    self.gen_expression(&expr.right);
-                    self.code.extend_from_slice(&[Pop, R0]);
-                    self.code.extend_from_slice(&[Pop, R1]);
-                    self.code.extend_from_slice(&[Div, R0, R1]);
+                self.gen_load_temp(temp);
+            }
+            Expression::Array(array) => {
+                for element in array.elements.iter() {
+                    self.gen_expression(element)?;
                     self.code.extend_from_slice(&[Push, R0]);
                 }
-                "%" => {
-                    self.gen_expression(&expr.left);
-                    self.gen_expression(&expr.right);
-                    self.code.extend_from_slice(&[Pop, R0]);
-                    self.code.extend_from_slice(&[Pop, R1]);
-                    self.code.extend_from_slice(&[Mod, R0, R1]);
+                // signature: `[CreateArrayLiteral, element count]`, the elements on the stack
+                self.code.push(CreateArrayLiteral);
+                self.code
+                    .extend_from_slice(&Self::into_bytes(array.elements.len() as i64));
+            }
+
+            Expression::Member(expr) => {
+                self.gen_member(expr, false)?;
+            }
+
+            Expression::Function(function) => {
+                let function = Self::gen_function(function)?;
+                let id = self.constant_table.add_function(Rc::new(function));
+                self.code.push(CreateClosure);
+                self.code.extend_from_slice(&Self::into_bytes(id as i64));
+            }
+
+            // the stack of a call: `[callee, this, arguments...]`
+            Expression::Call(call_expr) => {
+                let op = match call_expr.callee.as_ref() {
+                    Expression::Member(member_expr) => {
+                        // the object is the receiver
+                        let object = self.gen_member(member_expr, true)?;
+                        self.code.extend_from_slice(&[Push, R0, Push, object]);
+                        CallProperty
+                    }
+                    callee => {
+                        self.gen_expression(callee)?;
+                        self.code
+                            .extend_from_slice(&[Push, R0, LdaUndefined, Push, R0]);
+                        CallAnyReceiver
+                    }
+                };
+                for argument in call_expr.arguments.iter() {
+                    self.gen_expression(argument)?;
                     self.code.extend_from_slice(&[Push, R0]);
                 }
-                _ => todo!(),
+
+                // signature: `[CallProperty/CallAnyReceiver, argument count]`, result in r0
+                self.code.push(op);
+                self.code
+                    .extend_from_slice(&Self::into_bytes(call_expr.arguments.len() as i64));
+            }
+
+            Expression::Binary(expr) => match expr.operator.as_str() {
+                "=" => self.gen_assignment(&expr.left, &expr.right)?,
+                "&&" | "||" | "??" => self.gen_logical(&expr.operator, &expr.left, &expr.right)?,
+                operator => {
+                    // `!=` and `!==` negate the equality test
+                    let (op, negate) = match operator {
+                        "+" => (Add, false),
+                        "-" => (Sub, false),
+                        "*" => (Mul, false),
+                        "/" => (Div, false),
+                        "%" => (Mod, false),
+                        "**" => (Exp, false),
+                        "|" => (BitwiseOr, false),
+                        "^" => (BitwiseXor, false),
+                        "&" => (BitwiseAnd, false),
+                        "<<" => (ShiftLeft, false),
+                        ">>" => (ShiftRight, false),
+                        ">>>" => (ShiftRightLogical, false),
+                        "==" => (TestEqual, false),
+                        "!=" => (TestEqual, true),
+                        "===" => (TestEqualStrict, false),
+                        "!==" => (TestEqualStrict, true),
+                        "<" => (TestLessThan, false),
+                        ">" => (TestGreaterThan, false),
+                        "<=" => (TestLessThanOrEqual, false),
+                        ">=" => (TestGreaterThanOrEqual, false),
+                        _ => return Err(Self::unexpected_token(operator)),
+                    };
+                    self.gen_expression(&expr.left)?;
+                    let (left, right) = self.gen_operand(&expr.right)?;
+                    self.code.extend_from_slice(&[op, left, right]);
+                    if negate {
+                        self.code.extend_from_slice(&[LogicalNot, R0]);
+                    }
+                }
             },
-            Expression::Identifier(name) => {
-                let name = name.as_bytes();
-                let len_bytes = (name.len() as i64).to_le_bytes();
+            Expression::Unary(expr) => {
+                let op = match expr.operator.as_str() {
+                    "-" => Negate,
+                    "!" => LogicalNot,
+                    "~" => BitwiseNot,
+                    "typeof" => TypeOf,
+                    operator => return Err(Self::unexpected_token(operator)),
+                };
+                self.gen_expression(&expr.right)?;
+                self.code.extend_from_slice(&[op, R0]);
+            }
+            Expression::Update(expr) => {
+                // postfix: the old value, converted to a number, is the result
+                let op = match expr.operator.as_str() {
+                    "++" => Inc,
+                    "--" => Dec,
+                    operator => return Err(Self::unexpected_token(operator)),
+                };
+                self.gen_name_op(LdaContextSlot, &expr.target_var_name);
+                self.code.extend_from_slice(&[ToNumeric, R0]);
+                self.gen_star(R7);
+                self.code.extend_from_slice(&[op, R0]);
+                self.gen_name_op(StaLookupSlot, &expr.target_var_name);
+                self.code.extend_from_slice(&[Ldar, R7]);
+            }
 
+            Expression::Identifier(name) => self.gen_name_op(LdaContextSlot, name),
+            Expression::This => {
+                // `this` is a slot of the function context, the global object at the top level
+                self.gen_name_op(LdaContextSlot, "this");
+            }
+        }
+        Ok(())
+    }
+
+    /// `r0 = object.property` or `r0 = object[property]`. with `receiver` the object is left
+    /// in the returned register until the next instruction that is not a `Push`.
+    fn gen_member(&mut self, expr: &MemberExpression, receiver: bool) -> Result<u8, VMError> {
+        self.gen_expression(&expr.object)?;
+        match expr.property.as_ref() {
+            Expression::String(name) => {
+                let object = match receiver {
+                    true => {
+                        self.gen_star(R7);
+                        R7
+                    }
+                    false => R0,
+                };
+
+                // signature: `[GetNamedProperty, object, name, feedback slot]`
+                let id = self.constant_table.add(name.clone());
+                let slot = self.feedback.add_load_slot();
+                self.code.extend_from_slice(&[GetNamedProperty, object]);
+                self.code.extend_from_slice(&Self::into_bytes(id as i64));
+                self.code.extend_from_slice(&Self::into_bytes(slot as i64));
+                Ok(object)
+            }
+            key => {
+                let (object, key) = match receiver {
+                    true => (self.gen_hold(key, R7)?, R0),
+                    false => self.gen_operand(key)?,
+                };
                 self.code
-                    .extend_from_slice(&[&[LdaContextSlot], &len_bytes[0..], name].concat());
-                self.code.extend_from_slice(&[Push, R0]);
+                    .extend_from_slice(&[GetKeyedProperty, object, key]);
+                Ok(object)
+            }
+        }
+    }
+
+    /// `reg.name = r0` through a new store feedback slot.
+    fn gen_set_named_property(&mut self, reg: u8, name: &str) {
+        // signature: `[SetNamedProperty, object, name, feedback slot]`, value in r0
+        let id = self.constant_table.add(name.to_string());
+        let slot = self.feedback.add_store_slot();
+        self.code.extend_from_slice(&[SetNamedProperty, reg]);
+        self.code.extend_from_slice(&Self::into_bytes(id as i64));
+        self.code.extend_from_slice(&Self::into_bytes(slot as i64));
+    }
+
+    /// `target = value`, the value is left in r0.
+    fn gen_assignment(&mut self, target: &Expression, value: &Expression) -> Result<(), VMError> {
+        match target {
+            Expression::Identifier(name) => {
+                self.gen_expression(value)?;
+                self.gen_name_op(StaLookupSlot, name);
             }
-            _ => todo!(),
+            Expression::Member(member) => {
+                self.gen_expression(&member.object)?;
+                match member.property.as_ref() {
+                    Expression::String(name) => {
+                        let object = self.gen_hold(value, R7)?;
+                        self.gen_set_named_property(object, name);
+                    }
+                    key => {
+                        // signature: `[SetKeyedProperty, object, key]`, value in r0
+                        let temp = self.gen_store_temp();
+                        self.gen_expression(key)?;
+                        let key = self.gen_hold(value, R7)?;
+                        let object = self.release_temp(temp, R6);
+                        self.code
+                            .extend_from_slice(&[SetKeyedProperty, object, key]);
+                    }
+                }
+            }
+            _ => return Err(Self::syntax_error("Invalid left-hand side in assignment")),
+        }
+        Ok(())
+    }
+
+    /// generate `right` while r0 is held, for an instruction `op left, right` with the
+    /// returned registers. a number or other immediate goes to r7 and leaves r0 alone.
+    fn gen_operand(&mut self, right: &Expression) -> Result<(u8, u8), VMError> {
+        let immediate = match right {
+            Expression::Undefined => Some(Value::undefined()),
+            Expression::Null => Some(Value::null()),
+            Expression::Boolean(b) => Some(Value::boolean(*b)),
+            Expression::Number(n) => Some(Value::number(*n)),
+            _ => None,
+        };
+        match immediate {
+            Some(v) => {
+                self.gen_mov(R7, v);
+                Ok((R0, R7))
+            }
+            None => Ok((self.gen_hold(right, R7)?, R0)),
+        }
+    }
+
+    /// generate `expr` while r0 is held, in the returned register. an expression that only
+    /// loads into r0 leaves `scratch` alone, so the value can wait there instead of in a
+    /// temporary.
+    fn gen_hold(&mut self, expr: &Expression, scratch: u8) -> Result<u8, VMError> {
+        let is_load = matches!(
+            expr,
+            Expression::Undefined
+                | Expression::Null
+                | Expression::Boolean(_)
+                | Expression::Number(_)
+                | Expression::String(_)
+                | Expression::Identifier(_)
+                | Expression::This
+                | Expression::Function(_)
+        );
+        if is_load {
+            self.gen_star(scratch);
+            self.gen_expression(expr)?;
+            return Ok(scratch);
+        }
+        let temp = self.gen_store_temp();
+        self.gen_expression(expr)?;
+        Ok(self.release_temp(temp, scratch))
+    }
+
+    /// where the temporary after `temps` others lives.
+    fn temp_at(temps: usize) -> Temp {
+        match temps < TEMP_REGISTERS {
+            true => Temp::Register(temps as u8 + 1),
+            false => Temp::Stack,
         }
     }
 
-    fn into_bytes(n: f64) -> [u8; 8] {
-        [
-            ((n as i64) & 0xff_i64) as u8,
-            ((n as i64 >> 8) & 0xff_i64) as u8,
-            ((n as i64 >> 16) & 0xff_i64) as u8,
-            ((n as i64 >> 24) & 0xff_i64) as u8,
-            ((n as i64 >> 32) & 0xff_i64) as u8,
-            ((n as i64 >> 40) & 0xff_i64) as u8,
-            ((n as i64 >> 48) & 0xff_i64) as u8,
-            ((n as i64 >> 56) & 0xff_i64) as u8,
-        ]
+    fn alloc_temp(&mut self) -> Temp {
+        let temp = Self::temp_at(self.temps);
+        self.temps += 1;
+        if let Temp::Stack = temp {
+            self.stack_depth += 1;
+        }
+        temp
+    }
+
+    /// hold r0 in a new temporary.
+    fn gen_store_temp(&mut self) -> Temp {
+        let temp = self.alloc_temp();
+        match temp {
+            Temp::Register(r) => self.gen_star(r),
+            Temp::Stack => self.code.extend_from_slice(&[Push, R0]),
+        }
+        temp
+    }
+
+    /// release the last temporary. the register returned holds its value until the next
+    /// temporary is allocated, a spilled one is popped into `scratch`.
+    fn release_temp(&mut self, temp: Temp, scratch: u8) -> u8 {
+        self.temps -= 1;
+        match temp {
+            Temp::Register(r) => r,
+            Temp::Stack => {
+                self.stack_depth -= 1;
+                self.code.extend_from_slice(&[Pop, scratch]);
+                scratch
+            }
+        }
+    }
+
+    /// release the last temporary into r0.
+    fn gen_load_temp(&mut self, temp: Temp) {
+        match temp {
+            Temp::Register(r) => {
+                self.temps -= 1;
+                self.code.extend_from_slice(&[Ldar, r]);
+            }
+            Temp::Stack => {
+                self.release_temp(temp, R0);
+            }
+        }
+    }
+
+    /// `reg = r0`
+    fn gen_star(&mut self, reg: u8) {
+        self.code.push(Star0 + reg);
+    }
+
+    /// `reg = v`
+    fn gen_mov(&mut self, reg: u8, v: Value) {
+        self.code.extend_from_slice(&[Mov, reg]);
+        self.code
+            .extend_from_slice(&Self::into_bytes(v.to_bits() as i64));
+    }
+
+    /// `&&`, `||` and `??` evaluate the right side only when the left one does not decide.
+    ///
+    ///   <left>
+    ///   JumpIfFalse/JumpIfTrue end
+    ///   <right>
+    /// end:
+    ///
+    /// `??` jumps over a `Jump end` to the right side instead.
+    fn gen_logical(
+        &mut self,
+        operator: &str,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<(), VMError> {
+        self.gen_expression(left)?;
+        let end_label = match operator {
+            "&&" => self.gen_jump(JumpIfFalse),
+            "||" => self.gen_jump(JumpIfTrue),
+            _ => {
+                let right_label = self.gen_jump(JumpIfUndefinedOrNull);
+                let end_label = self.gen_jump(Jump);
+                self.bind(right_label);
+                end_label
+            }
+        };
+        self.gen_expression(right)?;
+        self.bind(end_label);
+        Ok(())
+    }
+
+    /// an instruction whose operand is a name: `[op, length, bytes...]`.
+    fn gen_name_op(&mut self, op: u8, name: &str) {
+        let name = name.as_bytes();
+        self.code.push(op);
+        self.code
+            .extend_from_slice(&Self::into_bytes(name.len() as i64));
+        self.code.extend_from_slice(name);
+    }
+
+    fn unexpected_token(token: &str) -> VMError {
+        VMError::new(VMErrorKind::Syntax, format!("Unexpected token '{}'", token))
+    }
+
+    fn into_bytes(n: i64) -> [u8; 8] {
+        n.to_le_bytes()
     }
 }
@@ -1,4 +1,27 @@
-type Constant = String;
+use std::rc::Rc;
+
+use super::function::FunctionCode;
+
+/// constants are rust values, never `Value`s: `LdaConstant` allocates a fresh heap value from
+/// them, so the heap holds no pointer into the table and the table none into the heap, and it
+/// is not a root of the collector.
+pub(crate) enum Constant {
+    String(String),
+    /// a number literal that is not a smi
+    Number(f64),
+    Function(Rc<FunctionCode>),
+}
+
+impl Constant {
+    /// `true` for every constant. a variant holding a heap pointer has to be added to the
+    /// roots of `VM::collect_garbage`, and to answer `false` here until then.
+    pub(crate) fn holds_no_heap_pointer(&self) -> bool {
+        match self {
+            Constant::String(_) | Constant::Number(_) => true,
+            Constant::Function(f) => f.constant_table.iter().all(Self::holds_no_heap_pointer),
+        }
+    }
+}
 
 pub(crate) struct ConstantTable {
     table: Vec<Constant>,
@@ -9,17 +32,51 @@ impl ConstantTable {
         ConstantTable { table: vec![] }
     }
 
-    pub(crate) fn add(&mut self, constant: Constant) -> u32 {
+    pub(crate) fn add(&mut self, constant: String) -> u32 {
+        self.push(Constant::String(constant))
+    }
+
+    pub(crate) fn add_number(&mut self, n: f64) -> u32 {
+        self.push(Constant::Number(n))
+    }
+
+    pub(crate) fn add_function(&mut self, function: Rc<FunctionCode>) -> u32 {
+        self.push(Constant::Function(function))
+    }
+
+    fn push(&mut self, constant: Constant) -> u32 {
         let index = self.table.len();
         self.table.push(constant);
         index as u32
     }
 
-    pub(crate) fn get(&self, index: u32) -> &Constant {
-        &self.table[index as usize]
+    pub(crate) fn get(&self, index: u32) -> &str {
+        match &self.table[index as usize] {
+            Constant::String(s) => s,
+            _ => panic!("constant {} is not a string", index),
+        }
+    }
+
+    /// the number at `index`, `None` for other constants.
+    pub(crate) fn get_number(&self, index: u32) -> Option<f64> {
+        match &self.table[index as usize] {
+            Constant::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Constant> {
+        self.table.iter()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Constant> {
+        self.table.iter_mut()
     }
 
-    pub(crate) fn get_mut(&mut self, index: u32) -> &mut Constant {
-        &mut self.table[index as usize]
+    pub(crate) fn get_function(&self, index: u32) -> &Rc<FunctionCode> {
+        match &self.table[index as usize] {
+            Constant::Function(f) => f,
+            _ => panic!("constant {} is not a function", index),
+        }
     }
 }
@@ -1,45 +1,116 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::collections::HashSet;
+
+use super::{heap::Heap, objects::js_object::JSType, value::Value, VMError, VMErrorKind};
 
 pub(crate) struct ExecutionContext {
-    // this: JSObject,
-    pub(crate) context: Rc<RefCell<Context>>,
+    /// the innermost context, a heap object so closures can keep it alive
+    pub(crate) context: Value,
 }
 impl ExecutionContext {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(heap: &mut Heap) -> Self {
         ExecutionContext {
-            context: Rc::new(RefCell::new(Context::new(None))),
+            context: Context::create(heap, Value::undefined()).ok().unwrap(),
         }
     }
 
-    pub(crate) fn scope_in(&mut self) {
-        let new_context = Rc::new(RefCell::new(Context::new(Some(self.context.clone()))));
-        self.context = new_context;
+    pub(crate) fn scope_in(&mut self, heap: &mut Heap) -> Result<(), VMError> {
+        self.context = Context::create(heap, self.context)?;
+        Ok(())
     }
 
     pub(crate) fn scope_out(&mut self) {
-        let new_context = self.context.borrow().outer.clone().unwrap();
-        self.context = new_context
+        self.context = Context::of(self.context).outer;
+    }
+
+    /// leave every scope but the outermost one.
+    pub(crate) fn reset(&mut self) {
+        while !Context::of(self.context).outer.is_undefined() {
+            self.scope_out();
+        }
+    }
+
+    /// look `name` up in the current context, then in the outer contexts.
+    pub(crate) fn get(&self, name: &str) -> Option<Value> {
+        let mut context = self.context;
+        loop {
+            let object = context.as_object()?;
+            if let Some(v) = object.get_own(name) {
+                return Some(v);
+            }
+            context = Context::of(context).outer;
+        }
+    }
+
+    /// whether `name` is a constant declared in the current context.
+    pub(crate) fn is_constant(&self, name: &str) -> bool {
+        Context::of(self.context).constants.contains(name)
+    }
+
+    /// declare `name` in the current context.
+    pub(crate) fn set(&mut self, heap: &mut Heap, name: &str, value: Value) {
+        let object = self.context.as_object().unwrap();
+        heap.write_barrier(object, value);
+        object.set(name, value);
+    }
+
+    pub(crate) fn set_constant(&mut self, heap: &mut Heap, name: &str, value: Value) {
+        Context::of(self.context).constants.insert(name.to_string());
+        self.set(heap, name, value);
+    }
+
+    /// assign to the nearest declaration of `name`. like sloppy mode scripts, an undeclared
+    /// name is declared in the outermost context.
+    pub(crate) fn assign(
+        &mut self,
+        heap: &mut Heap,
+        name: &str,
+        value: Value,
+    ) -> Result<(), VMError> {
+        let mut context = self.context;
+        loop {
+            let object = context.as_object().unwrap();
+            let outer = Context::of(context).outer;
+            if object.get_own(name).is_some() || outer.is_undefined() {
+                if Context::of(context).constants.contains(name) {
+                    return Err(VMError::new(
+                        VMErrorKind::Type,
+                        "Assignment to constant variable.".to_string(),
+                    ));
+                }
+                heap.write_barrier(object, value);
+                object.set(name, value);
+                return Ok(());
+            }
+            context = outer;
+        }
+    }
+
+    /// visit the current context. it is a root of the garbage collector.
+    pub(crate) fn for_each_value(&mut self, mut f: impl FnMut(&mut Value)) {
+        f(&mut self.context);
     }
 }
 
-type ContextSlot = HashMap<String, i64>;
 pub(crate) struct Context {
-    slots: Rc<RefCell<ContextSlot>>,
-    outer: Option<Rc<RefCell<Context>>>,
+    pub(crate) outer: Value,
+    constants: HashSet<String>,
 }
 impl Context {
-    fn new(outer: Option<Rc<RefCell<Context>>>) -> Self {
-        Context {
-            slots: Rc::new(RefCell::new(HashMap::new())),
+    /// a new empty context inside `outer`.
+    pub(crate) fn create(heap: &mut Heap, outer: Value) -> Result<Value, VMError> {
+        let object = heap.alloc().ok_or_else(VMError::out_of_memory)?;
+        heap.write_barrier(object, outer);
+        object._type = JSType::Context(Context {
             outer,
-        }
+            constants: HashSet::new(),
+        });
+        Ok(Value::from(object))
     }
 
-    pub(crate) fn set(&self, name: String, value: i64) {
-        self.slots.borrow_mut().insert(name, value);
-    }
-
-    pub(crate) fn get(&self, name: &str) -> Option<i64> {
-        self.slots.borrow().get(name).copied()
+    fn of<'a>(context: Value) -> &'a mut Context {
+        match context.as_object().map(|o| &mut o._type) {
+            Some(JSType::Context(context)) => context,
+            _ => unreachable!("not a context"),
+        }
     }
 }
@@ -0,0 +1,581 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use super::{
+    bytecodes::decode,
+    constant_table::{Constant, ConstantTable},
+    context::Context,
+    disassembler::Listing,
+    function::FunctionCode,
+    objects::js_object::JSType,
+    value::Value,
+    VMError, VMErrorKind, VirtualMachine,
+};
+
+const HELP: &str = "\
+break <line>              stop at the first instruction of a source line
+break [<function>]@<pc>   stop at an offset of the script, or of a function named as in
+                          --print-bytecode (`0.1@12`)
+delete [<n>]              delete breakpoint n, or every breakpoint
+breakpoints               list the breakpoints
+step                      run one instruction
+next                      run to the next statement
+continue                  run to the next breakpoint
+registers                 print the registers
+stack                     print the stack
+context                   print the variables of the current context and its outer ones
+print <address>           print the heap object at an address
+list                      print the code around the current instruction
+quit                      stop the script
+";
+
+/// how far the code runs before the next prompt.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    Next,
+    Continue,
+}
+
+enum Breakpoint {
+    Line(u32),
+    /// an offset in a function, `None` for the script of `run`
+    Offset {
+        function: Option<Rc<FunctionCode>>,
+        name: String,
+        offset: usize,
+    },
+}
+
+/// the debugger of `gls --vm --debug`. the vm asks it before each instruction whether to stop,
+/// and then reads commands until one of them resumes the code. it stops at the first
+/// instruction of the script.
+pub(crate) struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    breakpoints: Vec<(usize, Breakpoint)>,
+    next_breakpoint: usize,
+    mode: Mode,
+}
+
+impl Debugger {
+    pub(crate) fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Debugger {
+            input,
+            output,
+            breakpoints: Vec::new(),
+            next_breakpoint: 0,
+            mode: Mode::Step,
+        }
+    }
+
+    /// a debugger on the terminal.
+    pub(crate) fn stdio() -> Self {
+        Debugger::new(Box::new(io::stdin().lock()), Box::new(io::stdout()))
+    }
+
+    /// stop before the instruction at `vm.pc` if a breakpoint or the last command says so. the
+    /// error of `quit` terminates the code.
+    pub(crate) fn pause(&mut self, vm: &mut VirtualMachine) -> Result<(), VMError> {
+        if vm.pc >= vm.current_code().len() {
+            return Ok(());
+        }
+        let hit = self.breakpoints.iter().find(|(_, b)| match b {
+            Breakpoint::Line(line) => statement_line(vm) == Some(*line),
+            Breakpoint::Offset {
+                function, offset, ..
+            } => *offset == vm.pc && same_function(function, &vm.function),
+        });
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next => statement_line(vm).is_some(),
+            Mode::Continue => hit.is_some(),
+        };
+        if !stop && hit.is_none() {
+            return Ok(());
+        }
+
+        let mut text = String::new();
+        if let Some((n, _)) = hit {
+            let _ = writeln!(text, "breakpoint {}", n);
+        }
+        text.push_str(&where_text(vm));
+        self.print(&text);
+
+        loop {
+            self.print("(debug) ");
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Err(quit()),
+                Ok(_) => {}
+            }
+            if let Some(mode) = self.command(vm, line.trim())? {
+                self.mode = mode;
+                return Ok(());
+            }
+        }
+    }
+
+    /// run a command. `Some` resumes the code.
+    fn command(&mut self, vm: &VirtualMachine, line: &str) -> Result<Option<Mode>, VMError> {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        let text = match command {
+            "" => return Ok(None),
+            "s" | "step" => return Ok(Some(Mode::Step)),
+            "n" | "next" => return Ok(Some(Mode::Next)),
+            "c" | "continue" => return Ok(Some(Mode::Continue)),
+            "q" | "quit" => return Err(quit()),
+            "b" | "break" => self.add_breakpoint(vm, argument),
+            "d" | "delete" => self.delete_breakpoint(argument),
+            "breakpoints" => self.breakpoints_text(),
+            "r" | "registers" => registers_text(vm),
+            "stack" => stack_text(vm),
+            "context" => context_text(vm),
+            "p" | "print" => object_text(vm, argument),
+            "l" | "list" => list_text(vm),
+            "h" | "help" => HELP.to_string(),
+            _ => format!("unknown command '{}', try help\n", command),
+        };
+        self.print(&text);
+        Ok(None)
+    }
+
+    fn add_breakpoint(&mut self, vm: &VirtualMachine, argument: &str) -> String {
+        let breakpoint = match argument.split_once('@') {
+            None => match argument.parse() {
+                Ok(line) => Breakpoint::Line(line),
+                Err(_) => return format!("expected a line or [<function>]@<pc>: '{}'\n", argument),
+            },
+            Some((path, offset)) => {
+                let Ok(offset) = offset.parse() else {
+                    return format!("expected an offset: '{}'\n", offset);
+                };
+                let script = script(vm);
+                let function = match path {
+                    "" => script.clone(),
+                    _ => match function_at(root_constants(vm, &script), path) {
+                        Some(function) => Some(function),
+                        None => return format!("no function {}\n", path),
+                    },
+                };
+                let name = match path {
+                    "" => "script".to_string(),
+                    _ => format!("function {}", path),
+                };
+                let code = match &function {
+                    Some(function) => &function.code,
+                    None => &vm.code,
+                };
+                if !is_instruction(code, offset) {
+                    return format!("no instruction at {}@{}\n", name, offset);
+                }
+                Breakpoint::Offset {
+                    function,
+                    name,
+                    offset,
+                }
+            }
+        };
+        let n = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        let text = format!("breakpoint {} at {}\n", n, breakpoint_text(&breakpoint));
+        self.breakpoints.push((n, breakpoint));
+        text
+    }
+
+    fn delete_breakpoint(&mut self, argument: &str) -> String {
+        if argument.is_empty() {
+            self.breakpoints.clear();
+            return String::new();
+        }
+        match argument.parse() {
+            Ok(n) if self.breakpoints.iter().any(|(i, _)| *i == n) => {
+                self.breakpoints.retain(|(i, _)| *i != n);
+                String::new()
+            }
+            _ => format!("no breakpoint {}\n", argument),
+        }
+    }
+
+    fn breakpoints_text(&self) -> String {
+        let mut text = String::new();
+        for (n, breakpoint) in self.breakpoints.iter() {
+            let _ = writeln!(text, "{:>3} {}", n, breakpoint_text(breakpoint));
+        }
+        if text.is_empty() {
+            text.push_str("no breakpoints\n");
+        }
+        text
+    }
+
+    fn print(&mut self, text: &str) {
+        let _ = self.output.write_all(text.as_bytes());
+        let _ = self.output.flush();
+    }
+}
+
+fn quit() -> VMError {
+    VMError::new(
+        VMErrorKind::Terminated,
+        "terminated by the debugger".to_string(),
+    )
+}
+
+fn breakpoint_text(breakpoint: &Breakpoint) -> String {
+    match breakpoint {
+        Breakpoint::Line(line) => format!("line {}", line),
+        Breakpoint::Offset { name, offset, .. } => format!("{}@{}", name, offset),
+    }
+}
+
+fn same_function(a: &Option<Rc<FunctionCode>>, b: &Option<Rc<FunctionCode>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// whether an instruction starts at `offset`.
+fn is_instruction(code: &[u8], offset: usize) -> bool {
+    let mut pc = 0;
+    while pc < offset {
+        match decode(code, pc) {
+            Ok(instruction) => pc += instruction.len,
+            Err(_) => return false,
+        }
+    }
+    pc == offset && offset < code.len()
+}
+
+/// the line of the statement that starts at `vm.pc`, `None` inside a statement.
+fn statement_line(vm: &VirtualMachine) -> Option<u32> {
+    let entries = match &vm.function {
+        Some(function) => function.source_positions.entries(),
+        None => vm.source_positions.entries(),
+    };
+    entries
+        .iter()
+        .find(|&&(offset, _)| offset == vm.pc)
+        .map(|&(_, line)| line)
+}
+
+/// the compiled script the running code belongs to, `None` for the code of `run`.
+fn script(vm: &VirtualMachine) -> Option<Rc<FunctionCode>> {
+    match vm.frames.first() {
+        Some(frame) => frame.function.clone(),
+        None => vm.function.clone(),
+    }
+}
+
+fn root_constants<'a>(
+    vm: &'a VirtualMachine,
+    script: &'a Option<Rc<FunctionCode>>,
+) -> &'a ConstantTable {
+    match script {
+        Some(script) => &script.constant_table,
+        None => &vm.constant_table,
+    }
+}
+
+/// the function at a path of constant indices like `0.1`.
+fn function_at(constant_table: &ConstantTable, path: &str) -> Option<Rc<FunctionCode>> {
+    let mut function: Option<Rc<FunctionCode>> = None;
+    for index in path.split('.') {
+        let index: usize = index.parse().ok()?;
+        let table = function
+            .as_ref()
+            .map_or(constant_table, |f| &f.constant_table);
+        let next = match table.iter().nth(index)? {
+            Constant::Function(f) => f.clone(),
+            _ => return None,
+        };
+        function = Some(next);
+    }
+    function
+}
+
+/// the path of constant indices of `function`.
+fn path_of(constant_table: &ConstantTable, function: &Rc<FunctionCode>) -> Option<Vec<usize>> {
+    for (i, constant) in constant_table.iter().enumerate() {
+        if let Constant::Function(f) = constant {
+            if Rc::ptr_eq(f, function) {
+                return Some(vec![i]);
+            }
+            if let Some(mut path) = path_of(&f.constant_table, function) {
+                path.insert(0, i);
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// `script` or `function 0.1`, as --print-bytecode names the running code.
+fn code_name(vm: &VirtualMachine) -> String {
+    let script = script(vm);
+    let Some(function) = &vm.function else {
+        return "script".to_string();
+    };
+    if same_function(&script, &vm.function) {
+        return "script".to_string();
+    }
+    match path_of(root_constants(vm, &script), function) {
+        Some(path) => {
+            let path: Vec<String> = path.iter().map(|i| i.to_string()).collect();
+            format!("function {}", path.join("."))
+        }
+        None => "function ?".to_string(),
+    }
+}
+
+/// the listing of the running code, which the instruction at `vm.pc` is in.
+fn listing(vm: &VirtualMachine) -> String {
+    let listing = match &vm.function {
+        Some(function) => Listing::of(function),
+        None => Listing {
+            code: &vm.code,
+            constant_table: &vm.constant_table,
+            handler_table: &vm.handler_table,
+            source_positions: &vm.source_positions,
+        },
+    };
+    listing.disassemble_code(&code_name(vm))
+}
+
+/// the offset of an instruction line of a listing.
+fn line_offset(line: &str) -> Option<usize> {
+    line.get(6..)?.split(' ').next()?.parse().ok()
+}
+
+/// where the code stopped, and the instruction it stopped at.
+fn where_text(vm: &VirtualMachine) -> String {
+    let listing = listing(vm);
+    let instruction = listing
+        .lines()
+        .find(|&line| line_offset(line) == Some(vm.pc))
+        .unwrap_or_default();
+    let line = match &vm.function {
+        Some(function) => function.source_positions.lookup(vm.pc),
+        None => vm.source_positions.lookup(vm.pc),
+    };
+    let line = line.map_or(String::new(), |line| format!(", line {}", line));
+    format!("{}@{}{}\n=> {}\n", code_name(vm), vm.pc, line, instruction)
+}
+
+fn list_text(vm: &VirtualMachine) -> String {
+    let listing = listing(vm);
+    let lines: Vec<&str> = listing
+        .lines()
+        .filter(|&line| line_offset(line).is_some())
+        .collect();
+    let at = lines
+        .iter()
+        .position(|&line| line_offset(line) == Some(vm.pc))
+        .unwrap_or(0);
+    let mut text = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i + 4 >= at && i <= at + 5 {
+            let marker = if i == at { "=>" } else { "  " };
+            let _ = writeln!(text, "{} {}", marker, line);
+        }
+    }
+    text
+}
+
+/// a value with the kind of the object it points to.
+fn describe(v: Value) -> String {
+    match v.as_object() {
+        Some(object) => format!("{:?} {:?}", v, object._type),
+        None => format!("{:?}", v),
+    }
+}
+
+fn registers_text(vm: &VirtualMachine) -> String {
+    let mut text = String::new();
+    for (i, v) in vm.register.values().into_iter().enumerate() {
+        let _ = writeln!(text, "r{}  {}", i, describe(v));
+    }
+    text
+}
+
+fn stack_text(vm: &VirtualMachine) -> String {
+    let mut text = String::new();
+    for (i, v) in vm.stack.iter().enumerate().rev() {
+        let _ = writeln!(text, "{:>4}  {}", i, describe(*v));
+    }
+    if text.is_empty() {
+        text.push_str("the stack is empty\n");
+    }
+    let _ = writeln!(text, "{} frames", vm.frames.len());
+    text
+}
+
+/// the slots of the current context, then of the contexts around it.
+fn context_text(vm: &VirtualMachine) -> String {
+    let mut text = String::new();
+    let mut context = vm.execution_context.context;
+    let mut depth = 0;
+    while let Some(object) = context.as_object() {
+        let _ = writeln!(text, "context {} {:?}", depth, context);
+        for (key, v) in object.shape.keys().iter().zip(object.slots.iter()) {
+            let _ = writeln!(text, "  {}: {}", key, describe(*v));
+        }
+        context = match &object._type {
+            JSType::Context(Context { outer, .. }) => *outer,
+            _ => break,
+        };
+        depth += 1;
+    }
+    text
+}
+
+fn object_text(vm: &VirtualMachine, argument: &str) -> String {
+    let address = match argument.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => argument.parse(),
+    };
+    let Ok(address) = address else {
+        return format!("expected an address: '{}'\n", argument);
+    };
+    if !vm.heap.contains(address) {
+        return format!("no object at {:#x}\n", address);
+    }
+
+    let object = Value::object(address).as_object().unwrap();
+    let mut text = format!("{:#x} {:?}\n", address, object._type);
+    match &object._type {
+        JSType::Array(elements) => {
+            for (i, v) in elements.iter().enumerate() {
+                let _ = writeln!(text, "  [{}]: {}", i, describe(*v));
+            }
+        }
+        JSType::Function(closure) => {
+            let _ = writeln!(text, "  parameters: {}", closure.code.parameters.join(", "));
+            let _ = writeln!(text, "  context: {}", describe(closure.context));
+        }
+        JSType::Context(context) => {
+            let _ = writeln!(text, "  outer: {}", describe(context.outer));
+        }
+        _ => {}
+    }
+    for (key, v) in object.shape.keys().iter().zip(object.slots.iter()) {
+        let _ = writeln!(text, "  {}: {}", key, describe(*v));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Cursor};
+
+    use super::*;
+    use crate::engine::parsing::BuiltinParser;
+
+    /// what the debugger wrote, kept after the vm is done with it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn debug(vm: &mut VirtualMachine, commands: &str) -> Output {
+        let output = Output::default();
+        let input = Cursor::new(commands.as_bytes().to_vec());
+        vm.set_debugger(Debugger::new(Box::new(input), Box::new(output.clone())));
+        output
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let output = debug(
+            &mut vm,
+            "b 4\nb 9.9@0\nbreakpoints\nc\ncontext\nregisters\nn\ns\nq\n",
+        );
+        let source = "let a = 1;
+const f = function (x) {
+  let y = x * 2;
+  return y + a;
+};
+let b = f(20);
+b + 1;
+";
+        let error = vm.exec(source.to_string()).err().unwrap();
+        assert_eq!(error.to_string(), "terminated by the debugger");
+
+        let text = output.text();
+        let stops: Vec<&str> = text
+            .lines()
+            .filter(|l| l.contains("@") && l.contains(", line "))
+            .collect();
+        // the first instruction, the breakpoint, the next statement and one instruction on
+        assert_eq!(stops.len(), 4, "{}", text);
+        assert_eq!(stops[0], "script@0, line 1");
+        assert!(stops[1].starts_with("function ") && stops[1].ends_with(", line 4"));
+        assert!(stops[2].ends_with(", line 7") && stops[2].contains("script@"));
+        assert!(stops[3].ends_with(", line 7"));
+
+        assert!(text.contains("breakpoint 0 at line 4\n"));
+        assert!(text.contains("no function 9.9\n"));
+        assert!(text.contains("  0 line 4\n"));
+        assert!(text.contains("breakpoint 0\nfunction "));
+        // the function's context is inside the global one
+        assert!(text.contains("context 0 Object(") && text.contains("context 1 Object("));
+        assert!(text.contains("  x: Smi(20)\n  y: Smi(40)\n"));
+        assert!(text.contains("  a: Smi(1)\n"));
+        assert!(text.contains("r0  ") && text.contains("\nr7  "));
+    }
+
+    #[test]
+    fn test_inspect() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        vm.exec("let o = { x: 'y', z: [1] };".to_string())
+            .ok()
+            .unwrap();
+        let o = vm.execution_context.get("o").unwrap();
+        let address = o.as_object_ptr().unwrap();
+
+        // the new code is appended to the old
+        let start = vm.code.len();
+        let commands = format!(
+            "p {:#x}\np 0x10\nb @{}\nb @{}\nstack\nl\nd 0\nd 0\nbreakpoints\nwat\nc\n",
+            address,
+            start + 1,
+            start
+        );
+        let output = debug(&mut vm, &commands);
+        vm.exec("o.x;".to_string()).ok().unwrap();
+
+        let text = output.text();
+        assert!(text.starts_with(&format!("script@{}, line 1\n=> ", start)));
+        assert!(text.contains(&format!("{:#x} Object\n  __proto__: Object(0x", address)));
+        assert!(text.contains("\n  x: Object(0x"));
+        assert!(text.contains(" String(y)\n  z: Object(0x"));
+        assert!(text.contains("no object at 0x10\n"));
+        // an offset inside an instruction never runs
+        assert!(text.contains(&format!("no instruction at script@{}\n", start + 1)));
+        assert!(text.contains(&format!("breakpoint 0 at script@{}\n", start)));
+        assert!(text.contains("the stack is empty\n0 frames\n"));
+        assert!(text.contains(&format!("=>       {:04}       LdaContextSlot", start)));
+        assert!(text.contains("no breakpoint 0\n(debug) no breakpoints\n"));
+        assert!(text.contains("unknown command 'wat', try help\n"));
+    }
+}
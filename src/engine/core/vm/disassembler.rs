@@ -0,0 +1,344 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::engine::parsing::{BuiltinParser, Parser};
+
+use super::{
+    bytecodes::{decode, name, operands, Bytecodes, DecodeError, Instruction, Operand},
+    codegen::CodeGenerator,
+    constant_table::{Constant, ConstantTable},
+    function::FunctionCode,
+    handler_table::HandlerTable,
+    optimizer::{optimize_function, Passes},
+    source_positions::SourcePositionTable,
+    value::Value,
+};
+
+/// the listing of the script compiled from `source` with `passes`, and of its functions: an
+/// instruction a line with the line of the source it came from, jump targets as labels and
+/// constants resolved inline.
+pub fn disassemble_script(source: &str, passes: Passes) -> Result<String, String> {
+    let program = BuiltinParser
+        .try_parse(source.to_string())
+        .map_err(|e| format!("SyntaxError: {}", e))?;
+    let mut script = CodeGenerator::gen_script(&program).map_err(|e| e.to_string())?;
+    optimize_function(&mut script, passes);
+    Ok(disassemble(&script))
+}
+
+/// the listing of a script and of the functions in its constants, which are named by their
+/// path of constant indices like the errors of the verifier.
+pub(crate) fn disassemble(script: &FunctionCode) -> String {
+    Listing::of(script).disassemble()
+}
+
+fn write_functions(out: &mut String, constant_table: &ConstantTable, path: &mut Vec<u32>) {
+    for (i, constant) in constant_table.iter().enumerate() {
+        if let Constant::Function(f) = constant {
+            path.push(i as u32);
+            let path_text: Vec<String> = path.iter().map(|i| i.to_string()).collect();
+            let title = format!(
+                "function {}({})",
+                path_text.join("."),
+                f.parameters.join(", ")
+            );
+            out.push('\n');
+            Listing::of(f).write(out, &title);
+            write_functions(out, &f.constant_table, path);
+            path.pop();
+        }
+    }
+}
+
+/// a code and the tables that describe it.
+pub(crate) struct Listing<'a> {
+    pub(crate) code: &'a [u8],
+    pub(crate) constant_table: &'a ConstantTable,
+    pub(crate) handler_table: &'a HandlerTable,
+    pub(crate) source_positions: &'a SourcePositionTable,
+}
+
+impl<'a> Listing<'a> {
+    pub(crate) fn of(function: &'a FunctionCode) -> Self {
+        Listing {
+            code: &function.code,
+            constant_table: &function.constant_table,
+            handler_table: &function.handler_table,
+            source_positions: &function.source_positions,
+        }
+    }
+
+    /// the listing of the code as a script, followed by its functions.
+    pub(crate) fn disassemble(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, "script");
+        write_functions(&mut out, self.constant_table, &mut vec![]);
+        out
+    }
+
+    /// the listing of the code alone, under `title`.
+    pub(crate) fn disassemble_code(&self, title: &str) -> String {
+        let mut out = String::new();
+        self.write(&mut out, title);
+        out
+    }
+
+    /// the instructions by their offset, up to the first byte that does not decode.
+    fn decode(&self) -> Result<Vec<(usize, Instruction)>, (usize, DecodeError)> {
+        let mut instructions = vec![];
+        let mut pc = 0;
+        while pc < self.code.len() {
+            let instruction = decode(self.code, pc).map_err(|e| (pc, e))?;
+            let len = instruction.len;
+            instructions.push((pc, instruction));
+            pc += len;
+        }
+        Ok(instructions)
+    }
+
+    fn write(&self, out: &mut String, title: &str) {
+        let constants: Vec<&Constant> = self.constant_table.iter().collect();
+        let (instructions, error) = match self.decode() {
+            Ok(instructions) => (instructions, None),
+            Err((pc, e)) => {
+                // list what does decode
+                let code = Listing {
+                    code: &self.code[..pc],
+                    ..*self
+                };
+                (code.decode().unwrap_or_default(), Some((pc, e)))
+            }
+        };
+
+        // the jump targets and exception handlers, numbered in the order of the code
+        let mut labels: BTreeMap<usize, usize> = BTreeMap::new();
+        for (pc, instruction) in instructions.iter() {
+            if let Some(target) = jump_target(*pc, instruction) {
+                labels.insert(target, 0);
+            }
+        }
+        for entry in self.handler_table.entries() {
+            labels.insert(entry.handler, 0);
+        }
+        for (i, label) in labels.values_mut().enumerate() {
+            *label = i;
+        }
+        let label = |offset: usize| match labels.get(&offset) {
+            Some(label) => format!("L{}", label),
+            None => format!("@{}", offset),
+        };
+
+        let _ = writeln!(out, "{} ({} bytes)", title, self.code.len());
+        let mut line = None;
+        for (pc, instruction) in instructions.iter() {
+            let source_line = self.source_positions.lookup(*pc);
+            let column = match source_line {
+                Some(n) if source_line != line => format!("{:>5}", n),
+                _ => String::new(),
+            };
+            line = source_line;
+            let target = match labels.get(pc) {
+                Some(label) => format!("L{}:", label),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                out,
+                "{:>5} {:04} {:<5} {}",
+                column,
+                pc,
+                target,
+                self.instruction(*pc, instruction, &constants, &label)
+            );
+        }
+        if let Some(label) = labels.get(&self.code.len()) {
+            let _ = writeln!(
+                out,
+                "{:>5} {:04} {:<5} <end>",
+                "",
+                self.code.len(),
+                format!("L{}:", label)
+            );
+        }
+        match error {
+            Some((pc, DecodeError::UnknownOpcode)) => {
+                let _ = writeln!(
+                    out,
+                    "{:>5} {:04}       <unknown opcode 0x{:02x}>",
+                    "", pc, self.code[pc]
+                );
+            }
+            Some((pc, DecodeError::Truncated)) => {
+                let _ = writeln!(
+                    out,
+                    "{:>5} {:04}       <{} runs past the end of the code>",
+                    "",
+                    pc,
+                    name(self.code[pc]).unwrap()
+                );
+            }
+            None => {}
+        }
+
+        if !constants.is_empty() {
+            let _ = writeln!(out, "constants:");
+            for i in 0..constants.len() {
+                let _ = writeln!(out, "{:>11} {}", i, constant_text(i as i64, &constants));
+            }
+        }
+        if !self.handler_table.entries().is_empty() {
+            let _ = writeln!(out, "handlers:");
+            for entry in self.handler_table.entries() {
+                let _ = writeln!(
+                    out,
+                    "{:>11}..{} -> {} (stack {}, contexts {})",
+                    entry.start,
+                    entry.end,
+                    label(entry.handler),
+                    entry.stack_depth,
+                    entry.context_depth
+                );
+            }
+        }
+    }
+
+    fn instruction(
+        &self,
+        pc: usize,
+        instruction: &Instruction,
+        constants: &[&Constant],
+        label: &dyn Fn(usize) -> String,
+    ) -> String {
+        let kinds = operands(instruction.opcode).unwrap();
+        let operands: Vec<String> = kinds
+            .iter()
+            .zip(instruction.operands.iter())
+            .map(|(&kind, &n)| match kind {
+                Operand::Register => format!("r{}", n),
+                Operand::Value => format!("{:?}", Value::from_bits(n as u64)),
+                Operand::Immediate | Operand::Count => n.to_string(),
+                Operand::Constant => format!("[{}] {}", n, constant_text(n, constants)),
+                Operand::LoadSlot | Operand::StoreSlot => format!("slot {}", n),
+                Operand::Offset => match jump_target(pc, instruction) {
+                    Some(target) => label(target),
+                    None => format!("<offset {}>", n),
+                },
+                Operand::Name => {
+                    let end = pc + instruction.len;
+                    let bytes = &self.code[end - n as usize..end];
+                    format!("{:?}", String::from_utf8_lossy(bytes))
+                }
+            })
+            .collect();
+
+        let name = name(instruction.opcode).unwrap();
+        match operands.is_empty() {
+            true => name.to_string(),
+            false => format!("{} {}", name, operands.join(", ")),
+        }
+    }
+}
+
+/// the offset a jump goes to, `None` for other instructions or an offset out of the code.
+fn jump_target(pc: usize, instruction: &Instruction) -> Option<usize> {
+    if operands(instruction.opcode)? != [Operand::Offset] {
+        return None;
+    }
+    let end = (pc + instruction.len) as i64;
+    let target = match instruction.opcode {
+        Bytecodes::JumpLoop => end.checked_sub(instruction.operands[0])?,
+        _ => end.checked_add(instruction.operands[0])?,
+    };
+    usize::try_from(target).ok()
+}
+
+fn constant_text(index: i64, constants: &[&Constant]) -> String {
+    match usize::try_from(index).ok().and_then(|i| constants.get(i)) {
+        Some(Constant::String(s)) => format!("{:?}", s),
+        Some(Constant::Number(n)) => n.to_string(),
+        Some(Constant::Function(f)) => format!("<function({})>", f.parameters.join(", ")),
+        None => "<out of range>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::engine::core::vm::{feedback::FeedbackVector, jit::JitState};
+
+    #[test]
+    fn test_disassemble_script() {
+        let source = "let a = 'x';
+for (let i = 0; i < 2; i++) {
+  a = a + i;
+}
+const f = function (b) { return b.length; };
+try { f(a); } catch (e) {}
+";
+        let listing = disassemble_script(source, Passes::ALL).unwrap();
+        let lines: Vec<&str> = listing.lines().collect();
+        let has = |text: &str| lines.iter().any(|l| l.ends_with(text));
+
+        assert!(lines[0].starts_with("script ("));
+        assert_eq!(lines[1], "    1 0000       LdaConstant [0] \"x\"");
+        assert!(lines.contains(&"      0009       StaContextSlot \"a\""));
+        // the loop jumps back to its test and leaves past its body
+        assert!(lines
+            .iter()
+            .any(|l| l.ends_with("L0:   LdaContextSlot \"i\"")));
+        assert!(has("JumpIfFalse L1") && has("JumpLoop L0"));
+        // the left side waits in a register, the number is an immediate
+        assert!(has("Star7") && has("Add r7, r0") && has("TestLessThan r0, r7"));
+        assert!(lines.iter().any(|l| l.starts_with("    3 ")));
+        assert!(has("CreateClosure [1] <function(b)>"));
+        assert!(lines
+            .iter()
+            .any(|l| l.contains(" -> L2 (stack 0, contexts 0)")));
+
+        let f = lines.iter().position(|&l| l.starts_with("function 1(b) ("));
+        let f = f.unwrap_or_else(|| panic!("{}", listing));
+        assert_eq!(lines[f + 1], "    5 0000       LdaContextSlot \"b\"");
+        assert!(has("GetNamedProperty r0, [0] \"length\", slot 0"));
+
+        assert!(disassemble_script("let = 1;", Passes::ALL)
+            .unwrap_err()
+            .starts_with("SyntaxError: "));
+    }
+
+    #[test]
+    fn test_every_opcode() {
+        let mut code = vec![];
+        for opcode in 0..=u8::MAX {
+            if let Some(kinds) = operands(opcode) {
+                code.push(opcode);
+                for kind in kinds {
+                    match kind {
+                        Operand::Register => code.push(1),
+                        Operand::Name => code.extend_from_slice(&[0; 8]),
+                        _ => code.extend_from_slice(&0i64.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        let listing = disassemble(&FunctionCode {
+            parameters: vec![],
+            code,
+            constant_table: ConstantTable::new(),
+            feedback: RefCell::new(FeedbackVector::new()),
+            handler_table: HandlerTable::new(),
+            source_positions: SourcePositionTable::new(),
+            jit: RefCell::new(JitState::default()),
+        });
+        for opcode in 0..=u8::MAX {
+            if let Some(name) = name(opcode) {
+                let found = listing.lines().any(|l| {
+                    l.split_whitespace().nth(1) == Some(name)
+                        || l.split_whitespace().nth(2) == Some(name)
+                });
+                assert!(found, "{} is missing from\n{}", name, listing);
+            }
+        }
+        assert!(listing.contains("StaLookupSlot \"\""));
+        assert!(listing.contains("LdaConstant [0] <out of range>"));
+    }
+}
@@ -0,0 +1,261 @@
+use std::rc::Rc;
+
+use super::objects::{js_object::JSObject, shape::Shape};
+use super::value::Value;
+
+/// shapes a site caches before it gives up and always takes the slow path.
+const MAX_POLYMORPHISM: usize = 4;
+
+/// an inline cache of a property access site, keyed by the shape of the receiver.
+pub(crate) enum InlineCache<H> {
+    Uninitialized,
+    Monomorphic(H),
+    Polymorphic(Vec<H>),
+    Megamorphic,
+}
+
+pub(crate) trait Handler {
+    fn receiver_shape(&self) -> &Rc<Shape>;
+}
+
+impl<H: Handler> InlineCache<H> {
+    fn handlers(&self) -> &[H] {
+        match self {
+            InlineCache::Monomorphic(h) => std::slice::from_ref(h),
+            InlineCache::Polymorphic(hs) => hs,
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn lookup(&self, shape: &Rc<Shape>) -> Option<&H> {
+        self.handlers()
+            .iter()
+            .find(|h| Rc::ptr_eq(h.receiver_shape(), shape))
+    }
+
+    /// record `handler` after a miss. a handler for the same receiver shape is replaced.
+    pub(crate) fn update(&mut self, handler: H) {
+        let state = std::mem::replace(self, InlineCache::Megamorphic);
+        *self = match state {
+            InlineCache::Uninitialized => InlineCache::Monomorphic(handler),
+            InlineCache::Monomorphic(h)
+                if Rc::ptr_eq(h.receiver_shape(), handler.receiver_shape()) =>
+            {
+                InlineCache::Monomorphic(handler)
+            }
+            InlineCache::Monomorphic(h) => InlineCache::Polymorphic(vec![h, handler]),
+            InlineCache::Polymorphic(mut hs) => {
+                hs.retain(|h| !Rc::ptr_eq(h.receiver_shape(), handler.receiver_shape()));
+                if hs.len() < MAX_POLYMORPHISM {
+                    hs.push(handler);
+                    InlineCache::Polymorphic(hs)
+                } else {
+                    InlineCache::Megamorphic
+                }
+            }
+            InlineCache::Megamorphic => InlineCache::Megamorphic,
+        };
+    }
+
+    pub(crate) fn is_megamorphic(&self) -> bool {
+        matches!(self, InlineCache::Megamorphic)
+    }
+}
+
+/// how to load a property: the shapes from the receiver along the prototype chain up to
+/// the object that holds it, and its slot there.
+pub(crate) struct LoadHandler {
+    shapes: Vec<Rc<Shape>>,
+    slot: usize,
+}
+
+impl Handler for LoadHandler {
+    fn receiver_shape(&self) -> &Rc<Shape> {
+        &self.shapes[0]
+    }
+}
+
+impl LoadHandler {
+    /// look `key` up the slow way.
+    pub(crate) fn compute(object: &JSObject, key: &str) -> Option<(Self, Value)> {
+        let mut shapes = Vec::new();
+        let mut object = object;
+        loop {
+            shapes.push(object.shape.clone());
+            if let Some(slot) = object.shape.lookup(key) {
+                return Some((LoadHandler { shapes, slot }, object.slots[slot]));
+            }
+            object = object.prototype()?;
+        }
+    }
+
+    /// the value, if every object on the way still has the cached shape.
+    pub(crate) fn load(&self, object: &JSObject) -> Option<Value> {
+        let (holder, path) = self.shapes.split_last().unwrap();
+        let mut object = object;
+        for shape in path {
+            if !Rc::ptr_eq(&object.shape, shape) {
+                return None;
+            }
+            let prototype = object.slots[shape.prototype_slot()?];
+            object = prototype.as_object()?;
+        }
+        Rc::ptr_eq(&object.shape, holder).then(|| object.slots[self.slot])
+    }
+}
+
+/// how to store a property: overwrite an own slot, or append one and change the shape.
+pub(crate) struct StoreHandler {
+    shape: Rc<Shape>,
+    transition: Option<Rc<Shape>>,
+    slot: usize,
+}
+
+impl Handler for StoreHandler {
+    fn receiver_shape(&self) -> &Rc<Shape> {
+        &self.shape
+    }
+}
+
+impl StoreHandler {
+    pub(crate) fn compute(object: &JSObject, key: &str) -> Self {
+        match object.shape.lookup(key) {
+            Some(slot) => StoreHandler {
+                shape: object.shape.clone(),
+                transition: None,
+                slot,
+            },
+            None => StoreHandler {
+                shape: object.shape.clone(),
+                transition: Some(object.shape.add(key)),
+                slot: object.shape.len(),
+            },
+        }
+    }
+
+    /// store `value`. the object must have the handler's shape.
+    pub(crate) fn store(&self, object: &mut JSObject, value: Value) {
+        debug_assert!(Rc::ptr_eq(&object.shape, &self.shape));
+        match &self.transition {
+            Some(shape) => {
+                object.shape = shape.clone();
+                object.slots.push(value);
+            }
+            None => object.slots[self.slot] = value,
+        }
+    }
+}
+
+/// the inline caches of the property access sites of the code, indexed by the slot operand
+/// the code generator gives each site.
+pub(crate) struct FeedbackVector {
+    loads: Vec<InlineCache<LoadHandler>>,
+    stores: Vec<InlineCache<StoreHandler>>,
+}
+
+impl FeedbackVector {
+    pub(crate) fn new() -> Self {
+        FeedbackVector {
+            loads: Vec::new(),
+            stores: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add_load_slot(&mut self) -> usize {
+        self.loads.push(InlineCache::Uninitialized);
+        self.loads.len() - 1
+    }
+
+    pub(crate) fn add_store_slot(&mut self) -> usize {
+        self.stores.push(InlineCache::Uninitialized);
+        self.stores.len() - 1
+    }
+
+    /// the number of load and store slots.
+    pub(crate) fn slots(&self) -> (usize, usize) {
+        (self.loads.len(), self.stores.len())
+    }
+
+    pub(crate) fn load(&mut self, slot: usize) -> &mut InlineCache<LoadHandler> {
+        &mut self.loads[slot]
+    }
+
+    pub(crate) fn store(&mut self, slot: usize) -> &mut InlineCache<StoreHandler> {
+        &mut self.stores[slot]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(shape: &Rc<Shape>, keys: &[&str]) -> Box<JSObject> {
+        let mut object = Box::new(JSObject::new(shape.clone()));
+        for (i, key) in keys.iter().enumerate() {
+            object.set(key, Value::smi(i as i32));
+        }
+        object
+    }
+
+    #[test]
+    fn test_load_ic() {
+        let root = Shape::root();
+        let mut ic = InlineCache::Uninitialized;
+
+        // objects built alike share one handler
+        let a = object(&root, &["x", "y"]);
+        let b = object(&root, &["x", "y"]);
+        let (handler, v) = LoadHandler::compute(&a, "y").unwrap();
+        assert_eq!(v, Value::smi(1));
+        ic.update(handler);
+        assert!(matches!(ic, InlineCache::Monomorphic(_)));
+        assert_eq!(ic.lookup(&b.shape).unwrap().load(&b), Some(Value::smi(1)));
+
+        // other layouts make it polymorphic, then megamorphic
+        let c = object(&root, &["y"]);
+        assert!(ic.lookup(&c.shape).is_none());
+        ic.update(LoadHandler::compute(&c, "y").unwrap().0);
+        assert!(matches!(&ic, InlineCache::Polymorphic(hs) if hs.len() == 2));
+        assert_eq!(ic.lookup(&c.shape).unwrap().load(&c), Some(Value::smi(0)));
+        for keys in [["a", "y"], ["b", "y"], ["c", "y"]] {
+            let o = object(&root, &keys);
+            ic.update(LoadHandler::compute(&o, "y").unwrap().0);
+        }
+        assert!(ic.is_megamorphic());
+    }
+
+    #[test]
+    fn test_load_ic_prototype() {
+        let root = Shape::root();
+        let mut prototype = object(&root, &["f"]);
+        let mut a = object(&root, &[]);
+        a.set("__proto__", Value::from(&*prototype));
+
+        let (handler, v) = LoadHandler::compute(&a, "f").unwrap();
+        assert_eq!(v, Value::smi(0));
+        prototype.set("f", Value::smi(7));
+        assert_eq!(handler.load(&a), Some(Value::smi(7)));
+
+        // a change of the prototype's layout misses
+        prototype.set("g", Value::smi(1));
+        assert_eq!(handler.load(&a), None);
+    }
+
+    #[test]
+    fn test_store_ic() {
+        let root = Shape::root();
+        let mut a = object(&root, &[]);
+        let mut b = object(&root, &[]);
+
+        let add = StoreHandler::compute(&a, "x");
+        add.store(&mut a, Value::smi(1));
+        add.store(&mut b, Value::smi(2));
+        assert!(Rc::ptr_eq(&a.shape, &b.shape));
+        assert_eq!(b.get("x"), Some(Value::smi(2)));
+
+        let replace = StoreHandler::compute(&a, "x");
+        replace.store(&mut a, Value::smi(3));
+        assert_eq!(a.get("x"), Some(Value::smi(3)));
+        assert!(Rc::ptr_eq(&a.shape, &b.shape));
+    }
+}
@@ -0,0 +1,37 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{
+    constant_table::ConstantTable, feedback::FeedbackVector, handler_table::HandlerTable,
+    jit::JitState, register::Register, source_positions::SourcePositionTable, value::Value,
+};
+
+/// the bytecode of a function literal. every closure created from the literal shares it.
+pub(crate) struct FunctionCode {
+    pub(crate) parameters: Vec<String>,
+    pub(crate) code: Vec<u8>,
+    pub(crate) constant_table: ConstantTable,
+    pub(crate) feedback: RefCell<FeedbackVector>,
+    pub(crate) handler_table: HandlerTable,
+    pub(crate) source_positions: SourcePositionTable,
+    pub(crate) jit: RefCell<JitState>,
+}
+
+/// a function object: its code and the context it was created in.
+pub(crate) struct Closure {
+    pub(crate) code: Rc<FunctionCode>,
+    pub(crate) context: Value,
+}
+
+/// the state of a caller, restored by `Return`.
+pub(crate) struct Frame {
+    /// `None` for the script
+    pub(crate) function: Option<Rc<FunctionCode>>,
+    pub(crate) pc: usize,
+    pub(crate) context: Value,
+    /// contexts pushed by the caller since it started
+    pub(crate) context_depth: usize,
+    /// the stack without the callee, receiver and arguments
+    pub(crate) stack_len: usize,
+    /// the registers of the caller, but for r0 which gets the result
+    pub(crate) registers: Register,
+}
@@ -0,0 +1,87 @@
+/// a `try` region of the code and where its exceptions go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HandlerTableEntry {
+    /// the region, offsets into the code
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) handler: usize,
+    /// stack values and contexts of the function when the `try` was entered
+    pub(crate) stack_depth: usize,
+    pub(crate) context_depth: usize,
+}
+
+/// the exception handlers of a code, the innermost of nested regions first.
+pub(crate) struct HandlerTable {
+    entries: Vec<HandlerTableEntry>,
+}
+
+impl HandlerTable {
+    pub(crate) fn new() -> Self {
+        HandlerTable {
+            entries: Vec::new(),
+        }
+    }
+
+    /// regions must be added as they are closed, so inner ones come first.
+    pub(crate) fn add(&mut self, entry: HandlerTableEntry) {
+        self.entries.push(entry);
+    }
+
+    /// append the entries of code that is placed at `offset`.
+    pub(crate) fn extend(&mut self, other: HandlerTable, offset: usize) {
+        self.entries
+            .extend(other.entries.into_iter().map(|entry| HandlerTableEntry {
+                start: entry.start + offset,
+                end: entry.end + offset,
+                handler: entry.handler + offset,
+                ..entry
+            }));
+    }
+
+    pub(crate) fn entries(&self) -> &[HandlerTableEntry] {
+        &self.entries
+    }
+
+    /// the innermost handler of the instruction that ends at or contains `pc`. the pc of a
+    /// throwing instruction has moved past its opcode, the pc of a caller past its call.
+    pub(crate) fn lookup(&self, pc: usize) -> Option<HandlerTableEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.start < pc && pc <= entry.end)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(start: usize, end: usize, handler: usize) -> HandlerTableEntry {
+        HandlerTableEntry {
+            start,
+            end,
+            handler,
+            stack_depth: 0,
+            context_depth: 0,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut table = HandlerTable::new();
+        table.add(entry(10, 20, 30));
+        table.add(entry(0, 40, 50));
+
+        assert_eq!(table.lookup(15).map(|e| e.handler), Some(30));
+        assert_eq!(table.lookup(20).map(|e| e.handler), Some(30));
+        assert_eq!(table.lookup(25).map(|e| e.handler), Some(50));
+        // the first instruction of a region has not run yet at its start
+        assert_eq!(table.lookup(0), None);
+        assert_eq!(table.lookup(41), None);
+
+        let mut script = HandlerTable::new();
+        script.extend(table, 100);
+        assert_eq!(script.lookup(115).map(|e| e.handler), Some(130));
+        assert_eq!(script.lookup(15), None);
+    }
+}
@@ -1,25 +1,116 @@
-pub(super) struct GarbageCollector {}
+use std::collections::HashMap;
+
+use super::space::Space;
+use crate::engine::core::vm::value::Value;
+
+/// mark-sweep-compact collector of the old space.
+///
+/// roots are object pointers from registers, the stack, the running contexts and young objects.
+/// pointers outside the space are ignored.
+pub(super) struct GarbageCollector {
+    marks: Vec<bool>,
+    /// old address -> new address of the objects moved by the last compaction
+    forwarding: HashMap<i64, i64>,
+    collections: usize,
+}
 
 impl GarbageCollector {
     pub(super) fn new() -> Self {
-        Self {}
+        Self {
+            marks: Vec::new(),
+            forwarding: HashMap::new(),
+            collections: 0,
+        }
     }
 
-    pub(super) fn collect(&mut self) {
-        self.mark();
-        self.sweep();
-        self.compact();
+    pub(super) fn collect(&mut self, space: &mut Space, roots: &[i64]) {
+        self.mark(space, roots);
+        self.sweep(space);
+        self.compact(space);
+        self.collections += 1;
     }
 
-    fn mark(&mut self) {
-        todo!()
+    pub(super) fn forwarding_address(&self, ptr: i64) -> i64 {
+        self.forwarding.get(&ptr).copied().unwrap_or(ptr)
     }
 
-    fn sweep(&mut self) {
-        todo!()
+    pub(super) fn collections(&self) -> usize {
+        self.collections
+    }
+
+    fn mark(&mut self, space: &Space, roots: &[i64]) {
+        self.marks.clear();
+        self.marks.resize(space.top, false);
+
+        let mut worklist: Vec<usize> = roots.iter().filter_map(|&r| space.index_of(r)).collect();
+        while let Some(index) = worklist.pop() {
+            if self.marks[index] {
+                continue;
+            }
+            self.marks[index] = true;
+
+            let object = unsafe { &*space.cell(index) };
+            for child in object.children().filter_map(|v| v.as_object_ptr()) {
+                if let Some(child) = space.index_of(child) {
+                    if !self.marks[child] {
+                        worklist.push(child);
+                    }
+                }
+            }
+        }
     }
 
-    fn compact(&mut self) {
-        todo!()
+    /// drop unmarked objects and put their cells on the free list.
+    fn sweep(&mut self, space: &mut Space) {
+        for index in 0..space.top {
+            if space.live[index] && !self.marks[index] {
+                unsafe { std::ptr::drop_in_place(space.cell(index)) };
+                space.live[index] = false;
+                space.free_list.push(index);
+            }
+        }
+    }
+
+    /// slide live objects from the top into the free cells at the bottom (two-finger),
+    /// then fix up the pointers between objects. allocation is a bump again afterwards.
+    fn compact(&mut self, space: &mut Space) {
+        self.forwarding.clear();
+
+        let mut free = 0;
+        let mut scan = space.top;
+        loop {
+            while free < scan && space.live[free] {
+                free += 1;
+            }
+            while scan > free && !space.live[scan - 1] {
+                scan -= 1;
+            }
+            if free >= scan {
+                break;
+            }
+
+            let from = scan - 1;
+            unsafe { std::ptr::copy_nonoverlapping(space.cell(from), space.cell(free), 1) };
+            space.live[from] = false;
+            space.live[free] = true;
+            self.forwarding
+                .insert(space.cell(from) as i64, space.cell(free) as i64);
+        }
+
+        // everything below `free` is live now, everything above free
+        space.top = free;
+        space.free_list.clear();
+
+        if self.forwarding.is_empty() {
+            return;
+        }
+        for index in 0..space.top {
+            let object = unsafe { &mut *space.cell(index) };
+            for child in object.children_mut() {
+                if let Some(&to) = child.as_object_ptr().and_then(|p| self.forwarding.get(&p)) {
+                    *child = Value::object(to);
+                }
+            }
+        }
     }
 }
@@ -1,52 +1,293 @@
-use self::{gc::GarbageCollector, page_allocator::PageAllocator, virtual_memory::VirtualMemory};
-use crate::engine::core::vm::objects::js_object::JSObject;
+use std::{collections::HashSet, rc::Rc};
+
+use self::{
+    gc::GarbageCollector,
+    nursery::{Nursery, SEMISPACE_SIZE},
+    space::{Space, PAGE_SIZE},
+};
+use crate::engine::core::vm::{
+    objects::{js_object::JSObject, shape::Shape},
+    value::Value,
+};
 
 mod gc;
+mod nursery;
 mod page_allocator;
+mod space;
 mod virtual_memory;
 
+/// collect at the next safepoint once fewer cells than this are left in the nursery.
+/// an instruction allocates a handful of cells at most.
+const GC_RESERVE_CELLS: usize = 128;
+
+/// the heap: a nursery for new objects and a paged old space that grows up to `max_size`.
+///
+/// a minor collection (scavenge) copies the live young objects and promotes the ones that
+/// survived before, a major one marks, sweeps and compacts the old space as well.
+/// old objects that point into the nursery are kept in a remembered set by the write barrier.
 pub(crate) struct Heap {
-    virtual_memory: VirtualMemory,
+    nursery: Nursery,
+    old: Space,
     gc: GarbageCollector,
-    next: *mut u8,
-    end: *mut u8,
+    remembered_set: HashSet<i64>,
+    /// the shape of new objects
+    root_shape: Rc<Shape>,
+    max_size: usize,
+    /// live old cells that trigger the next major collection
+    major_threshold: usize,
+    scavenges: usize,
 }
 
 impl Heap {
-    pub(crate) fn new(size: usize) -> Self {
-        let page_allocator = Box::new(PageAllocator);
-        let virtual_memory = VirtualMemory::new(page_allocator, size);
-        let next = virtual_memory.address().unwrap().as_ptr();
-        let end = unsafe { next.add(size) };
+    pub(crate) fn new(max_size: usize) -> Self {
+        let mut old = Space::new();
+        old.add_page();
         Heap {
-            virtual_memory,
+            nursery: Nursery::new(),
+            old,
             gc: GarbageCollector::new(),
-            next,
-            end,
+            remembered_set: HashSet::new(),
+            root_shape: Shape::root(),
+            max_size,
+            major_threshold: Space::cells_per_page(),
+            scavenges: 0,
         }
     }
 
+    pub(crate) fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
     pub(crate) fn alloc(&mut self) -> Option<&'static mut JSObject> {
-        let size = std::mem::size_of::<JSObject>();
-        let align = std::mem::align_of::<JSObject>();
-        let aligned_next = (self.next as usize + align - 1) & !(align - 1);
-        let next = aligned_next as *mut u8;
-
-        if next.wrapping_add(size) <= self.end {
-            let ptr = next as *mut JSObject;
-            let js_obj = JSObject::new();
-            unsafe {
-                ptr.write(js_obj);
-                self.next = ptr.add(1) as *mut u8;
+        let ptr = match self.nursery.alloc() {
+            Some(ptr) => ptr,
+            // the nursery is full until the next safepoint, allocate old
+            None => self.alloc_old()?,
+        };
+        unsafe { ptr.write(JSObject::new(self.root_shape.clone())) };
+        Some(JSObject::from_raw_ptr_mut(ptr as i64))
+    }
+
+    fn alloc_old(&mut self) -> Option<*mut JSObject> {
+        if let Some(ptr) = self.old.alloc() {
+            return Some(ptr);
+        }
+        if self.can_grow() && self.old.add_page() {
+            return self.old.alloc();
+        }
+        None
+    }
+
+    fn can_grow(&self) -> bool {
+        self.old.pages() < self.max_old_pages()
+    }
+
+    fn max_old_pages(&self) -> usize {
+        self.max_size.saturating_sub(2 * SEMISPACE_SIZE) / PAGE_SIZE
+    }
+
+    /// record a pointer from `object` to `value`. must be called whenever a property of an
+    /// object that may be old is set outside the collector.
+    pub(crate) fn write_barrier(&mut self, object: &JSObject, value: Value) {
+        let young = matches!(value.as_object_ptr(), Some(v) if self.nursery.contains(v));
+        if young && !self.nursery.contains(object.as_raw_ptr()) {
+            self.remembered_set.insert(object.as_raw_ptr());
+        }
+    }
+
+    /// whether the next safepoint should run a collection.
+    pub(crate) fn should_collect(&self) -> bool {
+        self.nursery.available() < GC_RESERVE_CELLS
+    }
+
+    /// whether the heap is full: after a collection the nursery is still short of its reserve
+    /// and the old space has no room for the survivors.
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.should_collect() && !self.can_grow() && self.old.available() < self.nursery.top
+    }
+
+    /// collect garbage. objects move: `roots` (object pointers) are updated in place, and
+    /// the caller must not hold references into the heap.
+    pub(crate) fn collect(&mut self, roots: &mut [i64]) {
+        self.scavenge(roots);
+
+        let old_full = self.old.available() < Nursery::capacity() && !self.can_grow();
+        if self.old.live_count() >= self.major_threshold || old_full {
+            self.major(roots);
+        }
+    }
+
+    fn scavenge(&mut self, roots: &mut [i64]) {
+        let remembered: Vec<i64> = self.remembered_set.drain().collect();
+        let max_old_pages = self.max_old_pages();
+        let result = self
+            .nursery
+            .scavenge(&mut self.old, max_old_pages, roots, &remembered);
+        self.scavenges += 1;
+
+        // old objects that still point into the nursery, promoted ones included
+        for object in remembered.into_iter().chain(result.promoted) {
+            if self.points_into_nursery(object) {
+                self.remembered_set.insert(object);
+            }
+        }
+    }
+
+    /// mark-sweep-compact the old space. young objects are treated as roots.
+    fn major(&mut self, roots: &mut [i64]) {
+        let mut old_roots = roots.to_vec();
+        for index in 0..self.nursery.top {
+            let object = unsafe { &*self.nursery.cell(index) };
+            old_roots.extend(object.children().filter_map(|v| v.as_object_ptr()));
+        }
+
+        self.gc.collect(&mut self.old, &old_roots);
+
+        for root in roots.iter_mut() {
+            *root = self.gc.forwarding_address(*root);
+        }
+        for index in 0..self.nursery.top {
+            let object = unsafe { &mut *self.nursery.cell(index) };
+            for child in object.children_mut() {
+                if let Some(ptr) = child.as_object_ptr() {
+                    let to = self.gc.forwarding_address(ptr);
+                    if to != ptr {
+                        *child = Value::object(to);
+                    }
+                }
             }
-            Some(JSObject::from_raw_ptr_mut(ptr as i64))
-        } else {
-            None
         }
+
+        self.remembered_set = (0..self.old.top)
+            .map(|index| self.old.cell(index) as i64)
+            .filter(|&object| self.points_into_nursery(object))
+            .collect();
+
+        self.old.shrink();
+        self.major_threshold = Space::cells_per_page().max(self.old.live_count() * 2);
+    }
+
+    fn points_into_nursery(&self, object: i64) -> bool {
+        JSObject::from_raw_ptr(object)
+            .children()
+            .filter_map(|v| v.as_object_ptr())
+            .any(|v| self.nursery.contains(v))
     }
 
-    // TODO: collect
-    // pub(crate) fn free(&mut self) {
-    //     self.virtual_memory.free();
-    // }
+    /// whether `ptr` is the address of an allocated object.
+    pub(crate) fn contains(&self, ptr: i64) -> bool {
+        self.nursery.contains(ptr) || self.old.index_of(ptr).is_some()
+    }
+
+    pub(crate) fn statistics(&self) -> HeapStatistics {
+        HeapStatistics {
+            young: self.nursery.top,
+            old: self.old.live_count(),
+            pages: self.old.pages(),
+            scavenges: self.scavenges,
+            major_collections: self.gc.collections(),
+        }
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        self.nursery.clear();
+        self.old.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HeapStatistics {
+    /// live cells, one object each
+    pub(crate) young: usize,
+    pub(crate) old: usize,
+    /// pages of the old space
+    pub(crate) pages: usize,
+    pub(crate) scavenges: usize,
+    pub(crate) major_collections: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::core::vm::objects::js_object::JSType;
+
+    fn set(heap: &mut Heap, object: i64, key: &str, value: &'static mut JSObject) {
+        let object = JSObject::from_raw_ptr_mut(object);
+        let value = Value::from(value);
+        heap.write_barrier(object, value);
+        object.set(key, value);
+    }
+
+    #[test]
+    fn test_scavenge_and_promote() {
+        let mut heap = Heap::new(64 * 1024 * 1024);
+        let mut roots = [heap.alloc().unwrap().as_raw_ptr()];
+        let child = heap.alloc().unwrap();
+        child._type = JSType::String("child".to_string());
+        set(&mut heap, roots[0], "child", child);
+        for _ in 0..100 {
+            heap.alloc().unwrap();
+        }
+
+        // survivors are copied, garbage dropped
+        heap.collect(&mut roots);
+        assert_eq!(heap.statistics().young, 2);
+        assert!(heap.nursery.contains(roots[0]));
+
+        // the second survival promotes
+        heap.collect(&mut roots);
+        assert_eq!(heap.statistics().young, 0);
+        assert_eq!(heap.statistics().old, 2);
+        let root = JSObject::from_raw_ptr(roots[0]);
+        let child = root.get("child").unwrap().as_object().unwrap();
+        assert!(matches!(&child._type, JSType::String(s) if s == "child"));
+
+        // old -> young is kept alive through the remembered set
+        let young = heap.alloc().unwrap();
+        young._type = JSType::String("young".to_string());
+        set(&mut heap, roots[0], "young", young);
+        heap.collect(&mut roots);
+        let root = JSObject::from_raw_ptr(roots[0]);
+        let young = root.get("young").unwrap().as_object().unwrap();
+        assert!(matches!(&young._type, JSType::String(s) if s == "young"));
+    }
+
+    #[test]
+    fn test_grow_and_major() {
+        let mut heap = Heap::new(2 * SEMISPACE_SIZE + 4 * PAGE_SIZE);
+        let mut roots = [heap.alloc().unwrap().as_raw_ptr()];
+
+        // a long-lived list grows the old space page by page
+        let mut len = 0;
+        while heap.statistics().pages < 3 {
+            let next = heap.alloc().unwrap();
+            let head = JSObject::from_raw_ptr_mut(roots[0]);
+            if let Some(prev) = head.remove("next") {
+                next.set("next", prev);
+            }
+            set(&mut heap, roots[0], "next", next);
+            len += 1;
+            if heap.should_collect() {
+                heap.collect(&mut roots);
+            }
+        }
+
+        // dropping it lets a major collection release the pages
+        JSObject::from_raw_ptr_mut(roots[0]).remove("next");
+        heap.collect(&mut roots);
+        heap.major(&mut roots);
+        assert_eq!(heap.statistics().pages, 1);
+        assert!(heap.statistics().major_collections > 0);
+        assert!(len > 2 * Space::cells_per_page());
+
+        // up to the maximum
+        let mut count = 0;
+        while heap.alloc().is_some() {
+            count += 1;
+        }
+        assert!(count > 0);
+        assert_eq!(heap.statistics().pages, 4);
+    }
 }
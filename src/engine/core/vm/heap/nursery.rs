@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use super::{page_allocator::PageAllocator, space::Space, virtual_memory::VirtualMemory};
+use crate::engine::core::vm::{objects::js_object::JSObject, value::Value};
+
+pub(super) const SEMISPACE_SIZE: usize = 256 * 1024;
+
+/// objects that survived this many scavenges are promoted to the old space.
+const PROMOTION_AGE: u8 = 1;
+
+struct Semispace {
+    memory: VirtualMemory,
+    start: *mut JSObject,
+}
+
+impl Semispace {
+    fn new() -> Self {
+        let memory = VirtualMemory::new(Box::new(PageAllocator), SEMISPACE_SIZE);
+        let start = memory.address().unwrap().as_ptr() as *mut JSObject;
+        Semispace { memory, start }
+    }
+}
+
+impl Drop for Semispace {
+    fn drop(&mut self) {
+        self.memory.free();
+    }
+}
+
+/// the young generation. objects are bump-allocated in `from`, survivors of a scavenge are
+/// copied to `to` (or promoted) and the two spaces swap, so dying young is free.
+pub(super) struct Nursery {
+    from: Semispace,
+    to: Semispace,
+    pub(super) top: usize,
+    /// scavenges survived by each object of `from`
+    ages: Vec<u8>,
+}
+
+pub(super) struct ScavengeResult {
+    /// old-space objects created by promotion
+    pub(super) promoted: Vec<i64>,
+}
+
+impl Nursery {
+    pub(super) fn new() -> Self {
+        Nursery {
+            from: Semispace::new(),
+            to: Semispace::new(),
+            top: 0,
+            ages: Vec::new(),
+        }
+    }
+
+    pub(super) fn capacity() -> usize {
+        SEMISPACE_SIZE / std::mem::size_of::<JSObject>()
+    }
+
+    pub(super) fn available(&self) -> usize {
+        Self::capacity() - self.top
+    }
+
+    /// an uninitialized cell.
+    pub(super) fn alloc(&mut self) -> Option<*mut JSObject> {
+        if self.top >= Self::capacity() {
+            return None;
+        }
+        self.top += 1;
+        self.ages.push(0);
+        Some(self.cell(self.top - 1))
+    }
+
+    pub(super) fn cell(&self, index: usize) -> *mut JSObject {
+        unsafe { self.from.start.add(index) }
+    }
+
+    pub(super) fn contains(&self, ptr: i64) -> bool {
+        self.index_of(ptr).is_some()
+    }
+
+    fn index_of(&self, ptr: i64) -> Option<usize> {
+        let offset = (ptr as usize).checked_sub(self.from.start as usize)?;
+        let cell = std::mem::size_of::<JSObject>();
+        (offset % cell == 0 && offset / cell < self.top).then_some(offset / cell)
+    }
+
+    /// copy the young objects reachable from `roots` and `remembered` (old objects pointing
+    /// into the nursery) out of `from`, updating every pointer to them, and drop the rest.
+    pub(super) fn scavenge(
+        &mut self,
+        old: &mut Space,
+        max_old_pages: usize,
+        roots: &mut [i64],
+        remembered: &[i64],
+    ) -> ScavengeResult {
+        let mut scavenger = Scavenger {
+            nursery: self,
+            old,
+            max_old_pages,
+            forwarding: HashMap::new(),
+            to_top: 0,
+            to_ages: Vec::new(),
+            promoted: Vec::new(),
+        };
+
+        for root in roots.iter_mut() {
+            *root = scavenger.evacuate(*root);
+        }
+        for &object in remembered {
+            scavenger.update_children(object);
+        }
+
+        // cheney scan: the copied objects are the worklist
+        let mut scan = 0;
+        let mut promoted_scan = 0;
+        while scan < scavenger.to_top || promoted_scan < scavenger.promoted.len() {
+            if scan < scavenger.to_top {
+                let object = unsafe { scavenger.nursery.to.start.add(scan) } as i64;
+                scavenger.update_children(object);
+                scan += 1;
+            } else {
+                let object = scavenger.promoted[promoted_scan];
+                scavenger.update_children(object);
+                promoted_scan += 1;
+            }
+        }
+
+        let Scavenger {
+            forwarding,
+            to_top,
+            to_ages,
+            promoted,
+            ..
+        } = scavenger;
+
+        // whatever was not copied is garbage
+        for index in 0..self.top {
+            let ptr = self.cell(index);
+            if !forwarding.contains_key(&(ptr as i64)) {
+                unsafe { std::ptr::drop_in_place(ptr) };
+            }
+        }
+
+        std::mem::swap(&mut self.from, &mut self.to);
+        self.top = to_top;
+        self.ages = to_ages;
+
+        ScavengeResult { promoted }
+    }
+
+    /// drop every object.
+    pub(super) fn clear(&mut self) {
+        for index in 0..self.top {
+            unsafe { std::ptr::drop_in_place(self.cell(index)) };
+        }
+        self.top = 0;
+        self.ages.clear();
+    }
+}
+
+struct Scavenger<'a> {
+    nursery: &'a mut Nursery,
+    old: &'a mut Space,
+    max_old_pages: usize,
+    forwarding: HashMap<i64, i64>,
+    to_top: usize,
+    to_ages: Vec<u8>,
+    promoted: Vec<i64>,
+}
+
+impl<'a> Scavenger<'a> {
+    /// the new address of `ptr`, copying it on the first visit. not young pointers stay.
+    fn evacuate(&mut self, ptr: i64) -> i64 {
+        let index = match self.nursery.index_of(ptr) {
+            Some(index) => index,
+            None => return ptr,
+        };
+        if let Some(&to) = self.forwarding.get(&ptr) {
+            return to;
+        }
+
+        let age = self.nursery.ages[index];
+        let promoted = if age >= PROMOTION_AGE {
+            self.alloc_old()
+        } else {
+            None
+        };
+        // `to` is as large as `from`, so there is always room when promotion fails
+        let to = match promoted {
+            Some(cell) => {
+                self.promoted.push(cell as i64);
+                cell
+            }
+            None => {
+                let cell = unsafe { self.nursery.to.start.add(self.to_top) };
+                self.to_top += 1;
+                self.to_ages.push(age.saturating_add(1));
+                cell
+            }
+        };
+        unsafe { std::ptr::copy_nonoverlapping(ptr as *const JSObject, to, 1) };
+        self.forwarding.insert(ptr, to as i64);
+        to as i64
+    }
+
+    fn alloc_old(&mut self) -> Option<*mut JSObject> {
+        if let Some(cell) = self.old.alloc() {
+            return Some(cell);
+        }
+        if self.old.pages() < self.max_old_pages && self.old.add_page() {
+            return self.old.alloc();
+        }
+        None
+    }
+
+    fn update_children(&mut self, object: i64) {
+        let object = JSObject::from_raw_ptr_mut(object);
+        for child in object.children_mut() {
+            if let Some(ptr) = child.as_object_ptr() {
+                let to = self.evacuate(ptr);
+                if to != ptr {
+                    *child = Value::object(to);
+                }
+            }
+        }
+    }
+}
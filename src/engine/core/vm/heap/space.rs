@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use super::{page_allocator::PageAllocator, virtual_memory::VirtualMemory};
+use crate::engine::core::vm::objects::js_object::JSObject;
+
+pub(super) const PAGE_SIZE: usize = 256 * 1024;
+
+/// a block of cells requested from the page allocator.
+struct Page {
+    memory: VirtualMemory,
+    start: *mut JSObject,
+}
+
+impl Page {
+    fn new() -> Option<Self> {
+        let memory = VirtualMemory::new(Box::new(PageAllocator), PAGE_SIZE);
+        let start = memory.address()?.as_ptr() as *mut JSObject;
+        Some(Page { memory, start })
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        self.memory.free();
+    }
+}
+
+/// the old space: `JSObject`-sized cells over a growable list of pages.
+/// cells are numbered across pages. cells below `top` have been handed out,
+/// free ones among them are kept in `free_list`.
+pub(super) struct Space {
+    pages: Vec<Page>,
+    /// page start address -> page number
+    page_index: BTreeMap<usize, usize>,
+    pub(super) top: usize,
+    pub(super) live: Vec<bool>,
+    pub(super) free_list: Vec<usize>,
+}
+
+impl Space {
+    pub(super) fn new() -> Self {
+        Space {
+            pages: Vec::new(),
+            page_index: BTreeMap::new(),
+            top: 0,
+            live: Vec::new(),
+            free_list: Vec::new(),
+        }
+    }
+
+    pub(super) fn cells_per_page() -> usize {
+        PAGE_SIZE / std::mem::size_of::<JSObject>()
+    }
+
+    pub(super) fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.pages.len() * Self::cells_per_page()
+    }
+
+    pub(super) fn available(&self) -> usize {
+        self.capacity() - self.top + self.free_list.len()
+    }
+
+    pub(super) fn live_count(&self) -> usize {
+        self.top - self.free_list.len()
+    }
+
+    pub(super) fn add_page(&mut self) -> bool {
+        match Page::new() {
+            Some(page) => {
+                self.page_index
+                    .insert(page.start as usize, self.pages.len());
+                self.pages.push(page);
+                self.live.resize(self.capacity(), false);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// release the pages above `top`, keeping at least one.
+    pub(super) fn shrink(&mut self) {
+        let needed = ((self.top + Self::cells_per_page() - 1) / Self::cells_per_page()).max(1);
+        while self.pages.len() > needed {
+            let page = self.pages.pop().unwrap();
+            self.page_index.remove(&(page.start as usize));
+        }
+        self.live.truncate(self.capacity());
+    }
+
+    /// an uninitialized cell.
+    pub(super) fn alloc(&mut self) -> Option<*mut JSObject> {
+        let index = match self.free_list.pop() {
+            Some(index) => index,
+            None if self.top < self.capacity() => {
+                self.top += 1;
+                self.top - 1
+            }
+            None => return None,
+        };
+        self.live[index] = true;
+        Some(self.cell(index))
+    }
+
+    pub(super) fn cell(&self, index: usize) -> *mut JSObject {
+        let page = &self.pages[index / Self::cells_per_page()];
+        unsafe { page.start.add(index % Self::cells_per_page()) }
+    }
+
+    /// the index of the allocated cell `ptr` points at.
+    pub(super) fn index_of(&self, ptr: i64) -> Option<usize> {
+        let ptr = ptr as usize;
+        let (&start, &page) = self.page_index.range(..=ptr).next_back()?;
+        let offset = ptr - start;
+        let cell = std::mem::size_of::<JSObject>();
+        let index = page * Self::cells_per_page() + offset / cell;
+        (offset % cell == 0
+            && offset / cell < Self::cells_per_page()
+            && index < self.top
+            && self.live[index])
+            .then_some(index)
+    }
+
+    /// drop every object.
+    pub(super) fn clear(&mut self) {
+        for index in 0..self.top {
+            if self.live[index] {
+                unsafe { std::ptr::drop_in_place(self.cell(index)) };
+                self.live[index] = false;
+            }
+        }
+        self.top = 0;
+        self.free_list.clear();
+    }
+}
@@ -1,63 +1,272 @@
 #![allow(dead_code)]
 
-use super::{InlineAssembler, InlineAssembler64BitsRegister};
+use super::{Condition, InlineAssembler, InlineAssembler64BitsRegister};
 
+/// x9 is the scratch register, x16 holds the operands that do not fit an instruction. the
+/// stack pointer stays 16 byte aligned, so a push takes a pair of slots.
 pub(crate) struct Arm64InlineAssembler {
     codes: Vec<u8>,
+    /// the offset of each label, `None` until it is bound
+    labels: Vec<Option<usize>>,
+    /// the branches to patch with the offset of their label
+    patches: Vec<(usize, u64)>,
 }
 
 impl Arm64InlineAssembler {
     pub(crate) fn new() -> Self {
-        Arm64InlineAssembler { codes: vec![] }
+        Arm64InlineAssembler {
+            codes: vec![],
+            labels: vec![],
+            patches: vec![],
+        }
+    }
+
+    fn emit(&mut self, instruction: u32) {
+        self.codes.extend_from_slice(&instruction.to_le_bytes());
+    }
+
+    /// a data processing instruction of three registers.
+    fn rrr(&mut self, op: u32, d: u64, n: u64, m: u64) {
+        self.emit(op | (m as u32) << 16 | (n as u32) << 5 | d as u32);
+    }
+
+    /// `movz`, `movn` or `movk` of the 16 bits at `hw * 16`.
+    fn mov_wide(&mut self, op: u32, reg: u64, imm: u16, hw: u32) {
+        self.emit(op | hw << 21 | (imm as u32) << 5 | reg as u32);
+    }
+
+    /// `add d, n, #0`, the move to or from sp.
+    fn mov_sp(&mut self, d: u64, n: u64) {
+        self.emit(0x9100_0000 | (n as u32) << 5 | d as u32);
+    }
+
+    /// `stp t1, t2, [sp, #-16]!`
+    fn stp_pre(&mut self, t1: u64, t2: u64) {
+        self.emit(0xa9bf_0000 | (t2 as u32) << 10 | (Registers::SP as u32) << 5 | t1 as u32);
+    }
+
+    /// `ldp t1, t2, [sp], #16`
+    fn ldp_post(&mut self, t1: u64, t2: u64) {
+        self.emit(0xa8c1_0000 | (t2 as u32) << 10 | (Registers::SP as u32) << 5 | t1 as u32);
+    }
+
+    /// a load or store of `size` bytes: `ops` are its forms with a scaled unsigned offset, an
+    /// unscaled signed one and an offset register. the offsets that fit neither immediate go
+    /// through x16.
+    fn memory(&mut self, ops: [u32; 3], size: i32, t: u64, base: u64, offset: i32) {
+        let (op, imm) = match offset {
+            // [base, #offset], scaled by the size
+            _ if (0..=4095 * size).contains(&offset) && offset % size == 0 => {
+                (ops[0], ((offset / size) as u32) << 10)
+            }
+            // [base, #offset]
+            -256..=255 => (ops[1], (offset as u32 & 0x1ff) << 12),
+            // [base, x16]
+            _ => {
+                self.mov_imm(Registers::X16, offset as i64);
+                (ops[2], (Registers::X16 as u32) << 16)
+            }
+        };
+        self.emit(op | imm | (base as u32) << 5 | t as u32);
+    }
+
+    /// `add` of an immediate, or `sub` of its magnitude if it is negative. `x16` holds one of
+    /// more than 12 bits. `d` may be xzr for a compare, `n` is never sp.
+    fn add_sub_imm(&mut self, add: u32, sub: u32, d: u64, n: u64, imm: i32) {
+        let (op, imm) = match imm < 0 {
+            false => (add, imm as u32),
+            true => (sub, imm.unsigned_abs()),
+        };
+        if imm < 4096 {
+            self.emit(op | imm << 10 | (n as u32) << 5 | d as u32);
+        } else {
+            self.mov_imm(Registers::X16, imm as i64);
+            // the shifted register form of the same operation
+            self.rrr((op & 0xe000_0000) | 0x0b00_0000, d, n, Registers::X16);
+        }
+    }
+
+    fn branch(&mut self, op: u32, label: u64) {
+        self.patches.push((self.codes.len(), label));
+        self.emit(op);
     }
 }
 
-impl<T> InlineAssembler<T> for Arm64InlineAssembler {
-    fn unsafe_transmute(&mut self) -> extern "C" fn() -> T {
-        unsafe {
-            let ptr = self.codes.as_ptr() as *const T;
-            std::mem::transmute(ptr)
+impl InlineAssembler for Arm64InlineAssembler {
+    fn enter(&mut self) {
+        // stp x29, x30, [sp, #-16]!; mov x29, sp
+        self.stp_pre(Registers::FP, Registers::LR);
+        self.mov_sp(Registers::FP, Registers::SP);
+    }
+
+    fn leave(&mut self) {
+        // mov sp, x29; ldp x29, x30, [sp], #16
+        self.mov_sp(Registers::SP, Registers::FP);
+        self.ldp_post(Registers::FP, Registers::LR);
+    }
+
+    fn mov_imm(&mut self, reg: u64, imm: i64) {
+        let chunks: Vec<u16> = (0..4).map(|i| (imm >> (i * 16)) as u16).collect();
+        // negative numbers are mostly ones, start from those with movn
+        let ones = chunks.iter().filter(|&&c| c == 0xffff).count();
+        let zeros = chunks.iter().filter(|&&c| c == 0).count();
+        let (fill, first) = match ones > zeros {
+            true => (0xffff, 0x9280_0000),
+            false => (0, 0xd280_0000),
+        };
+
+        let start = chunks.iter().position(|&c| c != fill).unwrap_or(0);
+        let value = match fill {
+            0 => chunks[start],
+            _ => !chunks[start],
+        };
+        self.mov_wide(first, reg, value, start as u32);
+        for (hw, &chunk) in chunks.iter().enumerate().skip(start + 1) {
+            if chunk != fill {
+                self.mov_wide(0xf280_0000, reg, chunk, hw as u32);
+            }
         }
     }
 
-    fn push(&mut self, _reg: u64) {
-        todo!()
+    fn mov(&mut self, dst: u64, src: u64) {
+        // orr dst, xzr, src
+        self.rrr(0xaa00_0000, dst, Registers::XZR, src);
+    }
+
+    fn push(&mut self, reg: u64) {
+        self.stp_pre(reg, Registers::XZR);
+    }
+
+    fn pop(&mut self, reg: u64) {
+        self.ldp_post(reg, Registers::XZR);
+    }
+
+    fn load(&mut self, dst: u64, base: u64, offset: i32) {
+        // ldr, ldur
+        let ops = [0xf940_0000, 0xf840_0000, 0xf860_6800];
+        self.memory(ops, 8, dst, base, offset);
+    }
+
+    fn load_byte(&mut self, dst: u64, base: u64, offset: i32) {
+        // ldrb, ldurb
+        let ops = [0x3940_0000, 0x3840_0000, 0x3860_6800];
+        self.memory(ops, 1, dst, base, offset);
+    }
+
+    fn store(&mut self, src: u64, base: u64, offset: i32) {
+        // str, stur
+        let ops = [0xf900_0000, 0xf800_0000, 0xf820_6800];
+        self.memory(ops, 8, src, base, offset);
     }
 
-    fn pop(&mut self, _reg: u64) {
-        todo!()
+    fn load_slot(&mut self, dst: u64, index: u64) {
+        self.load(dst, Registers::FP, -16 * (index as i32 + 1));
     }
 
-    fn add(&mut self, _a: u64, _b: u64) {
-        todo!()
+    fn store_slot(&mut self, src: u64, index: u64) {
+        self.store(src, Registers::FP, -16 * (index as i32 + 1));
     }
 
-    fn sub(&mut self, _a: u64, _b: u64) {
-        todo!()
+    fn add(&mut self, a: u64, b: u64) {
+        self.rrr(0x8b00_0000, a, a, b);
     }
 
-    fn mul(&mut self, _a: u64, _b: u64) {
-        todo!()
+    fn add_imm(&mut self, reg: u64, imm: i32) {
+        self.add_sub_imm(0x9100_0000, 0xd100_0000, reg, reg, imm);
     }
 
-    fn div(&mut self, _a: u64, _b: u64) {
-        todo!()
+    fn sub(&mut self, a: u64, b: u64) {
+        self.rrr(0xcb00_0000, a, a, b);
+    }
+
+    fn mul(&mut self, a: u64, b: u64) {
+        // madd a, a, b, xzr
+        self.rrr(0x9b00_7c00, a, a, b);
+    }
+
+    fn div(&mut self, a: u64, b: u64) {
+        self.rrr(0x9ac0_0c00, a, a, b);
+    }
+
+    fn shl(&mut self, reg: u64, bits: u8) {
+        // ubfm reg, reg, #(-bits mod 64), #(63 - bits)
+        let bits = bits as u32;
+        let immr = (64 - bits) % 64;
+        self.emit(0xd340_0000 | immr << 16 | (63 - bits) << 10 | (reg as u32) << 5 | reg as u32);
+    }
+
+    fn sar(&mut self, reg: u64, bits: u8) {
+        // sbfm reg, reg, #bits, #63
+        self.emit(0x9340_fc00 | (bits as u32) << 16 | (reg as u32) << 5 | reg as u32);
+    }
+
+    fn cmp(&mut self, a: u64, b: u64) {
+        // subs xzr, a, b
+        self.rrr(0xeb00_0000, Registers::XZR, a, b);
+    }
+
+    fn cmp_imm(&mut self, reg: u64, imm: i32) {
+        // subs xzr, reg, #imm, or adds of a negative one
+        self.add_sub_imm(0xf100_0000, 0xb100_0000, Registers::XZR, reg, imm);
     }
 
     fn label(&mut self) -> u64 {
-        todo!()
+        self.labels.push(None);
+        (self.labels.len() - 1) as u64
+    }
+
+    fn bind(&mut self, label: u64) {
+        self.labels[label as usize] = Some(self.codes.len());
     }
 
-    fn jmp(&mut self, _label: u64) {
-        todo!()
+    fn jmp(&mut self, label: u64) {
+        self.branch(0x1400_0000, label);
     }
 
-    fn call(&mut self, _label: u64) {
-        todo!()
+    fn jcc(&mut self, condition: Condition, label: u64) {
+        let code = match condition {
+            Condition::Equal => 0x0,
+            Condition::NotEqual => 0x1,
+            Condition::GreaterOrEqual => 0xa,
+            Condition::Less => 0xb,
+            Condition::Greater => 0xc,
+            Condition::LessOrEqual => 0xd,
+        };
+        // b.cond
+        self.branch(0x5400_0000 | code, label);
+    }
+
+    fn call(&mut self, label: u64) {
+        self.branch(0x9400_0000, label);
     }
 
     fn ret(&mut self) {
-        todo!()
+        self.emit(0xd65f_03c0);
+    }
+
+    fn argument_register(&self) -> u64 {
+        Registers::X0
+    }
+
+    fn scratch_register(&self) -> u64 {
+        Registers::X9
+    }
+
+    fn finalize(&mut self) -> Vec<u8> {
+        for &(at, label) in self.patches.iter() {
+            let target = self.labels[label as usize].expect("unbound label");
+            // in instructions, relative to the branch itself
+            let offset = (target as i64 - at as i64) / 4;
+            let mut instruction = u32::from_le_bytes(self.codes[at..at + 4].try_into().unwrap());
+            if instruction & 0xff00_0010 == 0x5400_0000 {
+                // b.cond has 19 bits above its condition
+                instruction |= (offset as u32 & 0x7_ffff) << 5;
+            } else {
+                instruction |= offset as u32 & 0x03ff_ffff;
+            }
+            self.codes[at..at + 4].copy_from_slice(&instruction.to_le_bytes());
+        }
+        self.codes.clone()
     }
 
     fn register_map(&self, reg: InlineAssembler64BitsRegister) -> u64 {
@@ -84,4 +293,119 @@ mod Registers {
     pub const X5: u64 = 5;
     pub const X6: u64 = 6;
     pub const X7: u64 = 7;
+    pub const X9: u64 = 9;
+    pub const X16: u64 = 16;
+    pub const FP: u64 = 29;
+    pub const LR: u64 = 30;
+    /// sp or xzr, depending on the instruction
+    pub const SP: u64 = 31;
+    pub const XZR: u64 = 31;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(f: impl FnOnce(&mut Arm64InlineAssembler)) -> Vec<u32> {
+        let mut asm = Arm64InlineAssembler::new();
+        f(&mut asm);
+        asm.finalize()
+            .chunks(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_encoding() {
+        use Registers::*;
+
+        assert_eq!(assemble(|a| a.add(X0, X1)), [0x8b01_0000]); // add x0, x0, x1
+        assert_eq!(assemble(|a| a.sub(X2, X7)), [0xcb07_0042]); // sub x2, x2, x7
+        assert_eq!(assemble(|a| a.mul(X9, X3)), [0x9b03_7d29]); // mul x9, x9, x3
+        assert_eq!(assemble(|a| a.div(X4, X5)), [0x9ac5_0c84]); // sdiv x4, x4, x5
+        assert_eq!(assemble(|a| a.mov(X0, X9)), [0xaa09_03e0]); // mov x0, x9
+        assert_eq!(assemble(|a| a.push(X1)), [0xa9bf_7fe1]); // stp x1, xzr, [sp, #-16]!
+        assert_eq!(assemble(|a| a.pop(X1)), [0xa8c1_7fe1]); // ldp x1, xzr, [sp], #16
+        assert_eq!(assemble(|a| a.load(X2, X9, 24)), [0xf940_0d22]); // ldr x2, [x9, #24]
+        assert_eq!(assemble(|a| a.load(X2, X9, -8)), [0xf85f_8122]); // ldur x2, [x9, #-8]
+        assert_eq!(assemble(|a| a.load_slot(X9, 0)), [0xf85f_03a9]); // ldur x9, [x29, #-16]
+
+        // offsets out of the range of the immediates go through x16
+        assert_eq!(
+            assemble(|a| a.load(X0, X9, -264)),
+            [0x9280_20f0, 0xf870_6920] // movn x16, #263; ldr x0, [x9, x16]
+        );
+        assert_eq!(
+            assemble(|a| a.load(X1, FP, 32768)),
+            [0xd290_0010, 0xf870_6ba1] // movz x16, #0x8000; ldr x1, [x29, x16]
+        );
+        assert_eq!(
+            assemble(|a| a.load_slot(X2, 16)),
+            [0x9280_21f0, 0xf870_6ba2] // movn x16, #271; ldr x2, [x29, x16]
+        );
+        assert_eq!(assemble(|a| a.load_byte(X9, X9, 0)), [0x3940_0129]); // ldrb w9, [x9]
+        assert_eq!(assemble(|a| a.store(X3, X9, 8)), [0xf900_0523]); // str x3, [x9, #8]
+        assert_eq!(assemble(|a| a.store_slot(X9, 1)), [0xf81e_03a9]); // stur x9, [x29, #-32]
+        assert_eq!(assemble(|a| a.add_imm(X9, -1)), [0xd100_0529]); // sub x9, x9, #1
+        assert_eq!(
+            assemble(|a| a.add_imm(X0, 5000)),
+            [0xd282_7110, 0x8b10_0000] // movz x16, #5000; add x0, x0, x16
+        );
+        assert_eq!(assemble(|a| a.shl(X9, 11)), [0xd375_d129]); // lsl x9, x9, #11
+        assert_eq!(assemble(|a| a.sar(X9, 11)), [0x934b_fd29]); // asr x9, x9, #11
+        assert_eq!(assemble(|a| a.cmp(X9, X0)), [0xeb00_013f]); // cmp x9, x0
+        assert_eq!(assemble(|a| a.cmp_imm(X0, 0)), [0xf100_001f]); // cmp x0, #0
+        assert_eq!(assemble(|a| a.cmp_imm(X0, -3)), [0xb100_0c1f]); // cmn x0, #3
+        assert_eq!(assemble(|a| a.ret()), [0xd65f_03c0]);
+        assert_eq!(
+            assemble(|a| {
+                a.enter();
+                a.leave();
+            }),
+            [
+                0xa9bf_7bfd, // stp x29, x30, [sp, #-16]!
+                0x9100_03fd, // mov x29, sp
+                0x9100_03bf, // mov sp, x29
+                0xa8c1_7bfd, // ldp x29, x30, [sp], #16
+            ]
+        );
+
+        // movz, then movk of the chunks that are not zero
+        assert_eq!(assemble(|a| a.mov_imm(X0, 0)), [0xd280_0000]);
+        assert_eq!(assemble(|a| a.mov_imm(X3, 42)), [0xd280_0543]);
+        assert_eq!(
+            assemble(|a| a.mov_imm(X1, 0x1234_0000_5678)),
+            [0xd28a_cf01, 0xf2c2_4681] // movz x1, #0x5678; movk x1, #0x1234, lsl #32
+        );
+        // movn for the negative ones
+        assert_eq!(assemble(|a| a.mov_imm(X2, -1)), [0x9280_0002]);
+        assert_eq!(assemble(|a| a.mov_imm(X2, -21)), [0x9280_0282]);
+        assert_eq!(
+            assemble(|a| a.mov_imm(X0, -0x10000)),
+            [0x929f_ffe0] // movn x0, #0xffff
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        // forward and backward branches count instructions from the branch
+        let code = assemble(|a| {
+            let end = a.label();
+            let start = a.label();
+            a.bind(start);
+            a.jmp(end);
+            a.call(start);
+            a.bind(end);
+            a.ret();
+        });
+        assert_eq!(code, [0x1400_0002, 0x97ff_ffff, 0xd65f_03c0]);
+
+        let code = assemble(|a| {
+            let start = a.label();
+            a.bind(start);
+            a.ret();
+            a.jcc(Condition::Less, start);
+        });
+        assert_eq!(code, [0xd65f_03c0, 0x54ff_ffeb]); // b.lt -1
+    }
 }
@@ -1,18 +1,64 @@
 pub(super) mod arm64;
+pub(super) mod x86_64;
 
-pub(crate) trait InlineAssembler<T> {
-    fn unsafe_transmute(&mut self) -> extern "C" fn() -> T;
+/// an assembler of one instruction set. registers are the numbers of `register_map`, two
+/// operand instructions write their first operand.
+pub(crate) trait InlineAssembler {
+    /// set up a frame that `leave` drops with everything pushed since.
+    fn enter(&mut self);
+    fn leave(&mut self);
+    fn mov_imm(&mut self, reg: u64, imm: i64);
+    fn mov(&mut self, dst: u64, src: u64);
     fn push(&mut self, reg: u64);
     fn pop(&mut self, reg: u64);
+    /// `dst = [base + offset]`
+    fn load(&mut self, dst: u64, base: u64, offset: i32);
+    /// `dst = [base + offset]` of one byte, zero extended.
+    fn load_byte(&mut self, dst: u64, base: u64, offset: i32);
+    /// `[base + offset] = src`
+    fn store(&mut self, src: u64, base: u64, offset: i32);
+    /// load the `index`th register pushed after `enter`.
+    fn load_slot(&mut self, dst: u64, index: u64);
+    /// overwrite the `index`th register pushed after `enter`.
+    fn store_slot(&mut self, src: u64, index: u64);
     fn add(&mut self, a: u64, b: u64);
+    fn add_imm(&mut self, reg: u64, imm: i32);
     fn sub(&mut self, a: u64, b: u64);
     fn mul(&mut self, a: u64, b: u64);
+    /// signed division, rounding toward zero. a zero divisor traps on x86-64 and gives 0 on arm64.
     fn div(&mut self, a: u64, b: u64);
+    fn shl(&mut self, reg: u64, bits: u8);
+    /// shift right, filling with the sign.
+    fn sar(&mut self, reg: u64, bits: u8);
+    /// compare `a` with `b` for the next `jcc`.
+    fn cmp(&mut self, a: u64, b: u64);
+    fn cmp_imm(&mut self, reg: u64, imm: i32);
+    /// a new label, placed by `bind`.
     fn label(&mut self) -> u64;
+    fn bind(&mut self, label: u64);
     fn jmp(&mut self, label: u64);
+    /// jump if the operands of the last compare meet `condition`.
+    fn jcc(&mut self, condition: Condition, label: u64);
     fn call(&mut self, label: u64);
     fn ret(&mut self);
     fn register_map(&self, reg: InlineAssembler64BitsRegister) -> u64;
+    /// the register of the first argument of the C calling convention.
+    fn argument_register(&self) -> u64;
+    /// a register none of the vm registers is mapped to.
+    fn scratch_register(&self) -> u64;
+    /// the machine code with the jumps patched. every label jumped to must be bound.
+    fn finalize(&mut self) -> Vec<u8>;
+}
+
+/// a relation of the operands of a compare, as signed integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
 }
 
 pub(crate) enum InlineAssembler64BitsRegister {
@@ -25,3 +71,20 @@ pub(crate) enum InlineAssembler64BitsRegister {
     R6,
     R7,
 }
+
+impl InlineAssembler64BitsRegister {
+    /// the register of a bytecode operand.
+    pub(crate) fn from_operand(operand: u8) -> Option<Self> {
+        Some(match operand {
+            0 => Self::R0,
+            1 => Self::R1,
+            2 => Self::R2,
+            3 => Self::R3,
+            4 => Self::R4,
+            5 => Self::R5,
+            6 => Self::R6,
+            7 => Self::R7,
+            _ => return None,
+        })
+    }
+}
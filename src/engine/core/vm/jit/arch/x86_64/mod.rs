@@ -0,0 +1,386 @@
+#![allow(dead_code)]
+
+use super::{Condition, InlineAssembler, InlineAssembler64BitsRegister};
+
+/// the vm registers live in caller-saved registers, so the code needs to save none.
+/// r11 is the scratch register, rax and rdx are spilled around `idiv`.
+pub(crate) struct X86_64InlineAssembler {
+    codes: Vec<u8>,
+    /// the offset of each label, `None` until it is bound
+    labels: Vec<Option<usize>>,
+    /// the rel32 operands to patch with the offset of their label
+    patches: Vec<(usize, u64)>,
+}
+
+impl X86_64InlineAssembler {
+    pub(crate) fn new() -> Self {
+        X86_64InlineAssembler {
+            codes: vec![],
+            labels: vec![],
+            patches: vec![],
+        }
+    }
+
+    /// REX prefix with W set. `reg` extends the ModRM reg field, `rm` the r/m field.
+    fn rex_w(&mut self, reg: u64, rm: u64) {
+        self.codes
+            .push(0x48 | (((reg >> 3) & 1) << 2) as u8 | ((rm >> 3) & 1) as u8);
+    }
+
+    /// ModRM of a register to register operation.
+    fn modrm(&mut self, reg: u64, rm: u64) {
+        self.codes
+            .push(0xc0 | ((reg & 7) << 3) as u8 | (rm & 7) as u8);
+    }
+
+    /// `op r/m64, r64`
+    fn alu(&mut self, op: u8, a: u64, b: u64) {
+        self.rex_w(b, a);
+        self.codes.push(op);
+        self.modrm(b, a);
+    }
+
+    /// a one byte opcode with the register in its low bits, such as push and pop.
+    fn short(&mut self, op: u8, reg: u64) {
+        if reg >= 8 {
+            self.codes.push(0x41);
+        }
+        self.codes.push(op + (reg & 7) as u8);
+    }
+
+    /// `op reg, [base + disp32]`, or the other way around for a store.
+    fn memory(&mut self, op: &[u8], reg: u64, base: u64, offset: i32) {
+        self.rex_w(reg, base);
+        self.codes.extend_from_slice(op);
+        self.codes
+            .push(0x80 | ((reg & 7) << 3) as u8 | (base & 7) as u8);
+        if base & 7 == Registers::RSP {
+            // rsp and r12 need a SIB byte
+            self.codes.push(0x24);
+        }
+        self.codes.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    /// `op r/m64, imm` of the group whose ModRM reg field is `ext`.
+    fn group(&mut self, op: u8, ext: u64, reg: u64) {
+        self.rex_w(0, reg);
+        self.codes.push(op);
+        self.modrm(ext, reg);
+    }
+
+    fn rel32(&mut self, op: u8, label: u64) {
+        self.codes.push(op);
+        self.patches.push((self.codes.len(), label));
+        self.codes.extend_from_slice(&[0; 4]);
+    }
+}
+
+impl InlineAssembler for X86_64InlineAssembler {
+    fn enter(&mut self) {
+        // push rbp; mov rbp, rsp
+        self.short(0x50, Registers::RBP);
+        self.mov(Registers::RBP, Registers::RSP);
+    }
+
+    fn leave(&mut self) {
+        // mov rsp, rbp; pop rbp
+        self.mov(Registers::RSP, Registers::RBP);
+        self.short(0x58, Registers::RBP);
+    }
+
+    fn mov_imm(&mut self, reg: u64, imm: i64) {
+        // movabs r64, imm64
+        self.rex_w(0, reg);
+        self.codes.push(0xb8 + (reg & 7) as u8);
+        self.codes.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    fn mov(&mut self, dst: u64, src: u64) {
+        self.alu(0x89, dst, src);
+    }
+
+    fn push(&mut self, reg: u64) {
+        self.short(0x50, reg);
+    }
+
+    fn pop(&mut self, reg: u64) {
+        self.short(0x58, reg);
+    }
+
+    fn load(&mut self, dst: u64, base: u64, offset: i32) {
+        // mov r64, [r/m64 + disp32]
+        self.memory(&[0x8b], dst, base, offset);
+    }
+
+    fn load_byte(&mut self, dst: u64, base: u64, offset: i32) {
+        // movzx r64, byte [r/m64 + disp32]
+        self.memory(&[0x0f, 0xb6], dst, base, offset);
+    }
+
+    fn store(&mut self, src: u64, base: u64, offset: i32) {
+        // mov [r/m64 + disp32], r64
+        self.memory(&[0x89], src, base, offset);
+    }
+
+    fn load_slot(&mut self, dst: u64, index: u64) {
+        self.load(dst, Registers::RBP, -8 * (index as i32 + 1));
+    }
+
+    fn store_slot(&mut self, src: u64, index: u64) {
+        self.store(src, Registers::RBP, -8 * (index as i32 + 1));
+    }
+
+    fn add(&mut self, a: u64, b: u64) {
+        self.alu(0x01, a, b);
+    }
+
+    fn add_imm(&mut self, reg: u64, imm: i32) {
+        // add r/m64, imm32
+        self.group(0x81, 0, reg);
+        self.codes.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    fn sub(&mut self, a: u64, b: u64) {
+        self.alu(0x29, a, b);
+    }
+
+    fn mul(&mut self, a: u64, b: u64) {
+        // imul r64, r/m64
+        self.rex_w(a, b);
+        self.codes.extend_from_slice(&[0x0f, 0xaf]);
+        self.modrm(a, b);
+    }
+
+    fn div(&mut self, a: u64, b: u64) {
+        // idiv divides rdx:rax, which may hold other operands
+        let scratch = self.scratch_register();
+        self.mov(scratch, b);
+        self.push(Registers::RAX);
+        self.push(Registers::RDX);
+        self.mov(Registers::RAX, a);
+        self.codes.extend_from_slice(&[0x48, 0x99]); // cqo
+        self.rex_w(0, scratch);
+        self.codes.push(0xf7);
+        self.modrm(7, scratch);
+        self.mov(scratch, Registers::RAX);
+        self.pop(Registers::RDX);
+        self.pop(Registers::RAX);
+        self.mov(a, scratch);
+    }
+
+    fn shl(&mut self, reg: u64, bits: u8) {
+        self.group(0xc1, 4, reg);
+        self.codes.push(bits);
+    }
+
+    fn sar(&mut self, reg: u64, bits: u8) {
+        self.group(0xc1, 7, reg);
+        self.codes.push(bits);
+    }
+
+    fn cmp(&mut self, a: u64, b: u64) {
+        self.alu(0x39, a, b);
+    }
+
+    fn cmp_imm(&mut self, reg: u64, imm: i32) {
+        // cmp r/m64, imm32
+        self.group(0x81, 7, reg);
+        self.codes.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    fn label(&mut self) -> u64 {
+        self.labels.push(None);
+        (self.labels.len() - 1) as u64
+    }
+
+    fn bind(&mut self, label: u64) {
+        self.labels[label as usize] = Some(self.codes.len());
+    }
+
+    fn jmp(&mut self, label: u64) {
+        self.rel32(0xe9, label);
+    }
+
+    fn jcc(&mut self, condition: Condition, label: u64) {
+        let code = match condition {
+            Condition::Equal => 0x4,
+            Condition::NotEqual => 0x5,
+            Condition::Less => 0xc,
+            Condition::GreaterOrEqual => 0xd,
+            Condition::LessOrEqual => 0xe,
+            Condition::Greater => 0xf,
+        };
+        self.codes.push(0x0f);
+        self.rel32(0x80 | code, label);
+    }
+
+    fn call(&mut self, label: u64) {
+        self.rel32(0xe8, label);
+    }
+
+    fn ret(&mut self) {
+        self.codes.push(0xc3);
+    }
+
+    fn register_map(&self, reg: InlineAssembler64BitsRegister) -> u64 {
+        match reg {
+            InlineAssembler64BitsRegister::R0 => Registers::RAX,
+            InlineAssembler64BitsRegister::R1 => Registers::RCX,
+            InlineAssembler64BitsRegister::R2 => Registers::RDX,
+            InlineAssembler64BitsRegister::R3 => Registers::RSI,
+            InlineAssembler64BitsRegister::R4 => Registers::RDI,
+            InlineAssembler64BitsRegister::R5 => Registers::R8,
+            InlineAssembler64BitsRegister::R6 => Registers::R9,
+            InlineAssembler64BitsRegister::R7 => Registers::R10,
+        }
+    }
+
+    fn argument_register(&self) -> u64 {
+        Registers::RDI
+    }
+
+    fn scratch_register(&self) -> u64 {
+        Registers::R11
+    }
+
+    fn finalize(&mut self) -> Vec<u8> {
+        for &(at, label) in self.patches.iter() {
+            let target = self.labels[label as usize].expect("unbound label");
+            // relative to the end of the instruction
+            let offset = target as i64 - (at + 4) as i64;
+            self.codes[at..at + 4].copy_from_slice(&(offset as i32).to_le_bytes());
+        }
+        self.codes.clone()
+    }
+}
+
+#[allow(non_snake_case)]
+mod Registers {
+    pub const RAX: u64 = 0;
+    pub const RCX: u64 = 1;
+    pub const RDX: u64 = 2;
+    pub const RBX: u64 = 3;
+    pub const RSP: u64 = 4;
+    pub const RBP: u64 = 5;
+    pub const RSI: u64 = 6;
+    pub const RDI: u64 = 7;
+    pub const R8: u64 = 8;
+    pub const R9: u64 = 9;
+    pub const R10: u64 = 10;
+    pub const R11: u64 = 11;
+    pub const R12: u64 = 12;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(f: impl FnOnce(&mut X86_64InlineAssembler)) -> Vec<u8> {
+        let mut asm = X86_64InlineAssembler::new();
+        f(&mut asm);
+        asm.finalize()
+    }
+
+    #[test]
+    fn test_encoding() {
+        use Registers::*;
+
+        assert_eq!(assemble(|a| a.push(RAX)), [0x50]);
+        assert_eq!(assemble(|a| a.push(R10)), [0x41, 0x52]);
+        assert_eq!(assemble(|a| a.pop(RDI)), [0x5f]);
+        assert_eq!(assemble(|a| a.pop(R8)), [0x41, 0x58]);
+        assert_eq!(
+            assemble(|a| a.mov_imm(RCX, -2)),
+            [0x48, 0xb9, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            assemble(|a| a.mov_imm(R9, 1)),
+            [0x49, 0xb9, 1, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(assemble(|a| a.mov(RAX, R11)), [0x4c, 0x89, 0xd8]);
+        assert_eq!(
+            assemble(|a| a.load(RAX, R11, 16)),
+            [0x49, 0x8b, 0x83, 0x10, 0, 0, 0] // mov rax, [r11 + 16]
+        );
+        assert_eq!(
+            assemble(|a| a.load(R9, R12, -8)),
+            [0x4d, 0x8b, 0x8c, 0x24, 0xf8, 0xff, 0xff, 0xff] // mov r9, [r12 - 8]
+        );
+        assert_eq!(
+            assemble(|a| a.load_slot(R11, 1)),
+            [0x4c, 0x8b, 0x9d, 0xf0, 0xff, 0xff, 0xff] // mov r11, [rbp - 16]
+        );
+        assert_eq!(
+            assemble(|a| a.load_byte(RCX, R11, 0)),
+            [0x49, 0x0f, 0xb6, 0x8b, 0, 0, 0, 0] // movzx rcx, byte [r11]
+        );
+        assert_eq!(
+            assemble(|a| a.store(R10, RSP, 8)),
+            [0x4c, 0x89, 0x94, 0x24, 8, 0, 0, 0] // mov [rsp + 8], r10
+        );
+        assert_eq!(
+            assemble(|a| a.store_slot(RAX, 2)),
+            [0x48, 0x89, 0x85, 0xe8, 0xff, 0xff, 0xff] // mov [rbp - 24], rax
+        );
+        assert_eq!(assemble(|a| a.add(RAX, RCX)), [0x48, 0x01, 0xc8]);
+        assert_eq!(
+            assemble(|a| a.add_imm(R11, -1)),
+            [0x49, 0x81, 0xc3, 0xff, 0xff, 0xff, 0xff] // add r11, -1
+        );
+        assert_eq!(assemble(|a| a.shl(R11, 11)), [0x49, 0xc1, 0xe3, 11]);
+        assert_eq!(assemble(|a| a.sar(RDX, 11)), [0x48, 0xc1, 0xfa, 11]);
+        assert_eq!(assemble(|a| a.cmp(R11, RAX)), [0x49, 0x39, 0xc3]); // cmp r11, rax
+        assert_eq!(
+            assemble(|a| a.cmp_imm(RCX, 0)),
+            [0x48, 0x81, 0xf9, 0, 0, 0, 0] // cmp rcx, 0
+        );
+        assert_eq!(assemble(|a| a.add(R8, RDX)), [0x49, 0x01, 0xd0]);
+        assert_eq!(assemble(|a| a.sub(RSI, RDI)), [0x48, 0x29, 0xfe]);
+        assert_eq!(assemble(|a| a.mul(RAX, R10)), [0x49, 0x0f, 0xaf, 0xc2]);
+        assert_eq!(assemble(|a| a.mul(R11, RCX)), [0x4c, 0x0f, 0xaf, 0xd9]);
+        assert_eq!(
+            assemble(|a| a.div(RCX, RDX)),
+            [
+                0x49, 0x89, 0xd3, // mov r11, rdx
+                0x50, 0x52, // push rax; push rdx
+                0x48, 0x89, 0xc8, // mov rax, rcx
+                0x48, 0x99, // cqo
+                0x49, 0xf7, 0xfb, // idiv r11
+                0x49, 0x89, 0xc3, // mov r11, rax
+                0x5a, 0x58, // pop rdx; pop rax
+                0x4c, 0x89, 0xd9, // mov rcx, r11
+            ]
+        );
+        assert_eq!(
+            assemble(|a| {
+                a.enter();
+                a.leave();
+                a.ret();
+            }),
+            [0x55, 0x48, 0x89, 0xe5, 0x48, 0x89, 0xec, 0x5d, 0xc3]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        // forward and backward jumps are patched relative to the next instruction
+        let code = assemble(|a| {
+            let end = a.label();
+            let start = a.label();
+            a.bind(start);
+            a.jmp(end);
+            a.call(start);
+            a.bind(end);
+            a.ret();
+        });
+        assert_eq!(code, [0xe9, 5, 0, 0, 0, 0xe8, 0xf6, 0xff, 0xff, 0xff, 0xc3]);
+
+        let code = assemble(|a| {
+            let end = a.label();
+            a.jcc(Condition::Less, end);
+            a.jcc(Condition::NotEqual, end);
+            a.bind(end);
+        });
+        assert_eq!(code, [0x0f, 0x8c, 6, 0, 0, 0, 0x0f, 0x85, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::Error;
+
+/// machine code in pages of its own. the pages are writable while the code is copied in,
+/// then only executable (W^X).
+pub(crate) struct ExecutableMemory {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl ExecutableMemory {
+    #[cfg(unix)]
+    pub(crate) fn new(code: &[u8]) -> Result<Self, Error> {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = (code.len().max(1) + page - 1) / page * page;
+
+        unsafe {
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return Err(Error::last_os_error());
+            }
+            let memory = ExecutableMemory {
+                ptr: ptr as *mut u8,
+                len,
+            };
+
+            std::ptr::copy_nonoverlapping(code.as_ptr(), memory.ptr, code.len());
+            // arm64 fetches instructions through a cache of its own
+            #[cfg(target_arch = "aarch64")]
+            __clear_cache(memory.ptr as *mut _, memory.ptr.add(code.len()) as *mut _);
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(memory)
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn new(_code: &[u8]) -> Result<Self, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "executable memory is only supported on unix",
+        ))
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+}
+
+#[cfg(all(unix, target_arch = "aarch64"))]
+extern "C" {
+    fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
@@ -1,37 +1,1268 @@
-use self::arch::{arm64::Arm64InlineAssembler, InlineAssembler};
+use std::collections::{HashMap, HashSet};
+
+use self::{
+    arch::{
+        arm64::Arm64InlineAssembler, x86_64::X86_64InlineAssembler, Condition, InlineAssembler,
+        InlineAssembler64BitsRegister,
+    },
+    memory::ExecutableMemory,
+};
+
+use super::{
+    bytecodes::{decode, Bytecodes, Instruction},
+    function::FunctionCode,
+    value::Value,
+};
+use crate::engine::core::limits::Budget;
 
 mod arch;
+mod memory;
+
+/// calls of a function before it is compiled.
+const CALL_THRESHOLD: u32 = 64;
+/// iterations of one loop before the function around it is compiled.
+const LOOP_THRESHOLD: u32 = 1024;
+/// calls handed back to the interpreter before the function stays there.
+const MAX_BAILOUTS: u32 = 64;
+
+/// integers of this many bits are exact in a double. larger results are not compiled, so
+/// the integer arithmetic of the jit gives the numbers the interpreter would.
+const MAX_BITS: u32 = 53;
+/// factors of this many bits have an exact product.
+const HALF_BITS: u32 = MAX_BITS / 2;
+
+/// the results of the machine code that are no integer. an integer has at most `MAX_BITS`
+/// bits, so it is none of these.
+const BAILOUT: i64 = i64::MIN;
+const FALSE: i64 = i64::MIN + 1;
+const TRUE: i64 = i64::MIN + 2;
+const UNDEFINED: i64 = i64::MIN + 3;
+
+/// the slots of a frame: the environment the code is called with, the loop iterations left,
+/// then the parameters and the names the code declares. the environment holds the address of
+/// the interrupt flag, the iterations the loops may run and the arguments.
+const ENVIRONMENT_SLOT: u64 = 0;
+const FUEL_SLOT: u64 = 1;
+const FIRST_LOCAL_SLOT: u64 = 2;
 
-pub(crate) fn jit_compile<T>(code: &[u8], isa: Isa) -> extern "C" fn() -> T {
-    let mut compiler = JitCompiler::new(code, isa);
-    compiler.compile()
+/// compile `code` to machine code of `isa`. `None` when the code uses bytecodes the jit
+/// does not compile, or the code can not be made executable.
+///
+/// the jit computes with the integers of smis, and with booleans as 0 and 1: the parameters,
+/// the names the code declares and immediate numbers. code without jumps runs straight to its
+/// first `Return`, its arguments may have as many bits as keep every result within `MAX_BITS`.
+/// code with jumps checks its results as it runs instead, and what the jit does not compile
+/// bails out: the call is handed back to the interpreter, which runs it from the start. the
+/// machine code writes nothing but its frame, so nothing of the bailed out run is seen.
+pub(crate) fn jit_compile(code: &[u8], parameters: &[String], isa: Isa) -> Option<JitFunction> {
+    (1..=32).rev().find_map(|argument_bits| {
+        let mut compiler = JitCompiler::new(code, parameters, argument_bits, isa)?;
+        compiler.compile()
+    })
 }
 
+#[derive(Clone, Copy)]
 pub(crate) enum Isa {
     Arm64,
+    X86_64,
+}
+
+impl Isa {
+    /// the instruction set of the machine running the vm.
+    pub(crate) fn host() -> Option<Isa> {
+        if cfg!(target_arch = "x86_64") {
+            Some(Isa::X86_64)
+        } else if cfg!(target_arch = "aarch64") {
+            Some(Isa::Arm64)
+        } else {
+            None
+        }
+    }
+
+    fn assembler(self) -> Box<dyn InlineAssembler> {
+        match self {
+            Isa::Arm64 => Box::new(Arm64InlineAssembler::new()),
+            Isa::X86_64 => Box::new(X86_64InlineAssembler::new()),
+        }
+    }
+}
+
+/// compiled code. the code lives as long as the function.
+pub(crate) struct JitFunction {
+    memory: ExecutableMemory,
+    parameters: usize,
+    /// the bits an argument may have
+    argument_bits: u32,
+    /// a multiplication makes -0 of 0 and a negative number, the integers can not tell
+    has_mul: bool,
+}
+
+impl JitFunction {
+    /// run the code with `args`. `None` when the interpreter has to run the function
+    /// instead: an argument is not a smi or too large, the code bailed out, or the result
+    /// may be -0. the iterations of the loops are steps of `budget`.
+    pub(crate) fn call(&self, args: &[Value], budget: &mut Budget) -> Option<Value> {
+        let args = (0..self.parameters)
+            .map(|i| {
+                let n = args.get(i)?.as_smi()? as i64;
+                (bits(n) <= self.argument_bits).then_some(n)
+            })
+            .collect::<Option<Vec<i64>>>()?;
+        let fuel = budget.jit_fuel() as i64;
+        let mut environment = vec![budget.interrupt().as_ptr() as i64, fuel];
+        environment.extend(args);
+
+        // the code is a complete function of the C calling convention
+        let entry: extern "C" fn(*mut i64) -> i64 =
+            unsafe { std::mem::transmute(self.memory.as_ptr()) };
+        let result = entry(environment.as_mut_ptr());
+        budget.charge((fuel - environment[1].max(0)) as u64);
+        match result {
+            BAILOUT => None,
+            FALSE => Some(Value::boolean(false)),
+            TRUE => Some(Value::boolean(true)),
+            UNDEFINED => Some(Value::undefined()),
+            0 if self.has_mul => None,
+            n => Some(Value::number(n as f64)),
+        }
+    }
+}
+
+/// how hot a function is and, once it is hot, its machine code.
+#[derive(Default)]
+pub(crate) struct JitState {
+    calls: u32,
+    /// iterations of each loop, by the pc after its `JumpLoop`
+    loops: HashMap<usize, u32>,
+    /// calls the machine code handed back to the interpreter
+    bailouts: u32,
+    tier: Tier,
+}
+
+#[derive(Default)]
+enum Tier {
+    #[default]
+    Interpreted,
+    Compiled(JitFunction),
+    /// the function stays in the interpreter
+    Unsupported,
 }
 
-struct JitCompiler<T> {
-    code: Vec<u8>,
-    arch: Box<dyn InlineAssembler<T>>,
+impl JitState {
+    /// count a call of `function`, compiling it on the call that makes it hot.
+    pub(crate) fn enter(&mut self, function: &FunctionCode) -> Option<&JitFunction> {
+        if let Tier::Interpreted = self.tier {
+            self.calls += 1;
+            if self.calls >= CALL_THRESHOLD {
+                self.compile(function);
+            }
+        }
+        match &self.tier {
+            Tier::Compiled(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// count a call of `function` and run its machine code, if it has some. `None` when the
+    /// interpreter runs the call. a function whose calls bail out again and again stays in
+    /// the interpreter.
+    pub(crate) fn call(
+        &mut self,
+        function: &FunctionCode,
+        args: &[Value],
+        budget: &mut Budget,
+    ) -> Option<Value> {
+        let result = self.enter(function)?.call(args, budget);
+        if result.is_none() {
+            self.bailouts += 1;
+            if self.bailouts >= MAX_BAILOUTS {
+                self.tier = Tier::Unsupported;
+            }
+        }
+        result
+    }
+
+    /// count an iteration of the loop ending at `pc`. a hot loop compiles its function for
+    /// the next calls.
+    pub(crate) fn iterate(&mut self, function: &FunctionCode, pc: usize) {
+        if let Tier::Interpreted = self.tier {
+            let count = self.loops.entry(pc).or_default();
+            *count += 1;
+            if *count >= LOOP_THRESHOLD {
+                self.compile(function);
+            }
+        }
+    }
+
+    pub(crate) fn is_compiled(&self) -> bool {
+        matches!(self.tier, Tier::Compiled(_))
+    }
+
+    fn compile(&mut self, function: &FunctionCode) {
+        let compiled =
+            Isa::host().and_then(|isa| jit_compile(&function.code, &function.parameters, isa));
+        self.tier = match compiled {
+            Some(f) => Tier::Compiled(f),
+            None => Tier::Unsupported,
+        };
+    }
+}
+
+/// what the compiler knows of a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bound {
+    /// a bound of the bits of its magnitude
+    bits: u32,
+    kind: Kind,
 }
 
-impl<T> JitCompiler<T> {
-    fn new(code: &[u8], isa: Isa) -> Self {
-        match isa {
-            Isa::Arm64 => JitCompiler {
-                arch: Box::new(Arm64InlineAssembler::new()),
-                code: code.to_vec(),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Integer,
+    /// 0 or 1
+    Boolean,
+    /// an integer on some paths and a boolean on others
+    Either,
+    /// held as 0
+    Undefined,
+    /// undefined on some paths, or a register the code did not set
+    Unknown,
+}
+
+impl Bound {
+    const BOOLEAN: Bound = Bound {
+        bits: 1,
+        kind: Kind::Boolean,
+    };
+
+    const UNDEFINED: Bound = Bound {
+        bits: 0,
+        kind: Kind::Undefined,
+    };
+    const UNKNOWN: Bound = Bound {
+        bits: MAX_BITS,
+        kind: Kind::Unknown,
+    };
+
+    fn integer(bits: u32) -> Self {
+        Bound {
+            bits,
+            kind: Kind::Integer,
+        }
+    }
+
+    fn join(self, other: Bound) -> Bound {
+        Bound {
+            bits: self.bits.max(other.bits),
+            kind: match (self.kind, other.kind) {
+                (a, b) if a == b => a,
+                (a, b) if Bound::number(a) && Bound::number(b) => Kind::Either,
+                _ => Kind::Unknown,
+            },
+        }
+    }
+
+    /// a value of `kind` is a number to the arithmetic and the comparisons.
+    fn number(kind: Kind) -> bool {
+        matches!(kind, Kind::Integer | Kind::Boolean | Kind::Either)
+    }
+}
+
+/// what the compiler knows where an instruction starts: the registers, the pushed values and
+/// the slots of the frame. a slot is `None` when the name of the slot is not assigned on
+/// every path, the interpreter would find another declaration of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    registers: [Bound; 8],
+    stack: Vec<Bound>,
+    slots: Vec<Option<Bound>>,
+    /// the contexts pushed since the function started
+    depth: usize,
+}
+
+impl State {
+    /// what holds on either path. `None` if the paths disagree on the stack or the contexts,
+    /// which the code generator does not do.
+    fn join(&self, other: &State) -> Option<State> {
+        if self.stack.len() != other.stack.len() || self.depth != other.depth {
+            return None;
+        }
+        let join = |a: &[Bound], b: &[Bound]| -> Vec<Bound> {
+            a.iter().zip(b).map(|(a, b)| a.join(*b)).collect()
+        };
+        let slots = self.slots.iter().zip(&other.slots);
+        Some(State {
+            registers: join(&self.registers, &other.registers).try_into().ok()?,
+            stack: join(&self.stack, &other.stack),
+            slots: slots
+                .map(|(a, b)| Some(a.as_ref()?.join(*b.as_ref()?)))
+                .collect(),
+            depth: self.depth,
+        })
+    }
+
+    /// `join`, where a value that grew may have `MAX_BITS`. a loop started from the wider
+    /// state ends in it, instead of growing by a bit on every pass over the loop.
+    fn widen(&self, other: &State) -> Option<State> {
+        let mut state = self.join(other)?;
+        let widen = |to: &mut Bound, from: &Bound| {
+            if to.bits > from.bits {
+                to.bits = MAX_BITS;
+            }
+        };
+        let registers = state.registers.iter_mut().zip(&self.registers);
+        let stack = state.stack.iter_mut().zip(&self.stack);
+        registers
+            .chain(stack)
+            .for_each(|(to, from)| widen(to, from));
+        for (to, from) in state.slots.iter_mut().zip(&self.slots) {
+            if let (Some(to), Some(from)) = (to, from) {
+                widen(to, from);
+            }
+        }
+        Some(state)
+    }
+}
+
+/// add the paths of `state` to `into`.
+fn merge(into: &mut Option<State>, state: &State) -> Option<()> {
+    *into = Some(match into {
+        Some(into) => into.join(state)?,
+        None => state.clone(),
+    });
+    Some(())
+}
+
+/// the names declared in the contexts of the function, the innermost last. the code
+/// generator nests the contexts in the order of the code, so a name is resolved where the
+/// compiler meets it to the declaration the interpreter would find.
+struct Scopes {
+    contexts: Vec<Vec<Local>>,
+    /// the slot of the next name
+    next: u64,
+}
+
+struct Local {
+    name: Vec<u8>,
+    slot: u64,
+    constant: bool,
+}
+
+impl Scopes {
+    fn new() -> Self {
+        Scopes {
+            contexts: vec![vec![]],
+            next: FIRST_LOCAL_SLOT,
+        }
+    }
+
+    /// the slot of `name` declared in the innermost context, which a second declaration
+    /// shares. `None` if it is a constant already, which the interpreter throws for.
+    fn declare(&mut self, name: &[u8], constant: bool) -> Option<u64> {
+        let context = self.contexts.last_mut()?;
+        if let Some(local) = context.iter_mut().find(|l| l.name == name) {
+            if local.constant {
+                return None;
+            }
+            local.constant = constant;
+            return Some(local.slot);
+        }
+        let slot = self.next;
+        self.next += 1;
+        context.push(Local {
+            name: name.to_vec(),
+            slot,
+            constant,
+        });
+        Some(slot)
+    }
+
+    fn resolve(&self, name: &[u8]) -> Option<&Local> {
+        self.contexts
+            .iter()
+            .rev()
+            .flat_map(|context| context.iter())
+            .find(|l| l.name == name)
+    }
+
+    fn push(&mut self) {
+        self.contexts.push(vec![]);
+    }
+
+    /// the names of the innermost context go out of scope.
+    fn pop(&mut self) -> Option<Vec<Local>> {
+        match self.contexts.len() {
+            1 => None,
+            _ => self.contexts.pop(),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.contexts.len() - 1
+    }
+}
+
+/// where the code goes after an instruction.
+enum Flow {
+    Next(State),
+    /// it jumped or returned
+    Stop,
+}
+
+/// the right operand of an arithmetic instruction.
+enum Right {
+    Register(u8),
+    Immediate(i64),
+}
+
+struct JitCompiler<'a> {
+    code: &'a [u8],
+    parameters: &'a [String],
+    argument_bits: u32,
+    isa: Isa,
+    arch: Box<dyn InlineAssembler>,
+    /// the code jumps: the results are checked as the code runs, and what the jit does not
+    /// compile bails out, it may be on a path that is never taken
+    checked: bool,
+    /// the instructions jumps land on, and those that `JumpLoop`s land on
+    targets: HashSet<usize>,
+    loop_starts: HashSet<usize>,
+    /// the slots of the frame
+    slots: u64,
+    /// the states a loop is compiled for, wider than the states before it
+    loops: HashMap<usize, State>,
+    has_mul: bool,
+
+    // a pass over the code
+    labels: HashMap<usize, u64>,
+    bailout: u64,
+    exit: u64,
+    scopes: Scopes,
+    /// the states of the jumps to each instruction further on
+    pending: HashMap<usize, State>,
+    /// the state each loop started in
+    entries: HashMap<usize, State>,
+    /// a loop ended in a state it was not compiled for
+    widened: bool,
+}
+
+impl<'a> JitCompiler<'a> {
+    /// `None` if the code jumps anywhere but to an instruction or its end, or backwards
+    /// with another jump than `JumpLoop`.
+    fn new(code: &'a [u8], parameters: &'a [String], argument_bits: u32, isa: Isa) -> Option<Self> {
+        let mut starts = HashSet::new();
+        let mut targets = HashSet::new();
+        let mut loop_starts = HashSet::new();
+        let mut declarations = 0;
+        let mut pc = 0;
+        while pc < code.len() {
+            let instruction = decode(code, pc).ok()?;
+            let end = pc + instruction.len;
+            match instruction.opcode {
+                Bytecodes::JumpLoop => {
+                    let target = end.checked_sub(usize::try_from(instruction.operands[0]).ok()?)?;
+                    if target > pc {
+                        return None;
+                    }
+                    targets.insert(target);
+                    loop_starts.insert(target);
+                }
+                Bytecodes::Jump
+                | Bytecodes::JumpIfTrue
+                | Bytecodes::JumpIfFalse
+                | Bytecodes::JumpIfNotUndefined => {
+                    targets.insert(end + usize::try_from(instruction.operands[0]).ok()?);
+                }
+                Bytecodes::StaContextSlot | Bytecodes::StaConstContextSlot => declarations += 1,
+                _ => {}
+            }
+            starts.insert(pc);
+            pc = end;
+        }
+        if !targets
+            .iter()
+            .all(|t| starts.contains(t) || *t == code.len())
+        {
+            return None;
+        }
+
+        let arch = isa.assembler();
+        Some(JitCompiler {
+            code,
+            parameters,
+            argument_bits,
+            isa,
+            arch,
+            checked: !targets.is_empty(),
+            targets,
+            loop_starts,
+            slots: FIRST_LOCAL_SLOT + parameters.len() as u64 + declarations,
+            loops: HashMap::new(),
+            has_mul: false,
+            labels: HashMap::new(),
+            bailout: 0,
+            exit: 0,
+            scopes: Scopes::new(),
+            pending: HashMap::new(),
+            entries: HashMap::new(),
+            widened: false,
+        })
+    }
+
+    fn compile(&mut self) -> Option<JitFunction> {
+        // a pass that widens the state of a loop compiles the code again
+        while !self.generate()? {}
+        let code = self.arch.finalize();
+        let memory = ExecutableMemory::new(&code).ok()?;
+        Some(JitFunction {
+            memory,
+            parameters: self.parameters.len(),
+            argument_bits: self.argument_bits,
+            has_mul: self.has_mul,
+        })
+    }
+
+    /// a pass over the code. `Some(false)` if the state of a loop was widened.
+    fn generate(&mut self) -> Option<bool> {
+        self.arch = self.isa.assembler();
+        self.has_mul = false;
+        self.labels.clear();
+        for &target in self.targets.iter() {
+            self.labels.insert(target, self.arch.label());
+        }
+        self.bailout = self.arch.label();
+        self.exit = self.arch.label();
+        self.scopes = Scopes::new();
+        self.pending.clear();
+        self.entries.clear();
+        self.widened = false;
+
+        let mut state = Some(self.prologue()?);
+        let code = self.code;
+        let mut pc = 0;
+        while pc <= code.len() {
+            // the paths into the instruction
+            let mut incoming = state.take();
+            if let Some(pending) = self.pending.remove(&pc) {
+                merge(&mut incoming, &pending)?;
+            }
+            if self.loop_starts.contains(&pc) {
+                if let Some(assumed) = self.loops.get(&pc).cloned() {
+                    merge(&mut incoming, &assumed)?;
+                }
+                if let Some(entry) = &incoming {
+                    self.entries.insert(pc, entry.clone());
+                }
+            }
+            if let Some(&label) = self.labels.get(&pc) {
+                self.arch.bind(label);
+            }
+
+            if pc == code.len() {
+                // the end returns like `Return`
+                if let Some(state) = incoming {
+                    self.ret(&state)?;
+                }
+                break;
+            }
+            let instruction = decode(code, pc).ok()?;
+            state = match incoming {
+                Some(incoming) => match self.instruction(pc, &instruction, incoming)? {
+                    Flow::Next(state) => Some(state),
+                    Flow::Stop => None,
+                },
+                None => {
+                    // nothing runs it, but it declares its names all the same
+                    self.declare(pc, &instruction);
+                    None
+                }
+            };
+            if self.widened {
+                return Some(false);
+            }
+            pc += instruction.len;
+        }
+
+        let r0 = self.register(0)?;
+        let scratch = self.arch.scratch_register();
+        self.arch.bind(self.bailout);
+        self.arch.mov_imm(r0, BAILOUT);
+        self.arch.bind(self.exit);
+        // hand back the iterations left
+        self.arch.push(r0);
+        self.arch.load_slot(r0, FUEL_SLOT);
+        self.arch.load_slot(scratch, ENVIRONMENT_SLOT);
+        self.arch.store(r0, scratch, 8);
+        self.arch.pop(r0);
+        self.arch.leave();
+        self.arch.ret();
+        Some(true)
+    }
+
+    /// set up the frame, and the state the code starts in.
+    fn prologue(&mut self) -> Option<State> {
+        let scratch = self.arch.scratch_register();
+        let environment = self.arch.argument_register();
+        self.arch.enter();
+        self.arch.push(environment);
+        self.arch.load(scratch, environment, 8);
+        for _ in FUEL_SLOT..self.slots {
+            self.arch.push(scratch);
+        }
+
+        let mut slots = vec![None; self.slots as usize];
+        // the last of parameters with the same name wins
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            let slot = self.scopes.declare(parameter.as_bytes(), false)?;
+            self.arch.load(scratch, environment, 16 + 8 * i as i32);
+            self.arch.store_slot(scratch, slot);
+            slots[slot as usize] = Some(Bound::integer(self.argument_bits));
+        }
+        Some(State {
+            registers: [Bound::UNKNOWN; 8],
+            stack: vec![],
+            slots,
+            depth: 0,
+        })
+    }
+
+    fn instruction(
+        &mut self,
+        pc: usize,
+        instruction: &Instruction,
+        mut state: State,
+    ) -> Option<Flow> {
+        if state.depth != self.scopes.depth() {
+            return None;
+        }
+        let r0 = self.register(0)?;
+        let opcode = instruction.opcode;
+        let operands = &instruction.operands;
+        let end = pc + instruction.len;
+
+        match opcode {
+            Bytecodes::Mov => {
+                let r = operands[0] as u8;
+                let value = Value::from_bits(operands[1] as u64);
+                if let Some(n) = value.as_smi() {
+                    return self.set(state, r, n as i64);
+                }
+                let bound = match value.as_boolean() {
+                    Some(b) => {
+                        self.arch.mov_imm(self.register(r)?, b as i64);
+                        Bound::BOOLEAN
+                    }
+                    None if value.is_undefined() => {
+                        self.arch.mov_imm(self.register(r)?, 0);
+                        Bound::UNDEFINED
+                    }
+                    None => return self.bailout(),
+                };
+                state.registers[r as usize] = bound;
+            }
+            Bytecodes::Star0..=Bytecodes::Star7 => {
+                let r = opcode - Bytecodes::Star0;
+                self.arch.mov(self.register(r)?, r0);
+                state.registers[r as usize] = state.registers[0];
+            }
+            Bytecodes::Ldar => {
+                let r = operands[0] as u8;
+                self.arch.mov(r0, self.register(r)?);
+                state.registers[0] = state.registers[r as usize];
+            }
+            Bytecodes::LdaSmi => return self.set(state, 0, operands[0]),
+            Bytecodes::LdaUndefined => {
+                self.arch.mov_imm(r0, 0);
+                state.registers[0] = Bound::UNDEFINED;
+            }
+            Bytecodes::LdaTrue | Bytecodes::LdaFalse => {
+                let b = opcode == Bytecodes::LdaTrue;
+                self.arch.mov_imm(r0, b as i64);
+                state.registers[0] = Bound::BOOLEAN;
+            }
+
+            Bytecodes::LdaContextSlot => match self.scopes.resolve(self.name(end, instruction)) {
+                Some(local) if state.slots[local.slot as usize].is_some() => {
+                    let slot = local.slot;
+                    self.arch.load_slot(r0, slot);
+                    state.registers[0] = state.slots[slot as usize]?;
+                }
+                // `this`, or a name of the closure or the global object
+                _ => return self.bailout(),
             },
+            Bytecodes::StaContextSlot | Bytecodes::StaConstContextSlot => {
+                let constant = opcode == Bytecodes::StaConstContextSlot;
+                match self.scopes.declare(self.name(end, instruction), constant) {
+                    Some(slot) => {
+                        self.arch.store_slot(r0, slot);
+                        state.slots[slot as usize] = Some(state.registers[0]);
+                    }
+                    None => return self.bailout(),
+                }
+            }
+            Bytecodes::StaLookupSlot => match self.scopes.resolve(self.name(end, instruction)) {
+                Some(local) if !local.constant && state.slots[local.slot as usize].is_some() => {
+                    let slot = local.slot;
+                    self.arch.store_slot(r0, slot);
+                    state.slots[slot as usize] = Some(state.registers[0]);
+                }
+                _ => return self.bailout(),
+            },
+            Bytecodes::PushContext => {
+                self.scopes.push();
+                state.depth += 1;
+            }
+            Bytecodes::PopContext => {
+                for local in self.scopes.pop()? {
+                    state.slots[local.slot as usize] = None;
+                }
+                state.depth -= 1;
+            }
+
+            Bytecodes::Push => {
+                let r = operands[0] as u8;
+                self.arch.push(self.register(r)?);
+                state.stack.push(state.registers[r as usize]);
+            }
+            Bytecodes::Pop => {
+                let r = operands[0] as u8;
+                self.arch.pop(self.register(r)?);
+                state.registers[r as usize] = state.stack.pop()?;
+            }
+
+            // r0 = r1 op r2, either may be r0
+            Bytecodes::Add | Bytecodes::Sub | Bytecodes::Mul => {
+                let right = Right::Register(operands[1] as u8);
+                return self.arithmetic(state, opcode, operands[0] as u8, right);
+            }
+            // r0 = r op n
+            Bytecodes::AddSmi | Bytecodes::SubSmi | Bytecodes::MulSmi => {
+                let right = Right::Immediate(operands[1]);
+                return self.arithmetic(state, opcode, operands[0] as u8, right);
+            }
+            Bytecodes::Inc | Bytecodes::Dec => {
+                let op = match opcode {
+                    Bytecodes::Inc => Bytecodes::AddSmi,
+                    _ => Bytecodes::SubSmi,
+                };
+                return self.arithmetic(state, op, operands[0] as u8, Right::Immediate(1));
+            }
+            // a boolean is the number 0 or 1
+            Bytecodes::ToNumeric => {
+                let r = operands[0] as u8;
+                if !Bound::number(state.registers[r as usize].kind) {
+                    return self.bailout();
+                }
+                self.arch.mov(r0, self.register(r)?);
+                state.registers[0] = Bound::integer(state.registers[r as usize].bits);
+            }
+            Bytecodes::TestEqual
+            | Bytecodes::TestEqualStrict
+            | Bytecodes::TestLessThan
+            | Bytecodes::TestGreaterThan
+            | Bytecodes::TestLessThanOrEqual
+            | Bytecodes::TestGreaterThanOrEqual => {
+                let (a, b) = (operands[0] as u8, operands[1] as u8);
+                return self.compare(state, opcode, a, b);
+            }
+
+            Bytecodes::Jump => {
+                self.jump(end + operands[0] as usize, &state)?;
+                return Some(Flow::Stop);
+            }
+            // the values of the machine code are never null, the compiler knows which are
+            // undefined
+            Bytecodes::JumpIfNotUndefined | Bytecodes::JumpIfUndefinedOrNull => {
+                let undefined = match state.registers[0].kind {
+                    Kind::Undefined => true,
+                    Kind::Unknown => return self.bailout(),
+                    _ => false,
+                };
+                if undefined == (opcode == Bytecodes::JumpIfUndefinedOrNull) {
+                    self.jump(end + operands[0] as usize, &state)?;
+                    return Some(Flow::Stop);
+                }
+            }
+            Bytecodes::JumpIfTrue | Bytecodes::JumpIfFalse => {
+                if state.registers[0].kind == Kind::Unknown {
+                    return self.bailout();
+                }
+                // 0 is false, as a number, as a boolean and as undefined
+                let condition = match opcode {
+                    Bytecodes::JumpIfTrue => Condition::NotEqual,
+                    _ => Condition::Equal,
+                };
+                let target = end + operands[0] as usize;
+                self.arch.cmp_imm(r0, 0);
+                self.arch.jcc(condition, self.labels[&target]);
+                self.merge_pending(target, &state)?;
+            }
+            Bytecodes::JumpLoop => return self.jump_loop(end - operands[0] as usize, &state),
+            Bytecodes::Return | Bytecodes::Hlt => return self.ret(&state),
+
+            _ => return self.bailout(),
+        }
+        Some(Flow::Next(state))
+    }
+
+    /// the declarations of an instruction nothing runs.
+    fn declare(&mut self, pc: usize, instruction: &Instruction) {
+        let end = pc + instruction.len;
+        match instruction.opcode {
+            Bytecodes::PushContext => self.scopes.push(),
+            Bytecodes::PopContext => {
+                self.scopes.pop();
+            }
+            Bytecodes::StaContextSlot | Bytecodes::StaConstContextSlot => {
+                let constant = instruction.opcode == Bytecodes::StaConstContextSlot;
+                self.scopes.declare(self.name(end, instruction), constant);
+            }
+            _ => {}
+        }
+    }
+
+    /// the name that ends the instruction ending at `end`.
+    fn name(&self, end: usize, instruction: &Instruction) -> &'a [u8] {
+        let code = self.code;
+        &code[end - instruction.operands[0] as usize..end]
+    }
+
+    /// hand the call back to the interpreter here. code without jumps would always do that,
+    /// so it is not compiled.
+    fn bailout(&mut self) -> Option<Flow> {
+        if !self.checked {
+            return None;
+        }
+        self.arch.jmp(self.bailout);
+        Some(Flow::Stop)
+    }
+
+    /// `r = n`
+    fn set(&mut self, mut state: State, r: u8, n: i64) -> Option<Flow> {
+        let bits = bits(n);
+        if bits > MAX_BITS {
+            return self.bailout();
+        }
+        self.arch.mov_imm(self.register(r)?, n);
+        state.registers[r as usize] = Bound::integer(bits);
+        Some(Flow::Next(state))
+    }
+
+    /// `r0 = r op right` of `Add`, `Sub` or `Mul` or their smi forms. a result that may have
+    /// more than `MAX_BITS` is not compiled, or checked as the code runs.
+    fn arithmetic(&mut self, mut state: State, op: u8, r: u8, right: Right) -> Option<Flow> {
+        let r0 = self.register(0)?;
+        let scratch = self.arch.scratch_register();
+        let a = self.register(r)?;
+        let mut a_bits = state.registers[r as usize].bits;
+        let (b, mut b_bits) = match right {
+            // undefined makes NaN
+            _ if !Bound::number(state.registers[r as usize].kind) => return self.bailout(),
+            Right::Register(r) if !Bound::number(state.registers[r as usize].kind) => {
+                return self.bailout()
+            }
+            Right::Register(r) => (Some(self.register(r)?), state.registers[r as usize].bits),
+            Right::Immediate(n) if bits(n) <= MAX_BITS => (None, bits(n)),
+            Right::Immediate(_) => return self.bailout(),
+        };
+        let multiply = matches!(op, Bytecodes::Mul | Bytecodes::MulSmi);
+        let mut bits = match multiply {
+            true => a_bits + b_bits,
+            false => a_bits.max(b_bits) + 1,
+        };
+
+        if bits > MAX_BITS && !self.checked {
+            return None;
+        }
+        if bits > MAX_BITS && multiply {
+            // bail out unless the factors are small enough for an exact product
+            let (a_max, b_max) = match b {
+                None if b_bits < MAX_BITS => (MAX_BITS - b_bits, b_bits),
+                None => return self.bailout(),
+                Some(_) if a_bits <= HALF_BITS => (a_bits, MAX_BITS - a_bits),
+                Some(_) if b_bits <= HALF_BITS => (MAX_BITS - b_bits, b_bits),
+                Some(_) => (HALF_BITS, HALF_BITS),
+            };
+            if a_max < a_bits {
+                self.check_bits(a, a_max);
+                a_bits = a_max;
+            }
+            if let (Some(b), true) = (b, b_max < b_bits) {
+                self.check_bits(b, b_max);
+                b_bits = b_max;
+            }
+            bits = a_bits + b_bits;
+        }
+
+        self.arch.mov(scratch, a);
+        let b = match right {
+            Right::Register(_) => b?,
+            Right::Immediate(n) => {
+                self.arch.mov_imm(r0, n);
+                r0
+            }
+        };
+        match op {
+            Bytecodes::Add | Bytecodes::AddSmi => self.arch.add(scratch, b),
+            Bytecodes::Sub | Bytecodes::SubSmi => self.arch.sub(scratch, b),
+            _ => {
+                self.arch.mul(scratch, b);
+                self.has_mul = true;
+            }
+        }
+        self.arch.mov(r0, scratch);
+        if bits > MAX_BITS {
+            // the sum of two values of `MAX_BITS` is exact in 64 bits
+            self.check_bits(r0, MAX_BITS);
+            bits = MAX_BITS;
         }
+        state.registers[0] = Bound::integer(bits);
+        Some(Flow::Next(state))
     }
 
-    fn compile(&mut self) -> extern "C" fn() -> T {
-        self.generate();
-        self.arch.unsafe_transmute()
+    /// bail out unless `reg` is a signed integer of `bits` bits: shifted out and back, it is
+    /// still the same.
+    fn check_bits(&mut self, reg: u64, bits: u32) {
+        let scratch = self.arch.scratch_register();
+        let shift = (64 - bits) as u8;
+        self.arch.mov(scratch, reg);
+        self.arch.shl(scratch, shift);
+        self.arch.sar(scratch, shift);
+        self.arch.cmp(scratch, reg);
+        self.arch.jcc(Condition::NotEqual, self.bailout);
     }
 
-    fn generate(&mut self) {
-        todo!()
+    /// `r0 = a op b` of a `Test*` opcode. booleans compare like the numbers 0 and 1, but are
+    /// never strictly equal to one.
+    fn compare(&mut self, mut state: State, op: u8, a: u8, b: u8) -> Option<Flow> {
+        let (a_kind, b_kind) = (
+            state.registers[a as usize].kind,
+            state.registers[b as usize].kind,
+        );
+        if !Bound::number(a_kind) || !Bound::number(b_kind) {
+            return self.bailout();
+        }
+        if op == Bytecodes::TestEqualStrict && (a_kind != b_kind || a_kind == Kind::Either) {
+            return self.bailout();
+        }
+        let condition = match op {
+            Bytecodes::TestEqual | Bytecodes::TestEqualStrict => Condition::Equal,
+            Bytecodes::TestLessThan => Condition::Less,
+            Bytecodes::TestGreaterThan => Condition::Greater,
+            Bytecodes::TestLessThanOrEqual => Condition::LessOrEqual,
+            _ => Condition::GreaterOrEqual,
+        };
+
+        let r0 = self.register(0)?;
+        let done = self.arch.label();
+        self.arch.cmp(self.register(a)?, self.register(b)?);
+        self.arch.mov_imm(r0, 1);
+        self.arch.jcc(condition, done);
+        self.arch.mov_imm(r0, 0);
+        self.arch.bind(done);
+        state.registers[0] = Bound::BOOLEAN;
+        Some(Flow::Next(state))
+    }
+
+    /// a jump forward to `target`.
+    fn jump(&mut self, target: usize, state: &State) -> Option<()> {
+        self.arch.jmp(self.labels[&target]);
+        self.merge_pending(target, state)
+    }
+
+    fn merge_pending(&mut self, target: usize, state: &State) -> Option<()> {
+        let mut pending = self.pending.remove(&target);
+        merge(&mut pending, state)?;
+        self.pending.insert(target, pending?);
+        Some(())
+    }
+
+    /// the jump back to the start of a loop. the loop runs as long as there is fuel and no
+    /// interrupt is requested, the interpreter then runs the call again and checks the limits.
+    fn jump_loop(&mut self, target: usize, state: &State) -> Option<Flow> {
+        let scratch = self.arch.scratch_register();
+        self.arch.load_slot(scratch, FUEL_SLOT);
+        self.arch.add_imm(scratch, -1);
+        self.arch.store_slot(scratch, FUEL_SLOT);
+        self.arch.cmp_imm(scratch, 0);
+        self.arch.jcc(Condition::Less, self.bailout);
+        self.arch.load_slot(scratch, ENVIRONMENT_SLOT);
+        self.arch.load(scratch, scratch, 0);
+        self.arch.load_byte(scratch, scratch, 0);
+        self.arch.cmp_imm(scratch, 0);
+        self.arch.jcc(Condition::NotEqual, self.bailout);
+        self.arch.jmp(self.labels[&target]);
+
+        let entry = self.entries.get(&target)?;
+        if entry.join(state)? != *entry {
+            // compiled for too narrow a state, compile it again from a wider one
+            let wider = entry.widen(state)?;
+            self.loops.insert(target, wider);
+            self.widened = true;
+        }
+        Some(Flow::Stop)
+    }
+
+    /// return r0, a boolean as `FALSE` or `TRUE`.
+    fn ret(&mut self, state: &State) -> Option<Flow> {
+        let r0 = self.register(0)?;
+        let scratch = self.arch.scratch_register();
+        match state.registers[0].kind {
+            Kind::Integer => {}
+            Kind::Boolean => {
+                self.arch.mov_imm(scratch, FALSE);
+                self.arch.add(r0, scratch);
+            }
+            Kind::Undefined => self.arch.mov_imm(r0, UNDEFINED),
+            Kind::Either | Kind::Unknown => return self.bailout(),
+        }
+        self.arch.jmp(self.exit);
+        Some(Flow::Stop)
+    }
+
+    fn register(&self, operand: u8) -> Option<u64> {
+        let reg = InlineAssembler64BitsRegister::from_operand(operand)?;
+        Some(self.arch.register_map(reg))
+    }
+}
+
+/// the bits of the magnitude of `n`.
+fn bits(n: i64) -> u32 {
+    64 - n.unsigned_abs().leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::engine::{
+        core::limits::{InterruptHandle, Limits},
+        core::vm::{
+            codegen::CodeGenerator,
+            constant_table::{Constant, ConstantTable},
+            feedback::FeedbackVector,
+            handler_table::HandlerTable,
+            optimizer::{optimize_function, Passes},
+            source_positions::SourcePositionTable,
+        },
+        parsing::{BuiltinParser, Parser},
+    };
+
+    fn bytecode(source: &str) -> Vec<u8> {
+        let program = BuiltinParser.parse(source.to_string());
+        CodeGenerator::gen_script(&program).ok().unwrap().code
+    }
+
+    fn budget() -> Budget {
+        Budget::new(Limits::default(), InterruptHandle::new())
+    }
+
+    /// the code of the function literal `source`.
+    fn literal(source: &str) -> Rc<FunctionCode> {
+        let program = BuiltinParser.parse(format!("({});", source));
+        let script = CodeGenerator::gen_script(&program).ok().unwrap();
+        let function = script.constant_table.iter().find_map(|c| match c {
+            Constant::Function(f) => Some(f.clone()),
+            _ => None,
+        });
+        function.unwrap()
+    }
+
+    fn compile_function(source: &str, isa: Isa) -> Option<JitFunction> {
+        let f = literal(source);
+        jit_compile(&f.code, &f.parameters, isa)
+    }
+
+    fn compile(source: &str, parameters: &[&str], isa: Isa) -> Option<JitFunction> {
+        let parameters: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+        jit_compile(&bytecode(source), &parameters, isa)
+    }
+
+    #[test]
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), unix))]
+    fn test_jit_compile() {
+        let isa = Isa::host;
+        let f = compile("2 + 3 * 4 - 10;", &[], isa().unwrap()).unwrap();
+        assert_eq!(f.call(&[], &mut budget()), Some(Value::smi(4)));
+        assert_eq!(f.call(&[], &mut budget()), Some(Value::smi(4)));
+
+        let f = compile("(a - 8) * (b + 1);", &["a", "b"], isa().unwrap()).unwrap();
+        assert_eq!(
+            f.call(&[Value::smi(1), Value::smi(2)], &mut budget()),
+            Some(Value::smi(-21))
+        );
+        // past the smis
+        let n = 1 << 20;
+        assert_eq!(
+            f.call(&[Value::smi(n), Value::smi(n)], &mut budget()),
+            Some(Value::number((n as f64 - 8.0) * (n as f64 + 1.0)))
+        );
+        // the interpreter takes other arguments, and a 0 that may be -0
+        assert_eq!(
+            f.call(&[Value::smi(i32::MAX), Value::smi(1)], &mut budget()),
+            None
+        );
+        assert_eq!(f.call(&[Value::smi(1)], &mut budget()), None);
+        assert_eq!(
+            f.call(&[Value::smi(1), Value::double(0.5)], &mut budget()),
+            None
+        );
+        assert_eq!(
+            f.call(&[Value::smi(8), Value::smi(-2)], &mut budget()),
+            None
+        );
+
+        // r1 and r2 of the bytecode, set by Mov
+        let mut code = vec![Bytecodes::Mov, 1];
+        code.extend_from_slice(&Value::smi(6).to_bits().to_le_bytes());
+        code.extend_from_slice(&[Bytecodes::Mov, 2]);
+        code.extend_from_slice(&Value::smi(7).to_bits().to_le_bytes());
+        code.extend_from_slice(&[Bytecodes::Mul, 1, 2, Bytecodes::Hlt]);
+        let f = jit_compile(&code, &[], isa().unwrap()).unwrap();
+        assert_eq!(f.call(&[], &mut budget()), Some(Value::smi(42)));
+
+        // the immediate operands of optimized code
+        let program = BuiltinParser.parse("(a - 8) * 3 + 1;".to_string());
+        let mut script = CodeGenerator::gen_script(&program).ok().unwrap();
+        optimize_function(&mut script, Passes::ALL);
+        assert!(script.code.contains(&Bytecodes::MulSmi));
+        let f = jit_compile(&script.code, &["a".to_string()], isa().unwrap()).unwrap();
+        assert_eq!(
+            f.call(&[Value::smi(10)], &mut budget()),
+            Some(Value::smi(7))
+        );
+        assert_eq!(
+            f.call(&[Value::smi(-1)], &mut budget()),
+            Some(Value::smi(-26))
+        );
+    }
+
+    #[test]
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), unix))]
+    fn test_jit_compile_jumps() {
+        let isa = Isa::host().unwrap();
+        let sum =
+            "function (n) { let s = 0; for (let i = 0; i < n; i++) { s = s + i; } return s; }";
+        let f = compile_function(sum, isa).unwrap();
+        assert_eq!(
+            f.call(&[Value::smi(10)], &mut budget()),
+            Some(Value::smi(45))
+        );
+        // an iteration is a step
+        let mut limited = Budget::new(Limits::new().max_steps(1000), InterruptHandle::new());
+        assert_eq!(
+            f.call(&[Value::smi(100)], &mut limited),
+            Some(Value::smi(4950))
+        );
+        assert_eq!(limited.jit_fuel(), 1000 - 100);
+        // the interpreter runs out of steps and the terminated calls
+        assert_eq!(f.call(&[Value::smi(2000)], &mut limited), None);
+        assert_eq!(limited.jit_fuel(), 0);
+        let mut terminated = budget();
+        terminated.interrupt().terminate();
+        assert_eq!(f.call(&[Value::smi(10)], &mut terminated), None);
+
+        // results past `MAX_BITS` bail out where they are made
+        let power =
+            "function (n) { let p = 1; for (let i = 0; i < n; i++) { p = p * 3; } return p; }";
+        let f = compile_function(power, isa).unwrap();
+        assert_eq!(
+            f.call(&[Value::smi(10)], &mut budget()),
+            Some(Value::smi(59049))
+        );
+        assert_eq!(f.call(&[Value::smi(40)], &mut budget()), None);
+
+        // what the jit does not compile bails out on the paths that run it
+        let half = "function (n) { let r = n; if (n > 100) { r = n / 2; } return r; }";
+        let f = compile_function(half, isa).unwrap();
+        assert_eq!(f.call(&[Value::smi(7)], &mut budget()), Some(Value::smi(7)));
+        assert_eq!(f.call(&[Value::smi(200)], &mut budget()), None);
+
+        // booleans, and a name declared on one path only
+        let f = compile_function("function (n) { return n > 3; }", isa).unwrap();
+        assert_eq!(
+            f.call(&[Value::smi(4)], &mut budget()),
+            Some(Value::boolean(true))
+        );
+        assert_eq!(
+            f.call(&[Value::smi(3)], &mut budget()),
+            Some(Value::boolean(false))
+        );
+        let f = compile_function("function (n) { if (n) { let x = 1; } return x; }", isa);
+        let f = f.unwrap();
+        assert_eq!(f.call(&[Value::smi(1)], &mut budget()), None);
+    }
+
+    #[test]
+    fn test_jit_compile_unsupported() {
+        // a double constant, a division, a name that is not a parameter
+        assert!(compile("1.5;", &[], Isa::X86_64).is_none());
+        assert!(compile("4 / 2;", &[], Isa::X86_64).is_none());
+        assert!(compile("4 / 2;", &[], Isa::Arm64).is_none());
+        assert!(compile("a + c;", &["a", "b"], Isa::X86_64).is_none());
+        // results that a double can not hold exactly
+        assert!(compile("100000 * 100000 * 100000 * 100000;", &[], Isa::X86_64).is_none());
+        let f = compile("a * a * a;", &["a"], Isa::X86_64).unwrap();
+        assert_eq!(f.argument_bits, 17);
+        // a jump into the middle of an instruction
+        let mut code = vec![Bytecodes::Jump];
+        code.extend_from_slice(&1i64.to_le_bytes());
+        code.extend_from_slice(&[Bytecodes::Ldar, 0, Bytecodes::Hlt]);
+        assert!(jit_compile(&code, &[], Isa::X86_64).is_none());
+    }
+
+    #[test]
+    fn test_tier_up() {
+        let function = |source: &str| FunctionCode {
+            parameters: vec![String::from("a")],
+            code: bytecode(source),
+            constant_table: ConstantTable::new(),
+            feedback: RefCell::new(FeedbackVector::new()),
+            handler_table: HandlerTable::new(),
+            source_positions: SourcePositionTable::new(),
+            jit: RefCell::new(JitState::default()),
+        };
+
+        // compiled on the call that makes the function hot
+        let f = function("a + 1;");
+        let mut state = JitState::default();
+        for _ in 1..CALL_THRESHOLD {
+            assert!(state.enter(&f).is_none());
+        }
+        assert!(state.enter(&f).is_some() == Isa::host().is_some());
+
+        // a hot loop compiles the function too, which may fail for good
+        let f = function("a / 2;");
+        let mut state = JitState::default();
+        for _ in 1..LOOP_THRESHOLD {
+            state.iterate(&f, 10);
+            state.iterate(&f, 20);
+        }
+        assert!(matches!(state.tier, Tier::Interpreted));
+        state.iterate(&f, 10);
+        assert!(matches!(state.tier, Tier::Unsupported));
+        for _ in 0..CALL_THRESHOLD {
+            assert!(state.enter(&f).is_none());
+        }
+        assert_eq!(state.calls, 0);
+
+        // calls that bail out again and again go back to the interpreter
+        let f = literal("function (a) { if (a > 1) { a = a / 2; } return a; }");
+        let mut state = JitState::default();
+        let mut budget = Budget::new(Limits::default(), InterruptHandle::new());
+        for _ in 1..CALL_THRESHOLD {
+            assert!(state.call(&f, &[Value::smi(2)], &mut budget).is_none());
+        }
+        if Isa::host().is_some() {
+            assert_eq!(
+                state.call(&f, &[Value::smi(1)], &mut budget),
+                Some(Value::smi(1))
+            );
+            for _ in 0..MAX_BAILOUTS {
+                assert!(state.call(&f, &[Value::smi(2)], &mut budget).is_none());
+            }
+            assert!(matches!(state.tier, Tier::Unsupported));
+        }
     }
 }
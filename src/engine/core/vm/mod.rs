@@ -1,31 +1,60 @@
 #![allow(dead_code)]
 
-use crate::engine::parsing::Parser;
+use crate::engine::{
+    core::limits::{Budget, InterruptHandle, LimitExceeded, Limits},
+    parsing::Parser,
+};
 
 use self::{
     bytecodes::{Bytecodes, RName},
+    code_cache::CodeCache,
     codegen::CodeGenerator,
-    constant_table::ConstantTable,
-    context::ExecutionContext,
+    constant_table::{Constant, ConstantTable},
+    context::{Context, ExecutionContext},
+    debugger::Debugger,
+    disassembler::Listing,
+    feedback::{FeedbackVector, LoadHandler, StoreHandler},
+    function::{Closure, Frame, FunctionCode},
+    handler_table::HandlerTable,
     heap::Heap,
+    jit::Isa,
     objects::{
-        js_number::JSNumber,
+        constant::PROTOTYPE_KEY_NAME,
+        js_console::JSConsole,
+        js_error::JSError,
         js_object::{JSObject, JSType},
         js_string::JSString,
     },
+    optimizer::{optimize, optimize_function, optimize_functions, Passes},
+    realm::Realm,
     register::Register,
+    serializer::{source_hash, Script},
+    source_positions::SourcePositionTable,
+    value::Value,
 };
 
-use std::fmt::Display;
+use std::{cmp::Ordering, fmt::Display, rc::Rc};
 
 pub(crate) mod bytecodes;
+pub(crate) mod code_cache;
 pub(crate) mod codegen;
 pub(crate) mod constant_table;
 pub(crate) mod context;
+pub(crate) mod debugger;
+pub mod disassembler;
+pub(crate) mod feedback;
+pub(crate) mod function;
+pub(crate) mod handler_table;
 pub(crate) mod heap;
 pub(crate) mod jit;
 pub(crate) mod objects;
+pub mod optimizer;
+pub(crate) mod realm;
 pub(crate) mod register;
+pub(crate) mod serializer;
+pub(crate) mod source_positions;
+pub(crate) mod value;
+pub(crate) mod verifier;
 
 enum VMErrorKind {
     Type,
@@ -33,71 +62,193 @@ enum VMErrorKind {
     Syntax,
     Range,
     Eval,
-    Internal,
+    /// aborted by a step limit, a timeout or an interrupt. can not be caught.
+    Terminated,
+    /// a value thrown by `throw`
+    Thrown,
+}
+impl VMErrorKind {
+    /// the constructor of the error object a script catches.
+    fn name(&self) -> &'static str {
+        match self {
+            VMErrorKind::Type => "TypeError",
+            VMErrorKind::Reference => "ReferenceError",
+            VMErrorKind::Syntax => "SyntaxError",
+            VMErrorKind::Range => "RangeError",
+            VMErrorKind::Eval => "EvalError",
+            VMErrorKind::Terminated | VMErrorKind::Thrown => unreachable!(),
+        }
+    }
 }
 pub(crate) struct VMError {
     kind: VMErrorKind,
     message: String,
+    /// the exception of `Thrown`
+    value: Option<Value>,
 }
 impl VMError {
     fn new(kind: VMErrorKind, message: String) -> Self {
-        VMError { kind, message }
+        VMError {
+            kind,
+            message,
+            value: None,
+        }
+    }
+
+    fn thrown(value: Value) -> Self {
+        VMError {
+            kind: VMErrorKind::Thrown,
+            message: JSError::describe(value).unwrap_or_else(|| to_js_string(value)),
+            value: Some(value),
+        }
+    }
+
+    /// the heap is full and can not grow.
+    fn out_of_memory() -> Self {
+        VMError::new(VMErrorKind::Range, "Maximum heap size exceeded".to_string())
+    }
+
+    /// whether a `catch` can handle the error. the limits abort the run.
+    fn is_catchable(&self) -> bool {
+        !matches!(self.kind, VMErrorKind::Terminated)
+    }
+}
+impl From<LimitExceeded> for VMError {
+    fn from(e: LimitExceeded) -> Self {
+        match e {
+            LimitExceeded::CallDepth => VMError::new(
+                VMErrorKind::Range,
+                "Maximum call stack size exceeded".to_string(),
+            ),
+            _ => VMError::new(VMErrorKind::Terminated, e.to_string()),
+        }
     }
 }
 impl Display for VMError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}: {}",
-            match self.kind {
-                VMErrorKind::Type => "TypeError",
-                VMErrorKind::Reference => "ReferenceError",
-                VMErrorKind::Syntax => "SyntaxError",
-                VMErrorKind::Range => "RangeError",
-                VMErrorKind::Eval => "EvalError",
-                VMErrorKind::Internal => "InternalError",
-            },
-            self.message
-        )
+        match self.kind {
+            VMErrorKind::Terminated => write!(f, "{}", self.message),
+            VMErrorKind::Thrown => write!(f, "Uncaught {}", self.message),
+            _ => write!(f, "{}: {}", self.kind.name(), self.message),
+        }
     }
 }
 
+/// array stores this far past the end are kept as named properties instead of growing the
+/// elements.
+const MAX_ELEMENTS_GAP: usize = 1024;
+
+/// the heap grows on demand up to this size unless `Limits::max_heap_bytes` says otherwise.
+const DEFAULT_MAX_HEAP_SIZE: usize = 256 * 1024 * 1024;
+
+/// calls nest up to this depth unless `Limits::max_call_depth` says otherwise. the frames are
+/// on the heap, the depth only bounds their memory.
+const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
 pub(crate) struct VirtualMachine {
     execution_context: ExecutionContext,
     pub(crate) constant_table: ConstantTable,
+    feedback: FeedbackVector,
+    handler_table: HandlerTable,
+    source_positions: SourcePositionTable,
     parser: Box<dyn Parser>,
 
     register: Register,
     pc: usize,
     code: Vec<u8>,
-    stack: Vec<i64>,
+    /// the running function, `None` while the script runs
+    function: Option<Rc<FunctionCode>>,
+    frames: Vec<Frame>,
+    /// contexts pushed by the running code since it started
+    context_depth: usize,
+    stack: Vec<Value>,
     pub(crate) heap: Heap,
+    pub(crate) realm: Realm,
+
+    limits: Limits,
+    interrupt: InterruptHandle,
+    budget: Budget,
+    /// compile hot functions to machine code
+    jit: bool,
+    /// the optimizations of new code
+    passes: Passes,
+    /// asked before every instruction whether to stop
+    debugger: Option<Debugger>,
 }
 
 impl VirtualMachine {
     pub(crate) fn new(parser: Box<dyn Parser>) -> Self {
-        let execution_context = ExecutionContext::new();
-        let mut heap = Heap::new(1024 * 1024);
+        let mut heap = Heap::new(DEFAULT_MAX_HEAP_SIZE);
+        let realm = Realm::new(&mut heap);
 
-        // create global objects
-        let base_obj = heap.alloc().unwrap();
-        base_obj._type = JSType::Undefined;
-        execution_context
-            .context
-            .borrow_mut()
-            .set("undefined".to_string(), base_obj.as_raw_ptr());
+        // the global scope
+        let mut execution_context = ExecutionContext::new(&mut heap);
+        execution_context.set(&mut heap, "this", realm.global_object);
+        let console = JSConsole::create(&realm, &mut heap);
+        execution_context.set(&mut heap, "console", console);
+        let error = JSError::create_constructor(&realm, &mut heap);
+        execution_context.set(&mut heap, "Error", error);
 
         Self {
             execution_context,
             constant_table: ConstantTable::new(),
+            feedback: FeedbackVector::new(),
+            handler_table: HandlerTable::new(),
+            source_positions: SourcePositionTable::new(),
             parser,
             register: Register::new(),
             pc: 0,
             stack: Vec::new(),
             code: Vec::new(),
+            function: None,
+            frames: Vec::new(),
+            context_depth: 0,
             heap,
+            realm,
+            limits: Limits::default(),
+            interrupt: InterruptHandle::new(),
+            budget: Budget::new(Limits::default(), InterruptHandle::new()),
+            jit: Isa::host().is_some(),
+            passes: Passes::ALL,
+            debugger: None,
         }
     }
+
+    /// limits applied to every `run`.
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        self.heap
+            .set_max_size(limits.max_heap_bytes.unwrap_or(DEFAULT_MAX_HEAP_SIZE));
+        self.limits = limits;
+    }
+
+    /// the budget of one run.
+    fn new_budget(&self) -> Budget {
+        let mut limits = self.limits.clone();
+        limits.max_call_depth = limits.max_call_depth.or(Some(DEFAULT_MAX_CALL_DEPTH));
+        Budget::new(limits, self.interrupt.clone())
+    }
+
+    /// turn the baseline jit on or off. functions compiled already keep their code.
+    pub(crate) fn set_jit(&mut self, enabled: bool) {
+        self.jit = enabled;
+    }
+
+    /// the optimizations of the code compiled from now on.
+    pub(crate) fn set_passes(&mut self, passes: Passes) {
+        self.passes = passes;
+    }
+
+    /// run the code under `debugger`. the jit is turned off, machine code would run past
+    /// the breakpoints.
+    pub(crate) fn set_debugger(&mut self, debugger: Debugger) {
+        self.jit = false;
+        self.debugger = Some(debugger);
+    }
+
+    /// a handle that can terminate the running code from another thread.
+    pub(crate) fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
 }
 
 /// core impl
@@ -114,12 +265,7 @@ impl VirtualMachine {
                 self.print_bytecode();
             }
             _ => {
-                let program = self.parser.parse(source);
-                let mut codegen = CodeGenerator::new(&mut self.constant_table);
-                let mut code = codegen.gen(&program);
-                self.code.append(&mut code);
-
-                if let Err(e) = self.interpret() {
+                if let Err(e) = self.exec(source) {
                     println!("{}", e);
                 } else {
                     self.print_current_expr();
@@ -128,15 +274,200 @@ impl VirtualMachine {
         };
     }
 
+    fn exec(&mut self, source: String) -> Result<(), VMError> {
+        let program = self.parser.parse(source);
+        let mut handler_table = HandlerTable::new();
+        let mut source_positions = SourcePositionTable::new();
+        let functions = self.constant_table.iter().count();
+        let mut codegen = CodeGenerator::new(
+            &mut self.constant_table,
+            &mut self.feedback,
+            &mut handler_table,
+            &mut source_positions,
+        );
+        let mut code = codegen.gen(&program)?;
+        optimize(
+            &mut code,
+            &mut handler_table,
+            &mut source_positions,
+            &self.constant_table,
+            self.passes,
+        );
+        optimize_functions(&mut self.constant_table, functions, self.passes);
+        self.handler_table.extend(handler_table, self.code.len());
+        self.source_positions
+            .extend(source_positions, self.code.len());
+        self.code.append(&mut code);
+
+        self.budget = self.new_budget();
+        let result = self.interpret();
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    /// drop the rest of the aborted code so the next run starts clean.
+    fn reset(&mut self) {
+        self.pc = self.code.len();
+        self.function = None;
+        self.frames.clear();
+        self.stack.clear();
+        self.execution_context.reset();
+        self.context_depth = 0;
+    }
+
+    /// compile `source` without running it. a parse error is returned rather than printed.
+    pub(crate) fn compile_script(&self, source: String) -> Result<Script, VMError> {
+        let source_hash = source_hash(&source);
+        let program = self
+            .parser
+            .try_parse(source)
+            .map_err(|e| VMError::new(VMErrorKind::Syntax, e.to_string()))?;
+        let code = CodeGenerator::gen_script(&program)?;
+        Ok(Script {
+            source_hash,
+            code: Rc::new(code),
+        })
+    }
+
+    /// run a compiled script in the global scope, like `exec` of its source. the script
+    /// runs as the current function, with its own constants, but in no frame.
+    pub(crate) fn run_script(&mut self, script: &Script) -> Result<(), VMError> {
+        self.function = Some(script.code.clone());
+        self.pc = 0;
+        self.budget = self.new_budget();
+        let result = self.interpret();
+        if result.is_err() {
+            self.reset();
+        }
+        self.function = None;
+        self.pc = self.code.len();
+        result
+    }
+
+    /// `run` through `cache`: a cached script is not parsed and compiled again. the cache
+    /// keeps the code as compiled, it is optimized on every run.
+    pub(crate) fn run_cached(&mut self, source: String, cache: &CodeCache) {
+        let mut script = match cache.get(&source) {
+            Some(script) => script,
+            None => match self.compile_script(source.clone()) {
+                Ok(script) => {
+                    cache.put(&script);
+                    script
+                }
+                // `run` reports the error as always
+                Err(_) => return self.run(source),
+            },
+        };
+        if let Some(code) = Rc::get_mut(&mut script.code) {
+            optimize_function(code, self.passes);
+        }
+        match self.run_script(&script) {
+            Ok(()) => self.print_current_expr(),
+            Err(e) => println!("{}", e),
+        }
+    }
+
     fn interpret(&mut self) -> Result<(), VMError> {
         loop {
+            match self.dispatch() {
+                Ok(()) => return Ok(()),
+                Err(error) => self.unwind(error)?,
+            }
+        }
+    }
+
+    /// go to the innermost handler of `error`, leaving the frames that have none. the
+    /// handler gets the exception in r0, errors of the vm become error objects.
+    fn unwind(&mut self, error: VMError) -> Result<(), VMError> {
+        if !error.is_catchable() {
+            return Err(error);
+        }
+        loop {
+            let pc = self.pc;
+            let entry = match &self.function {
+                Some(function) => function.handler_table.lookup(pc),
+                None => self.handler_table.lookup(pc),
+            };
+            if let Some(entry) = entry {
+                let base = self.frames.last().map_or(0, |frame| frame.stack_len);
+                self.stack.truncate(base + entry.stack_depth);
+                for _ in entry.context_depth..self.context_depth {
+                    self.execution_context.scope_out();
+                }
+                self.context_depth = entry.context_depth;
+
+                let exception = match error.value {
+                    Some(value) => value,
+                    None => self.error_object(&error)?,
+                };
+                self.mov(RName::R0, exception);
+                self.pc = entry.handler;
+                return Ok(());
+            }
+
+            match self.frames.pop() {
+                Some(frame) => self.return_to(frame),
+                None => return Err(error),
+            }
+        }
+    }
+
+    /// the error object of an error of the vm. the aborted instruction holds no references
+    /// into the heap any more, so if the heap is full its garbage is collected first.
+    fn error_object(&mut self, error: &VMError) -> Result<Value, VMError> {
+        let create = |vm: &mut Self| {
+            JSError::create(&vm.realm, &mut vm.heap, error.kind.name(), &error.message)
+        };
+        create(self).or_else(|_| {
+            self.collect_garbage();
+            create(self)
+        })
+    }
+
+    /// run until the code halts, the script returns or an error is thrown.
+    fn dispatch(&mut self) -> Result<(), VMError> {
+        loop {
+            self.budget.step()?;
+
+            // safepoint: no references into the heap are held between instructions
+            if self.heap.should_collect() {
+                self.collect_garbage();
+                if self.heap.is_exhausted() {
+                    return Err(VMError::out_of_memory());
+                }
+            }
+
+            if let Some(mut debugger) = self.debugger.take() {
+                let result = debugger.pause(self);
+                self.debugger = Some(debugger);
+                result?;
+            }
+
             let opcode = self.fetch();
 
             match opcode {
                 Bytecodes::Mov => {
                     let r = self.fetch();
                     let v = self.fetch_i64();
-                    self.mov(r, v);
+                    self.mov(r, Value::from_bits(v as u64));
+                }
+                Bytecodes::Star0
+                | Bytecodes::Star1
+                | Bytecodes::Star2
+                | Bytecodes::Star3
+                | Bytecodes::Star4
+                | Bytecodes::Star5
+                | Bytecodes::Star6
+                | Bytecodes::Star7 => {
+                    let v = self.get_reg_v(RName::R0);
+                    self.mov(opcode - Bytecodes::Star0, v);
+                }
+                Bytecodes::Ldar => {
+                    let r = self.fetch();
+                    let v = self.get_reg_v(r);
+                    self.mov(RName::R0, v);
                 }
                 Bytecodes::Push => {
                     let r = self.fetch();
@@ -150,39 +481,31 @@ impl VirtualMachine {
                     break;
                 }
 
-                Bytecodes::LdaUndefined => self.load_undefined()?,
+                Bytecodes::LdaUndefined => self.mov(RName::R0, Value::undefined()),
+                Bytecodes::LdaNull => self.mov(RName::R0, Value::null()),
+                Bytecodes::LdaTrue => self.mov(RName::R0, Value::boolean(true)),
+                Bytecodes::LdaFalse => self.mov(RName::R0, Value::boolean(false)),
                 Bytecodes::LdaSmi => {
                     let v = self.fetch_i64();
-                    if let Some(base_obj) = self.heap.alloc() {
-                        let num_obj = JSNumber::create(v as f64, base_obj, self);
-                        let ptr = num_obj.as_raw_ptr();
-                        self.mov(RName::R0, ptr);
-                    } else {
-                        return Err(VMError::new(
-                            VMErrorKind::Internal,
-                            "allocation failed".to_string(),
-                        ));
-                    }
+                    self.mov(RName::R0, Value::number(v as f64));
                 }
                 Bytecodes::LdaConstant => {
                     let id = self.fetch_i64();
-                    if let Some(base_obj) = self.heap.alloc() {
-                        let s = self.constant_table.get(id as u32).clone();
-                        let str_obj = JSString::create(s, base_obj, self);
-                        self.mov(RName::R0, str_obj.as_raw_ptr());
-                    } else {
-                        return Err(VMError::new(
-                            VMErrorKind::Internal,
-                            "allocation failed".to_string(),
-                        ));
+                    if let Some(n) =
+                        self.with_constants(|constants| constants.get_number(id as u32))
+                    {
+                        self.mov(RName::R0, Value::double(n));
+                        continue;
                     }
+                    let base_obj = self.alloc()?;
+                    let s = self.with_constants(|constants| constants.get(id as u32).to_string());
+                    let str_obj = JSString::create(s, base_obj, self);
+                    self.mov(RName::R0, Value::from(str_obj));
                 }
                 Bytecodes::LdaContextSlot => {
                     let name = self.fetch_string();
-                    if let Some(raw_ptr) =
-                        self.execution_context.context.clone().borrow().get(&name)
-                    {
-                        self.mov(RName::R0, raw_ptr);
+                    if let Some(v) = self.execution_context.get(&name) {
+                        self.mov(RName::R0, v);
                     } else {
                         return Err(VMError::new(
                             VMErrorKind::Reference,
@@ -192,82 +515,374 @@ impl VirtualMachine {
                 }
                 Bytecodes::GetNamedProperty => {
                     let reg = self.fetch();
-                    let obj_ptr = self.get_reg_v(reg);
+                    let v = self.get_reg_v(reg);
                     let id = self.fetch_i64();
-                    let name = self.constant_table.get(id as u32).clone();
-                    let obj = JSObject::from_raw_ptr(obj_ptr);
-                    if let Some(prop) = obj.get(&name) {
-                        self.mov(RName::R0, prop.as_raw_ptr());
+                    let slot = self.fetch_i64() as usize;
+                    let prop = self.load_named_property(v, id as u32, slot)?;
+                    self.mov(RName::R0, prop);
+                }
+                Bytecodes::GetKeyedProperty => {
+                    let r1 = self.fetch();
+                    let r2 = self.fetch();
+                    let prop = self.load_keyed_property(self.get_reg_v(r1), self.get_reg_v(r2))?;
+                    self.mov(RName::R0, prop);
+                }
+                Bytecodes::SetKeyedProperty => {
+                    let r1 = self.fetch();
+                    let r2 = self.fetch();
+                    let value = self.get_reg_v(RName::R0);
+                    self.store_keyed_property(self.get_reg_v(r1), self.get_reg_v(r2), value)?;
+                }
+                Bytecodes::SetNamedProperty => {
+                    let reg = self.fetch();
+                    let object = self.get_reg_v(reg);
+                    let id = self.fetch_i64();
+                    let slot = self.fetch_i64() as usize;
+                    let value = self.get_reg_v(RName::R0);
+                    self.store_named_property(object, id as u32, slot, value)?;
+                }
+
+                Bytecodes::StaContextSlot | Bytecodes::StaConstContextSlot => {
+                    let reg_v = self.get_reg_v(RName::R0);
+                    let name = self.fetch_string();
+                    let context = &mut self.execution_context;
+                    if context.is_constant(&name) {
+                        return Err(VMError::new(
+                            VMErrorKind::Syntax,
+                            format!("Identifier '{}' has already been declared", name),
+                        ));
+                    }
+                    if opcode == Bytecodes::StaConstContextSlot {
+                        context.set_constant(&mut self.heap, &name, reg_v);
                     } else {
-                        let _ = self.load_undefined();
+                        context.set(&mut self.heap, &name, reg_v);
                     }
                 }
 
-                Bytecodes::StaContextSlot => {
-                    let reg_v = self.get_reg_v(RName::R0);
+                Bytecodes::StaLookupSlot => {
                     let name = self.fetch_string();
+                    let value = self.get_reg_v(RName::R0);
                     self.execution_context
-                        .context
-                        .clone()
-                        .borrow_mut()
-                        .set(name, reg_v);
-                }
-
-                Bytecodes::CallProperty => {
-                    // get callee function
-                    let callee_pointer_reg = self.fetch();
-                    let callee_pointer = self.get_reg_v(callee_pointer_reg);
-                    let callee = JSObject::from_raw_ptr(callee_pointer);
-                    let callee_fn = match callee._type {
-                        JSType::NativeFunction(f) => f,
-                        // TODO: JSFunction,
-                        _ => {
-                            return Err(VMError::new(
-                                VMErrorKind::Type,
-                                "callee is not a function".to_string(),
-                            ))
-                        }
-                    };
+                        .assign(&mut self.heap, &name, value)?;
+                }
+
+                Bytecodes::PushContext => {
+                    self.execution_context.scope_in(&mut self.heap)?;
+                    self.context_depth += 1;
+                }
+                Bytecodes::PopContext => {
+                    self.execution_context.scope_out();
+                    self.context_depth -= 1;
+                }
+
+                Bytecodes::Jump => {
+                    let offset = self.fetch_i64();
+                    self.pc = (self.pc as i64 + offset) as usize;
+                }
+                Bytecodes::JumpLoop => {
+                    let offset = self.fetch_i64();
+                    if let (true, Some(function)) = (self.jit, &self.function) {
+                        function.jit.borrow_mut().iterate(function, self.pc);
+                    }
+                    self.pc = (self.pc as i64 - offset) as usize;
+                }
+                Bytecodes::JumpIfTrue | Bytecodes::JumpIfFalse => {
+                    let offset = self.fetch_i64();
+                    let condition = to_boolean(self.get_reg_v(RName::R0));
+                    if condition == (opcode == Bytecodes::JumpIfTrue) {
+                        self.pc = (self.pc as i64 + offset) as usize;
+                    }
+                }
+                Bytecodes::JumpIfNotUndefined => {
+                    let offset = self.fetch_i64();
+                    if !self.get_reg_v(RName::R0).is_undefined() {
+                        self.pc = (self.pc as i64 + offset) as usize;
+                    }
+                }
+                Bytecodes::JumpIfUndefinedOrNull => {
+                    let offset = self.fetch_i64();
+                    let v = self.get_reg_v(RName::R0);
+                    if v.is_undefined() || v.is_null() {
+                        self.pc = (self.pc as i64 + offset) as usize;
+                    }
+                }
 
-                    // TODO: arguments
-                    // let arg_pointer_reg = self.fetch();
-                    // let arg_pointer = self.get_reg_v(arg_pointer_reg);
+                Bytecodes::TestEqual | Bytecodes::TestEqualStrict => {
+                    let r1 = self.fetch();
+                    let r2 = self.fetch();
+                    let (l, r) = (self.get_reg_v(r1), self.get_reg_v(r2));
+                    let equal = match opcode {
+                        Bytecodes::TestEqual => loose_equals(l, r),
+                        _ => strict_equals(l, r),
+                    };
+                    self.mov(RName::R0, Value::boolean(equal));
+                }
 
-                    // parent object
-                    let parent_obj_pointer_reg = self.fetch();
-                    let parent_obj_pointer = self.get_reg_v(parent_obj_pointer_reg);
-                    let parent_obj = JSObject::from_raw_ptr_mut(parent_obj_pointer);
+                Bytecodes::TestLessThan
+                | Bytecodes::TestGreaterThan
+                | Bytecodes::TestLessThanOrEqual
+                | Bytecodes::TestGreaterThanOrEqual => self.compare(opcode),
+
+                Bytecodes::CreateEmptyObjectLiteral => {
+                    let object = self.alloc()?;
+                    let prototype = self.realm.object_prototype;
+                    self.heap.write_barrier(object, prototype);
+                    object.set(PROTOTYPE_KEY_NAME, prototype);
+                    self.mov(RName::R0, Value::from(object));
+                }
+                Bytecodes::CreateArrayLiteral => {
+                    let count = self.fetch_i64() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    let array = self.alloc()?;
+                    for &element in elements.iter() {
+                        self.heap.write_barrier(array, element);
+                    }
+                    array._type = JSType::Array(elements);
+                    let prototype = self.realm.array_prototype;
+                    self.heap.write_barrier(array, prototype);
+                    array.set(PROTOTYPE_KEY_NAME, prototype);
+                    self.mov(RName::R0, Value::from(array));
+                }
 
-                    // call
-                    let ret = callee_fn(self, parent_obj, vec![]);
-                    self.mov(RName::R0, ret.as_raw_ptr());
+                Bytecodes::CreateClosure => {
+                    let id = self.fetch_i64();
+                    let code =
+                        self.with_constants(|constants| constants.get_function(id as u32).clone());
+                    let context = self.execution_context.context;
+                    let function = self.alloc()?;
+                    self.heap.write_barrier(function, context);
+                    function._type = JSType::Function(Closure { code, context });
+                    let prototype = self.realm.function_prototype;
+                    self.heap.write_barrier(function, prototype);
+                    function.set(PROTOTYPE_KEY_NAME, prototype);
+                    self.mov(RName::R0, Value::from(function));
                 }
 
-                Bytecodes::Return => {
-                    // TODO: return to called point
-                    break;
+                Bytecodes::CallProperty | Bytecodes::CallAnyReceiver => {
+                    let argc = self.fetch_i64() as usize;
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    let this = self.stack.pop().unwrap();
+                    let callee = self.stack.pop().unwrap();
+                    self.call(callee, this, args)?;
                 }
 
+                Bytecodes::Return => match self.frames.pop() {
+                    Some(frame) => self.return_to(frame),
+                    None => break,
+                },
+                Bytecodes::Throw => return Err(VMError::thrown(self.get_reg_v(RName::R0))),
+
                 // binary operations
-                Bytecodes::Add => self.add(),
-                Bytecodes::Sub => self.sub(),
-                Bytecodes::Mul => self.mul(),
-                Bytecodes::Div => self.div(),
-                Bytecodes::Mod => self.r#mod(),
+                Bytecodes::Add | Bytecodes::AddSmi => self.add(opcode)?,
+                Bytecodes::Sub
+                | Bytecodes::Mul
+                | Bytecodes::Div
+                | Bytecodes::Mod
+                | Bytecodes::Exp
+                | Bytecodes::BitwiseOr
+                | Bytecodes::BitwiseXor
+                | Bytecodes::BitwiseAnd
+                | Bytecodes::ShiftLeft
+                | Bytecodes::ShiftRight
+                | Bytecodes::ShiftRightLogical
+                | Bytecodes::SubSmi
+                | Bytecodes::MulSmi
+                | Bytecodes::DivSmi
+                | Bytecodes::ModSmi
+                | Bytecodes::ExpSmi
+                | Bytecodes::BitwiseOrSmi
+                | Bytecodes::BitwiseXorSmi
+                | Bytecodes::BitwiseAndSmi
+                | Bytecodes::ShiftLeftSmi
+                | Bytecodes::ShiftRightSmi
+                | Bytecodes::ShiftRightLogicalSmi => self.arithmetic(opcode),
+
+                // unary operations
+                Bytecodes::Negate
+                | Bytecodes::BitwiseNot
+                | Bytecodes::Inc
+                | Bytecodes::Dec
+                | Bytecodes::ToNumeric => {
+                    let r = self.fetch();
+                    let op = unary_operation(opcode).unwrap();
+                    let n = op(to_number(self.get_reg_v(r)));
+                    self.mov(RName::R0, Value::number(n));
+                }
+                Bytecodes::LogicalNot => {
+                    let r = self.fetch();
+                    let v = Value::boolean(!to_boolean(self.get_reg_v(r)));
+                    self.mov(RName::R0, v);
+                }
+                Bytecodes::TypeOf => {
+                    let r = self.fetch();
+                    let s = type_of(self.get_reg_v(r)).to_string();
+                    let base_obj = self.alloc()?;
+                    let str_obj = JSString::create(s, base_obj, self);
+                    self.mov(RName::R0, Value::from(str_obj));
+                }
+
+                // the code is compiled by the vm or verified, its opcodes are known
+                _ => unreachable!("unknown opcode 0x{:02x}", opcode),
+            }
+        }
+        Ok(())
+    }
 
-                _ => todo!(),
+    /// call `callee`. a native function returns in r0 right away, a bytecode function gets a
+    /// new frame and starts at its first instruction in a context inside its closure's.
+    fn call(&mut self, callee: Value, this: Value, args: Vec<Value>) -> Result<(), VMError> {
+        let (code, outer) = match callee.as_object().map(|o| &o._type) {
+            Some(JSType::NativeFunction(f)) => {
+                let f = *f;
+                self.budget.enter_call()?;
+                let ret = f(self, this, args);
+                self.budget.exit_call();
+                self.mov(RName::R0, ret?);
+                return Ok(());
+            }
+            Some(JSType::Function(closure)) => (closure.code.clone(), closure.context),
+            _ => {
+                return Err(VMError::new(
+                    VMErrorKind::Type,
+                    "callee is not a function".to_string(),
+                ))
+            }
+        };
+        self.budget.enter_call()?;
+
+        if self.jit {
+            // the interpreter runs what the machine code can not, from the start
+            let result = code.jit.borrow_mut().call(&code, &args, &mut self.budget);
+            if let Some(v) = result {
+                self.budget.exit_call();
+                self.mov(RName::R0, v);
+                return Ok(());
             }
         }
+
+        let mut context = ExecutionContext {
+            context: Context::create(&mut self.heap, outer)?,
+        };
+        // sloppy mode: no receiver means the global object
+        let this = match this.is_undefined() || this.is_null() {
+            true => self.realm.global_object,
+            false => this,
+        };
+        context.set(&mut self.heap, "this", this);
+        let mut args = args.into_iter();
+        for parameter in code.parameters.iter() {
+            let arg = args.next().unwrap_or_else(Value::undefined);
+            context.set(&mut self.heap, parameter, arg);
+        }
+
+        self.frames.push(Frame {
+            function: self.function.replace(code),
+            pc: std::mem::replace(&mut self.pc, 0),
+            context: std::mem::replace(&mut self.execution_context.context, context.context),
+            context_depth: std::mem::replace(&mut self.context_depth, 0),
+            stack_len: self.stack.len(),
+            registers: self.register,
+        });
         Ok(())
     }
 
+    /// leave the running function for its caller.
+    fn return_to(&mut self, frame: Frame) {
+        self.function = frame.function;
+        self.pc = frame.pc;
+        self.execution_context.context = frame.context;
+        self.context_depth = frame.context_depth;
+        self.stack.truncate(frame.stack_len);
+        self.register = Register {
+            r0: self.register.r0,
+            ..frame.registers
+        };
+        self.budget.exit_call();
+    }
+
+    fn alloc(&mut self) -> Result<&'static mut JSObject, VMError> {
+        self.heap.alloc().ok_or_else(VMError::out_of_memory)
+    }
+
+    /// the code of the running function or the script.
+    fn current_code(&self) -> &[u8] {
+        match &self.function {
+            Some(function) => &function.code,
+            None => &self.code,
+        }
+    }
+
+    fn with_constants<R>(&self, f: impl FnOnce(&ConstantTable) -> R) -> R {
+        match &self.function {
+            Some(function) => f(&function.constant_table),
+            None => f(&self.constant_table),
+        }
+    }
+
+    /// the feedback vector and the constants of the running code.
+    fn with_feedback<R>(&mut self, f: impl FnOnce(&mut FeedbackVector, &ConstantTable) -> R) -> R {
+        match &self.function {
+            Some(function) => f(
+                &mut function.feedback.borrow_mut(),
+                &function.constant_table,
+            ),
+            None => f(&mut self.feedback, &self.constant_table),
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        // the constants are not roots, they hold no heap pointers
+        debug_assert!(self
+            .constant_table
+            .iter()
+            .all(Constant::holds_no_heap_pointer));
+
+        // only values tagged as pointers are roots
+        let mut roots = Vec::new();
+        let mut gather = |v: &mut Value| roots.extend(v.as_object_ptr());
+        self.register.values_mut().into_iter().for_each(&mut gather);
+        self.stack.iter_mut().for_each(&mut gather);
+        self.realm.for_each_value(&mut gather);
+        self.execution_context.for_each_value(&mut gather);
+        for frame in self.frames.iter_mut() {
+            gather(&mut frame.context);
+            frame
+                .registers
+                .values_mut()
+                .into_iter()
+                .for_each(&mut gather);
+        }
+
+        self.heap.collect(&mut roots);
+
+        // write the moved pointers back in the same order
+        let mut moved = roots.into_iter();
+        let mut update = |v: &mut Value| {
+            if v.is_object() {
+                *v = Value::object(moved.next().unwrap());
+            }
+        };
+        self.register.values_mut().into_iter().for_each(&mut update);
+        self.stack.iter_mut().for_each(&mut update);
+        self.realm.for_each_value(&mut update);
+        self.execution_context.for_each_value(&mut update);
+        for frame in self.frames.iter_mut() {
+            update(&mut frame.context);
+            frame
+                .registers
+                .values_mut()
+                .into_iter()
+                .for_each(&mut update);
+        }
+    }
+
     fn fetch(&mut self) -> u8 {
-        if self.pc < self.code.len() {
-            let opcode = self.code[self.pc];
-            self.pc += 1;
-            opcode
-        } else {
-            Bytecodes::Hlt
+        match self.current_code().get(self.pc).copied() {
+            Some(opcode) => {
+                self.pc += 1;
+                opcode
+            }
+            None => Bytecodes::Hlt,
         }
     }
 
@@ -301,7 +916,7 @@ impl VirtualMachine {
         s
     }
 
-    fn mov(&mut self, r: u8, v: i64) {
+    fn mov(&mut self, r: u8, v: Value) {
         match r {
             RName::R0 => self.register.r0 = v,
             RName::R1 => self.register.r1 = v,
@@ -343,147 +958,189 @@ impl VirtualMachine {
         }
     }
 
-    fn load_undefined(&mut self) -> Result<(), VMError> {
-        if let Some(raw_ptr) = self
-            .execution_context
-            .context
-            .clone()
-            .borrow()
-            .get("undefined")
-        {
-            self.mov(RName::R0, raw_ptr);
-            Ok(())
-        } else {
-            Err(VMError::new(
-                VMErrorKind::Internal,
-                "internal error".to_string(),
-            ))
+    /// the object property lookups on `v` go to. primitives use their prototype.
+    fn to_object(&self, v: Value) -> Result<&'static JSObject, VMError> {
+        if let Some(object) = v.as_object() {
+            return Ok(object);
+        }
+        match self.realm.number_prototype.as_object() {
+            Some(prototype) if v.is_number() => Ok(prototype),
+            _ => Err(VMError::new(
+                VMErrorKind::Type,
+                format!("Cannot read properties of {:?}", v),
+            )),
         }
     }
 
-    fn add(&mut self) {
-        let r1 = self.fetch();
-        let r2 = self.fetch();
-        let l_obj = JSObject::from_raw_ptr(self.get_reg_v(r1));
-        let r_obj = JSObject::from_raw_ptr(self.get_reg_v(r2));
-
-        match (&l_obj._type, &r_obj._type) {
-            (JSType::Number(n1), JSType::Number(n2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(n1 + n2, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            (JSType::String(s1), JSType::String(s2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let mut s = s2.clone();
-                s.push_str(s1);
-                let num_obj = JSString::create(s, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            (JSType::Number(n1), JSType::String(s2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let mut s = s2.clone();
-                s.push_str(&n1.to_string());
-                let num_obj = JSString::create(s, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            (JSType::String(s1), JSType::Number(n2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let mut s = n2.to_string();
-                s.push_str(s1);
-                let num_obj = JSString::create(s, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            _ => {
-                // TODO: string + others
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(f64::NAN, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
+    /// `object.name` through the inline cache of the site.
+    fn load_named_property(&mut self, v: Value, id: u32, slot: usize) -> Result<Value, VMError> {
+        let object = self.to_object(v)?;
+        if let Some(length) = length_of(object) {
+            if self.with_constants(|constants| constants.get(id) == "length") {
+                return Ok(Value::number(length as f64));
             }
         }
+        Ok(self.with_feedback(|feedback, constants| {
+            let ic = feedback.load(slot);
+            if let Some(prop) = ic.lookup(&object.shape).and_then(|h| h.load(object)) {
+                return prop;
+            }
+
+            // miss: the slow lookup, then remember how it went
+            match LoadHandler::compute(object, constants.get(id)) {
+                Some((handler, prop)) => {
+                    if !ic.is_megamorphic() {
+                        ic.update(handler);
+                    }
+                    prop
+                }
+                None => Value::undefined(),
+            }
+        }))
     }
 
-    fn sub(&mut self) {
-        let r1 = self.fetch();
-        let r2 = self.fetch();
-        let l_obj = JSObject::from_raw_ptr(self.get_reg_v(r1));
-        let r_obj = JSObject::from_raw_ptr(self.get_reg_v(r2));
+    /// `object.name = value` through the inline cache of the site.
+    /// stores to primitives are ignored.
+    fn store_named_property(
+        &mut self,
+        object: Value,
+        id: u32,
+        slot: usize,
+        value: Value,
+    ) -> Result<(), VMError> {
+        let object = match object.as_object() {
+            Some(object) => object,
+            None => return Ok(()),
+        };
+        if value.is_object() && self.with_constants(|c| c.get(id) == PROTOTYPE_KEY_NAME) {
+            check_prototype(object, value)?;
+        }
+        self.heap.write_barrier(object, value);
 
-        match (&l_obj._type, &r_obj._type) {
-            (JSType::Number(n1), JSType::Number(n2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(n1 - n2, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
+        self.with_feedback(|feedback, constants| {
+            let ic = feedback.store(slot);
+            if let Some(handler) = ic.lookup(&object.shape) {
+                handler.store(object, value);
+                return;
             }
-            _ => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(f64::NAN, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
+
+            let handler = StoreHandler::compute(object, constants.get(id));
+            handler.store(object, value);
+            if !ic.is_megamorphic() {
+                ic.update(handler);
             }
-        }
+        });
+        Ok(())
     }
 
-    fn mul(&mut self) {
-        let r1 = self.fetch();
-        let r2 = self.fetch();
-        let l_obj = JSObject::from_raw_ptr(self.get_reg_v(r1));
-        let r_obj = JSObject::from_raw_ptr(self.get_reg_v(r2));
-
-        match (&l_obj._type, &r_obj._type) {
-            (JSType::Number(n1), JSType::Number(n2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(n1 * n2, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            _ => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(f64::NAN, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
+    /// `object[key]`. array elements and string characters by index, other keys are names.
+    fn load_keyed_property(&mut self, v: Value, key: Value) -> Result<Value, VMError> {
+        let object = self.to_object(v)?;
+        if let Some(index) = as_array_index(key) {
+            match &object._type {
+                JSType::Array(elements) if index < elements.len() => return Ok(elements[index]),
+                JSType::String(s) => {
+                    return match s.encode_utf16().nth(index) {
+                        Some(c) => {
+                            let base_obj = self.alloc()?;
+                            let s = String::from_utf16_lossy(&[c]);
+                            Ok(Value::from(JSString::create(s, base_obj, self)))
+                        }
+                        None => Ok(Value::undefined()),
+                    }
+                }
+                _ => {}
             }
         }
+
+        let key = to_js_string(key);
+        match length_of(object) {
+            Some(length) if key == "length" => Ok(Value::number(length as f64)),
+            _ => Ok(object.get(&key).unwrap_or_else(Value::undefined)),
+        }
     }
 
-    fn div(&mut self) {
-        let r1 = self.fetch();
-        let r2 = self.fetch();
-        let l_obj = JSObject::from_raw_ptr(self.get_reg_v(r1));
-        let r_obj = JSObject::from_raw_ptr(self.get_reg_v(r2));
+    /// `object[key] = value`. stores to primitives are ignored.
+    fn store_keyed_property(
+        &mut self,
+        object: Value,
+        key: Value,
+        value: Value,
+    ) -> Result<(), VMError> {
+        let object = match object.as_object() {
+            Some(object) => object,
+            None => return Ok(()),
+        };
+        self.heap.write_barrier(object, value);
 
-        match (&l_obj._type, &r_obj._type) {
-            (JSType::Number(n1), JSType::Number(n2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(n1 / n2, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            _ => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(f64::NAN, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
+        if let (JSType::Array(elements), Some(index)) = (&mut object._type, as_array_index(key)) {
+            if index < elements.len() + MAX_ELEMENTS_GAP {
+                if index >= elements.len() {
+                    elements.resize(index + 1, Value::undefined());
+                }
+                elements[index] = value;
+                return Ok(());
             }
         }
+        let key = to_js_string(key);
+        if key == PROTOTYPE_KEY_NAME {
+            check_prototype(object, value)?;
+        }
+        object.set(&key, value);
+        Ok(())
     }
 
-    fn r#mod(&mut self) {
+    /// a relational test of r1 and r2.
+    fn compare(&mut self, opcode: u8) {
         let r1 = self.fetch();
         let r2 = self.fetch();
-        let l_obj = JSObject::from_raw_ptr(self.get_reg_v(r1));
-        let r_obj = JSObject::from_raw_ptr(self.get_reg_v(r2));
+        let l = self.get_reg_v(r1);
+        let r = self.get_reg_v(r2);
 
-        match (&l_obj._type, &r_obj._type) {
-            (JSType::Number(n1), JSType::Number(n2)) => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(n1 % n2, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
-            }
-            _ => {
-                let base_obj = self.heap.alloc().unwrap();
-                let num_obj = JSNumber::create(f64::NAN, base_obj, self);
-                self.mov(RName::R0, num_obj.as_raw_ptr());
+        let test = relational_test(opcode).unwrap();
+        // NaN compares false with everything
+        let v = Value::boolean(matches!(compare_values(l, r), Some(o) if test(o)));
+        self.mov(RName::R0, v);
+    }
+
+    /// the operands of a binary operation: two registers, or for the `*Smi` forms a register
+    /// and an immediate.
+    fn binary_operands(&mut self, opcode: u8) -> (Value, Value) {
+        let r1 = self.fetch();
+        let l = self.get_reg_v(r1);
+        let r = match (Bytecodes::AddSmi..=Bytecodes::ShiftRightLogicalSmi).contains(&opcode) {
+            true => Value::number(self.fetch_i64() as f64),
+            false => {
+                let r2 = self.fetch();
+                self.get_reg_v(r2)
             }
+        };
+        (l, r)
+    }
+
+    fn add(&mut self, opcode: u8) -> Result<(), VMError> {
+        let (l, r) = self.binary_operands(opcode);
+
+        if let Some(v) = add_primitives(l, r) {
+            self.mov(RName::R0, v);
+            return Ok(());
         }
+        let s = format!("{}{}", to_js_string(l), to_js_string(r));
+        let base_obj = self.alloc()?;
+        let str_obj = JSString::create(s, base_obj, self);
+        self.mov(RName::R0, Value::from(str_obj));
+        Ok(())
+    }
+
+    /// a numeric binary operation, converted with ToNumber.
+    fn arithmetic(&mut self, opcode: u8) {
+        let (l, r) = self.binary_operands(opcode);
+        let op = numeric_operation(opcode).unwrap();
+        let v = Value::number(op(to_number(l), to_number(r)));
+        self.mov(RName::R0, v);
     }
 
-    pub(crate) fn get_reg_v(&self, r: u8) -> i64 {
+    pub(crate) fn get_reg_v(&self, r: u8) -> Value {
         match r {
             RName::R0 => self.register.r0,
             RName::R1 => self.register.r1,
@@ -498,12 +1155,273 @@ impl VirtualMachine {
     }
 }
 
+/// `+` unless either side is an object (a string included), which concatenates.
+fn add_primitives(l: Value, r: Value) -> Option<Value> {
+    // smis add without leaving the immediate range in the common case
+    if let (Some(n1), Some(n2)) = (l.as_smi(), r.as_smi()) {
+        return Some(match n1.checked_add(n2) {
+            Some(n) => Value::smi(n),
+            None => Value::double(n1 as f64 + n2 as f64),
+        });
+    }
+    match l.is_object() || r.is_object() {
+        true => None,
+        false => Some(Value::number(to_number(l) + to_number(r))),
+    }
+}
+
+/// the operation on numbers of a binary opcode but `Add`, or of its `*Smi` form.
+fn numeric_operation(opcode: u8) -> Option<fn(f64, f64) -> f64> {
+    Some(match opcode {
+        Bytecodes::Sub | Bytecodes::SubSmi => |n1, n2| n1 - n2,
+        Bytecodes::Mul | Bytecodes::MulSmi => |n1, n2| n1 * n2,
+        Bytecodes::Div | Bytecodes::DivSmi => |n1, n2| n1 / n2,
+        Bytecodes::Mod | Bytecodes::ModSmi => |n1, n2| n1 % n2,
+        Bytecodes::Exp | Bytecodes::ExpSmi => exponentiate,
+        Bytecodes::BitwiseOr | Bytecodes::BitwiseOrSmi => {
+            |n1, n2| (to_int32(n1) | to_int32(n2)) as f64
+        }
+        Bytecodes::BitwiseXor | Bytecodes::BitwiseXorSmi => {
+            |n1, n2| (to_int32(n1) ^ to_int32(n2)) as f64
+        }
+        Bytecodes::BitwiseAnd | Bytecodes::BitwiseAndSmi => {
+            |n1, n2| (to_int32(n1) & to_int32(n2)) as f64
+        }
+        Bytecodes::ShiftLeft | Bytecodes::ShiftLeftSmi => {
+            |n1, n2| to_int32(n1).wrapping_shl(to_uint32(n2)) as f64
+        }
+        Bytecodes::ShiftRight | Bytecodes::ShiftRightSmi => {
+            |n1, n2| to_int32(n1).wrapping_shr(to_uint32(n2)) as f64
+        }
+        Bytecodes::ShiftRightLogical | Bytecodes::ShiftRightLogicalSmi => {
+            |n1, n2| to_uint32(n1).wrapping_shr(to_uint32(n2)) as f64
+        }
+        _ => return None,
+    })
+}
+
+/// the operation on numbers of a unary opcode but `LogicalNot` and `TypeOf`.
+fn unary_operation(opcode: u8) -> Option<fn(f64) -> f64> {
+    Some(match opcode {
+        Bytecodes::Negate => |n| -n,
+        Bytecodes::BitwiseNot => |n| !to_int32(n) as f64,
+        Bytecodes::Inc => |n| n + 1.0,
+        Bytecodes::Dec => |n| n - 1.0,
+        Bytecodes::ToNumeric => |n| n,
+        _ => return None,
+    })
+}
+
+/// strings compare by content, everything else as numbers. `None` with a NaN.
+fn compare_values(l: Value, r: Value) -> Option<Ordering> {
+    match (as_string(l), as_string(r)) {
+        (Some(s1), Some(s2)) => Some(s1.cmp(s2)),
+        _ => to_number(l).partial_cmp(&to_number(r)),
+    }
+}
+
+/// the orderings a relational opcode is true for.
+fn relational_test(opcode: u8) -> Option<fn(Ordering) -> bool> {
+    Some(match opcode {
+        Bytecodes::TestLessThan => |o| o == Ordering::Less,
+        Bytecodes::TestGreaterThan => |o| o == Ordering::Greater,
+        Bytecodes::TestLessThanOrEqual => |o| o != Ordering::Greater,
+        Bytecodes::TestGreaterThanOrEqual => |o| o != Ordering::Less,
+        _ => return None,
+    })
+}
+
+/// a TypeError if `object` is on the prototype chain of `prototype`. chains are kept free of
+/// cycles, the lookups walk them in a single instruction the limits can not stop.
+fn check_prototype(object: &JSObject, prototype: Value) -> Result<(), VMError> {
+    let mut next = prototype.as_object().map(|p| &*p);
+    while let Some(p) = next {
+        if std::ptr::eq(p, object) {
+            return Err(VMError::new(
+                VMErrorKind::Type,
+                "Cyclic __proto__ value".to_string(),
+            ));
+        }
+        next = p.prototype();
+    }
+    Ok(())
+}
+
+/// ToBoolean.
+fn to_boolean(v: Value) -> bool {
+    if let Some(b) = v.as_boolean() {
+        return b;
+    }
+    if let Some(n) = v.as_number() {
+        return n != 0.0 && !n.is_nan();
+    }
+    if let Some(s) = as_string(v) {
+        return !s.is_empty();
+    }
+    v.is_object()
+}
+
+/// `===`. numbers compare by value, strings by content, everything else by identity.
+fn strict_equals(l: Value, r: Value) -> bool {
+    if let (Some(n1), Some(n2)) = (l.as_number(), r.as_number()) {
+        return n1 == n2;
+    }
+    if let (Some(s1), Some(s2)) = (as_string(l), as_string(r)) {
+        return s1 == s2;
+    }
+    l == r
+}
+
+/// `==`. null and undefined equal each other, strings and booleans compare with numbers as
+/// numbers. other objects only equal themselves.
+fn loose_equals(l: Value, r: Value) -> bool {
+    if strict_equals(l, r) {
+        return true;
+    }
+    let nullish = |v: Value| v.is_null() || v.is_undefined();
+    if nullish(l) || nullish(r) {
+        return nullish(l) && nullish(r);
+    }
+    let is_object = |v: Value| v.is_object() && as_string(v).is_none();
+    if is_object(l) || is_object(r) || (as_string(l).is_some() && as_string(r).is_some()) {
+        return false;
+    }
+    to_number(l) == to_number(r)
+}
+
+/// ToNumber. objects other than strings are NaN.
+fn to_number(v: Value) -> f64 {
+    if let Some(n) = v.as_number() {
+        return n;
+    }
+    if let Some(b) = v.as_boolean() {
+        return b as i32 as f64;
+    }
+    if v.is_null() {
+        return 0.0;
+    }
+    match as_string(v).map(str::trim) {
+        Some("") => 0.0,
+        Some("Infinity" | "+Infinity") => f64::INFINITY,
+        Some("-Infinity") => f64::NEG_INFINITY,
+        Some(s) if s.starts_with("0x") || s.starts_with("0X") => {
+            i64::from_str_radix(&s[2..], 16).map_or(f64::NAN, |n| n as f64)
+        }
+        // rust also parses "inf" and "nan"
+        Some(s) if s.contains(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E') => {
+            f64::NAN
+        }
+        Some(s) => s.parse().unwrap_or(f64::NAN),
+        None => f64::NAN,
+    }
+}
+
+/// ToInt32.
+fn to_int32(n: f64) -> i32 {
+    to_uint32(n) as i32
+}
+
+/// ToUint32.
+fn to_uint32(n: f64) -> u32 {
+    if !n.is_finite() {
+        return 0;
+    }
+    (n.trunc() % 4294967296.0) as i64 as u32
+}
+
+/// `**`. unlike `powf`, a NaN exponent is always NaN and so is ±1 to an infinite power.
+fn exponentiate(base: f64, exponent: f64) -> f64 {
+    if exponent.is_nan() || (base.abs() == 1.0 && exponent.is_infinite()) {
+        return f64::NAN;
+    }
+    base.powf(exponent)
+}
+
+/// ToString.
+pub(crate) fn to_js_string(v: Value) -> String {
+    if let Some(n) = v.as_number() {
+        return match n {
+            n if n.is_nan() => "NaN".to_string(),
+            f64::INFINITY => "Infinity".to_string(),
+            f64::NEG_INFINITY => "-Infinity".to_string(),
+            // -0 too
+            0.0 => "0".to_string(),
+            n => n.to_string(),
+        };
+    }
+    if let Some(b) = v.as_boolean() {
+        return b.to_string();
+    }
+    match v.as_object().map(|o| &o._type) {
+        Some(JSType::String(s)) => s.clone(),
+        Some(JSType::Array(elements)) => elements
+            .iter()
+            .map(|&e| match e.is_undefined() || e.is_null() {
+                true => String::new(),
+                false => to_js_string(e),
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+        Some(JSType::Function(_) | JSType::NativeFunction(_)) => {
+            "function () { [native code] }".to_string()
+        }
+        Some(_) => "[object Object]".to_string(),
+        None if v.is_null() => "null".to_string(),
+        None => "undefined".to_string(),
+    }
+}
+
+/// the result of `typeof`.
+fn type_of(v: Value) -> &'static str {
+    if v.is_number() {
+        return "number";
+    }
+    if v.as_boolean().is_some() {
+        return "boolean";
+    }
+    match v.as_object().map(|o| &o._type) {
+        Some(JSType::String(_)) => "string",
+        Some(JSType::Function(_) | JSType::NativeFunction(_)) => "function",
+        Some(_) => "object",
+        None if v.is_null() => "object",
+        None => "undefined",
+    }
+}
+
+/// the index `key` denotes, if it is a canonical array index.
+fn as_array_index(key: Value) -> Option<usize> {
+    if let Some(n) = key.as_smi() {
+        return usize::try_from(n).ok();
+    }
+    if let Some(n) = key.as_number() {
+        let valid = n >= 0.0 && n < u32::MAX as f64 && n.trunc() == n;
+        return valid.then_some(n as usize);
+    }
+    let s = as_string(key)?;
+    let index: u32 = s.parse().ok()?;
+    (index != u32::MAX && index.to_string() == s).then_some(index as usize)
+}
+
+/// the `length` of arrays and strings.
+fn length_of(object: &JSObject) -> Option<usize> {
+    match &object._type {
+        JSType::Array(elements) => Some(elements.len()),
+        JSType::String(s) => Some(s.encode_utf16().count()),
+        _ => None,
+    }
+}
+
+pub(crate) fn as_string<'a>(v: Value) -> Option<&'a str> {
+    match v.as_object().map(|o| &o._type) {
+        Some(JSType::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
 /// printer impl
 impl VirtualMachine {
     fn print_current_expr(&self) {
-        let ptr = self.get_reg_v(RName::R0);
-        let obj = JSObject::from_raw_ptr(ptr);
-        println!("{}", obj);
+        println!("{}", self.get_reg_v(RName::R0));
     }
 
     fn print_bytecode(&self) {
@@ -536,192 +1454,736 @@ impl VirtualMachine {
         println!();
     }
 
+    /// the disassembly of the code run so far, with the functions it created.
     fn print_ir(&self) {
-        let i = self.get_instructions(&self.code);
-        for (inst, _) in i {
-            print!("\x1b[30m{inst}\x1b[0m ");
-            println!();
-        }
+        let listing = Listing {
+            code: &self.code,
+            constant_table: &self.constant_table,
+            handler_table: &self.handler_table,
+            source_positions: &self.source_positions,
+        };
+        print!("\x1b[30m{}\x1b[0m", listing.disassemble());
     }
 
     fn print_dump(&self) {
-        let i = self.get_instructions(&self.code);
-        for (idx, (_, bytes)) in i.iter().enumerate() {
+        let mut pc = 0;
+        while let Ok(instruction) = bytecodes::decode(&self.code, pc) {
+            let bytes = &self.code[pc..pc + instruction.len];
             print!(
-                "  \x1b[30m0x{:x} @     0x{idx:08x} : \x1b[0m",
+                "  \x1b[30m0x{:x} @     0x{pc:08x} : \x1b[0m",
                 bytes.as_ptr() as usize
             );
             for byte in bytes.iter() {
                 print!("\x1b[30m{:02x}\x1b[0m ", byte);
             }
             println!();
+            pc += instruction.len;
+            if pc == self.code.len() {
+                break;
+            }
         }
     }
+}
 
-    fn get_instructions<'b>(&'b self, code: &'b Vec<u8>) -> Vec<(String, &[u8])> {
-        let mut res: Vec<(String, &[u8])> = Vec::new();
-        let mut i = 0;
-
-        while i < code.len() {
-            match code[i] {
-                Bytecodes::Mov => {
-                    let r1 = code[i + 1];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        core::vm::{feedback::InlineCache, objects::constant::PROTOTYPE_KEY_NAME},
+        parsing::BuiltinParser,
+    };
+    use std::rc::Rc;
+
+    impl VirtualMachine {
+        fn heap_object(&self, name: &str) -> &'static JSObject {
+            let v = self.execution_context.get(name).unwrap();
+            v.as_object().unwrap()
+        }
+    }
 
-                    let v = (code[i + 9] as i64) << 56
-                        | (code[i + 8] as i64) << 48
-                        | (code[i + 7] as i64) << 40
-                        | (code[i + 6] as i64) << 32
-                        | (code[i + 5] as i64) << 24
-                        | (code[i + 4] as i64) << 16
-                        | (code[i + 3] as i64) << 8
-                        | (code[i + 2] as i64);
+    #[test]
+    fn test_limits() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        vm.set_limits(Limits::new().max_steps(4));
+        let e = vm
+            .exec("for (let i = 0; i < 10; i++) {}".to_string())
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated: step limit exceeded");
+
+        vm.set_limits(Limits::new());
+        vm.interrupt_handle().terminate();
+        let e = vm.exec("1 + 2;".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated");
+
+        // the aborted code is not resumed
+        assert!(vm.exec("3 + 4;".to_string()).is_ok());
+        assert_eq!(vm.pc, vm.code.len());
+    }
 
-                    res.push((format!("Mov r{}, {}", r1, v), &code[i..i + 10]));
-                    i += 10;
+    #[test]
+    fn test_out_of_memory() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        // the nursery and one page of old space
+        vm.set_limits(Limits::new().max_heap_bytes(768 * 1024));
+        let source = r#"
+            const fill = function () {
+                const keep = [];
+                for (let i = 0; i < 1000000; i++) {
+                    keep[i] = {};
                 }
+            };
+            let m = 0;
+            try {
+                fill();
+            } catch (e) {
+                m = e.name + ': ' + e.message;
+            }
+            m;
+        "#;
+        assert!(vm.exec(source.to_string()).is_ok());
+        assert_eq!(
+            to_js_string(vm.register.r0),
+            "RangeError: Maximum heap size exceeded"
+        );
+
+        // the garbage is collected and the vm runs on
+        assert!(vm.exec("const o = { a: 1 }; o.a + 1;".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(2));
+    }
 
-                Bytecodes::Push => {
-                    let r1 = code[i + 1];
-                    res.push((format!("Push r{}", r1), &code[i..i + 2]));
-                    i += 2;
-                }
-                Bytecodes::Pop => {
-                    let r1 = code[i + 1];
-                    res.push((format!("Pop r{}", r1), &code[i..i + 2]));
-                    i += 2;
-                }
-                Bytecodes::Hlt => {
-                    res.push(("Hlt".to_string(), &code[i..i + 1]));
-                    i += 1;
-                }
-
-                Bytecodes::Add => {
-                    let r1 = code[i + 1];
-                    let r2 = code[i + 2];
-                    res.push((format!("Add r{}, r{}", r1, r2), &code[i..i + 3]));
-                    i += 3;
-                }
-                Bytecodes::Sub => {
-                    let r1 = code[i + 1];
-                    let r2 = code[i + 2];
-                    res.push((format!("Sub r{}, r{}", r1, r2), &code[i..i + 3]));
-                    i += 3;
-                }
-                Bytecodes::Mul => {
-                    let r1 = code[i + 1];
-                    let r2 = code[i + 2];
-                    res.push((format!("Mul r{}, r{}", r1, r2), &code[i..i + 3]));
-                    i += 3;
-                }
-                Bytecodes::Div => {
-                    let r1 = code[i + 1];
-                    let r2 = code[i + 2];
-                    res.push((format!("Div r{}, r{}", r1, r2), &code[i..i + 3]));
-                    i += 3;
-                }
-                Bytecodes::Mod => {
-                    let r1 = code[i + 1];
-                    let r2 = code[i + 2];
-                    res.push((format!("Mod r{}, r{}", r1, r2), &code[i..i + 3]));
-                    i += 3;
-                }
-
-                Bytecodes::Construct => {
-                    res.push(("Construct".to_string(), &code[i..i + 1]));
-                    i += 1;
-                }
-
-                Bytecodes::StaContextSlot => {
-                    let len = ((code[i + 8] as i64) << 56
-                        | (code[i + 7] as i64) << 48
-                        | (code[i + 6] as i64) << 40
-                        | (code[i + 5] as i64) << 32
-                        | (code[i + 4] as i64) << 24
-                        | (code[i + 3] as i64) << 16
-                        | (code[i + 2] as i64) << 8
-                        | (code[i + 1] as i64)) as usize;
-
-                    let name = String::from_utf8(code[i + 9..i + 9 + len].to_vec())
-                        .unwrap_or_else(|_| String::from(""));
-
-                    res.push((
-                        format!("StaContextSlot \"{}\"", name),
-                        &code[i..i + 9 + len],
-                    ));
-                    i += 9 + len;
-                }
+    #[test]
+    fn test_gc() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        assert!(vm.exec("let a = 'a';".to_string()).is_ok());
 
-                Bytecodes::LdaContextSlot => {
-                    let len = ((code[i + 8] as i64) << 56
-                        | (code[i + 7] as i64) << 48
-                        | (code[i + 6] as i64) << 40
-                        | (code[i + 5] as i64) << 32
-                        | (code[i + 4] as i64) << 24
-                        | (code[i + 3] as i64) << 16
-                        | (code[i + 2] as i64) << 8
-                        | (code[i + 1] as i64)) as usize;
+        // short-lived strings die young and the old space does not grow
+        for _ in 0..20000 {
+            assert!(vm.exec("a + 'b';".to_string()).is_ok());
+        }
+        let statistics = vm.heap.statistics();
+        assert!(statistics.scavenges > 0);
+        assert_eq!(statistics.pages, 1);
+
+        assert!(vm.exec("a + 'b';".to_string()).is_ok());
+        assert_eq!(as_string(vm.register.r0), Some("ab"));
+        assert!(vm.stack.is_empty());
+
+        // constants are not roots, every load makes a new string from them
+        let source = "const s = function () { return 'constant'; }; s();";
+        assert!(vm.exec(source.to_string()).is_ok());
+        assert!(vm
+            .constant_table
+            .iter()
+            .all(Constant::holds_no_heap_pointer));
+        vm.collect_garbage();
+        vm.collect_garbage();
+        assert!(vm.exec("s() + s();".to_string()).is_ok());
+        assert_eq!(as_string(vm.register.r0), Some("constantconstant"));
+    }
 
-                    let name = String::from_utf8(code[i + 9..i + 9 + len].to_vec())
-                        .unwrap_or_else(|_| String::from(""));
+    #[test]
+    fn test_shared_prototypes() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let before = vm.heap.statistics().young;
+        assert!(vm.exec("let a = 'a';".to_string()).is_ok());
+        assert!(vm.exec("let b = 'b';".to_string()).is_ok());
+        // one cell per string, the prototype is not copied
+        assert_eq!(vm.heap.statistics().young, before + 2);
+
+        let proto = |vm: &VirtualMachine| {
+            let object = vm.register.r0.as_object().unwrap();
+            object.get_own(PROTOTYPE_KEY_NAME).unwrap()
+        };
+        assert!(vm.exec("a;".to_string()).is_ok());
+        assert!(proto(&vm) == vm.realm.string_prototype);
+        assert!(vm.exec("b;".to_string()).is_ok());
+        assert!(proto(&vm) == vm.realm.string_prototype);
+
+        // patching the prototype is seen by every instance
+        let realm = &vm.realm;
+        realm.define_native(
+            &mut vm.heap,
+            realm.string_prototype,
+            "charCodeAt",
+            |_, _, _| Ok(Value::smi(0)),
+        );
+        assert!(vm.exec("a.charCodeAt();".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(0));
+        assert!(vm.exec("b.charCodeAt();".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(0));
+    }
 
-                    res.push((
-                        format!("LdaContextSlot \"{}\"", name),
-                        &code[i..i + 9 + len],
-                    ));
-                    i += 9 + len;
-                }
+    #[test]
+    fn test_statements() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let eval = |vm: &mut VirtualMachine, source: &str| {
+            assert!(vm.exec(source.to_string()).is_ok());
+            vm.register.r0
+        };
 
-                Bytecodes::LdaUndefined => {
-                    res.push(("LdaUndefined".to_string(), &code[i..i + 1]));
-                    i += 1;
+        let source = r#"
+            let s = 'x';
+            s.n = 5;
+            s.sum = 0;
+            for (let i = 0; s.n; s.n = s.n - 1) {
+                s.sum = s.sum + s.n;
+            }
+            s.sum;
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(15));
+
+        let source = "if (s.sum - 15) { s.r = 'then'; } else { s.r = 'else'; } s.r;";
+        assert_eq!(as_string(eval(&mut vm, source)), Some("else"));
+
+        // cases fall through until a break
+        let source = r#"
+            const c = 3;
+            switch (c) {
+                case 1: { s.k = 'one'; }
+                case 3: { s.k = 'three'; }
+                case 4: { s.k = s.k + '-four'; break; }
+                default: { s.k = 'other'; }
+            }
+            s.k;
+        "#;
+        assert_eq!(as_string(eval(&mut vm, source)), Some("three-four"));
+        let source = "switch (c) { case 1: { s.k = 'one'; } default: { s.k = 'other'; } } s.k;";
+        assert_eq!(as_string(eval(&mut vm, source)), Some("other"));
+
+        let source = r#"
+            s.m = 0;
+            for (let j = 0; 1; s.m = s.m + 1) {
+                switch (s.m) {
+                    case 3: { break; }
+                    default: { continue; }
                 }
+                break;
+            }
+            s.m;
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(3));
+        assert!(vm.stack.is_empty());
+
+        // blocks have their own scope
+        assert_eq!(
+            eval(&mut vm, "{ const c = 4; s.c = c; } s.c + c;").as_smi(),
+            Some(7)
+        );
+
+        let e = vm.exec("const c = 1;".to_string()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "SyntaxError: Identifier 'c' has already been declared"
+        );
+        let e = vm.exec("break;".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "SyntaxError: Illegal break statement");
+    }
 
-                Bytecodes::LdaSmi => {
-                    let v = ((code[i + 8] as i64) << 56
-                        | (code[i + 7] as i64) << 48
-                        | (code[i + 6] as i64) << 40
-                        | (code[i + 5] as i64) << 32
-                        | (code[i + 4] as i64) << 24
-                        | (code[i + 3] as i64) << 16
-                        | (code[i + 2] as i64) << 8
-                        | (code[i + 1] as i64)) as usize;
+    #[test]
+    fn test_functions() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let eval = |vm: &mut VirtualMachine, source: &str| {
+            assert!(vm.exec(source.to_string()).is_ok());
+            vm.register.r0
+        };
 
-                    res.push((format!("LdaSmi {}", v), &code[i..i + 9]));
-                    i += 9;
+        let source = r#"
+            const factorial = function (n) {
+                if (n == 0) {
+                    return 1;
                 }
+                return n * factorial(n - 1);
+            };
+            factorial(5);
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(120));
+        assert!(vm.frames.is_empty() && vm.stack.is_empty());
+
+        // missing arguments are undefined unless they have a default, extra ones are dropped
+        let source = "const f = function (a, b = 10, c) { return c; }; f(1);";
+        assert!(eval(&mut vm, source).is_undefined());
+        assert_eq!(
+            eval(&mut vm, "(function (a, b = 10) { return a + b; })(1);").as_smi(),
+            Some(11)
+        );
+        assert_eq!(
+            eval(
+                &mut vm,
+                "(function (a, b = 10) { return a + b; })(1, 2, 3);"
+            )
+            .as_smi(),
+            Some(3)
+        );
+
+        // closures keep their context alive, each call gets a new one
+        let source = r#"
+            const counter = function () {
+                let c = 'c';
+                c.n = 0;
+                return function (step = 1) {
+                    c.n = c.n + step;
+                    return c.n;
+                };
+            };
+            const a = counter();
+            const b = counter();
+            a();
+            a(5);
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(6));
+        vm.collect_garbage();
+        vm.collect_garbage();
+        assert_eq!(eval(&mut vm, "a() + b();").as_smi(), Some(8));
+
+        // methods get their receiver as `this`
+        let source = "const o = 'o'; o.v = 3; o.get = function () { return this.v; }; o.get();";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(3));
+
+        let e = vm.exec("return 1;".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "SyntaxError: Illegal return statement");
+        let e = vm.exec("o.v();".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "TypeError: callee is not a function");
+        let e = vm
+            .exec("const r = function () { return r(); }; r();".to_string())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "RangeError: Maximum call stack size exceeded"
+        );
+        assert!(vm.frames.is_empty() && vm.function.is_none());
+    }
 
-                Bytecodes::GetNamedProperty => {
-                    let obj_ptr_reg = code[i + 1];
-                    let prop_name_idx = code[i + 2];
-                    res.push((
-                        format!("GetNamedProperty r{obj_ptr_reg}, [{prop_name_idx}]",),
-                        &code[i..i + 3],
-                    ));
-                    i += 3;
-                }
+    #[test]
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), unix))]
+    fn test_jit() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        vm.set_jit(true);
+        let eval = |vm: &mut VirtualMachine, source: &str| {
+            assert!(vm.exec(source.to_string()).is_ok());
+            vm.register.r0
+        };
+        let is_compiled = |vm: &VirtualMachine, name: &str| match &vm.heap_object(name)._type {
+            JSType::Function(closure) => closure.code.jit.borrow().is_compiled(),
+            _ => unreachable!(),
+        };
+
+        // hot functions run as machine code, the results are the interpreter's
+        let source = r#"
+            const area = function (w, h) { return w * h + 1; };
+            const mul = function (a, b) { return a * b; };
+            let sum = 0;
+            for (let i = 0; i < 100; i++) {
+                sum = sum + area(i, 2) + mul(i, 100000);
+            }
+            sum;
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(10000 + 495000000));
+        assert!(is_compiled(&vm, "area") && is_compiled(&vm, "mul"));
+        assert_eq!(
+            eval(&mut vm, "mul(100000, 100000);").as_number(),
+            Some(1e10)
+        );
+
+        // and the interpreter runs what the machine code does not take
+        assert_eq!(eval(&mut vm, "area(1.5, 2);").as_number(), Some(4.0));
+        assert_eq!(
+            eval(&mut vm, "area(1);").as_number().map(f64::is_nan),
+            Some(true)
+        );
+        assert_eq!(
+            eval(&mut vm, "1 / mul(0, -1);").as_number(),
+            Some(f64::NEG_INFINITY)
+        );
+        assert_eq!(
+            eval(&mut vm, "mul(2147483647, 2147483647);").as_number(),
+            Some(2147483647.0 * 2147483647.0)
+        );
+
+        // a hot loop compiles its function, the next calls run the loop as machine code
+        let source = r#"
+            const count = function (n) {
+                let c = 0;
+                for (let i = 0; i < n; i++) { c = c + 1; }
+                return c;
+            };
+            count(2000);
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(2000));
+        assert!(is_compiled(&vm, "count"));
+        // an iteration of the machine code is a step, the interpreter takes several
+        vm.set_limits(Limits::new().max_steps(150000));
+        assert_eq!(eval(&mut vm, "count(100000);").as_smi(), Some(100000));
+        vm.set_jit(false);
+        let e = vm.exec("count(100000);".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated: step limit exceeded");
+        vm.set_jit(true);
+        let e = vm.exec("count(1000000);".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated: step limit exceeded");
+        vm.set_limits(Limits::new());
+
+        vm.set_jit(false);
+        let source = r#"
+            const inc = function (n) { return n + 1; };
+            let n = 0;
+            for (let i = 0; i < 100; i++) { n = inc(n); }
+            n;
+        "#;
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(100));
+        assert!(!is_compiled(&vm, "inc"));
+    }
+
+    #[test]
+    fn test_compiled_scripts() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let dir = std::env::temp_dir().join(format!("glasper-cache-{}", std::process::id()));
+        let cache = CodeCache::with_dir(dir.clone());
+
+        // a script that comes back from the cache runs like its source
+        let source = r#"
+            let total = 0;
+            const add = function (n) { total = total + n; return total; };
+            try { add(1); throw add(2); } catch (e) { add(e * 10); }
+            total;
+        "#;
+        assert!(cache.get(source).is_none());
+        cache.put(&vm.compile_script(source.to_string()).ok().unwrap());
+        let script = cache.get(source).unwrap();
+        assert!(vm.run_script(&script).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(33));
+        assert!(vm.function.is_none() && vm.stack.is_empty());
+
+        // in the same global scope as the code of `exec`
+        assert!(vm.exec("add(1);".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(34));
+        let script = vm.compile_script("add(total);".to_string()).ok().unwrap();
+        assert!(vm.run_script(&script).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(68));
+
+        let script = vm
+            .compile_script("add(1); missing;".to_string())
+            .ok()
+            .unwrap();
+        let e = vm.run_script(&script).unwrap_err();
+        assert_eq!(e.to_string(), "ReferenceError: missing is not defined");
+        assert!(vm.exec("total;".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(69));
+
+        // errors of the parser are returned, and not cached
+        let e = vm.compile_script("let = 1;".to_string()).err().unwrap();
+        assert!(matches!(e.kind, VMErrorKind::Syntax));
+        std::fs::write(dir.join("0000000000000000.glsc"), b"GLSC").unwrap();
+        assert!(cache.get("").is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expressions() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let eval = |vm: &mut VirtualMachine, source: &str| {
+            assert!(vm.exec(source.to_string()).is_ok(), "{}", source);
+            vm.register.r0
+        };
+        let show = |vm: &mut VirtualMachine, source: &str| to_js_string(eval(vm, source));
+
+        for (source, expected) in [
+            ("2 ** 10;", "1024"),
+            ("2 << 2;", "8"),
+            ("-16 >> 2;", "-4"),
+            ("-1 >>> 28;", "15"),
+            ("6 & 3;", "2"),
+            ("6 | 3;", "7"),
+            ("6 ^ 3;", "5"),
+            ("~-1;", "0"),
+            ("-(3);", "-3"),
+            ("1.1 + 2;", "3.1"),
+            ("1 / 0;", "Infinity"),
+            ("'3' * '4';", "12"),
+            ("'a' + 1 + 2;", "a12"),
+            ("1 + true;", "2"),
+            ("2 < 10;", "true"),
+            ("'2' < '10';", "false"),
+            ("2 >= 2;", "true"),
+            ("1 <= undefined;", "false"),
+            ("'1' == 1;", "true"),
+            ("null == undefined;", "true"),
+            ("2 != 2;", "false"),
+            ("'1' !== 1;", "true"),
+            ("!0;", "true"),
+            ("typeof 1;", "number"),
+            ("typeof 'a';", "string"),
+            ("typeof null;", "object"),
+            ("typeof undefined;", "undefined"),
+            ("typeof function () {};", "function"),
+            ("0 || 'b';", "b"),
+            ("'a' && 'b';", "b"),
+            ("null ?? 'c';", "c"),
+            ("0 ?? 'c';", "0"),
+        ] {
+            assert_eq!(show(&mut vm, source), expected, "{}", source);
+        }
 
-                Bytecodes::CallProperty => {
-                    let callee_pointer_reg = code[i + 1];
-                    // let arg_count_reg = code[i + 2];
-                    let parent_obj_pointer = code[i + 2];
+        // object and array literals, keyed access
+        let source = "const o = { a: 1, b: 'x' }; const arr = [1, 2, 3]; o.a + arr[2];";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(4));
+        assert_eq!(show(&mut vm, "arr[1 + 1] = 7; arr;"), "1,2,7");
+        assert_eq!(show(&mut vm, "arr[4] = 9; arr;"), "1,2,7,,9");
+        assert_eq!(eval(&mut vm, "arr.length;").as_smi(), Some(5));
+        assert_eq!(show(&mut vm, "o['b'] + 'abc'[1] + 'abc'.length;"), "xb3");
+        assert!(eval(&mut vm, "arr[10];").is_undefined());
+
+        // assignment goes to the nearest declaration
+        let source = "let x = 1; { let x = 2; x = 3; } x = x + 1; x;";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(2));
+        let e = vm.exec("o = 1;".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "TypeError: Assignment to constant variable.");
+
+        // the right side of a short circuit is not evaluated
+        let source = "let n = 0; false && (n = 1); true || (n = 2); 1 ?? (n = 3); n;";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(0));
+
+        // postfix updates give the old value
+        assert_eq!(eval(&mut vm, "n++;").as_smi(), Some(0));
+        assert_eq!(eval(&mut vm, "n--;").as_smi(), Some(1));
+        assert_eq!(eval(&mut vm, "n;").as_smi(), Some(0));
+        assert!(vm.stack.is_empty());
+    }
 
-                    res.push((
-                        format!("CallProperty r{callee_pointer_reg}, r{parent_obj_pointer}",),
-                        &code[i..i + 3],
-                    ));
-                    i += 3;
+    #[test]
+    fn test_registers() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let eval = |vm: &mut VirtualMachine, source: &str| {
+            assert!(vm.exec(source.to_string()).is_ok(), "{}", source);
+            vm.register.r0
+        };
+
+        // more temporaries than registers spill to the stack
+        let source = "const d = { v: 1 };
+            d.v + (d.v + (d.v + (d.v + (d.v + (d.v + (d.v + (d.v * 2)))))));";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(9));
+        let source = "const t = [];
+            d.v + (d.v + (d.v + (d.v + (d.v + (t[d.v + 1] = d.v + 2))))) + t[2];";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(11));
+        let source = "d.v + (d.v + (d.v + (d.v + (d.v + { b: d.v + 1, c: d.v }.b))));";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(7));
+
+        // a call keeps the registers of its caller, which the collector moves
+        let source = "const g = function (x) { return x + (x + x * 2); };
+            const k = function () {
+                let s = '';
+                for (let i = 0; i < 5000; i++) { s = 'x' + i; }
+                return 'x';
+            };
+            d.v + (g(1) + g(2)) + [{ x: 2 }][0][k()];";
+        assert_eq!(eval(&mut vm, source).as_smi(), Some(15));
+        assert!(vm.heap.statistics().scavenges > 0);
+
+        // the finally blocks of the innermost statements hold their completions on the stack
+        let source = "let log = '';
+            const h = function () {
+                for (let i = 0; i < 3; i++) {
+                    switch (i) {
+                        case 0:
+                            try { try { try { continue; } finally { log = log + 'a'; } }
+                                finally { log = log + 'b'; } } finally { log = log + 'c'; }
+                        case 1:
+                            try { try { try { break; } finally { log = log + 'd'; } }
+                                finally { log = log + 'e'; } } finally { log = log + 'f'; }
+                        default:
+                            try { try { try { return log + i; } finally { log = log + 'g'; } }
+                                finally { log = log + 'h'; } } finally { log = log + 'i'; }
+                    }
                 }
+            };
+            h() + log;";
+        assert_eq!(as_string(eval(&mut vm, source)), Some("abcdef2abcdefghi"));
+        assert!(vm.stack.is_empty());
+    }
 
-                Bytecodes::Return => {
-                    res.push(("Return".to_string(), &code[i..i + 1]));
-                    i += 1;
+    #[test]
+    fn test_exceptions() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let eval = |vm: &mut VirtualMachine, source: &str| {
+            assert!(vm.exec(source.to_string()).is_ok(), "{}", source);
+            vm.register.r0
+        };
+        let show = |vm: &mut VirtualMachine, source: &str| to_js_string(eval(vm, source));
+
+        let source = "const o = 'o'; let c = 0; try { throw o; } catch (e) { c = e === o; } c;";
+        assert_eq!(eval(&mut vm, source).as_boolean(), Some(true));
+
+        // errors of the vm are error objects
+        let source = "let m = 0; try { o.f(); } catch (e) { m = e.name + ': ' + e.message; } m;";
+        assert_eq!(show(&mut vm, source), "TypeError: callee is not a function");
+        let source = "try { nothing; } catch (e) { m = e.toString(); } m;";
+        assert_eq!(
+            show(&mut vm, source),
+            "ReferenceError: nothing is not defined"
+        );
+        // and so are the errors of the natives
+        let source =
+            "const n = 1; const p = { f: n.toString }; try { p.f(); } catch (e) { m = e.name; } m;";
+        assert_eq!(show(&mut vm, source), "TypeError");
+        let source =
+            "const s = { f: 'a'.charCodeAt }; try { s.f(0); } catch (e) { m = e.message; } m;";
+        assert_eq!(
+            show(&mut vm, source),
+            "String.prototype.charCodeAt requires that 'this' be a String"
+        );
+
+        // the exception unwinds the frames and the contexts entered since the try
+        let source = r#"
+            const deep = function (n) {
+                let local = n;
+                if (n == 0) {
+                    throw Error('bottom');
+                }
+                return deep(n - 1);
+            };
+            let x = 1;
+            try {
+                let x = 2;
+                { deep(5); }
+            } catch (e) {
+                m = e.message;
+            }
+            m + x;
+        "#;
+        assert_eq!(show(&mut vm, source), "bottom1");
+        assert!(vm.frames.is_empty() && vm.stack.is_empty());
+        assert_eq!(vm.context_depth, 0);
+        let source =
+            "const r = function () { return r(); }; try { r(); } catch (e) { m = e.name; } m;";
+        assert_eq!(show(&mut vm, source), "RangeError");
+
+        // finally runs however the block is left
+        let source = r#"
+            let log = '';
+            const f = function () {
+                try {
+                    return 'try';
+                } finally {
+                    log = log + 'f';
+                }
+            };
+            const g = function () {
+                try {
+                    throw 1;
+                } catch (e) {
+                    return 'catch';
+                } finally {
+                    return 'finally';
                 }
-                _ => {
-                    i += 1;
+            };
+            for (let i = 0; i < 4; i++) {
+                switch (i) {
+                    case 1:
+                        try { continue; } finally { log = log + 'c'; }
+                    case 3:
+                        try { break; } finally { log = log + 'b'; }
                 }
+                log = log + i;
             }
+            f() + g() + log;
+        "#;
+        assert_eq!(show(&mut vm, source), "tryfinally0c2b3f");
+        assert!(vm.stack.is_empty());
+        let source =
+            "try { try { throw 'a'; } finally { m = 'inner'; } } catch (e) { m = m + e; } m;";
+        assert_eq!(show(&mut vm, source), "innera");
+
+        // uncaught exceptions end the run
+        let e = vm.exec("try { throw 1; } catch (e) { throw e + 1; }".to_string());
+        assert_eq!(e.unwrap_err().to_string(), "Uncaught 2");
+        let e = vm.exec("throw Error('bye');".to_string()).unwrap_err();
+        assert_eq!(e.to_string(), "Uncaught Error: bye");
+
+        // the limits can not be caught
+        vm.set_limits(Limits::new().max_steps(1000));
+        let e = vm
+            .exec("try { for (let i = 0; true; i++) {} } catch (e) {}".to_string())
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated: step limit exceeded");
+    }
+
+    #[test]
+    fn test_inline_caches() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        assert!(vm.exec("let a = 'a';".to_string()).is_ok());
+        assert!(vm.exec("let b = 'b';".to_string()).is_ok());
+        assert!(vm.exec("a.x = 1;".to_string()).is_ok());
+        assert!(vm.exec("b.x = 2;".to_string()).is_ok());
+        assert!(Rc::ptr_eq(
+            &vm.heap_object("a").shape,
+            &vm.heap_object("b").shape
+        ));
+
+        // run the same load site again and again
+        let start = vm.code.len();
+        assert!(vm.exec("a.x;".to_string()).is_ok());
+        let site = vm.code[start..].to_vec();
+        let rerun = |vm: &mut VirtualMachine| {
+            vm.code.extend_from_slice(&site);
+            assert!(vm.interpret().is_ok());
+            vm.register.r0
+        };
+        assert!(matches!(vm.feedback.load(0), InlineCache::Monomorphic(_)));
+        for _ in 0..3 {
+            assert_eq!(rerun(&mut vm).as_smi(), Some(1));
         }
 
-        res
+        // another layout at the same site
+        assert!(vm.exec("a.y = 3;".to_string()).is_ok());
+        assert_eq!(rerun(&mut vm).as_smi(), Some(1));
+        assert!(matches!(vm.feedback.load(0), InlineCache::Polymorphic(hs) if hs.len() == 2));
+
+        // prototype methods are cached through the chain
+        assert!(vm.exec("a.charCodeAt;".to_string()).is_ok());
+        assert!(matches!(vm.feedback.load(1), InlineCache::Monomorphic(_)));
+    }
+
+    #[test]
+    fn test_prototype_cycles() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let error = |vm: &mut VirtualMachine, source: &str| {
+            vm.exec(source.to_string()).err().unwrap().to_string()
+        };
+        assert!(vm
+            .exec("let a = { x: 1 }; let b = {}; let c = {};".to_string())
+            .is_ok());
+        assert!(vm
+            .exec("b.__proto__ = a; c['__proto__'] = b;".to_string())
+            .is_ok());
+
+        // the lookups would never end
+        let cyclic = "TypeError: Cyclic __proto__ value";
+        assert_eq!(error(&mut vm, "a.__proto__ = c;"), cyclic);
+        assert_eq!(error(&mut vm, "a['__proto__'] = b;"), cyclic);
+        assert_eq!(error(&mut vm, "a.__proto__ = a;"), cyclic);
+        assert!(vm.exec("c.x;".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(1));
+
+        // a script can catch it
+        let source = "let m = 0; try { a.__proto__ = b; } catch (e) { m = e.message; } m;";
+        assert!(vm.exec(source.to_string()).is_ok());
+        assert_eq!(to_js_string(vm.register.r0), "Cyclic __proto__ value");
+    }
+
+    #[test]
+    fn test_immediate_numbers() {
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        assert!(vm.exec("let a = 40;".to_string()).is_ok());
+        let before = vm.heap.statistics();
+
+        // arithmetic does not allocate
+        assert!(vm.exec("a + 2;".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_smi(), Some(42));
+        assert!(vm.exec("2147483647 + 1;".to_string()).is_ok());
+        assert_eq!(vm.register.r0.as_number(), Some(2147483648.0));
+        assert_eq!(vm.heap.statistics(), before);
+
+        // methods are found through the shared prototype
+        assert!(vm.exec("a.toString();".to_string()).is_ok());
+        assert_eq!(as_string(vm.register.r0), Some("40"));
     }
 }
@@ -0,0 +1,29 @@
+use crate::engine::core::vm::{
+    as_string, heap::Heap, realm::Realm, value::Value, VMError, VirtualMachine,
+};
+
+use super::js_object::JSType;
+
+pub(crate) struct JSConsole;
+
+impl JSConsole {
+    /// the `console` global.
+    pub(crate) fn create(realm: &Realm, heap: &mut Heap) -> Value {
+        let console = realm.create_intrinsic(heap, JSType::Object, realm.object_prototype);
+        realm.define_native(heap, console, "log", console_log);
+        console
+    }
+}
+
+/// print the arguments like the host runtime does: strings as they are, each followed by a
+/// space.
+fn console_log(_: &mut VirtualMachine, _: Value, args: Vec<Value>) -> Result<Value, VMError> {
+    for arg in args {
+        match as_string(arg) {
+            Some(s) => print!("{} ", s),
+            None => print!("{} ", arg),
+        }
+    }
+    println!();
+    Ok(Value::undefined())
+}
@@ -0,0 +1,88 @@
+use crate::engine::core::vm::{
+    as_string, heap::Heap, realm::Realm, to_js_string, value::Value, VMError, VirtualMachine,
+};
+
+use super::{js_object::JSType, js_string::JSString};
+
+pub(crate) struct JSError;
+
+impl JSError {
+    pub(crate) fn init_prototype(realm: &Realm, heap: &mut Heap) {
+        set_string(realm, heap, realm.error_prototype, "name", "Error")
+            .ok()
+            .unwrap();
+        set_string(realm, heap, realm.error_prototype, "message", "")
+            .ok()
+            .unwrap();
+        realm.define_native(heap, realm.error_prototype, "toString", error_to_string);
+    }
+
+    /// the `Error` global. it can only be called, there is no `new`.
+    pub(crate) fn create_constructor(realm: &Realm, heap: &mut Heap) -> Value {
+        realm.create_intrinsic(
+            heap,
+            JSType::NativeFunction(error_constructor),
+            realm.function_prototype,
+        )
+    }
+
+    /// an error object like `new TypeError(message)`.
+    pub(crate) fn create(
+        realm: &Realm,
+        heap: &mut Heap,
+        name: &str,
+        message: &str,
+    ) -> Result<Value, VMError> {
+        let error = realm.create_object(heap, JSType::Object, realm.error_prototype)?;
+        set_string(realm, heap, error, "name", name)?;
+        set_string(realm, heap, error, "message", message)?;
+        Ok(error)
+    }
+
+    /// `name: message` of an error object, `None` for other values.
+    pub(crate) fn describe(v: Value) -> Option<String> {
+        let object = v.as_object()?;
+        let name = as_string(object.get("name")?)?;
+        let message = as_string(object.get("message")?)?;
+        Some(match message.is_empty() {
+            true => name.to_string(),
+            false => format!("{}: {}", name, message),
+        })
+    }
+}
+
+fn set_string(
+    realm: &Realm,
+    heap: &mut Heap,
+    object: Value,
+    key: &str,
+    value: &str,
+) -> Result<(), VMError> {
+    let s = realm.create_object(
+        heap,
+        JSType::String(value.to_string()),
+        realm.string_prototype,
+    )?;
+    let object = object.as_object().unwrap();
+    heap.write_barrier(object, s);
+    object.set(key, s);
+    Ok(())
+}
+
+fn error_constructor(
+    vm: &mut VirtualMachine,
+    _: Value,
+    args: Vec<Value>,
+) -> Result<Value, VMError> {
+    let message = match args.first() {
+        Some(v) if !v.is_undefined() => to_js_string(*v),
+        _ => String::new(),
+    };
+    JSError::create(&vm.realm, &mut vm.heap, "Error", &message)
+}
+
+fn error_to_string(vm: &mut VirtualMachine, this: Value, _: Vec<Value>) -> Result<Value, VMError> {
+    let s = JSError::describe(this).unwrap_or_else(|| to_js_string(this));
+    let base_obj = vm.alloc()?;
+    Ok(Value::from(JSString::create(s, base_obj, vm)))
+}
@@ -1,51 +1,31 @@
 #![allow(dead_code)]
 
-use super::{
-    constant::PROTOTYPE_KEY_NAME,
-    js_object::{JSObject, JSType},
+use super::js_string::JSString;
+use crate::engine::core::vm::{
+    heap::Heap, realm::Realm, to_js_string, value::Value, VMError, VMErrorKind, VirtualMachine,
 };
-use crate::engine::core::vm::VirtualMachine;
 
 pub(crate) struct JSNumber;
 
 impl JSNumber {
-    pub(crate) fn create<'a>(
-        n: f64,
-        allocated: &'a mut JSObject,
-        vm: &mut VirtualMachine,
-    ) -> &'a mut JSObject {
-        // number prototype
-        let prototype = vm.heap.alloc().unwrap();
-        let mut to_string_fn = vm.heap.alloc().unwrap();
-        to_string_fn._type = JSType::NativeFunction(number_to_string);
-        prototype
-            .properties
-            .insert("toString".to_string(), to_string_fn);
-
-        // create number instance
-
-        allocated._type = JSType::Number(n);
-        allocated
-            .properties
-            .insert(String::from(PROTOTYPE_KEY_NAME), prototype);
-
-        allocated
+    /// numbers are immediates, property lookups on them go to `Number.prototype`.
+    pub(crate) fn init_prototype(realm: &Realm, heap: &mut Heap) {
+        realm.define_native(heap, realm.number_prototype, "toString", number_to_string);
     }
 }
 
-fn number_to_string(
-    vm: &mut VirtualMachine,
-    this: &mut JSObject,
-    _: Vec<JSObject>,
-) -> &'static mut JSObject {
-    let n = match this._type {
-        JSType::Number(n) => n,
-        _ => panic!("TypeError: Number.prototype.toString is not generic"),
-    };
+fn number_to_string(vm: &mut VirtualMachine, this: Value, _: Vec<Value>) -> Result<Value, VMError> {
+    if this.as_number().is_none() {
+        return Err(VMError::new(
+            VMErrorKind::Type,
+            "Number.prototype.toString requires that 'this' be a Number".to_string(),
+        ));
+    }
 
-    // TODO: string object
-    let s = n.to_string();
-    let mut string = vm.heap.alloc().unwrap();
-    string._type = JSType::String(s);
-    string
+    let base_obj = vm.alloc()?;
+    Ok(Value::from(JSString::create(
+        to_js_string(this),
+        base_obj,
+        vm,
+    )))
 }
@@ -1,25 +1,31 @@
 #![allow(dead_code)]
 
-use crate::engine::core::vm::VirtualMachine;
+use crate::engine::core::vm::{
+    context::Context, function::Closure, value::Value, VMError, VirtualMachine,
+};
 
-use super::constant::PROTOTYPE_KEY_NAME;
+use super::shape::Shape;
 use std::{
-    collections::HashMap,
     fmt::{Debug, Display},
     ptr::NonNull,
+    rc::Rc,
 };
 
+/// property values are kept in `slots`, at the positions `shape` gives their keys.
 #[derive(Debug)]
 pub struct JSObject {
-    pub(crate) properties: HashMap<String, &'static mut JSObject>,
+    pub(crate) shape: Rc<Shape>,
+    pub(crate) slots: Vec<Value>,
     pub(crate) _type: JSType,
 }
 
 /// static impl
 impl JSObject {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(shape: Rc<Shape>) -> Self {
+        let slots = Vec::with_capacity(shape.len());
         JSObject {
-            properties: HashMap::new(),
+            shape,
+            slots,
             _type: JSType::Object,
         }
     }
@@ -41,64 +47,100 @@ impl JSObject {
         self as *const JSObject as i64
     }
 
-    pub(crate) fn get(&self, key: &str) -> Option<&JSObject> {
-        if let Some(prop) = self.properties.get(key) {
+    pub(crate) fn get_own(&self, key: &str) -> Option<Value> {
+        self.shape.lookup(key).map(|slot| self.slots[slot])
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Value> {
+        if let Some(prop) = self.get_own(key) {
             Some(prop)
         } else {
-            self.recursive_follow_prototype(key)
+            self.prototype()?.get(key)
         }
     }
 
-    fn recursive_follow_prototype(&self, key: &str) -> Option<&JSObject> {
-        if let Some(prop) = self.properties.get(key) {
-            Some(prop)
-        } else if let Some(prototype) = self.properties.get(PROTOTYPE_KEY_NAME) {
-            prototype.recursive_follow_prototype(key)
-        } else {
-            None
+    pub(crate) fn prototype(&self) -> Option<&JSObject> {
+        let slot = self.shape.prototype_slot()?;
+        self.slots[slot].as_object().map(|p| &*p)
+    }
+
+    /// set an own property. the caller runs the write barrier.
+    pub(crate) fn set(&mut self, key: &str, value: Value) {
+        match self.shape.lookup(key) {
+            Some(slot) => self.slots[slot] = value,
+            None => {
+                self.shape = self.shape.add(key);
+                self.slots.push(value);
+            }
         }
     }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<Value> {
+        let slot = self.shape.lookup(key)?;
+        self.shape = self.shape.remove(key);
+        Some(self.slots.remove(slot))
+    }
+
+    /// the values the collector follows: the properties, the elements and the internal
+    /// references.
+    pub(crate) fn children(&self) -> impl Iterator<Item = &Value> {
+        let (internal, elements): (_, &[Value]) = match &self._type {
+            JSType::Function(closure) => (Some(&closure.context), &[]),
+            JSType::Context(context) => (Some(&context.outer), &[]),
+            JSType::Array(elements) => (None, elements),
+            _ => (None, &[]),
+        };
+        self.slots.iter().chain(internal).chain(elements)
+    }
+
+    pub(crate) fn children_mut(&mut self) -> impl Iterator<Item = &mut Value> {
+        let (internal, elements): (_, &mut [Value]) = match &mut self._type {
+            JSType::Function(closure) => (Some(&mut closure.context), &mut []),
+            JSType::Context(context) => (Some(&mut context.outer), &mut []),
+            JSType::Array(elements) => (None, elements),
+            _ => (None, &mut []),
+        };
+        self.slots.iter_mut().chain(internal).chain(elements)
+    }
 }
 
 impl Display for JSObject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self._type {
-            JSType::Boolean(b) => write!(f, "\x1b[33m{}\x1b[0m", b),
-            JSType::Number(n) => write!(f, "\x1b[33m{}\x1b[0m", n),
             JSType::String(s) => write!(f, "\x1b[32m'{}'\x1b[0m", s),
             JSType::Object => write!(f, "\x1b[34m[Object]\x1b[0m"),
-            JSType::Array => write!(f, "\x1b[34m[Array]\x1b[0m"),
-            JSType::Function => write!(f, "[Function]"),
-            JSType::Undefined => write!(f, "\x1b[30mundefined\x1b[0m"),
+            JSType::Array(_) => write!(f, "\x1b[34m[Array]\x1b[0m"),
+            JSType::Function(_) => write!(f, "[Function]"),
             JSType::NativeFunction(_) => write!(f, "[native code]"),
+            JSType::Context(_) => write!(f, "[Context]"),
         }
     }
 }
 
+/// a built-in function. an error is thrown to the script like the errors of the vm.
+pub(crate) type NativeFunction =
+    fn(vm: &mut VirtualMachine, this: Value, args: Vec<Value>) -> Result<Value, VMError>;
+
 pub(crate) enum JSType {
-    Boolean(bool),
-    Number(f64),
     String(String),
-    Array,
+    /// the elements. other properties, and indices far past the end, are named properties
+    Array(Vec<Value>),
     Object,
-    Function,
-    Undefined,
-    NativeFunction(
-        fn(vm: &mut VirtualMachine, this: &mut JSObject, _: Vec<JSObject>) -> &'static mut JSObject,
-    ),
+    Function(Closure),
+    NativeFunction(NativeFunction),
+    /// a scope of variables, which are the properties of the object
+    Context(Context),
 }
 
 impl Debug for JSType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            JSType::Boolean(b) => write!(f, "Boolean({})", b),
-            JSType::Number(n) => write!(f, "Number({})", n),
             JSType::String(s) => write!(f, "String({})", s),
-            JSType::Array => write!(f, "Array"),
+            JSType::Array(_) => write!(f, "Array"),
             JSType::Object => write!(f, "Object"),
-            JSType::Function => write!(f, "Function"),
-            JSType::Undefined => write!(f, "Undefined"),
+            JSType::Function(_) => write!(f, "Function"),
             JSType::NativeFunction(_) => write!(f, "NativeFunction"),
+            JSType::Context(_) => write!(f, "Context"),
         }
     }
 }
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
-use crate::engine::core::vm::VirtualMachine;
+use crate::engine::core::vm::{
+    heap::Heap, realm::Realm, value::Value, VMError, VMErrorKind, VirtualMachine,
+};
 
 use super::{
     constant::PROTOTYPE_KEY_NAME,
@@ -9,43 +11,47 @@ use super::{
 pub(crate) struct JSString;
 
 impl JSString {
+    pub(crate) fn init_prototype(realm: &Realm, heap: &mut Heap) {
+        realm.define_native(
+            heap,
+            realm.string_prototype,
+            "charCodeAt",
+            string_char_code_at,
+        );
+    }
+
     pub(crate) fn create<'a>(
         s: String,
         allocated: &'a mut JSObject,
         vm: &mut VirtualMachine,
     ) -> &'a mut JSObject {
-        // string prototype
-        let prototype = vm.heap.alloc().unwrap();
-        let mut string_char_code_at_fn = vm.heap.alloc().unwrap();
-
-        string_char_code_at_fn._type = JSType::NativeFunction(string_char_code_at);
-        prototype
-            .properties
-            .insert("charCodeAt".to_string(), string_char_code_at_fn);
-
-        // create string instance
         allocated._type = JSType::String(s);
-        allocated
-            .properties
-            .insert(String::from(PROTOTYPE_KEY_NAME), prototype);
+        let prototype = vm.realm.string_prototype;
+        vm.heap.write_barrier(allocated, prototype);
+        allocated.set(PROTOTYPE_KEY_NAME, prototype);
 
         allocated
     }
 }
 
 fn string_char_code_at(
-    vm: &mut VirtualMachine,
-    this: &mut JSObject,
-    _: Vec<JSObject>,
-) -> &'static mut JSObject {
-    let n = match this._type {
-        JSType::Number(n) => n,
-        _ => panic!("TypeError: Number.prototype.toString is not generic"),
+    _: &mut VirtualMachine,
+    this: Value,
+    _: Vec<Value>,
+) -> Result<Value, VMError> {
+    let s = match this.as_object().map(|o| &o._type) {
+        Some(JSType::String(s)) => s,
+        _ => {
+            return Err(VMError::new(
+                VMErrorKind::Type,
+                "String.prototype.charCodeAt requires that 'this' be a String".to_string(),
+            ))
+        }
     };
 
-    // TODO: string object
-    let s = n.to_string();
-    let mut string = vm.heap.alloc().unwrap();
-    string._type = JSType::String(s);
-    string
+    // TODO: index argument
+    Ok(match s.encode_utf16().next() {
+        Some(c) => Value::smi(c as i32),
+        None => Value::double(f64::NAN),
+    })
 }
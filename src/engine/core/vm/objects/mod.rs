@@ -1,4 +1,7 @@
 pub(crate) mod constant;
+pub(crate) mod js_console;
+pub(crate) mod js_error;
 pub(crate) mod js_number;
 pub(crate) mod js_object;
 pub(crate) mod js_string;
+pub(crate) mod shape;
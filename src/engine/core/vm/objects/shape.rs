@@ -0,0 +1,123 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Debug,
+    rc::{Rc, Weak},
+};
+
+use super::constant::PROTOTYPE_KEY_NAME;
+
+/// a hidden class: the layout shared by every object that got the same keys in the same order.
+///
+/// shapes form a transition tree from the empty root shape. adding a key follows (or creates)
+/// the transition for it, so objects built alike end up with the same shape and their values
+/// at the same slots. transitions are weak, a shape lives as long as an object or a cache uses it.
+pub(crate) struct Shape {
+    parent: Option<Rc<Shape>>,
+    /// keys in slot order
+    keys: Vec<String>,
+    index: HashMap<String, usize>,
+    prototype_slot: Option<usize>,
+    transitions: RefCell<HashMap<String, Weak<Shape>>>,
+}
+
+impl Shape {
+    pub(crate) fn root() -> Rc<Shape> {
+        Rc::new(Shape {
+            parent: None,
+            keys: Vec::new(),
+            index: HashMap::new(),
+            prototype_slot: None,
+            transitions: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn lookup(&self, key: &str) -> Option<usize> {
+        self.index.get(key).copied()
+    }
+
+    pub(crate) fn prototype_slot(&self) -> Option<usize> {
+        self.prototype_slot
+    }
+
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// the shape with `key` appended at slot `self.len()`.
+    pub(crate) fn add(self: &Rc<Self>, key: &str) -> Rc<Shape> {
+        if let Some(shape) = self.transitions.borrow().get(key).and_then(Weak::upgrade) {
+            return shape;
+        }
+
+        let mut keys = self.keys.clone();
+        keys.push(key.to_string());
+        let mut index = self.index.clone();
+        index.insert(key.to_string(), self.keys.len());
+        let prototype_slot = match key {
+            PROTOTYPE_KEY_NAME => Some(self.keys.len()),
+            _ => self.prototype_slot,
+        };
+        let shape = Rc::new(Shape {
+            parent: Some(self.clone()),
+            keys,
+            index,
+            prototype_slot,
+            transitions: RefCell::new(HashMap::new()),
+        });
+
+        let mut transitions = self.transitions.borrow_mut();
+        transitions.retain(|_, s| s.strong_count() > 0);
+        transitions.insert(key.to_string(), Rc::downgrade(&shape));
+        shape
+    }
+
+    /// the shape without `key`. the slots after it move down by one.
+    pub(crate) fn remove(self: &Rc<Self>, key: &str) -> Rc<Shape> {
+        let mut root = self.clone();
+        while let Some(parent) = root.parent.clone() {
+            root = parent;
+        }
+        self.keys
+            .iter()
+            .filter(|k| *k != key)
+            .fold(root, |shape, k| shape.add(k))
+    }
+}
+
+impl Debug for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Shape({:?})", self.keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let root = Shape::root();
+        let a = root.add("x").add("y");
+        let b = root.add("x").add("y");
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(a.lookup("y"), Some(1));
+
+        // the order of the keys matters
+        let c = root.add("y").add("x");
+        assert!(!Rc::ptr_eq(&a, &c));
+
+        let d = a.remove("x");
+        assert!(Rc::ptr_eq(&d, &root.add("y")));
+        assert_eq!(d.lookup("y"), Some(0));
+
+        // unused shapes are freed
+        drop((a, b, c, d));
+        let x = root.add("x");
+        assert_eq!(Rc::strong_count(&x), 1);
+    }
+}
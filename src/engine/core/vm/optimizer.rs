@@ -0,0 +1,982 @@
+use std::rc::Rc;
+
+use super::{
+    add_primitives,
+    bytecodes::{decode, operands, smi_form, Bytecodes, Operand, RName},
+    compare_values,
+    constant_table::{Constant, ConstantTable},
+    function::FunctionCode,
+    handler_table::{HandlerTable, HandlerTableEntry},
+    loose_equals, numeric_operation, relational_test,
+    source_positions::SourcePositionTable,
+    strict_equals, to_boolean, to_number, unary_operation,
+    value::Value,
+};
+
+/// the passes of `optimize`. each can be turned off to find the one that breaks a script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Passes {
+    /// compute operations and branches on known values
+    pub constant_folding: bool,
+    /// give binary operations with a known small integer operand their `*Smi` form
+    pub smi_operations: bool,
+    /// drop a `Push` that is popped right away
+    pub push_pop: bool,
+    /// send jumps straight to where the jumps they land on go
+    pub jump_threading: bool,
+    /// drop unreachable code, jumps to the next instruction and loads nothing reads
+    pub dead_code: bool,
+}
+
+impl Passes {
+    pub const ALL: Passes = Passes {
+        constant_folding: true,
+        smi_operations: true,
+        push_pop: true,
+        jump_threading: true,
+        dead_code: true,
+    };
+
+    pub const NONE: Passes = Passes {
+        constant_folding: false,
+        smi_operations: false,
+        push_pop: false,
+        jump_threading: false,
+        dead_code: false,
+    };
+
+    /// the names of the passes, in the order they run.
+    pub const NAMES: [&'static str; 5] = [
+        "constant-folding",
+        "smi-operations",
+        "push-pop",
+        "jump-threading",
+        "dead-code",
+    ];
+
+    /// these passes without the ones named in the comma separated `names`.
+    pub fn without(mut self, names: &str) -> Result<Passes, String> {
+        for name in names.split(',').map(str::trim) {
+            let pass = match name {
+                "constant-folding" => &mut self.constant_folding,
+                "smi-operations" => &mut self.smi_operations,
+                "push-pop" => &mut self.push_pop,
+                "jump-threading" => &mut self.jump_threading,
+                "dead-code" => &mut self.dead_code,
+                _ => {
+                    return Err(format!(
+                        "unknown optimization pass '{}', the passes are {}",
+                        name,
+                        Passes::NAMES.join(", ")
+                    ))
+                }
+            };
+            *pass = false;
+        }
+        Ok(self)
+    }
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Passes::ALL
+    }
+}
+
+/// optimize a script, or a function, and the functions in its constants.
+pub(crate) fn optimize_function(function: &mut FunctionCode, passes: Passes) {
+    optimize(
+        &mut function.code,
+        &mut function.handler_table,
+        &mut function.source_positions,
+        &function.constant_table,
+        passes,
+    );
+    optimize_functions(&mut function.constant_table, 0, passes);
+}
+
+/// optimize the functions among the constants from `start` on. a function that is shared
+/// already may be running and is left as it is.
+pub(crate) fn optimize_functions(constant_table: &mut ConstantTable, start: usize, passes: Passes) {
+    for constant in constant_table.iter_mut().skip(start) {
+        if let Constant::Function(f) = constant {
+            if let Some(f) = Rc::get_mut(f) {
+                optimize_function(f, passes);
+            }
+        }
+    }
+}
+
+/// rewrite `code` with `passes`, keeping its tables in step. code that does not decode or
+/// that the vm does not run is left to the verifier to reject.
+pub(crate) fn optimize(
+    code: &mut Vec<u8>,
+    handler_table: &mut HandlerTable,
+    source_positions: &mut SourcePositionTable,
+    constant_table: &ConstantTable,
+    passes: Passes,
+) {
+    if passes == Passes::NONE {
+        return;
+    }
+    let mut ops = match Code::decode(code, handler_table, source_positions) {
+        Some(ops) => ops,
+        None => return,
+    };
+
+    if passes.constant_folding || passes.smi_operations {
+        let constants: Vec<&Constant> = constant_table.iter().collect();
+        ops.fold(&constants, passes);
+    }
+    if passes.push_pop {
+        ops.remove_push_pop();
+    }
+    if passes.jump_threading {
+        ops.thread_jumps();
+    }
+    if passes.dead_code {
+        ops.remove_dead_code();
+    }
+    (*code, *handler_table, *source_positions) = ops.encode();
+}
+
+/// a mask of registers, a bit by register number.
+type Registers = u8;
+
+const R0: Registers = 1 << RName::R0;
+const ALL_REGISTERS: Registers = 0xff;
+
+/// an instruction. jumps refer to the instruction they go to by its index, so instructions
+/// can be replaced and dropped before the offsets are known.
+#[derive(Clone, Debug, PartialEq)]
+struct Op {
+    opcode: u8,
+    operands: Vec<i64>,
+    /// the bytes of a name operand
+    name: Vec<u8>,
+    /// the index a jump goes to, the number of instructions for the end of the code
+    target: Option<usize>,
+}
+
+impl Op {
+    /// an instruction without a name or a jump offset.
+    fn new(opcode: u8, operands: &[i64]) -> Op {
+        Op {
+            opcode,
+            operands: operands.to_vec(),
+            name: vec![],
+            target: None,
+        }
+    }
+
+    fn jump(opcode: u8, target: usize) -> Op {
+        Op {
+            target: Some(target),
+            ..Op::new(opcode, &[0])
+        }
+    }
+
+    /// the instruction that loads `v` into r0.
+    fn load(v: Value) -> Op {
+        if let Some(n) = v.as_smi() {
+            return Op::new(Bytecodes::LdaSmi, &[n as i64]);
+        }
+        let opcode = match v.as_boolean() {
+            Some(true) => Bytecodes::LdaTrue,
+            Some(false) => Bytecodes::LdaFalse,
+            None if v.is_undefined() => Bytecodes::LdaUndefined,
+            None if v.is_null() => Bytecodes::LdaNull,
+            None => return Op::new(Bytecodes::Mov, &[RName::R0 as i64, v.to_bits() as i64]),
+        };
+        Op::new(opcode, &[])
+    }
+
+    fn kinds(&self) -> &'static [Operand] {
+        operands(self.opcode).unwrap()
+    }
+
+    fn len(&self) -> usize {
+        let operands: usize = self
+            .kinds()
+            .iter()
+            .map(|&kind| match kind {
+                Operand::Register => 1,
+                _ => 8,
+            })
+            .sum();
+        1 + operands + self.name.len()
+    }
+
+    /// append the bytes of the instruction, a jump with `offset`.
+    fn write(&self, code: &mut Vec<u8>, offset: Option<i64>) {
+        code.push(self.opcode);
+        for (&kind, &n) in self.kinds().iter().zip(self.operands.iter()) {
+            match kind {
+                Operand::Register => code.push(n as u8),
+                Operand::Offset => code.extend_from_slice(&offset.unwrap_or(n).to_le_bytes()),
+                _ => code.extend_from_slice(&n.to_le_bytes()),
+            }
+        }
+        code.extend_from_slice(&self.name);
+    }
+
+    fn register(&self, i: usize) -> usize {
+        self.operands[i] as usize
+    }
+
+    /// the registers the instruction reads and the ones it writes.
+    fn effects(&self) -> (Registers, Registers) {
+        let r = |i: usize| 1 << self.operands[i];
+        match self.opcode {
+            Bytecodes::Star0..=Bytecodes::Star7 => (R0, 1 << (self.opcode - Bytecodes::Star0)),
+            Bytecodes::Ldar | Bytecodes::GetNamedProperty => (r(0), R0),
+            Bytecodes::Mov | Bytecodes::Pop => (0, r(0)),
+            Bytecodes::Push => (r(0), 0),
+            // calls take their operands from the stack, the callee restores the registers
+            Bytecodes::LdaSmi
+            | Bytecodes::LdaUndefined
+            | Bytecodes::LdaNull
+            | Bytecodes::LdaTrue
+            | Bytecodes::LdaFalse
+            | Bytecodes::LdaConstant
+            | Bytecodes::LdaContextSlot
+            | Bytecodes::CreateClosure
+            | Bytecodes::CreateEmptyObjectLiteral
+            | Bytecodes::CreateArrayLiteral
+            | Bytecodes::CallProperty
+            | Bytecodes::CallAnyReceiver => (0, R0),
+            Bytecodes::StaContextSlot
+            | Bytecodes::StaConstContextSlot
+            | Bytecodes::StaLookupSlot
+            | Bytecodes::Return
+            | Bytecodes::Throw
+            | Bytecodes::Hlt
+            | Bytecodes::JumpIfTrue
+            | Bytecodes::JumpIfFalse
+            | Bytecodes::JumpIfNotUndefined
+            | Bytecodes::JumpIfUndefinedOrNull => (R0, 0),
+            Bytecodes::Jump
+            | Bytecodes::JumpLoop
+            | Bytecodes::PushContext
+            | Bytecodes::PopContext => (0, 0),
+            Bytecodes::SetNamedProperty => (r(0) | R0, 0),
+            Bytecodes::SetKeyedProperty => (r(0) | r(1) | R0, 0),
+            _ => match self.kinds() {
+                [Operand::Register, Operand::Register] => (r(0) | r(1), R0),
+                [Operand::Register] | [Operand::Register, Operand::Immediate] => (r(0), R0),
+                _ => (ALL_REGISTERS, ALL_REGISTERS),
+            },
+        }
+    }
+
+    /// whether the instruction only writes registers, so it can go when nothing reads them.
+    fn is_load(&self) -> bool {
+        matches!(
+            self.opcode,
+            Bytecodes::Star0
+                ..=Bytecodes::Star7
+                    | Bytecodes::Ldar
+                    | Bytecodes::Mov
+                    | Bytecodes::LdaSmi
+                    | Bytecodes::LdaUndefined
+                    | Bytecodes::LdaNull
+                    | Bytecodes::LdaTrue
+                    | Bytecodes::LdaFalse
+                    | Bytecodes::LdaConstant
+        )
+    }
+}
+
+/// a code as a list of instructions, with the regions of its handlers and its source lines
+/// by instruction index. a dropped instruction leaves `None` behind, so the indices hold.
+struct Code {
+    ops: Vec<Option<Op>>,
+    /// the handler table with indices for offsets
+    handlers: Vec<HandlerTableEntry>,
+    positions: Vec<(usize, u32)>,
+}
+
+impl Code {
+    fn decode(
+        code: &[u8],
+        handler_table: &HandlerTable,
+        source_positions: &SourcePositionTable,
+    ) -> Option<Code> {
+        // the index of the instruction at each offset, the end of the code included
+        let mut index = vec![None; code.len() + 1];
+        let mut instructions = vec![];
+        let mut pc = 0;
+        while pc < code.len() {
+            let instruction = decode(code, pc).ok()?;
+            index[pc] = Some(instructions.len());
+            pc += instruction.len;
+            instructions.push((pc - instruction.len, instruction));
+        }
+        index[code.len()] = Some(instructions.len());
+        let at = |offset: Option<i64>| {
+            let offset = usize::try_from(offset?).ok()?;
+            *index.get(offset)?
+        };
+
+        let mut ops = vec![];
+        for (pc, instruction) in instructions {
+            let end = (pc + instruction.len) as i64;
+            let mut op = Op::new(instruction.opcode, &instruction.operands);
+            for (&kind, &n) in op.kinds().iter().zip(instruction.operands.iter()) {
+                match kind {
+                    Operand::Register if n > RName::R7 as i64 => return None,
+                    Operand::Offset => {
+                        op.target = Some(at(match instruction.opcode {
+                            Bytecodes::JumpLoop => end.checked_sub(n),
+                            _ => end.checked_add(n),
+                        })?);
+                    }
+                    Operand::Name => {
+                        op.name = code[end as usize - n as usize..end as usize].to_vec()
+                    }
+                    _ => {}
+                }
+            }
+            ops.push(Some(op));
+        }
+
+        let mut handlers = vec![];
+        for entry in handler_table.entries() {
+            handlers.push(HandlerTableEntry {
+                start: at(Some(entry.start as i64))?,
+                end: at(Some(entry.end as i64))?,
+                handler: at(Some(entry.handler as i64))?,
+                ..*entry
+            });
+        }
+        let positions = source_positions
+            .entries()
+            .iter()
+            .map(|&(offset, line)| Some((at(Some(offset as i64))?, line)))
+            .collect::<Option<Vec<(usize, u32)>>>()?;
+
+        Some(Code {
+            ops,
+            handlers,
+            positions,
+        })
+    }
+
+    fn encode(&self) -> (Vec<u8>, HandlerTable, SourcePositionTable) {
+        // a dropped instruction is where the next one starts
+        let mut offsets = Vec::with_capacity(self.ops.len() + 1);
+        let mut offset = 0;
+        for op in self.ops.iter() {
+            offsets.push(offset);
+            offset += op.as_ref().map_or(0, Op::len);
+        }
+        offsets.push(offset);
+
+        let mut code = Vec::with_capacity(offset);
+        for (i, op) in self.ops.iter().enumerate() {
+            if let Some(op) = op {
+                let end = (offsets[i] + op.len()) as i64;
+                let offset = op.target.map(|target| match op.opcode {
+                    Bytecodes::JumpLoop => end - offsets[target] as i64,
+                    _ => offsets[target] as i64 - end,
+                });
+                op.write(&mut code, offset);
+            }
+        }
+
+        let mut handler_table = HandlerTable::new();
+        for entry in self.handlers.iter() {
+            let entry = HandlerTableEntry {
+                start: offsets[entry.start],
+                end: offsets[entry.end],
+                handler: offsets[entry.handler],
+                ..*entry
+            };
+            // nothing is left to throw in the region
+            if entry.start < entry.end {
+                handler_table.add(entry);
+            }
+        }
+        let mut source_positions = SourcePositionTable::new();
+        for &(i, line) in self.positions.iter() {
+            source_positions.add(offsets[i], line);
+        }
+        (code, handler_table, source_positions)
+    }
+
+    fn op(&self, i: usize) -> Option<&Op> {
+        self.ops.get(i)?.as_ref()
+    }
+
+    /// the first instruction kept at or after `i`, or the end of the code.
+    fn next(&self, i: usize) -> usize {
+        (i..self.ops.len())
+            .find(|&i| self.ops[i].is_some())
+            .unwrap_or(self.ops.len())
+    }
+
+    /// the instructions that may run after `i` when it does not throw.
+    fn successors(&self, i: usize) -> Vec<usize> {
+        let op = self.op(i).unwrap();
+        match (op.opcode, op.target) {
+            (Bytecodes::Return | Bytecodes::Throw | Bytecodes::Hlt, _) => vec![],
+            (Bytecodes::Jump | Bytecodes::JumpLoop, Some(target)) => vec![self.next(target)],
+            (_, Some(target)) => vec![self.next(i + 1), self.next(target)],
+            (_, None) => vec![self.next(i + 1)],
+        }
+    }
+
+    /// where an exception of `i` goes: the handler of the innermost region around it.
+    fn handler(&self, i: usize) -> Option<usize> {
+        self.handlers
+            .iter()
+            .find(|entry| entry.start <= i && i < entry.end)
+            .map(|entry| self.next(entry.handler))
+    }
+
+    /// the instructions that are reached other than from the one before them, and the ones
+    /// the regions of the handlers start and end at.
+    fn boundaries(&self) -> Vec<bool> {
+        let mut boundaries = vec![false; self.ops.len() + 1];
+        for op in self.ops.iter().flatten() {
+            if let Some(target) = op.target {
+                boundaries[self.next(target)] = true;
+            }
+        }
+        for entry in self.handlers.iter() {
+            for i in [entry.start, entry.end, entry.handler] {
+                boundaries[self.next(i)] = true;
+            }
+        }
+        boundaries
+    }
+
+    /// constant folding and `*Smi` forms, from the values the registers are known to have.
+    /// values are only followed within straight code, a jump target forgets them.
+    fn fold(&mut self, constants: &[&Constant], passes: Passes) {
+        let boundaries = self.boundaries();
+        let mut known: [Option<Value>; 8] = [None; 8];
+        for (i, &boundary) in boundaries.iter().take(self.ops.len()).enumerate() {
+            let op = match &self.ops[i] {
+                Some(op) => op,
+                None => continue,
+            };
+            if boundary {
+                known = [None; 8];
+            }
+
+            let folded = match passes.constant_folding {
+                true => fold(op, &known),
+                false => None,
+            };
+            let rewritten = match (folded, passes.smi_operations) {
+                (None, true) => smi_operation(op, &known).map(Some),
+                (folded, _) => folded,
+            };
+            if let Some(op) = rewritten {
+                self.ops[i] = op;
+            }
+            if let Some(op) = &self.ops[i] {
+                step(op, &mut known, constants);
+            }
+        }
+    }
+
+    /// drop `Push rX; Pop rY` where the `Pop` is not reached otherwise, moving the value
+    /// through r0 when one of them is r0.
+    fn remove_push_pop(&mut self) {
+        let boundaries = self.boundaries();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..self.ops.len() {
+                let j = self.next(i + 1);
+                let (x, y) = match (self.op(i), self.op(j)) {
+                    (Some(push), Some(pop))
+                        if push.opcode == Bytecodes::Push
+                            && pop.opcode == Bytecodes::Pop
+                            && !boundaries[j] =>
+                    {
+                        (push.register(0), pop.register(0))
+                    }
+                    _ => continue,
+                };
+                self.ops[i] = match (x, y) {
+                    _ if x == y => None,
+                    (x, 0) => Some(Op::new(Bytecodes::Ldar, &[x as i64])),
+                    (0, y) => Some(Op::new(Bytecodes::Star0 + y as u8, &[])),
+                    _ => continue,
+                };
+                self.ops[j] = None;
+                changed = true;
+            }
+        }
+    }
+
+    /// let a forward jump that lands on a `Jump`, or on a test of the same r0, go where that
+    /// one goes.
+    fn thread_jumps(&mut self) {
+        for i in 0..self.ops.len() {
+            let (opcode, mut target) = match self.op(i) {
+                Some(op) if op.target.is_some() && op.opcode != Bytecodes::JumpLoop => {
+                    (op.opcode, self.next(op.target.unwrap()))
+                }
+                _ => continue,
+            };
+            // a chain of jumps may go around in circles
+            for _ in 0..self.ops.len() {
+                let hop = match self.op(target) {
+                    Some(hop) => hop,
+                    None => break,
+                };
+                let next = match (opcode, hop.opcode) {
+                    (_, Bytecodes::Jump) => hop.target.unwrap(),
+                    (opcode, hop_opcode) if opcode == hop_opcode => hop.target.unwrap(),
+                    (Bytecodes::JumpIfTrue, Bytecodes::JumpIfFalse)
+                    | (Bytecodes::JumpIfFalse, Bytecodes::JumpIfTrue) => target + 1,
+                    _ => break,
+                };
+                match self.next(next) {
+                    next if next > i => target = next,
+                    _ => break,
+                }
+            }
+            self.ops[i].as_mut().unwrap().target = Some(target);
+        }
+    }
+
+    /// drop unreachable instructions, jumps to the next instruction and loads of registers
+    /// that are not read, until there are none left.
+    fn remove_dead_code(&mut self) {
+        loop {
+            let unreachable = self.remove_unreachable();
+            let unread = self.remove_unread_loads();
+            if !unreachable && !unread {
+                return;
+            }
+        }
+    }
+
+    fn remove_unreachable(&mut self) -> bool {
+        let len = self.ops.len();
+        let mut reachable = vec![false; len + 1];
+        let mut work = vec![self.next(0)];
+        while let Some(i) = work.pop() {
+            if reachable[i] || i == len {
+                continue;
+            }
+            reachable[i] = true;
+            work.extend(self.successors(i));
+            work.extend(self.handler(i));
+        }
+
+        let handlers: Vec<usize> = self.handlers.iter().map(|e| self.next(e.handler)).collect();
+        let mut changed = false;
+        for (i, &reachable) in reachable.iter().take(len).enumerate() {
+            let removed = match self.op(i) {
+                Some(_) if !reachable => true,
+                // a handler must stay an instruction
+                Some(op) if op.target.is_some() && !handlers.contains(&i) => {
+                    self.next(op.target.unwrap()) == self.next(i + 1)
+                }
+                _ => false,
+            };
+            if removed {
+                self.ops[i] = None;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    fn remove_unread_loads(&mut self) -> bool {
+        let live = self.live_registers();
+        let handlers: Vec<usize> = self.handlers.iter().map(|e| self.next(e.handler)).collect();
+        let mut changed = false;
+        for (i, live) in live.into_iter().enumerate() {
+            if let Some(op) = self.op(i) {
+                if op.is_load() && op.effects().1 & live == 0 && !handlers.contains(&i) {
+                    self.ops[i] = None;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// the registers that may be read after each instruction before they are written. the
+    /// end of the code reads r0, the value of the script. a handler gets the registers as
+    /// they are when its region throws, but for r0 which gets the exception.
+    fn live_registers(&self) -> Vec<Registers> {
+        let len = self.ops.len();
+        let mut live_in = vec![0; len + 1];
+        live_in[len] = R0;
+        let mut live_out = vec![0; len];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..len).rev() {
+                let op = match self.op(i) {
+                    Some(op) => op,
+                    None => continue,
+                };
+                let out = self
+                    .successors(i)
+                    .into_iter()
+                    .fold(0, |live, s| live | live_in[s]);
+                let (reads, writes) = op.effects();
+                let mut live = reads | (out & !writes);
+                if let Some(handler) = self.handler(i) {
+                    live |= live_in[handler] & !R0;
+                }
+                live_out[i] = out;
+                if live != live_in[i] {
+                    live_in[i] = live;
+                    changed = true;
+                }
+            }
+        }
+        live_out
+    }
+}
+
+/// `op` computed from known operands: a load of its value, a `Jump` for a branch that is
+/// taken and `None` for one that is not. `None` when the operands are not known.
+fn fold(op: &Op, known: &[Option<Value>; 8]) -> Option<Option<Op>> {
+    let value = |i: usize| known[op.register(i)];
+    let binary = || match op.kinds() {
+        [Operand::Register, Operand::Register] => Some((value(0)?, value(1)?)),
+        [Operand::Register, Operand::Immediate] => {
+            Some((value(0)?, Value::number(op.operands[1] as f64)))
+        }
+        _ => None,
+    };
+
+    let v = match op.opcode {
+        Bytecodes::Add | Bytecodes::AddSmi => {
+            let (l, r) = binary()?;
+            add_primitives(l, r)?
+        }
+        Bytecodes::TestEqual | Bytecodes::TestEqualStrict => {
+            let (l, r) = binary()?;
+            Value::boolean(match op.opcode {
+                Bytecodes::TestEqual => loose_equals(l, r),
+                _ => strict_equals(l, r),
+            })
+        }
+        Bytecodes::LogicalNot => Value::boolean(!to_boolean(value(0)?)),
+        Bytecodes::JumpIfTrue
+        | Bytecodes::JumpIfFalse
+        | Bytecodes::JumpIfNotUndefined
+        | Bytecodes::JumpIfUndefinedOrNull => {
+            let v = known[RName::R0 as usize]?;
+            let taken = match op.opcode {
+                Bytecodes::JumpIfTrue => to_boolean(v),
+                Bytecodes::JumpIfFalse => !to_boolean(v),
+                Bytecodes::JumpIfNotUndefined => !v.is_undefined(),
+                _ => v.is_undefined() || v.is_null(),
+            };
+            return Some(taken.then(|| Op::jump(Bytecodes::Jump, op.target.unwrap())));
+        }
+        opcode => {
+            if let Some(operation) = numeric_operation(opcode) {
+                let (l, r) = binary()?;
+                Value::number(operation(to_number(l), to_number(r)))
+            } else if let Some(test) = relational_test(opcode) {
+                let (l, r) = binary()?;
+                Value::boolean(matches!(compare_values(l, r), Some(o) if test(o)))
+            } else if let Some(operation) = unary_operation(opcode) {
+                Value::number(operation(to_number(value(0)?)))
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(Some(Op::load(v)))
+}
+
+/// the `*Smi` form of a binary operation with a known small integer on the right, or on
+/// either side when the order does not matter.
+fn smi_operation(op: &Op, known: &[Option<Value>; 8]) -> Option<Op> {
+    let opcode = smi_form(op.opcode)?;
+    let smi = |i: usize| known[op.register(i)]?.as_smi().map(i64::from);
+    if let Some(n) = smi(1) {
+        return Some(Op::new(opcode, &[op.operands[0], n]));
+    }
+    let commutative = matches!(
+        op.opcode,
+        Bytecodes::Mul | Bytecodes::BitwiseOr | Bytecodes::BitwiseXor | Bytecodes::BitwiseAnd
+    );
+    let n = smi(0).filter(|_| commutative)?;
+    Some(Op::new(opcode, &[op.operands[1], n]))
+}
+
+/// the values the registers have after `op`, from the ones they had before. objects are
+/// never known, they may move.
+fn step(op: &Op, known: &mut [Option<Value>; 8], constants: &[&Constant]) {
+    let r0 = RName::R0 as usize;
+    let (_, writes) = op.effects();
+    let loaded = match op.opcode {
+        Bytecodes::Star0..=Bytecodes::Star7 => known[r0],
+        Bytecodes::Ldar => known[op.register(0)],
+        Bytecodes::Mov => Some(Value::from_bits(op.operands[1] as u64)).filter(|v| !v.is_object()),
+        Bytecodes::LdaSmi => Some(Value::number(op.operands[0] as f64)),
+        Bytecodes::LdaUndefined => Some(Value::undefined()),
+        Bytecodes::LdaNull => Some(Value::null()),
+        Bytecodes::LdaTrue => Some(Value::boolean(true)),
+        Bytecodes::LdaFalse => Some(Value::boolean(false)),
+        Bytecodes::LdaConstant => match usize::try_from(op.operands[0])
+            .ok()
+            .and_then(|i| constants.get(i))
+        {
+            Some(Constant::Number(n)) => Some(Value::double(*n)),
+            _ => None,
+        },
+        _ => None,
+    };
+    for (r, value) in known.iter_mut().enumerate() {
+        if writes & 1 << r != 0 {
+            *value = loaded;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        core::vm::{
+            as_string, codegen::CodeGenerator, disassembler::disassemble, verifier::verify,
+            VirtualMachine,
+        },
+        parsing::{BuiltinParser, Parser},
+    };
+
+    fn compile(source: &str, passes: Passes) -> FunctionCode {
+        let program = BuiltinParser.parse(source.to_string());
+        let mut script = CodeGenerator::gen_script(&program).ok().unwrap();
+        optimize_function(&mut script, passes);
+        script
+    }
+
+    /// the instructions of the listing of `source`, without offsets and labels.
+    fn listing(source: &str, passes: Passes) -> Vec<String> {
+        disassemble(&compile(source, passes))
+            .lines()
+            .filter(|l| l.len() > 17 && l[6..10].bytes().all(|b| b.is_ascii_digit()))
+            .map(|l| l[17..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let source = "let x = 2 * 3 + 1; let y = -(1 / 2) < 0; let z = 1.5 * 2;";
+        assert_eq!(
+            listing(source, Passes::ALL),
+            [
+                "LdaSmi 7",
+                "StaContextSlot \"x\"",
+                "LdaTrue",
+                "StaContextSlot \"y\"",
+                "LdaSmi 3",
+                "StaContextSlot \"z\"",
+                "LdaUndefined",
+            ]
+        );
+        // a test on a known value is a jump or nothing
+        let source = "if (1 < 2) { a(); } else { b(); }";
+        let ops = listing(source, Passes::ALL);
+        assert!(ops.iter().any(|op| op == "LdaContextSlot \"a\""));
+        assert!(!ops
+            .iter()
+            .any(|op| op == "LdaContextSlot \"b\"" || op.starts_with("Jump")));
+
+        let passes = Passes {
+            constant_folding: false,
+            ..Passes::ALL
+        };
+        let ops = listing("let x = 2 * 3 + 1;", passes);
+        assert_eq!(ops[..3], ["LdaSmi 2", "MulSmi r0, 3", "AddSmi r0, 1"]);
+    }
+
+    #[test]
+    fn test_smi_operations() {
+        let source = "let a = 5; a - 1; 2 * a; a ** 2; 2 - a; 'a' + a; a + 0.5;";
+        let passes = Passes {
+            constant_folding: false,
+            ..Passes::ALL
+        };
+        let ops = listing(source, passes);
+        assert!(ops.contains(&"SubSmi r0, 1".to_string()));
+        // the operands of a product can swap, those of a difference can not
+        assert!(ops.contains(&"MulSmi r0, 2".to_string()));
+        assert!(ops.contains(&"ExpSmi r0, 2".to_string()));
+        assert!(ops.contains(&"Sub r7, r0".to_string()));
+        assert!(ops.contains(&"Add r7, r0".to_string()));
+        assert!(!ops.iter().any(|op| op.starts_with("AddSmi")));
+
+        let passes = Passes {
+            smi_operations: false,
+            ..passes
+        };
+        assert!(!listing(source, passes)
+            .iter()
+            .any(|op| op.contains("Smi r")));
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut code = vec![];
+        for (opcode, r) in [
+            (Bytecodes::Push, 1),
+            (Bytecodes::Push, 2),
+            (Bytecodes::Pop, 2),
+            (Bytecodes::Pop, 1),
+            (Bytecodes::Push, 3),
+            (Bytecodes::Pop, 0),
+            (Bytecodes::Push, 0),
+            (Bytecodes::Pop, 4),
+            (Bytecodes::Push, 1),
+            (Bytecodes::Pop, 2),
+        ] {
+            Op::new(opcode, &[r]).write(&mut code, None);
+        }
+        let passes = Passes {
+            push_pop: true,
+            ..Passes::NONE
+        };
+        let mut handler_table = HandlerTable::new();
+        let mut source_positions = SourcePositionTable::new();
+        optimize(
+            &mut code,
+            &mut handler_table,
+            &mut source_positions,
+            &ConstantTable::new(),
+            passes,
+        );
+        let mut expected = vec![];
+        Op::new(Bytecodes::Ldar, &[3]).write(&mut expected, None);
+        Op::new(Bytecodes::Star4, &[]).write(&mut expected, None);
+        Op::new(Bytecodes::Push, &[1]).write(&mut expected, None);
+        Op::new(Bytecodes::Pop, &[2]).write(&mut expected, None);
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn test_jump_threading() {
+        // the jumps that land on a jump, or on a test of the same value
+        let chained = |script: &FunctionCode| {
+            let code = Code::decode(
+                &script.code,
+                &script.handler_table,
+                &script.source_positions,
+            )
+            .unwrap();
+            let ops: Vec<&Op> = code.ops.iter().flatten().collect();
+            ops.iter()
+                .filter(|op| op.opcode != Bytecodes::JumpLoop)
+                .filter_map(|op| code.op(op.target?))
+                .filter(|hop| {
+                    matches!(
+                        hop.opcode,
+                        Bytecodes::Jump | Bytecodes::JumpIfTrue | Bytecodes::JumpIfFalse
+                    )
+                })
+                .count()
+        };
+        let source = "if (a && b || c) { log = log + 'x'; } else { log = log + 'y'; }";
+        let passes = Passes {
+            jump_threading: false,
+            ..Passes::ALL
+        };
+        assert!(chained(&compile(source, passes)) > 0);
+        assert_eq!(chained(&compile(source, Passes::ALL)), 0);
+
+        let mut vm = VirtualMachine::new(Box::new(BuiltinParser));
+        let declarations = "let log = ''; let a = 0; let b = 0; let c = 0;";
+        vm.exec(declarations.to_string()).ok().unwrap();
+        for (a, b, c) in [(0, 0, 0), (0, 0, 1), (1, 0, 0), (1, 1, 0), (1, 0, 1)] {
+            let source = format!("a = {}; b = {}; c = {}; {}", a, b, c, source);
+            vm.exec(source).ok().unwrap();
+        }
+        vm.exec("log;".to_string()).ok().unwrap();
+        assert_eq!(as_string(vm.register.r0), Some("yxyxx"));
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let source = "const f = function (a) {
+                return a;
+                a = 2;
+            };
+            try { f(1); } catch (e) {}";
+        let script = compile(source, Passes::ALL);
+        let f = script.constant_table.get_function(0);
+        let ops = listing_of(f);
+        assert_eq!(ops, ["LdaContextSlot \"a\"", "Return"]);
+        // the handler stays, with its region
+        assert_eq!(script.handler_table.entries().len(), 1);
+        verify(&script).unwrap();
+
+        // the value of a statement that is overwritten is not loaded
+        let ops = listing("let a = 1; a;", Passes::ALL);
+        assert!(!ops.contains(&"LdaUndefined".to_string()));
+        let passes = Passes {
+            dead_code: false,
+            ..Passes::ALL
+        };
+        let ops = listing("let a = 1; a;", passes);
+        assert!(ops.contains(&"LdaUndefined".to_string()));
+    }
+
+    fn listing_of(f: &FunctionCode) -> Vec<String> {
+        disassemble(f)
+            .lines()
+            .skip(1)
+            .take_while(|l| !l.ends_with(':'))
+            .map(|l| l[17..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_examples() {
+        // the examples still verify with all passes and with each alone
+        let (mut before, mut after) = (0, 0);
+        for entry in std::fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let program = match BuiltinParser.try_parse(source.clone()) {
+                Ok(program) => program,
+                Err(_) => continue,
+            };
+            if CodeGenerator::gen_script(&program).is_err() {
+                continue;
+            }
+            let alone = Passes::NAMES.iter().map(|name| {
+                let others = Passes::NAMES.iter().filter(|&other| other != name);
+                let others: Vec<&str> = others.copied().collect();
+                Passes::ALL.without(&others.join(",")).unwrap()
+            });
+            for passes in alone.chain([Passes::ALL]) {
+                let script = compile(&source, passes);
+                verify(&script).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            }
+            let size = |script: FunctionCode| disassemble(&script).lines().count();
+            before += size(compile(&source, Passes::NONE));
+            after += size(compile(&source, Passes::ALL));
+        }
+        assert!(after < before);
+    }
+
+    #[test]
+    fn test_without() {
+        let passes = Passes::ALL.without("dead-code, push-pop").unwrap();
+        assert!(!passes.dead_code && !passes.push_pop && passes.constant_folding);
+        let e = Passes::ALL.without("inlining").unwrap_err();
+        assert!(e.starts_with("unknown optimization pass 'inlining'"));
+        let all_off = Passes::NAMES
+            .iter()
+            .try_fold(Passes::ALL, |passes, name| passes.without(name));
+        assert_eq!(all_off, Ok(Passes::NONE));
+    }
+}
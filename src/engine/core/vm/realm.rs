@@ -0,0 +1,107 @@
+use super::{
+    heap::Heap,
+    objects::{
+        constant::PROTOTYPE_KEY_NAME,
+        js_error::JSError,
+        js_number::JSNumber,
+        js_object::{JSObject, JSType, NativeFunction},
+        js_string::JSString,
+    },
+    value::Value,
+    VMError,
+};
+
+/// the intrinsic objects of a vm. every object of a kind links to the same prototype,
+/// so they are allocated once and a change to a prototype is seen by all instances.
+pub(crate) struct Realm {
+    pub(crate) object_prototype: Value,
+    pub(crate) function_prototype: Value,
+    pub(crate) number_prototype: Value,
+    pub(crate) string_prototype: Value,
+    pub(crate) array_prototype: Value,
+    pub(crate) error_prototype: Value,
+    /// the `this` of the script, and of sloppy functions called without a receiver
+    pub(crate) global_object: Value,
+}
+
+impl Realm {
+    pub(crate) fn new(heap: &mut Heap) -> Self {
+        let object_prototype = Value::from(heap.alloc().unwrap());
+        let mut realm = Realm {
+            object_prototype,
+            function_prototype: object_prototype,
+            number_prototype: object_prototype,
+            string_prototype: object_prototype,
+            array_prototype: object_prototype,
+            error_prototype: object_prototype,
+            global_object: object_prototype,
+        };
+        // Function.prototype is itself callable and returns undefined
+        let function = JSType::NativeFunction(|_, _, _| Ok(Value::undefined()));
+        realm.function_prototype = realm.create_intrinsic(heap, function, object_prototype);
+        realm.number_prototype = realm.create_intrinsic(heap, JSType::Object, object_prototype);
+        realm.string_prototype = realm.create_intrinsic(heap, JSType::Object, object_prototype);
+        realm.array_prototype =
+            realm.create_intrinsic(heap, JSType::Array(Vec::new()), object_prototype);
+        realm.error_prototype = realm.create_intrinsic(heap, JSType::Object, object_prototype);
+        realm.global_object = realm.create_intrinsic(heap, JSType::Object, object_prototype);
+
+        JSNumber::init_prototype(&realm, heap);
+        JSString::init_prototype(&realm, heap);
+        JSError::init_prototype(&realm, heap);
+
+        realm
+    }
+
+    /// a new object of `_type` whose prototype is `prototype`.
+    pub(crate) fn create_object(
+        &self,
+        heap: &mut Heap,
+        _type: JSType,
+        prototype: Value,
+    ) -> Result<Value, VMError> {
+        let object = heap.alloc().ok_or_else(VMError::out_of_memory)?;
+        object._type = _type;
+        set_property(heap, object, PROTOTYPE_KEY_NAME, prototype);
+        Ok(Value::from(object))
+    }
+
+    /// an object the vm is set up with. a new heap has room for all of them.
+    pub(crate) fn create_intrinsic(
+        &self,
+        heap: &mut Heap,
+        _type: JSType,
+        prototype: Value,
+    ) -> Value {
+        self.create_object(heap, _type, prototype).ok().unwrap()
+    }
+
+    /// define a native method `name` on `object` while the vm is set up.
+    pub(crate) fn define_native(
+        &self,
+        heap: &mut Heap,
+        object: Value,
+        name: &str,
+        f: NativeFunction,
+    ) {
+        let function =
+            self.create_intrinsic(heap, JSType::NativeFunction(f), self.function_prototype);
+        set_property(heap, object.as_object().unwrap(), name, function);
+    }
+
+    /// visit the intrinsics. they are roots of the garbage collector.
+    pub(crate) fn for_each_value(&mut self, mut f: impl FnMut(&mut Value)) {
+        f(&mut self.object_prototype);
+        f(&mut self.function_prototype);
+        f(&mut self.number_prototype);
+        f(&mut self.string_prototype);
+        f(&mut self.array_prototype);
+        f(&mut self.error_prototype);
+        f(&mut self.global_object);
+    }
+}
+
+fn set_property(heap: &mut Heap, object: &mut JSObject, key: &str, value: Value) {
+    heap.write_barrier(object, value);
+    object.set(key, value);
+}
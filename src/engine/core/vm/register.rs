@@ -1,27 +1,50 @@
 #![allow(dead_code)]
 
+use super::value::Value;
+
+/// the registers of the running code. r0 is the accumulator.
+#[derive(Clone, Copy)]
 pub(crate) struct Register {
-    pub(crate) r0: i64,
-    pub(crate) r1: i64,
-    pub(crate) r2: i64,
-    pub(crate) r3: i64,
-    pub(crate) r4: i64,
-    pub(crate) r5: i64,
-    pub(crate) r6: i64,
-    pub(crate) r7: i64,
+    pub(crate) r0: Value,
+    pub(crate) r1: Value,
+    pub(crate) r2: Value,
+    pub(crate) r3: Value,
+    pub(crate) r4: Value,
+    pub(crate) r5: Value,
+    pub(crate) r6: Value,
+    pub(crate) r7: Value,
 }
 
 impl Register {
     pub(crate) fn new() -> Self {
         Self {
-            r0: 0,
-            r1: 0,
-            r2: 0,
-            r3: 0,
-            r4: 0,
-            r5: 0,
-            r6: 0,
-            r7: 0,
+            r0: Value::undefined(),
+            r1: Value::undefined(),
+            r2: Value::undefined(),
+            r3: Value::undefined(),
+            r4: Value::undefined(),
+            r5: Value::undefined(),
+            r6: Value::undefined(),
+            r7: Value::undefined(),
         }
     }
+
+    pub(crate) fn values(&self) -> [Value; 8] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r4, self.r5, self.r6, self.r7,
+        ]
+    }
+
+    pub(crate) fn values_mut(&mut self) -> [&mut Value; 8] {
+        [
+            &mut self.r0,
+            &mut self.r1,
+            &mut self.r2,
+            &mut self.r3,
+            &mut self.r4,
+            &mut self.r5,
+            &mut self.r6,
+            &mut self.r7,
+        ]
+    }
 }
@@ -0,0 +1,331 @@
+use std::{
+    cell::RefCell,
+    io::{Error, ErrorKind},
+    rc::Rc,
+};
+
+use super::{
+    constant_table::{Constant, ConstantTable},
+    feedback::FeedbackVector,
+    function::FunctionCode,
+    handler_table::{HandlerTable, HandlerTableEntry},
+    jit::JitState,
+    source_positions::SourcePositionTable,
+    verifier::verify,
+};
+
+/// the first bytes of a .glsc file.
+const MAGIC: &[u8; 4] = b"GLSC";
+
+/// the version of the layout below. bump it when the layout or the bytecodes change.
+pub(crate) const FORMAT_VERSION: u32 = 4;
+
+const CONSTANT_STRING: u8 = 0;
+const CONSTANT_NUMBER: u8 = 1;
+const CONSTANT_FUNCTION: u8 = 2;
+
+/// a compiled script, the contents of a .glsc file. integers are little endian, strings and
+/// byte arrays are prefixed with their u32 length.
+///
+/// ```text
+/// file     := "GLSC" format_version:u32 engine_version:string source_hash:u64 function
+/// function := parameters:u32 string* code:bytes
+///             constants:u32 constant* handlers:u32 handler* positions:u32 position*
+///             load_slots:u32 store_slots:u32
+/// constant := 0 string | 1 f64 | 2 function
+/// handler  := start:u64 end:u64 handler:u64 stack_depth:u64 context_depth:u64
+/// position := offset:u64 line:u32
+/// ```
+pub(crate) struct Script {
+    pub(crate) source_hash: u64,
+    pub(crate) code: Rc<FunctionCode>,
+}
+
+impl Script {
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut w = Writer { bytes: vec![] };
+        w.bytes.extend_from_slice(MAGIC);
+        w.u32(FORMAT_VERSION);
+        w.string(env!("CARGO_PKG_VERSION"));
+        w.u64(self.source_hash);
+        w.function(&self.code);
+        w.bytes
+    }
+
+    /// a script written by `serialize` of this version of the engine. the bytecode is
+    /// verified, the file may come from anywhere.
+    pub(crate) fn deserialize(bytes: &[u8]) -> Result<Script, Error> {
+        let mut r = Reader { bytes, at: 0 };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a .glsc file"));
+        }
+        let version = r.u32()?;
+        if version != FORMAT_VERSION || r.string()? != env!("CARGO_PKG_VERSION") {
+            return Err(invalid_data("the .glsc file is of another version"));
+        }
+        let source_hash = r.u64()?;
+        let code = Rc::new(r.function()?);
+        if r.at != bytes.len() {
+            return Err(invalid_data("trailing bytes after the script"));
+        }
+        verify(&code).map_err(|e| invalid_data(&e.to_string()))?;
+        Ok(Script { source_hash, code })
+    }
+}
+
+/// FNV-1a, which unlike the hasher of std is the same on every build.
+pub(crate) fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u32(&mut self, n: u32) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn function(&mut self, function: &FunctionCode) {
+        self.u32(function.parameters.len() as u32);
+        for parameter in function.parameters.iter() {
+            self.string(parameter);
+        }
+        self.bytes(&function.code);
+
+        let constants: Vec<&Constant> = function.constant_table.iter().collect();
+        self.u32(constants.len() as u32);
+        for constant in constants {
+            match constant {
+                Constant::String(s) => {
+                    self.bytes.push(CONSTANT_STRING);
+                    self.string(s);
+                }
+                Constant::Number(n) => {
+                    self.bytes.push(CONSTANT_NUMBER);
+                    self.u64(n.to_bits());
+                }
+                Constant::Function(f) => {
+                    self.bytes.push(CONSTANT_FUNCTION);
+                    self.function(f);
+                }
+            }
+        }
+
+        let entries = function.handler_table.entries();
+        self.u32(entries.len() as u32);
+        for entry in entries {
+            for n in [
+                entry.start,
+                entry.end,
+                entry.handler,
+                entry.stack_depth,
+                entry.context_depth,
+            ] {
+                self.u64(n as u64);
+            }
+        }
+
+        let positions = function.source_positions.entries();
+        self.u32(positions.len() as u32);
+        for &(offset, line) in positions {
+            self.u64(offset as u64);
+            self.u32(line);
+        }
+
+        // the caches start empty, only their number is kept
+        let (loads, stores) = function.feedback.borrow().slots();
+        self.u32(loads as u32);
+        self.u32(stores as u32);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .at
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("the .glsc file is truncated"))?;
+        let bytes = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| invalid_data("offset out of range"))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid_data("invalid utf-8"))
+    }
+
+    fn function(&mut self) -> Result<FunctionCode, Error> {
+        let parameters = (0..self.u32()?)
+            .map(|_| self.string())
+            .collect::<Result<Vec<String>, Error>>()?;
+        let code = self.bytes()?.to_vec();
+
+        let mut constant_table = ConstantTable::new();
+        for _ in 0..self.u32()? {
+            match self.u8()? {
+                CONSTANT_STRING => constant_table.add(self.string()?),
+                CONSTANT_NUMBER => constant_table.add_number(f64::from_bits(self.u64()?)),
+                CONSTANT_FUNCTION => constant_table.add_function(Rc::new(self.function()?)),
+                tag => return Err(invalid_data(&format!("unknown constant tag {}", tag))),
+            };
+        }
+
+        let mut handler_table = HandlerTable::new();
+        for _ in 0..self.u32()? {
+            handler_table.add(HandlerTableEntry {
+                start: self.usize()?,
+                end: self.usize()?,
+                handler: self.usize()?,
+                stack_depth: self.usize()?,
+                context_depth: self.usize()?,
+            });
+        }
+
+        let mut source_positions = SourcePositionTable::new();
+        for _ in 0..self.u32()? {
+            source_positions.add(self.usize()?, self.u32()?);
+        }
+
+        let mut feedback = FeedbackVector::new();
+        for _ in 0..self.u32()? {
+            feedback.add_load_slot();
+        }
+        for _ in 0..self.u32()? {
+            feedback.add_store_slot();
+        }
+
+        Ok(FunctionCode {
+            parameters,
+            code,
+            constant_table,
+            feedback: RefCell::new(feedback),
+            handler_table,
+            source_positions,
+            jit: RefCell::new(JitState::default()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        core::vm::codegen::CodeGenerator,
+        parsing::{BuiltinParser, Parser},
+    };
+
+    fn compile(source: &str) -> Script {
+        let program = BuiltinParser.parse(source.to_string());
+        Script {
+            source_hash: source_hash(source),
+            code: Rc::new(CodeGenerator::gen_script(&program).ok().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = r#"
+            const f = function (a, b = 'b') {
+                try { throw a; } catch (e) { return 1.5 + e.x + b; }
+            };
+            f('a');
+        "#;
+        let script = compile(source);
+        let bytes = script.serialize();
+        let copy = Script::deserialize(&bytes).ok().unwrap();
+        assert_eq!(copy.source_hash, source_hash(source));
+        assert_eq!(copy.serialize(), bytes);
+
+        let f = copy.code.constant_table.get_function(0);
+        assert_eq!(f.parameters, ["a", "b"]);
+        assert_eq!(f.handler_table.entries().len(), 1);
+        assert!(f
+            .constant_table
+            .iter()
+            .any(|c| matches!(c, Constant::Number(n) if *n == 1.5)));
+        assert_eq!(f.feedback.borrow().slots(), (1, 0));
+        // the default of `b` comes before the body on line 3
+        assert_eq!(f.source_positions.entries().len(), 1);
+        assert_eq!(f.source_positions.lookup(f.code.len() - 1), Some(3));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let bytes = compile("1 + 2;").serialize();
+        let error = |bytes: &[u8]| Script::deserialize(bytes).err().unwrap().to_string();
+
+        assert_eq!(error(b"GLSX"), "not a .glsc file");
+        assert_eq!(
+            error(&bytes[..bytes.len() - 1]),
+            "the .glsc file is truncated"
+        );
+        let mut other = bytes.clone();
+        other[4] += 1;
+        assert_eq!(error(&other), "the .glsc file is of another version");
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(error(&trailing), "trailing bytes after the script");
+        // `LdaSmi 1; Mov r7, 2; ...` with a register that does not exist
+        let code = &compile("1 + 2;").code.code;
+        let at = bytes.windows(code.len()).position(|w| w == code).unwrap();
+        let mut bad = bytes.clone();
+        bad[at + 10] = 9;
+        assert_eq!(
+            error(&bad),
+            "invalid bytecode at 9: register r9 out of range"
+        );
+
+        assert_ne!(source_hash("1 + 2;"), source_hash("1 + 3;"));
+        // the hash must not change between builds, or every cache misses
+        assert_eq!(source_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(source_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,67 @@
+/// the source lines of a code: each entry is the offset of the first instruction of a line.
+/// the instructions up to the next entry came from the same line.
+pub(crate) struct SourcePositionTable {
+    entries: Vec<(usize, u32)>,
+}
+
+impl SourcePositionTable {
+    pub(crate) fn new() -> Self {
+        SourcePositionTable {
+            entries: Vec::new(),
+        }
+    }
+
+    /// the code from `offset` on comes from `line`. offsets must be added in order.
+    pub(crate) fn add(&mut self, offset: usize, line: u32) {
+        match self.entries.last_mut() {
+            Some(&mut (_, last)) if last == line => {}
+            // no instruction came from the previous line
+            Some(entry) if entry.0 == offset => entry.1 = line,
+            _ => self.entries.push((offset, line)),
+        }
+    }
+
+    /// append the entries of code that is placed at `offset`.
+    pub(crate) fn extend(&mut self, other: SourcePositionTable, offset: usize) {
+        for (start, line) in other.entries {
+            self.add(start + offset, line);
+        }
+    }
+
+    pub(crate) fn entries(&self) -> &[(usize, u32)] {
+        &self.entries
+    }
+
+    /// the line of the instruction at `offset`, `None` before the first entry.
+    pub(crate) fn lookup(&self, offset: usize) -> Option<u32> {
+        let i = self.entries.partition_point(|&(start, _)| start <= offset);
+        i.checked_sub(1).map(|i| self.entries[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let mut table = SourcePositionTable::new();
+        table.add(2, 1);
+        table.add(5, 1);
+        table.add(8, 3);
+        table.add(8, 4);
+        table.add(12, 2);
+        assert_eq!(table.entries(), [(2, 1), (8, 4), (12, 2)]);
+
+        assert_eq!(table.lookup(0), None);
+        assert_eq!(table.lookup(2), Some(1));
+        assert_eq!(table.lookup(7), Some(1));
+        assert_eq!(table.lookup(8), Some(4));
+        assert_eq!(table.lookup(100), Some(2));
+
+        let mut script = SourcePositionTable::new();
+        script.add(0, 2);
+        script.extend(table, 10);
+        assert_eq!(script.entries(), [(0, 2), (12, 1), (18, 4), (22, 2)]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt::{Debug, Display};
+
+use super::objects::js_object::JSObject;
+
+// a value is a NaN-boxed 64 bit word. every double is stored as itself (NaNs canonicalized),
+// the other values live in the unused quiet NaN space:
+//
+//   pointer   1 11111111111 11 00 <48 bit address>
+//   smi         11111111111 11 01 0000 <32 bit integer>
+//   special     11111111111 11 10 0000 <tag>
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN: u64 = 0x8000_0000_0000_0000;
+const TAG_MASK: u64 = 0x0003_0000_0000_0000;
+const TAG_SMI: u64 = 0x0001_0000_0000_0000;
+const TAG_SPECIAL: u64 = 0x0002_0000_0000_0000;
+const POINTER_MASK: u64 = 0x0000_ffff_ffff_ffff;
+
+const UNDEFINED: u64 = QNAN | TAG_SPECIAL | 1;
+const NULL: u64 = QNAN | TAG_SPECIAL | 2;
+const FALSE: u64 = QNAN | TAG_SPECIAL | 3;
+const TRUE: u64 = QNAN | TAG_SPECIAL | 4;
+
+/// a vm value: an immediate small integer, double, boolean, undefined or null,
+/// or a pointer to a heap object.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Value(u64);
+
+/// constructors
+impl Value {
+    pub(crate) fn undefined() -> Self {
+        Value(UNDEFINED)
+    }
+
+    pub(crate) fn null() -> Self {
+        Value(NULL)
+    }
+
+    pub(crate) fn boolean(b: bool) -> Self {
+        Value(if b { TRUE } else { FALSE })
+    }
+
+    pub(crate) fn smi(n: i32) -> Self {
+        Value(QNAN | TAG_SMI | n as u32 as u64)
+    }
+
+    pub(crate) fn double(n: f64) -> Self {
+        if n.is_nan() {
+            Value(f64::NAN.to_bits())
+        } else {
+            Value(n.to_bits())
+        }
+    }
+
+    /// a smi when `n` is an integer that fits, otherwise a double.
+    pub(crate) fn number(n: f64) -> Self {
+        let i = n as i32;
+        if i as f64 == n && !(n == 0.0 && n.is_sign_negative()) {
+            Value::smi(i)
+        } else {
+            Value::double(n)
+        }
+    }
+
+    pub(crate) fn object(ptr: i64) -> Self {
+        debug_assert!(ptr as u64 & !POINTER_MASK == 0);
+        Value(SIGN | QNAN | ptr as u64)
+    }
+
+    pub(crate) fn from_bits(bits: u64) -> Self {
+        Value(bits)
+    }
+}
+
+/// core impl
+impl Value {
+    pub(crate) fn to_bits(self) -> u64 {
+        self.0
+    }
+
+    fn is_boxed(self) -> bool {
+        self.0 & QNAN == QNAN
+    }
+
+    pub(crate) fn is_object(self) -> bool {
+        self.is_boxed() && self.0 & SIGN != 0
+    }
+
+    pub(crate) fn is_smi(self) -> bool {
+        self.is_boxed() && self.0 & (SIGN | TAG_MASK) == TAG_SMI
+    }
+
+    pub(crate) fn is_double(self) -> bool {
+        !self.is_boxed()
+    }
+
+    pub(crate) fn is_number(self) -> bool {
+        self.is_smi() || self.is_double()
+    }
+
+    pub(crate) fn is_undefined(self) -> bool {
+        self.0 == UNDEFINED
+    }
+
+    pub(crate) fn is_null(self) -> bool {
+        self.0 == NULL
+    }
+
+    pub(crate) fn as_smi(self) -> Option<i32> {
+        self.is_smi().then_some(self.0 as u32 as i32)
+    }
+
+    pub(crate) fn as_number(self) -> Option<f64> {
+        if self.is_double() {
+            Some(f64::from_bits(self.0))
+        } else {
+            self.as_smi().map(|n| n as f64)
+        }
+    }
+
+    pub(crate) fn as_boolean(self) -> Option<bool> {
+        match self.0 {
+            TRUE => Some(true),
+            FALSE => Some(false),
+            _ => None,
+        }
+    }
+
+    /// the address of the object this value points to.
+    pub(crate) fn as_object_ptr(self) -> Option<i64> {
+        self.is_object().then_some((self.0 & POINTER_MASK) as i64)
+    }
+
+    pub(crate) fn as_object<'a>(self) -> Option<&'a mut JSObject> {
+        self.as_object_ptr().map(JSObject::from_raw_ptr_mut)
+    }
+}
+
+impl From<&JSObject> for Value {
+    fn from(object: &JSObject) -> Self {
+        Value::object(object.as_raw_ptr())
+    }
+}
+
+impl From<&mut JSObject> for Value {
+    fn from(object: &mut JSObject) -> Self {
+        Value::object(object.as_raw_ptr())
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(object) = self.as_object() {
+            return write!(f, "{}", object);
+        }
+        if let Some(n) = self.as_number() {
+            return write!(f, "\x1b[33m{}\x1b[0m", n);
+        }
+        match self.0 {
+            TRUE => write!(f, "\x1b[33mtrue\x1b[0m"),
+            FALSE => write!(f, "\x1b[33mfalse\x1b[0m"),
+            NULL => write!(f, "null"),
+            _ => write!(f, "\x1b[30mundefined\x1b[0m"),
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ptr) = self.as_object_ptr() {
+            return write!(f, "Object({:#x})", ptr);
+        }
+        if let Some(n) = self.as_smi() {
+            return write!(f, "Smi({})", n);
+        }
+        if let Some(n) = self.as_number() {
+            return write!(f, "Double({})", n);
+        }
+        match self.0 {
+            TRUE => write!(f, "True"),
+            FALSE => write!(f, "False"),
+            NULL => write!(f, "Null"),
+            _ => write!(f, "Undefined"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::core::vm::objects::shape::Shape;
+
+    #[test]
+    fn test_immediates() {
+        assert_eq!(Value::number(42.0).as_smi(), Some(42));
+        assert_eq!(Value::number(-1.0).as_smi(), Some(-1));
+        assert!(Value::number(1.5).is_double());
+        assert!(Value::number(-0.0).is_double());
+        assert!(Value::number(1e10).is_double());
+        assert!(Value::number(f64::NAN).as_number().unwrap().is_nan());
+        assert!(Value::number(f64::INFINITY).is_number());
+        assert!(!Value::number(f64::NAN).is_object());
+
+        assert_eq!(Value::boolean(true).as_boolean(), Some(true));
+        assert_eq!(Value::boolean(false).as_boolean(), Some(false));
+        assert!(Value::undefined().is_undefined());
+        assert!(Value::null().is_null());
+        for v in [Value::undefined(), Value::null(), Value::boolean(true)] {
+            assert!(!v.is_number());
+            assert!(!v.is_object());
+        }
+    }
+
+    #[test]
+    fn test_pointer() {
+        let object = Box::new(JSObject::new(Shape::root()));
+        let ptr = object.as_raw_ptr();
+        let v = Value::object(ptr);
+        assert!(v.is_object());
+        assert!(!v.is_number());
+        assert_eq!(v.as_object_ptr(), Some(ptr));
+        assert_eq!(Value::from_bits(v.to_bits()), v);
+        assert_eq!(Value::smi(7).as_object_ptr(), None);
+    }
+}
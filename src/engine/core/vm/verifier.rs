@@ -0,0 +1,555 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use super::{
+    bytecodes::{decode, name, operands, Bytecodes, DecodeError, Instruction, Operand, RName},
+    constant_table::Constant,
+    function::FunctionCode,
+    value::Value,
+};
+
+/// why a code was rejected. `path` leads from the script to the function of the code, through
+/// the indices of function constants.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct VerifyError {
+    pub(crate) path: Vec<u32>,
+    pub(crate) offset: usize,
+    pub(crate) message: String,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid bytecode at {}", self.offset)?;
+        if !self.path.is_empty() {
+            let path: Vec<String> = self.path.iter().map(|i| i.to_string()).collect();
+            write!(f, " of function {}", path.join("."))?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// check a script and its functions before the vm runs them, so that bytecode from a file
+/// can not make the vm read past its code, registers, constants or stack.
+///
+/// every path through the code must keep the stack and the contexts at one depth for each
+/// instruction, a script ends with an empty stack and a function ends with `Return` or
+/// `Throw`. contexts are popped before they are left by `Return` or the end of a script.
+pub(crate) fn verify(script: &FunctionCode) -> Result<(), VerifyError> {
+    Verifier::new(script, vec![], false).verify()
+}
+
+struct Verifier<'a> {
+    function: &'a FunctionCode,
+    path: Vec<u32>,
+    in_function: bool,
+    constants: Vec<&'a Constant>,
+    /// the instructions by their offset
+    instructions: BTreeMap<usize, Instruction>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(function: &'a FunctionCode, path: Vec<u32>, in_function: bool) -> Self {
+        Verifier {
+            function,
+            path,
+            in_function,
+            constants: function.constant_table.iter().collect(),
+            instructions: BTreeMap::new(),
+        }
+    }
+
+    fn verify(mut self) -> Result<(), VerifyError> {
+        self.decode()?;
+        for (&pc, instruction) in self.instructions.iter() {
+            self.check_operands(pc, instruction)?;
+        }
+        self.check_stack()?;
+
+        for (i, constant) in self.constants.iter().enumerate() {
+            if let Constant::Function(f) = constant {
+                let mut path = self.path.clone();
+                path.push(i as u32);
+                Verifier::new(f, path, true).verify()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&self, offset: usize, message: String) -> VerifyError {
+        VerifyError {
+            path: self.path.clone(),
+            offset,
+            message,
+        }
+    }
+
+    /// split the code into instructions of known opcodes and complete operands.
+    fn decode(&mut self) -> Result<(), VerifyError> {
+        let code = &self.function.code;
+        let mut pc = 0;
+        while pc < code.len() {
+            let opcode = code[pc];
+            let instruction = decode(code, pc).map_err(|e| match e {
+                DecodeError::UnknownOpcode => {
+                    self.error(pc, format!("unknown opcode 0x{:02x}", opcode))
+                }
+                DecodeError::Truncated => self.error(
+                    pc,
+                    format!("{} runs past the end of the code", name(opcode).unwrap()),
+                ),
+            })?;
+            let kinds = operands(opcode).unwrap();
+            for (&kind, &r) in kinds.iter().zip(instruction.operands.iter()) {
+                if kind == Operand::Register && r > RName::R7 as i64 {
+                    return Err(self.error(pc, format!("register r{} out of range", r)));
+                }
+            }
+
+            pc += instruction.len;
+            self.instructions.insert(pc - instruction.len, instruction);
+        }
+        Ok(())
+    }
+
+    fn check_operands(&self, pc: usize, instruction: &Instruction) -> Result<(), VerifyError> {
+        let opcode = instruction.opcode;
+        let kinds = operands(opcode).unwrap();
+        for (&kind, &n) in kinds.iter().zip(instruction.operands.iter()) {
+            match kind {
+                // a pointer from a file would point anywhere
+                Operand::Value if Value::from_bits(n as u64).is_object() => {
+                    return Err(self.error(pc, "immediate value is an object".to_string()));
+                }
+                Operand::Constant => {
+                    let constant = usize::try_from(n).ok().and_then(|i| self.constants.get(i));
+                    let expected = match (opcode, constant) {
+                        (_, None) => "in range",
+                        (Bytecodes::CreateClosure, Some(Constant::Function(_))) => continue,
+                        (Bytecodes::CreateClosure, _) => "a function",
+                        (Bytecodes::LdaConstant, Some(Constant::Function(_))) => {
+                            "a string or a number"
+                        }
+                        (Bytecodes::LdaConstant, _) => continue,
+                        (_, Some(Constant::String(_))) => continue,
+                        _ => "a string",
+                    };
+                    return Err(self.error(pc, format!("constant {} is not {}", n, expected)));
+                }
+                Operand::LoadSlot | Operand::StoreSlot => {
+                    let (loads, stores) = self.function.feedback.borrow().slots();
+                    let slots = match kind {
+                        Operand::LoadSlot => loads,
+                        _ => stores,
+                    };
+                    if usize::try_from(n).map_or(true, |slot| slot >= slots) {
+                        return Err(self.error(pc, format!("feedback slot {} out of range", n)));
+                    }
+                }
+                Operand::Offset if self.target(pc, instruction).is_none() => {
+                    return Err(self.error(
+                        pc,
+                        format!("jump offset {} does not reach an instruction", n),
+                    ));
+                }
+                Operand::Count if n < 0 => {
+                    return Err(self.error(pc, format!("negative count {}", n)));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// the offset a jump goes to: the start of an instruction, or the end of the code.
+    fn target(&self, pc: usize, instruction: &Instruction) -> Option<usize> {
+        let end = (pc + instruction.len) as i64;
+        let offset = instruction.operands[0];
+        let target = match instruction.opcode {
+            Bytecodes::JumpLoop => end.checked_sub(offset)?,
+            _ => end.checked_add(offset)?,
+        };
+        let target = usize::try_from(target).ok()?;
+        (target == self.function.code.len() || self.instructions.contains_key(&target))
+            .then_some(target)
+    }
+
+    /// follow every path from the start and from the exception handlers with the depth of the
+    /// stack and the contexts pushed.
+    fn check_stack(&self) -> Result<(), VerifyError> {
+        let len = self.function.code.len();
+        let mut work = vec![(0, Depths::default())];
+        for entry in self.function.handler_table.entries() {
+            if entry.start > entry.end
+                || entry.end > len
+                || !self.instructions.contains_key(&entry.handler)
+            {
+                return Err(self.error(
+                    entry.handler,
+                    format!(
+                        "handler of the region {}..{} is not an instruction",
+                        entry.start, entry.end
+                    ),
+                ));
+            }
+            let depths = Depths {
+                stack: entry.stack_depth as i64,
+                contexts: entry.context_depth as i64,
+            };
+            work.push((entry.handler, depths));
+        }
+
+        let mut seen: BTreeMap<usize, Depths> = BTreeMap::new();
+        while let Some((pc, depths)) = work.pop() {
+            let instruction = match self.instructions.get(&pc) {
+                Some(instruction) => instruction,
+                // the end of the code, like `Hlt`
+                None => {
+                    self.check_end(pc, depths)?;
+                    continue;
+                }
+            };
+            match seen.insert(pc, depths) {
+                Some(d) if d == depths => continue,
+                Some(d) if d.stack != depths.stack => {
+                    return Err(self.error(
+                        pc,
+                        format!(
+                            "stack depth is {} on one path and {} on another",
+                            d.stack, depths.stack
+                        ),
+                    ))
+                }
+                Some(d) => {
+                    return Err(self.error(
+                        pc,
+                        format!(
+                            "context depth is {} on one path and {} on another",
+                            d.contexts, depths.contexts
+                        ),
+                    ))
+                }
+                None => {}
+            }
+
+            let Depths { stack, contexts } = depths;
+            let popped = match instruction.opcode {
+                Bytecodes::Pop => 1,
+                Bytecodes::CreateArrayLiteral => instruction.operands[0],
+                // the callee, the receiver and the arguments
+                Bytecodes::CallProperty | Bytecodes::CallAnyReceiver => {
+                    instruction.operands[0].saturating_add(2)
+                }
+                _ => 0,
+            };
+            if popped > stack {
+                return Err(self.error(
+                    pc,
+                    format!("pops {} values off a stack of {}", popped, stack),
+                ));
+            }
+            if instruction.opcode == Bytecodes::PopContext && contexts == 0 {
+                return Err(self.error(pc, "pops a context that was not pushed".to_string()));
+            }
+            let depths = Depths {
+                stack: match instruction.opcode {
+                    Bytecodes::Push => stack + 1,
+                    _ => stack - popped,
+                },
+                contexts: match instruction.opcode {
+                    Bytecodes::PushContext => contexts + 1,
+                    Bytecodes::PopContext => contexts - 1,
+                    _ => contexts,
+                },
+            };
+
+            let next = pc + instruction.len;
+            match instruction.opcode {
+                Bytecodes::Return if depths.contexts != 0 => {
+                    return Err(self.error(
+                        pc,
+                        format!("returns with {} contexts pushed", depths.contexts),
+                    ))
+                }
+                Bytecodes::Return | Bytecodes::Throw => {}
+                Bytecodes::Hlt => self.check_end(pc, depths)?,
+                Bytecodes::Jump | Bytecodes::JumpLoop => {
+                    work.push((self.target(pc, instruction).unwrap(), depths))
+                }
+                Bytecodes::JumpIfTrue
+                | Bytecodes::JumpIfFalse
+                | Bytecodes::JumpIfNotUndefined
+                | Bytecodes::JumpIfUndefinedOrNull => {
+                    work.push((self.target(pc, instruction).unwrap(), depths));
+                    work.push((next, depths));
+                }
+                _ => work.push((next, depths)),
+            }
+        }
+
+        // a handler pops the contexts pushed in its region, it can not push any
+        for entry in self.function.handler_table.entries() {
+            let region = seen.range(entry.start..entry.end);
+            if let Some((&pc, depths)) = region
+                .into_iter()
+                .find(|(_, d)| d.contexts < entry.context_depth as i64)
+            {
+                return Err(self.error(
+                    pc,
+                    format!(
+                        "handler of the region {}..{} expects {} contexts, {} are pushed",
+                        entry.start, entry.end, entry.context_depth, depths.contexts
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// the script may stop with an empty stack and no context pushed, a function has to
+    /// return.
+    fn check_end(&self, pc: usize, depths: Depths) -> Result<(), VerifyError> {
+        if self.in_function {
+            return Err(self.error(pc, "function ends without a return".to_string()));
+        }
+        if depths.stack != 0 {
+            return Err(self.error(
+                pc,
+                format!("script ends with {} values on the stack", depths.stack),
+            ));
+        }
+        if depths.contexts != 0 {
+            return Err(self.error(
+                pc,
+                format!("script ends with {} contexts pushed", depths.contexts),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// the values on the stack and the contexts pushed at an instruction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Depths {
+    stack: i64,
+    contexts: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::engine::{
+        core::vm::{
+            codegen::CodeGenerator,
+            constant_table::ConstantTable,
+            feedback::FeedbackVector,
+            handler_table::{HandlerTable, HandlerTableEntry},
+            jit::JitState,
+            source_positions::SourcePositionTable,
+        },
+        parsing::{BuiltinParser, Parser},
+    };
+
+    fn function(code: Vec<u8>, constant_table: ConstantTable) -> FunctionCode {
+        FunctionCode {
+            parameters: vec![],
+            code,
+            constant_table,
+            feedback: RefCell::new(FeedbackVector::new()),
+            handler_table: HandlerTable::new(),
+            source_positions: SourcePositionTable::new(),
+            jit: RefCell::new(JitState::default()),
+        }
+    }
+
+    fn message(code: &[&[u8]]) -> String {
+        let f = function(code.concat(), ConstantTable::new());
+        verify(&f).unwrap_err().to_string()
+    }
+
+    fn n(n: i64) -> [u8; 8] {
+        n.to_le_bytes()
+    }
+
+    #[test]
+    fn test_generated_code() {
+        // everything the code generator emits passes
+        for entry in std::fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let program = BuiltinParser.parse(source);
+            let script = CodeGenerator::gen_script(&program).ok().unwrap();
+            assert_eq!(verify(&script), Ok(()), "{}", path.display());
+        }
+
+        // a return pops the contexts of the blocks around it
+        let source = r#"
+            const f = function (n) {
+                for (let i = 0; i < n; i++) {
+                    try { if (i == 2) { return i; } } catch (e) { return e; } finally { n; }
+                }
+            };
+            f(3);
+        "#;
+        let script = CodeGenerator::gen_script(&BuiltinParser.parse(source.to_string()));
+        assert_eq!(verify(&script.ok().unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_code() {
+        use Bytecodes::*;
+
+        assert_eq!(
+            message(&[&[0xff]]),
+            "invalid bytecode at 0: unknown opcode 0xff"
+        );
+        assert_eq!(
+            message(&[&[0x21, 0]]),
+            "invalid bytecode at 0: unknown opcode 0x21"
+        );
+        assert_eq!(
+            message(&[&[LdaUndefined, LdaSmi, 1, 0]]),
+            "invalid bytecode at 1: LdaSmi runs past the end of the code"
+        );
+        assert_eq!(
+            message(&[&[LdaContextSlot], &n(4), b"abc"]),
+            "invalid bytecode at 0: LdaContextSlot runs past the end of the code"
+        );
+        assert_eq!(
+            message(&[&[Push, 8]]),
+            "invalid bytecode at 0: register r8 out of range"
+        );
+        assert_eq!(
+            message(&[&[Mov, 1], &n(Value::object(0x1000).to_bits() as i64)]),
+            "invalid bytecode at 0: immediate value is an object"
+        );
+
+        // jumps into an instruction or out of the code
+        assert_eq!(
+            message(&[&[Jump], &n(1), &[LdaSmi], &n(0)]),
+            "invalid bytecode at 0: jump offset 1 does not reach an instruction"
+        );
+        assert_eq!(
+            message(&[&[LdaTrue, JumpLoop], &n(11)]),
+            "invalid bytecode at 1: jump offset 11 does not reach an instruction"
+        );
+
+        // constants of the wrong kind or missing
+        let mut constants = ConstantTable::new();
+        constants.add_number(1.5);
+        let f = function([&[CreateClosure][..], &n(0)].concat(), constants);
+        assert_eq!(
+            verify(&f).unwrap_err().to_string(),
+            "invalid bytecode at 0: constant 0 is not a function"
+        );
+        assert_eq!(
+            message(&[&[LdaConstant], &n(0)]),
+            "invalid bytecode at 0: constant 0 is not in range"
+        );
+        assert_eq!(
+            message(&[&[GetNamedProperty, 0], &n(0), &n(0)]),
+            "invalid bytecode at 0: constant 0 is not in range"
+        );
+
+        // the stack
+        assert_eq!(
+            message(&[&[Push, 0, Pop, 0, Pop, 0]]),
+            "invalid bytecode at 4: pops 1 values off a stack of 0"
+        );
+        assert_eq!(
+            message(&[&[Push, 0, Push, 1, CallAnyReceiver], &n(1)]),
+            "invalid bytecode at 4: pops 3 values off a stack of 2"
+        );
+        assert_eq!(
+            message(&[&[Push, 0]]),
+            "invalid bytecode at 2: script ends with 1 values on the stack"
+        );
+        // `if (r0) push` joins the path that did not push
+        assert_eq!(
+            message(&[&[JumpIfFalse], &n(2), &[Push, 0, Pop, 0]]),
+            "invalid bytecode at 11: stack depth is 1 on one path and 0 on another"
+        );
+
+        // the contexts
+        assert_eq!(
+            message(&[&[PopContext, LdaUndefined]]),
+            "invalid bytecode at 0: pops a context that was not pushed"
+        );
+        assert_eq!(
+            message(&[&[PushContext, LdaUndefined]]),
+            "invalid bytecode at 2: script ends with 1 contexts pushed"
+        );
+        assert_eq!(
+            message(&[&[PushContext, Hlt]]),
+            "invalid bytecode at 1: script ends with 1 contexts pushed"
+        );
+        assert_eq!(
+            message(&[
+                &[JumpIfFalse],
+                &n(1),
+                &[PushContext, LdaUndefined, PopContext]
+            ]),
+            "invalid bytecode at 10: context depth is 1 on one path and 0 on another"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        use Bytecodes::*;
+
+        // functions are checked with the path to them
+        let mut inner = ConstantTable::new();
+        inner.add_function(Rc::new(function(vec![LdaUndefined], ConstantTable::new())));
+        let mut outer = ConstantTable::new();
+        outer.add(String::from("f"));
+        outer.add_function(Rc::new(function(vec![Return], inner)));
+        let script = function(vec![], outer);
+        assert_eq!(
+            verify(&script).unwrap_err().to_string(),
+            "invalid bytecode at 1 of function 1.0: function ends without a return"
+        );
+        let mut constants = ConstantTable::new();
+        let code = vec![PushContext, LdaUndefined, Return];
+        constants.add_function(Rc::new(function(code, ConstantTable::new())));
+        let script = function(vec![], constants);
+        assert_eq!(
+            verify(&script).unwrap_err().to_string(),
+            "invalid bytecode at 2 of function 0: returns with 1 contexts pushed"
+        );
+
+        // handlers start with the stack depth of their region
+        let mut f = function(vec![Push, 0, Throw, Pop, 0], ConstantTable::new());
+        let entry = HandlerTableEntry {
+            start: 0,
+            end: 3,
+            handler: 3,
+            stack_depth: 1,
+            context_depth: 0,
+        };
+        f.handler_table.add(entry);
+        assert_eq!(verify(&f), Ok(()));
+        f.handler_table = HandlerTable::new();
+        f.handler_table.add(HandlerTableEntry {
+            handler: 1,
+            ..entry
+        });
+        assert_eq!(
+            verify(&f).unwrap_err().to_string(),
+            "invalid bytecode at 1: handler of the region 0..3 is not an instruction"
+        );
+
+        // and pop the contexts pushed in their region, they can not push more
+        let mut f = function(vec![Throw, PopContext], ConstantTable::new());
+        f.handler_table.add(HandlerTableEntry {
+            start: 0,
+            end: 1,
+            handler: 1,
+            stack_depth: 0,
+            context_depth: 1,
+        });
+        assert_eq!(
+            verify(&f).unwrap_err().to_string(),
+            "invalid bytecode at 0: handler of the region 0..1 expects 1 contexts, 0 are pushed"
+        );
+    }
+}
@@ -6,6 +6,10 @@ pub struct Lexer {
     position: usize,
     read_position: usize,
     ch: char,
+    /// the line of the last token, counting from 1
+    line: u32,
+    /// the position up to which `line` counts the line breaks
+    line_position: usize,
 }
 
 impl Lexer {
@@ -15,14 +19,22 @@ impl Lexer {
             position: 0,
             read_position: 0,
             ch: ' ',
+            line: 1,
+            line_position: 0,
         };
         l.read_char();
         l
     }
 
+    /// the line the last token returned by `next_token` starts on.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
     #[allow(dead_code)]
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        self.count_lines();
         let tok = match self.ch {
             '\u{0}' => Token::new(TokenType::Eof, self.ch.to_string()),
 
@@ -260,6 +272,21 @@ impl Lexer {
         }
     }
 
+    fn count_lines(&mut self) {
+        if self.position <= self.line_position {
+            return;
+        }
+        let breaks = self
+            .input
+            .chars()
+            .skip(self.line_position)
+            .take(self.position - self.line_position)
+            .filter(|&ch| ch == '\n')
+            .count();
+        self.line += breaks as u32;
+        self.line_position = self.position;
+    }
+
     fn peek_char(&self) -> char {
         if self.read_position >= self.input.len() {
             '\u{0}'
@@ -292,6 +319,17 @@ pub mod tests {
         assert_eq!(l.next_token().token_type, TokenType::Assign);
     }
 
+    #[test]
+    fn test_line() {
+        let source = String::from("a\n\n  b // c\n/* d\n */ e\n");
+        let mut l = Lexer::new(source);
+        let mut lines = vec![];
+        while l.next_token().token_type != TokenType::Eof {
+            lines.push(l.line());
+        }
+        assert_eq!(lines, [1, 3, 5]);
+    }
+
     #[test]
     fn test_digit() {
         let source = String::from("42;");
@@ -407,7 +445,7 @@ pub mod tests {
     #[test]
     fn test_keywords() {
         let source =
-            String::from("function let const true false if else switch break case default for continue return null undefined NaN this");
+            String::from("function let const true false if else switch break case default for continue return null undefined NaN this throw try catch finally");
         let mut l = Lexer::new(source);
         assert_eq!(l.next_token().token_type, TokenType::Function);
         assert_eq!(l.next_token().token_type, TokenType::Let);
@@ -427,6 +465,10 @@ pub mod tests {
         assert_eq!(l.next_token().token_type, TokenType::Undefined);
         assert_eq!(l.next_token().token_type, TokenType::NaN);
         assert_eq!(l.next_token().token_type, TokenType::This);
+        assert_eq!(l.next_token().token_type, TokenType::Throw);
+        assert_eq!(l.next_token().token_type, TokenType::Try);
+        assert_eq!(l.next_token().token_type, TokenType::Catch);
+        assert_eq!(l.next_token().token_type, TokenType::Finally);
     }
 
     #[test]
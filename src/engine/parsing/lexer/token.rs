@@ -91,6 +91,10 @@ pub enum TokenType {
     Null,
     Undefined,
     This,
+    Throw,
+    Try,
+    Catch,
+    Finally,
 }
 
 impl Token {
@@ -145,6 +149,10 @@ pub fn lookup_indent(ident: &str) -> TokenType {
         "typeof" => TokenType::Typeof,
         "NaN" => TokenType::NaN,
         "this" => TokenType::This,
+        "throw" => TokenType::Throw,
+        "try" => TokenType::Try,
+        "catch" => TokenType::Catch,
+        "finally" => TokenType::Finally,
         _ => TokenType::Ident,
     }
 }
@@ -1,3 +1,5 @@
+use std::io::Error;
+
 use super::ast::Program;
 
 mod lexer;
@@ -5,6 +7,11 @@ mod parser;
 
 pub trait Parser {
     fn parse(&self, source: String) -> Program;
+
+    /// the program, or the first error instead of the statements before it.
+    fn try_parse(&self, source: String) -> Result<Program, Error> {
+        Ok(self.parse(source))
+    }
 }
 
 pub struct BuiltinParser;
@@ -15,4 +22,10 @@ impl Parser for BuiltinParser {
         let mut parser = parser::Parser::new(&mut lexer);
         parser.parse_program()
     }
+
+    fn try_parse(&self, source: String) -> Result<Program, Error> {
+        let mut lexer = lexer::Lexer::new(source);
+        let mut parser = parser::Parser::new(&mut lexer);
+        parser.try_parse_program()
+    }
 }
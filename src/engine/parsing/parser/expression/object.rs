@@ -23,7 +23,9 @@ impl<'a> Parser<'a> {
     }
 
     pub(super) fn parse_object_property(&mut self) -> Result<ObjectProperty, Error> {
-        if self.cur_token.token_type != TokenType::Ident {
+        if self.cur_token.token_type != TokenType::Ident
+            && self.cur_token.token_type != TokenType::String
+        {
             return Err(Error::new(
                 ErrorKind::InvalidInput,
                 format!(
@@ -143,6 +145,19 @@ mod test {
                     Box::new(Expression::String(String::from("prop"))),
                 )))),
             ),
+            (
+                r#"
+                const ob = { "quoted key": 1 };
+            "#
+                .to_string(),
+                Statement::Const(ConstStatement::new(
+                    String::from("ob"),
+                    Expression::Object(ObjectExpression::new(vec![ObjectProperty::new(
+                        String::from("quoted key"),
+                        Expression::Number(1.0),
+                    )])),
+                )),
+            ),
         ];
 
         for (source, expected) in case {
@@ -1,6 +1,8 @@
 pub mod expression;
 pub mod statement;
 
+use std::io::Error;
+
 use crate::engine::{
     ast::{Precedence, Program},
     parsing::lexer::{
@@ -13,16 +15,23 @@ pub struct Parser<'a> {
     l: &'a mut Lexer,
     cur_token: Token,
     peeked_token: Token,
+    /// the lines the tokens start on
+    cur_line: u32,
+    peeked_line: u32,
 }
 impl<'a> Parser<'a> {
     pub fn new(l: &'a mut Lexer) -> Self {
         let first_token = l.next_token();
+        let first_line = l.line();
         let second_token = l.next_token();
+        let second_line = l.line();
 
         Parser {
             l,
             cur_token: first_token,
             peeked_token: second_token,
+            cur_line: first_line,
+            peeked_line: second_line,
         }
     }
 
@@ -30,10 +39,12 @@ impl<'a> Parser<'a> {
         let mut program = Program::new();
 
         while self.cur_token.token_type != TokenType::Eof {
+            let line = self.cur_line;
             let res = self.parse_statement();
             match res {
                 Ok(stmt) => {
                     program.statements.push(stmt);
+                    program.lines.push(line);
                     self.next_token();
                 }
                 Err(err) => {
@@ -46,6 +57,20 @@ impl<'a> Parser<'a> {
         program
     }
 
+    /// like `parse_program`, but the first error is returned instead of printed.
+    pub fn try_parse_program(&mut self) -> Result<Program, Error> {
+        let mut program = Program::new();
+
+        while self.cur_token.token_type != TokenType::Eof {
+            let line = self.cur_line;
+            program.statements.push(self.parse_statement()?);
+            program.lines.push(line);
+            self.next_token();
+        }
+
+        Ok(program)
+    }
+
     fn current_precedence(&self) -> Precedence {
         self.cur_token.clone().get_precedence()
     }
@@ -67,12 +92,18 @@ impl<'a> Parser<'a> {
                 | "return"
                 | "null"
                 | "undefined"
+                | "throw"
+                | "try"
+                | "catch"
+                | "finally"
         )
     }
 
     fn next_token(&mut self) {
         self.cur_token = self.peeked_token.clone();
+        self.cur_line = self.peeked_line;
         self.peeked_token = self.l.next_token();
+        self.peeked_line = self.l.line();
     }
 }
 
@@ -21,15 +21,19 @@ impl<'a> Parser<'a> {
         self.next_token(); // skip '{'
 
         let mut statements = vec![];
+        let mut lines = vec![];
         while self.cur_token.token_type != TokenType::RBrace
             && self.cur_token.token_type != TokenType::Eof
         {
+            lines.push(self.cur_line);
             let stmt = self.parse_statement()?;
             statements.push(stmt);
             self.next_token();
         }
 
-        Ok(Statement::Block(BlockStatement::new(statements)))
+        Ok(Statement::Block(BlockStatement::with_lines(
+            statements, lines,
+        )))
     }
 }
 
@@ -2,6 +2,7 @@ pub mod block;
 pub mod for_;
 pub mod if_;
 pub mod switch;
+pub mod try_;
 pub mod variables;
 
 use std::io::Error;
@@ -23,6 +24,8 @@ impl<'a> Parser<'a> {
             TokenType::LBrace => self.parse_block_statement(),
             TokenType::Break => self.parse_break_statement(),
             TokenType::Continue => self.parse_continue_statement(),
+            TokenType::Throw => self.parse_throw_statement(),
+            TokenType::Try => self.parse_try_statement(),
             _ => self.parse_expression_statement(),
         }
     }
@@ -49,6 +52,16 @@ impl<'a> Parser<'a> {
         Ok(Statement::Return(value))
     }
 
+    fn parse_throw_statement(&mut self) -> Result<Statement, Error> {
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+        if self.peeked_token.token_type == TokenType::SemiColon {
+            self.next_token()
+        }
+        Ok(Statement::Throw(value))
+    }
+
     fn parse_expression_statement(&mut self) -> Result<Statement, Error> {
         let expr = self.parse_expression(Precedence::Lowest)?;
         if self.peeked_token.token_type == TokenType::SemiColon {
@@ -0,0 +1,147 @@
+use std::io::{Error, ErrorKind};
+
+use crate::engine::{
+    ast::{BlockStatement, Statement, TryStatement},
+    parsing::{lexer::token::TokenType, parser::Parser},
+};
+
+impl<'a> Parser<'a> {
+    pub(super) fn parse_try_statement(&mut self) -> Result<Statement, Error> {
+        let block = self.parse_try_block()?;
+
+        // parse handler
+        let (param, handler) = match self.peeked_token.token_type {
+            TokenType::Catch => {
+                self.next_token(); // skip 'catch'
+
+                let param = match self.peeked_token.token_type {
+                    TokenType::LParen => {
+                        self.next_token();
+                        // guard
+                        if self.peeked_token.token_type != TokenType::Ident {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "expected identifier but found '{}' (at parse_try_statement)",
+                                    self.peeked_token.literal
+                                ),
+                            ));
+                        }
+                        self.next_token();
+                        let param = self.cur_token.literal.clone();
+
+                        // guard
+                        if self.peeked_token.token_type != TokenType::RParen {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "expected token ')' but found '{}' (at parse_try_statement)",
+                                    self.peeked_token.literal
+                                ),
+                            ));
+                        }
+                        self.next_token();
+                        Some(param)
+                    }
+                    _ => None,
+                };
+                (param, Some(self.parse_try_block()?))
+            }
+            _ => (None, None),
+        };
+
+        // parse finalizer
+        let finalizer = match self.peeked_token.token_type {
+            TokenType::Finally => {
+                self.next_token(); // skip 'finally'
+                Some(self.parse_try_block()?)
+            }
+            _ => None,
+        };
+
+        if handler.is_none() && finalizer.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Missing catch or finally after try",
+            ));
+        }
+
+        Ok(Statement::Try(TryStatement::new(
+            block, param, handler, finalizer,
+        )))
+    }
+
+    /// the block after `try`, `catch (e)` or `finally`. the current token is the keyword or `)`.
+    fn parse_try_block(&mut self) -> Result<BlockStatement, Error> {
+        // guard
+        if self.peeked_token.token_type != TokenType::LBrace {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "expected token '{{' but found '{}' (at parse_try_statement)",
+                    self.peeked_token.literal
+                ),
+            ));
+        }
+        self.next_token();
+
+        match self.parse_block_statement()? {
+            Statement::Block(block) => Ok(block),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::{
+        ast::{BlockStatement, Expression, Statement, TryStatement},
+        parsing::{lexer::Lexer, parser::Parser},
+    };
+
+    #[test]
+    fn test_parse_try_statements() {
+        let throw = |n: f64| BlockStatement::new(vec![Statement::Throw(Expression::Number(n))]);
+        let case = vec![
+            (
+                "try { throw 1; } catch (e) { throw 2; }",
+                vec![Statement::Try(TryStatement::new(
+                    throw(1.0),
+                    Some(String::from("e")),
+                    Some(throw(2.0)),
+                    None,
+                ))],
+            ),
+            (
+                "try { throw 1; } finally { throw 3; }",
+                vec![Statement::Try(TryStatement::new(
+                    throw(1.0),
+                    None,
+                    None,
+                    Some(throw(3.0)),
+                ))],
+            ),
+            (
+                "try { throw 1; } catch { throw 2; } finally { throw 3; }",
+                vec![Statement::Try(TryStatement::new(
+                    throw(1.0),
+                    None,
+                    Some(throw(2.0)),
+                    Some(throw(3.0)),
+                ))],
+            ),
+        ];
+
+        for (source, expected) in case {
+            let mut l = Lexer::new(source.to_string());
+            let mut p = Parser::new(&mut l);
+            let program = p.parse_program();
+            assert_eq!(program.statements, expected);
+        }
+
+        // a try needs a catch or a finally
+        let mut l = Lexer::new("try { throw 1; }".to_string());
+        let mut p = Parser::new(&mut l);
+        assert!(p.parse_program().statements.is_empty());
+    }
+}
@@ -1,9 +1,9 @@
 use runtime::cli::{
-    options::{get_execution_type, ExecutionType, HELP_MESSAGE},
+    options::{get_execution_type, get_vm_flags, ExecutionType, HELP_MESSAGE},
     repl::start_repl,
-    source::exec_source,
+    source::{exec_source, print_bytecode},
 };
-use std::env;
+use std::{env, process};
 
 mod engine;
 mod runtime;
@@ -13,12 +13,19 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 fn main() {
     let args: Vec<String> = env::args().collect();
     let execution_type = get_execution_type(&args);
+    let vm_flags = || {
+        get_vm_flags(&args).unwrap_or_else(|e| {
+            eprintln!("\x1b[31merror\x1b[0m: {}", e);
+            process::exit(2)
+        })
+    };
     match execution_type {
         ExecutionType::Help => println!("{}", HELP_MESSAGE),
         ExecutionType::Version => println!("{}", VERSION),
-        ExecutionType::VMInteract => start_repl(true),
-        ExecutionType::HostInteract => start_repl(false),
-        ExecutionType::VM { source_path } => exec_source(source_path, true),
-        ExecutionType::Host { source_path } => exec_source(source_path, false),
+        ExecutionType::VMInteract => start_repl(Some(vm_flags())),
+        ExecutionType::HostInteract => start_repl(None),
+        ExecutionType::VM { source_path } => exec_source(source_path, Some(vm_flags())),
+        ExecutionType::Host { source_path } => exec_source(source_path, None),
+        ExecutionType::PrintBytecode { source_path } => print_bytecode(source_path, vm_flags()),
     }
 }
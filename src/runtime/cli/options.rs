@@ -6,8 +6,16 @@ Options:
     -v, --version                 print version
     -h, --help                    print command line options (currently set)
     --vm                          run in vm mode (currently set)
+    --jit                         compile hot functions to machine code in vm mode (default)
+    --no-jit                      only interpret in vm mode
+    --no-code-cache               do not keep compiled scripts on disk in vm mode
+    --no-opt[=<pass,...>]         do not optimize the bytecode in vm mode, or skip only the listed passes
+    --debug                       run under the bytecode debugger in vm mode, `help` at its prompt lists the commands
+    --print-bytecode              print the bytecode of the script instead of running it
 "#;
 
+use crate::{engine::core::vm::optimizer::Passes, runtime::vm::VMFlags};
+
 pub(crate) enum ExecutionType<'a> {
     Help,
     Version,
@@ -15,6 +23,7 @@ pub(crate) enum ExecutionType<'a> {
     HostInteract,
     VM { source_path: &'a str },
     Host { source_path: &'a str },
+    PrintBytecode { source_path: &'a str },
 }
 
 pub(crate) fn get_execution_type(args: &Vec<String>) -> ExecutionType<'_> {
@@ -30,15 +39,18 @@ pub(crate) fn get_execution_type(args: &Vec<String>) -> ExecutionType<'_> {
             let help_arg = args.iter().any(|arg| arg == "-h" || arg == "--help");
             let version_arg = args.iter().any(|arg| arg == "-v" || arg == "--version");
             let vm_arg = args.iter().any(|arg| arg == "--vm");
+            let print_bytecode_arg = args.iter().any(|arg| arg == "--print-bytecode");
 
-            let file_arg = args.iter().find(|arg| !arg.starts_with('-'));
+            let file_arg = args.iter().skip(1).find(|arg| !arg.starts_with('-'));
 
             if help_arg {
                 ExecutionType::Help
             } else if version_arg {
                 ExecutionType::Version
             } else if let Some(file) = file_arg {
-                if vm_arg {
+                if print_bytecode_arg {
+                    ExecutionType::PrintBytecode { source_path: file }
+                } else if vm_arg {
                     ExecutionType::VM { source_path: file }
                 } else {
                     ExecutionType::Host { source_path: file }
@@ -51,3 +63,27 @@ pub(crate) fn get_execution_type(args: &Vec<String>) -> ExecutionType<'_> {
         }
     }
 }
+
+/// the flags of vm mode. the last of `--jit` and `--no-jit` wins. `--no-opt` turns every
+/// optimization pass off, `--no-opt=<pass,...>` the listed ones.
+pub(crate) fn get_vm_flags(args: &[String]) -> Result<VMFlags, String> {
+    let jit = args.iter().rev().find_map(|arg| match &**arg {
+        "--jit" => Some(true),
+        "--no-jit" => Some(false),
+        _ => None,
+    });
+    let mut passes = Passes::ALL;
+    for arg in args.iter() {
+        if arg == "--no-opt" {
+            passes = Passes::NONE;
+        } else if let Some(names) = arg.strip_prefix("--no-opt=") {
+            passes = passes.without(names)?;
+        }
+    }
+    Ok(VMFlags {
+        jit: jit.unwrap_or(true),
+        code_cache: !args.iter().any(|arg| arg == "--no-code-cache"),
+        passes,
+        debug: args.iter().any(|arg| arg == "--debug"),
+    })
+}
@@ -1,8 +1,8 @@
 use rustyline::error::ReadlineError;
 
-use crate::runtime::interface::JSRuntimeBuilder;
+use crate::runtime::{interface::JSRuntimeBuilder, vm::VMFlags};
 
-pub fn start_repl(vm: bool) {
+pub fn start_repl(vm: Option<VMFlags>) {
     println!("Welcome to Glasper v0.1.0 ");
     println!("exit using ctrl+c or ctrl+d or exit()");
 
@@ -1,18 +1,35 @@
-use crate::runtime::interface::JSRuntimeBuilder;
+use crate::{
+    engine::core::vm::disassembler::disassemble_script,
+    runtime::{interface::JSRuntimeBuilder, vm::VMFlags},
+};
 
-pub fn exec_source(path: &str, vm: bool) {
+/// run the script at `path`, in the vm when there are `vm` flags.
+pub fn exec_source(path: &str, vm: Option<VMFlags>) {
     let mut runtime = JSRuntimeBuilder::build(vm);
     match std::fs::read_to_string(path) {
         Ok(source) => {
-            runtime.run(source);
-        }
-        Err(_) => {
-            let crr_dir = std::env::current_dir().unwrap();
-            println!(
-                "\x1b[31merror\x1b[0m: Module not found \"file://{}/{}\".",
-                crr_dir.display(),
-                path
-            );
+            runtime.run_main(path, source);
         }
+        Err(_) => module_not_found(path),
     };
 }
+
+/// print the bytecode the vm would run for the script at `path`, optimized as `vm` says.
+pub fn print_bytecode(path: &str, vm: VMFlags) {
+    match std::fs::read_to_string(path) {
+        Ok(source) => match disassemble_script(&source, vm.passes) {
+            Ok(listing) => print!("{}", listing),
+            Err(e) => println!("{}", e),
+        },
+        Err(_) => module_not_found(path),
+    }
+}
+
+fn module_not_found(path: &str) {
+    let crr_dir = std::env::current_dir().unwrap();
+    println!(
+        "\x1b[31merror\x1b[0m: Module not found \"file://{}/{}\".",
+        crr_dir.display(),
+        path
+    );
+}
@@ -1,7 +1,8 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, io::Error, rc::Rc};
 
-use crate::engine::core::host::objects::{
-    JSBuiltinFunction, JSObject, JSString, JSUndefined, RuntimeObject,
+use crate::engine::core::host::{
+    api::{CallContext, Context, Exception, Function, Object, Value},
+    objects::{JSString, RuntimeObject},
 };
 
 pub struct ConsoleBuilder;
@@ -14,38 +15,47 @@ impl ConsoleBuilder {
     pub fn new() -> Self {
         Self
     }
-    pub fn build(self) -> RuntimeObject {
-        let mut properties = HashMap::new();
-        properties.insert(
-            String::from("log"),
-            RuntimeObject::BuiltinFunction(JSBuiltinFunction::new("log", log)),
-        );
-        properties.insert(
-            String::from("debug"),
-            RuntimeObject::BuiltinFunction(JSBuiltinFunction::new("log", log)),
-        );
-        properties.insert(
-            String::from("warn"),
-            RuntimeObject::BuiltinFunction(JSBuiltinFunction::new("log", log)),
+    pub fn build(self, context: &mut Context) -> Result<RuntimeObject, Error> {
+        let console = Object::new(context)?;
+        console.set("log", Function::new("log", log).into());
+        console.set("debug", Function::new("log", log).into());
+        console.set("warn", Function::new("log", log).into());
+
+        // `console.count` keeps its counters across calls
+        let counters: Rc<RefCell<HashMap<String, usize>>> = Rc::new(RefCell::new(HashMap::new()));
+        console.set(
+            "count",
+            Function::new("count", move |_, _, args| {
+                let label = match args.first() {
+                    Some(v) if !v.is_undefined() => to_display_string(v.clone()),
+                    _ => String::from("default"),
+                };
+                let mut counters = counters.borrow_mut();
+                let count = counters.entry(label.clone()).or_insert(0);
+                *count += 1;
+                println!("{}: {}", label, count);
+                Ok(Value::undefined())
+            })
+            .into(),
         );
 
-        RuntimeObject::Object(Rc::new(RefCell::new(JSObject { properties })))
+        Ok(Value::from(console).into_raw())
     }
 }
 
-fn log(args: Vec<RuntimeObject>) -> RuntimeObject {
+fn log(_: &mut CallContext, _: Value, args: Vec<Value>) -> Result<Value, Exception> {
     for arg in args {
-        match arg {
-            RuntimeObject::String(JSString { ref value }) => {
-                print!("{}", value);
-            }
-            _ => {
-                print!("{}", arg);
-            }
-        }
+        print!("{}", to_display_string(arg));
         print!("\x20");
     }
     println!();
 
-    RuntimeObject::Undefined(JSUndefined)
+    Ok(Value::undefined())
+}
+
+fn to_display_string(v: Value) -> String {
+    match v.into_raw() {
+        RuntimeObject::String(JSString { value }) => value,
+        o => o.to_string(),
+    }
 }
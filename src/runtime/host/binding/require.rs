@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+use crate::{
+    engine::core::host::{
+        api::{Exception, Function, Value},
+        objects::RuntimeObject,
+    },
+    runtime::host::module,
+};
+
+pub struct RequireBuilder {
+    dirname: PathBuf,
+}
+impl RequireBuilder {
+    /// `dirname` is the directory relative requires are resolved from.
+    pub fn new(dirname: PathBuf) -> Self {
+        Self { dirname }
+    }
+    pub fn build(self) -> RuntimeObject {
+        let dirname = self.dirname;
+        let require = Function::new("require", move |cx, _, args| {
+            let request = args
+                .first()
+                .and_then(|v| v.to::<String>().ok())
+                .ok_or_else(|| {
+                    Exception::type_error("The \"id\" argument must be of type string")
+                })?;
+
+            module::require(cx, &request, &dirname)
+                .map(Value::from)
+                .map_err(Exception::from)
+        });
+
+        Value::from(require).into_raw()
+    }
+}
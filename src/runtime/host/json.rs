@@ -0,0 +1,396 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+
+use crate::engine::core::host::{
+    api::Context,
+    objects::{JSBoolean, JSNull, JSNumber, JSObject, JSString, RuntimeObject},
+};
+
+/// arrays and objects nested deeper are rejected instead of overflowing the stack.
+const MAX_DEPTH: usize = 512;
+
+/// the value of the JSON text `text`. objects and arrays are allocated on the heap of
+/// `context`.
+pub(crate) fn parse(text: &str, context: &mut Context) -> Result<RuntimeObject, Error> {
+    // node skips the byte order mark of a file
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut parser = JsonParser {
+        text,
+        at: 0,
+        depth: 0,
+        context,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    match parser.at < text.len() {
+        true => Err(parser.unexpected()),
+        false => Ok(value),
+    }
+}
+
+struct JsonParser<'a, 'c> {
+    text: &'a str,
+    at: usize,
+    depth: usize,
+    context: &'c mut Context,
+}
+
+impl<'a, 'c> JsonParser<'a, 'c> {
+    fn value(&mut self) -> Result<RuntimeObject, Error> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(RuntimeObject::String(JSString {
+                value: self.string()?,
+            })),
+            Some(b't') => self.literal("true", RuntimeObject::Boolean(JSBoolean::new(true))),
+            Some(b'f') => self.literal("false", RuntimeObject::Boolean(JSBoolean::new(false))),
+            Some(b'n') => self.literal("null", RuntimeObject::Null(JSNull)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<RuntimeObject, Error>,
+    ) -> Result<RuntimeObject, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(syntax_error(format!(
+                "JSON nested deeper than {} levels at position {}",
+                MAX_DEPTH, self.at
+            )));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<RuntimeObject, Error> {
+        self.at += 1;
+        let mut properties = HashMap::new();
+        self.whitespace();
+        if self.peek() == Some(b'}') {
+            self.at += 1;
+        } else {
+            loop {
+                self.whitespace();
+                if self.peek() != Some(b'"') {
+                    return Err(self.unexpected());
+                }
+                let key = self.string()?;
+                self.whitespace();
+                self.expect(b':')?;
+                let value = self.value()?;
+                // the last of duplicate keys wins
+                properties.insert(key, value);
+                if self.separator(b'}')? {
+                    break;
+                }
+            }
+        }
+        let o = self.context.alloc(JSObject { properties })?;
+        Ok(RuntimeObject::Object(o))
+    }
+
+    fn array(&mut self) -> Result<RuntimeObject, Error> {
+        self.at += 1;
+        let mut elements = vec![];
+        self.whitespace();
+        if self.peek() == Some(b']') {
+            self.at += 1;
+        } else {
+            loop {
+                elements.push(self.value()?);
+                if self.separator(b']')? {
+                    break;
+                }
+            }
+        }
+        let prototype = self.context.array_prototype();
+        let o = self.context.alloc(JSObject::array(elements, prototype))?;
+        Ok(RuntimeObject::Object(o))
+    }
+
+    /// the `,` between the members of an object or an array, or its `end`. `true` at the end.
+    fn separator(&mut self, end: u8) -> Result<bool, Error> {
+        self.whitespace();
+        match self.peek() {
+            Some(b',') => {
+                self.at += 1;
+                Ok(false)
+            }
+            Some(c) if c == end => {
+                self.at += 1;
+                Ok(true)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.at += 1;
+        let mut value = String::new();
+        loop {
+            let start = self.at;
+            while !matches!(self.peek(), None | Some(b'"' | b'\\' | 0..=0x1f)) {
+                self.at += 1;
+            }
+            value.push_str(&self.text[start..self.at]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.at += 1;
+                    return Ok(value);
+                }
+                Some(b'\\') => {
+                    self.at += 1;
+                    value.push(self.escape()?);
+                }
+                // the end of the text, or a control character
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    /// the character of the escape sequence after a `\`.
+    fn escape(&mut self) -> Result<char, Error> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.at += 1;
+                let unit = self.hex()?;
+                if !(0xd800..0xdc00).contains(&unit) || !self.text[self.at..].starts_with("\\u") {
+                    // a lone surrogate can not be held by a rust string
+                    return Ok(char::from_u32(unit).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                let at = self.at;
+                self.at += 2;
+                let low = self.hex()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    self.at = at;
+                    return Ok(char::REPLACEMENT_CHARACTER);
+                }
+                let c = 0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00);
+                return Ok(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.at += 1;
+        Ok(c)
+    }
+
+    /// the 4 hex digits of a `\u` escape.
+    fn hex(&mut self) -> Result<u32, Error> {
+        let mut unit = 0;
+        for _ in 0..4 {
+            let digit = self.peek().and_then(|c| (c as char).to_digit(16));
+            unit = unit * 16 + digit.ok_or_else(|| self.unexpected())?;
+            self.at += 1;
+        }
+        Ok(unit)
+    }
+
+    fn number(&mut self) -> Result<RuntimeObject, Error> {
+        let start = self.at;
+        if self.peek() == Some(b'-') {
+            self.at += 1;
+        }
+        // no leading zeros
+        match self.peek() {
+            Some(b'0') => self.at += 1,
+            _ => self.digits()?,
+        }
+        if self.peek() == Some(b'.') {
+            self.at += 1;
+            self.digits()?;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.at += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.at += 1;
+            }
+            self.digits()?;
+        }
+        // the grammar of JSON numbers is a part of rust's
+        let value = self.text[start..self.at].parse().unwrap();
+        Ok(RuntimeObject::Number(JSNumber::new(value)))
+    }
+
+    /// one digit or more.
+    fn digits(&mut self) -> Result<(), Error> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return Err(self.unexpected());
+        }
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.at += 1;
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: RuntimeObject) -> Result<RuntimeObject, Error> {
+        for expected in word.bytes() {
+            if self.peek() != Some(expected) {
+                return Err(self.unexpected());
+            }
+            self.at += 1;
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), Error> {
+        match self.peek() == Some(expected) {
+            true => {
+                self.at += 1;
+                Ok(())
+            }
+            false => Err(self.unexpected()),
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.at).copied()
+    }
+
+    /// the error for the character at the position, the way node words it.
+    fn unexpected(&self) -> Error {
+        match self.text[self.at..].chars().next() {
+            Some(c) => syntax_error(format!(
+                "Unexpected token {} in JSON at position {}",
+                c, self.at
+            )),
+            None => syntax_error(String::from("Unexpected end of JSON input")),
+        }
+    }
+}
+
+fn syntax_error(message: String) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Uncaught SyntaxError: {}", message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::core::host::{api::Limits, handles::HandleScope};
+
+    fn property(o: &RuntimeObject, key: &str) -> RuntimeObject {
+        match o {
+            RuntimeObject::Object(o) => o.borrow().properties.get(key).cloned().unwrap(),
+            _ => panic!("not an object: {}", o),
+        }
+    }
+
+    fn error(text: &str) -> String {
+        let mut context = Context::new(HandleScope::new());
+        parse(text, &mut context).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut context = Context::new(HandleScope::new());
+        let text = r#"
+            {
+                "name": "gl\"asperé😀",
+                "version": -1.5e2,
+                "tags": [true, false, null, []],
+                "nested": { "a": { }, "a": 0 }
+            }
+        "#;
+        let o = parse(text, &mut context).unwrap();
+        assert_eq!(
+            property(&o, "name"),
+            RuntimeObject::String(JSString {
+                value: String::from("gl\"asperé😀")
+            })
+        );
+        assert_eq!(
+            property(&o, "version"),
+            RuntimeObject::Number(JSNumber::new(-150.0))
+        );
+        let tags = property(&o, "tags");
+        assert_eq!(
+            property(&tags, "length"),
+            RuntimeObject::Number(JSNumber::new(4.0))
+        );
+        assert_eq!(property(&tags, "2"), RuntimeObject::Null(JSNull));
+        assert_eq!(
+            property(&property(&o, "nested"), "a"),
+            RuntimeObject::Number(JSNumber::new(0.0))
+        );
+        assert_eq!(
+            parse("\u{feff} \"a\" ", &mut context).unwrap(),
+            RuntimeObject::String(JSString {
+                value: String::from("a")
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // JSON is not javascript
+        assert_eq!(
+            error("module.exports = 1"),
+            "Uncaught SyntaxError: Unexpected token m in JSON at position 0"
+        );
+        assert_eq!(
+            error(r#"{ "a": 1, }"#),
+            "Uncaught SyntaxError: Unexpected token } in JSON at position 10"
+        );
+        assert_eq!(
+            error("{ 'a': 1 }"),
+            "Uncaught SyntaxError: Unexpected token ' in JSON at position 2"
+        );
+        assert_eq!(
+            error("[1] [2]"),
+            "Uncaught SyntaxError: Unexpected token [ in JSON at position 4"
+        );
+        assert_eq!(
+            error("01"),
+            "Uncaught SyntaxError: Unexpected token 1 in JSON at position 1"
+        );
+        assert_eq!(
+            error("\"a\nb\""),
+            "Uncaught SyntaxError: Unexpected token \n in JSON at position 2"
+        );
+        assert_eq!(
+            error("[1, 2"),
+            "Uncaught SyntaxError: Unexpected end of JSON input"
+        );
+        assert_eq!(
+            error(""),
+            "Uncaught SyntaxError: Unexpected end of JSON input"
+        );
+        assert_eq!(
+            error(&"[".repeat(100000)),
+            "Uncaught SyntaxError: JSON nested deeper than 512 levels at position 512"
+        );
+
+        // the heap of the context accounts for the objects
+        let mut context = Context::new(HandleScope::new());
+        context.set_limits(Limits::new().max_heap_objects(2));
+        let e = parse("[[], [], []]", &mut context).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Uncaught RangeError: Out of memory: heap limit exceeded"
+        );
+    }
+}
@@ -1,4 +1,6 @@
 mod binding;
+mod json;
+mod module;
 
 use crate::{
     engine::{
@@ -11,7 +13,9 @@ use crate::{
     runtime::interface::JSRuntime,
 };
 
-use self::binding::console::ConsoleBuilder;
+use std::path::Path;
+
+use self::binding::{console::ConsoleBuilder, require::RequireBuilder};
 
 pub struct HostJSRuntime {
     isolate: Isolate,
@@ -27,12 +31,19 @@ impl HostJSRuntime {
     pub fn new() -> Self {
         let handle_scope = HandleScope::new();
         let mut context = Context::new(handle_scope);
-        let global = context.global();
 
         // binding
         let console_builder = ConsoleBuilder::new();
-        let console = console_builder.build();
+        // a fresh context has no heap limit yet
+        let console = console_builder
+            .build(&mut context)
+            .expect("a fresh context allocates the console");
+        let global = context.global();
         global.set("console", console);
+        global.set(
+            "require",
+            RequireBuilder::new(std::env::current_dir().unwrap_or_default()).build(),
+        );
 
         let mut isolate = Isolate::new(context, Box::new(BuiltinParser));
         isolate.install_functions(vec!["src/runtime/host/builtin-array-function.js"]);
@@ -51,6 +62,19 @@ impl JSRuntime for HostJSRuntime {
             Err(e) => println!("{}", e),
         }
     }
+
+    fn run_main(&mut self, path: &str, source: String) {
+        let filename = Path::new(path)
+            .canonicalize()
+            .unwrap_or_else(|_| Path::new(path).to_path_buf());
+
+        let main = match module::new_module(self.get_cxt(), &filename) {
+            Ok(main) => main,
+            Err(e) => return println!("{}", e),
+        };
+        module::bind(self.get_cxt().global(), &filename, &main);
+        self.run(source);
+    }
 }
 
 impl HostJSRuntime {
@@ -0,0 +1,494 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use super::{binding::require::RequireBuilder, json};
+use crate::engine::{
+    core::host::{
+        api::{CallContext, Context, Global},
+        handles::ModuleScope,
+        objects::{JSObject, JSString, JSUndefined, RuntimeObject},
+    },
+    parsing::{BuiltinParser, Parser},
+};
+
+/// create the `module` object for `filename` and register it in the modules of `context`.
+pub(crate) fn new_module(context: &mut Context, filename: &Path) -> Result<RuntimeObject, Error> {
+    let exports = context.alloc(JSObject {
+        properties: HashMap::new(),
+    })?;
+    let mut properties = HashMap::new();
+    properties.insert(String::from("exports"), RuntimeObject::Object(exports));
+    properties.insert(
+        String::from("id"),
+        RuntimeObject::String(JSString {
+            value: filename.display().to_string(),
+        }),
+    );
+    let module = RuntimeObject::Object(context.alloc(JSObject { properties })?);
+
+    context
+        .modules()
+        .insert(filename.to_path_buf(), module.clone());
+    Ok(module)
+}
+
+/// bind `require`, `module`, `exports`, `__filename` and `__dirname` of `module` into `global`.
+pub(crate) fn bind(global: &mut Global, filename: &Path, module: &RuntimeObject) {
+    for (name, value) in bindings(filename, module) {
+        global.set(name, value);
+    }
+}
+
+/// `require`, `module`, `exports`, `__filename` and `__dirname` of `module`.
+fn bindings(filename: &Path, module: &RuntimeObject) -> [(&'static str, RuntimeObject); 5] {
+    let string = |path: &Path| {
+        RuntimeObject::String(JSString {
+            value: path.display().to_string(),
+        })
+    };
+    [
+        ("require", RequireBuilder::new(dirname(filename)).build()),
+        ("module", module.clone()),
+        ("exports", exports_of(module)),
+        ("__filename", string(filename)),
+        ("__dirname", string(&dirname(filename))),
+    ]
+}
+
+/// the exports of the module `request` names, evaluated in the context of the calling script
+/// the first time it is required.
+pub(crate) fn require(
+    cx: &mut CallContext,
+    request: &str,
+    base_dir: &Path,
+) -> Result<RuntimeObject, Error> {
+    let filename = resolve(request, base_dir).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("Uncaught Error: Cannot find module '{}'", request),
+        )
+    })?;
+
+    // a module that is still being evaluated (circular require) yields its partial exports
+    if let Some(module) = cx.context().modules().get(&filename) {
+        return Ok(exports_of(module));
+    }
+
+    let module = new_module(cx.context(), &filename)?;
+    if let Err(e) = evaluate(cx, &filename, &module) {
+        cx.context().modules().remove(&filename);
+        return Err(e);
+    }
+
+    Ok(exports_of(&module))
+}
+
+/// run the module in the context of the script requiring it, so the limits, the interrupt
+/// handle and the heap of the context apply to it. a `.json` file is parsed, not run.
+fn evaluate(cx: &mut CallContext, filename: &Path, module: &RuntimeObject) -> Result<(), Error> {
+    let source = std::fs::read_to_string(filename)?;
+    if filename.extension().and_then(|ext| ext.to_str()) == Some("json") {
+        let exports = json::parse(&source, cx.context())?;
+        if let RuntimeObject::Object(o) = module {
+            o.borrow_mut()
+                .properties
+                .insert(String::from("exports"), exports);
+        }
+        return Ok(());
+    }
+
+    let scope = ModuleScope::default();
+    for (name, value) in bindings(filename, module) {
+        scope.declare(name, value);
+    }
+    let program = BuiltinParser.parse(source);
+    cx.run_module(&program, scope)?;
+    Ok(())
+}
+
+fn exports_of(module: &RuntimeObject) -> RuntimeObject {
+    match module {
+        RuntimeObject::Object(o) => o
+            .borrow()
+            .properties
+            .get("exports")
+            .cloned()
+            .unwrap_or(RuntimeObject::Undefined(JSUndefined)),
+        _ => RuntimeObject::Undefined(JSUndefined),
+    }
+}
+
+fn dirname(filename: &Path) -> PathBuf {
+    filename
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// resolve `request` the way node does: relative and absolute paths against `base_dir`,
+/// bare names through the `node_modules` directories of `base_dir` and its ancestors.
+pub(crate) fn resolve(request: &str, base_dir: &Path) -> Option<PathBuf> {
+    let is_path = request.starts_with("./")
+        || request.starts_with("../")
+        || request.starts_with('/')
+        || request == "."
+        || request == "..";
+
+    let found = if is_path {
+        let target = base_dir.join(request);
+        resolve_file(&target).or_else(|| resolve_directory(&target))
+    } else {
+        base_dir.ancestors().find_map(|dir| {
+            let target = dir.join("node_modules").join(request);
+            resolve_file(&target).or_else(|| resolve_directory(&target))
+        })
+    };
+
+    found.and_then(|path| path.canonicalize().ok())
+}
+
+fn resolve_file(target: &Path) -> Option<PathBuf> {
+    if target.is_file() {
+        return Some(target.to_path_buf());
+    }
+    ["js", "json"].iter().find_map(|ext| {
+        let mut candidate = target.as_os_str().to_owned();
+        candidate.push(".");
+        candidate.push(ext);
+        let candidate = PathBuf::from(candidate);
+        candidate.is_file().then_some(candidate)
+    })
+}
+
+fn resolve_directory(target: &Path) -> Option<PathBuf> {
+    ["index.js", "index.json"]
+        .iter()
+        .map(|index| target.join(index))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::core::host::{
+        api::{Context, Limits, Script},
+        handles::HandleScope,
+        objects::{JSBoolean, JSNumber},
+    };
+
+    /// files in a directory of their own under `temp_dir`, deleted with the fixture.
+    struct Fixture {
+        root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "glasper-module-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&root);
+            for (path, source) in files {
+                let path = root.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, source).unwrap();
+            }
+            Fixture {
+                root: root.canonicalize().unwrap(),
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn property(o: &RuntimeObject, key: &str) -> RuntimeObject {
+        match o {
+            RuntimeObject::Object(o) => o.borrow().properties.get(key).cloned().unwrap(),
+            _ => panic!("not an object: {}", o),
+        }
+    }
+
+    /// a context whose `require` resolves from `dir`.
+    fn context(dir: &Path) -> Context {
+        let mut context = Context::new(HandleScope::new());
+        let require = RequireBuilder::new(dir.to_path_buf()).build();
+        context.global().set("require", require);
+        context
+    }
+
+    fn run(context: &mut Context, source: &str) -> Result<RuntimeObject, Error> {
+        let mut parser: Box<dyn Parser> = Box::new(BuiltinParser);
+        let mut script = Script::compile(source.to_string(), context, &mut parser);
+        script.run()
+    }
+
+    fn require_from(dir: &Path, request: &str) -> RuntimeObject {
+        let source = format!("require(\"{}\");", request);
+        run(&mut context(dir), &source).unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let fixture = Fixture::new(
+            "resolve",
+            &[
+                ("a.js", ""),
+                ("data.json", "{}"),
+                ("lib/index.js", ""),
+                ("node_modules/pkg/index.js", ""),
+                ("src/deep/b.js", ""),
+            ],
+        );
+        let root = &fixture.root;
+
+        assert_eq!(resolve("./a", root), Some(root.join("a.js")));
+        assert_eq!(resolve("./a.js", root), Some(root.join("a.js")));
+        assert_eq!(resolve("./data", root), Some(root.join("data.json")));
+        assert_eq!(resolve("./lib", root), Some(root.join("lib/index.js")));
+        assert_eq!(
+            resolve("../../a", &root.join("src/deep")),
+            Some(root.join("a.js"))
+        );
+        assert_eq!(
+            resolve("pkg", &root.join("src/deep")),
+            Some(root.join("node_modules/pkg/index.js"))
+        );
+        assert_eq!(resolve("./missing", root), None);
+        assert_eq!(resolve("missing", root), None);
+    }
+
+    #[test]
+    fn test_require() {
+        let fixture = Fixture::new(
+            "require",
+            &[
+                (
+                    "main.js",
+                    r#"
+                        const math = require("./math");
+                        module.exports = {
+                            three: math.add(1, 2),
+                            filename: __filename,
+                            config: require("./config.json"),
+                        };
+                    "#,
+                ),
+                (
+                    "math.js",
+                    r#"
+                        exports.add = function(a, b) {
+                            return a + b;
+                        };
+                    "#,
+                ),
+                ("config.json", r#"{ "name": "glasper", "debug": false }"#),
+            ],
+        );
+
+        let root = &fixture.root;
+        let exports = require_from(root, "./main");
+        assert_eq!(
+            property(&exports, "three"),
+            RuntimeObject::Number(JSNumber::new(3.0))
+        );
+        assert_eq!(
+            property(&exports, "filename"),
+            RuntimeObject::String(JSString {
+                value: root.join("main.js").display().to_string()
+            })
+        );
+        assert_eq!(
+            property(&property(&exports, "config"), "name"),
+            RuntimeObject::String(JSString {
+                value: "glasper".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_require_cache_and_cycle() {
+        let fixture = Fixture::new(
+            "cycle",
+            &[
+                (
+                    "a.js",
+                    r#"
+                        exports.done = false;
+                        const b = require("./b");
+                        exports.b_saw_a_done = b.a_done;
+                        exports.done = true;
+                    "#,
+                ),
+                (
+                    "b.js",
+                    r#"
+                        const a = require("./a");
+                        exports.a_done = a.done;
+                    "#,
+                ),
+            ],
+        );
+
+        let root = &fixture.root;
+        let mut context = context(root);
+        let a = run(&mut context, "require('./a');").unwrap();
+        assert_eq!(
+            property(&a, "b_saw_a_done"),
+            RuntimeObject::Boolean(JSBoolean::new(false))
+        );
+
+        // the second require hits the cache and yields the same exports object
+        let again = run(&mut context, "require('./a');").unwrap();
+        assert_eq!(again, a);
+    }
+
+    #[test]
+    fn test_module_scope() {
+        let fixture = Fixture::new(
+            "scope",
+            &[
+                (
+                    "helper.js",
+                    r#"
+                        const h = require("./h");
+                        let calls = 0;
+                        exports.get = function() {
+                            calls = calls + 1;
+                            return h.value + calls;
+                        };
+                        exports.peek = function() {
+                            return secret;
+                        };
+                    "#,
+                ),
+                ("h.js", "exports.value = 40;"),
+            ],
+        );
+
+        // the functions of a module see its bindings, and keep their changes
+        let mut context = context(&fixture.root);
+        let source = "const helper = require('./helper'); helper.get(); helper.get();";
+        assert_eq!(
+            run(&mut context, source).unwrap(),
+            RuntimeObject::Number(JSNumber::new(42.0))
+        );
+        // but not the bindings of their caller
+        let e = run(&mut context, "const secret = 1; helper.peek();").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Uncaught ReferenceError: secret is not defined"
+        );
+    }
+
+    #[test]
+    fn test_require_json() {
+        let fixture = Fixture::new(
+            "json",
+            &[
+                ("list.json", "[1, {\"a\": null}]"),
+                ("code.json", "(function() { exports.ran = true; })()"),
+            ],
+        );
+
+        let list = require_from(&fixture.root, "./list");
+        assert_eq!(
+            property(&list, "length"),
+            RuntimeObject::Number(JSNumber::new(2.0))
+        );
+        // a data file is not run
+        let e = run(&mut context(&fixture.root), "require('./code.json');").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Uncaught SyntaxError: Unexpected token ( in JSON at position 0"
+        );
+    }
+
+    #[test]
+    fn test_require_limits() {
+        let fixture = Fixture::new(
+            "limits",
+            &[
+                ("spin.js", "for (let i = 0; true; i++) {}"),
+                ("objects.js", "exports.list = [{}, {}, {}, {}];"),
+            ],
+        );
+
+        // a module runs on the limits, the interrupt handle and the heap of the context
+        let mut context = context(&fixture.root);
+        context.set_limits(Limits::new().max_steps(1000));
+        let e = run(&mut context, "require('./spin');").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Interrupted);
+        assert_eq!(e.to_string(), "Execution terminated: step limit exceeded");
+        // and the limit can not be caught on the way out of `require`
+        let e = run(&mut context, "try { require('./spin'); } catch (e) {}").unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated: step limit exceeded");
+
+        context.set_limits(Limits::new());
+        context.interrupt_handle().terminate();
+        let e = run(&mut context, "require('./spin');").unwrap_err();
+        assert_eq!(e.to_string(), "Execution terminated");
+
+        context.set_limits(Limits::new().max_heap_objects(3));
+        let e = run(&mut context, "require('./objects');").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Uncaught RangeError: Out of memory: heap limit exceeded"
+        );
+        // a module that failed is evaluated again by the next require
+        context.set_limits(Limits::new());
+        let exports = run(&mut context, "require('./objects');").unwrap();
+        assert_eq!(
+            property(&property(&exports, "list"), "length"),
+            RuntimeObject::Number(JSNumber::new(4.0))
+        );
+    }
+
+    #[test]
+    fn test_modules_of_a_context() {
+        let fixture = Fixture::new(
+            "contexts",
+            &[
+                (
+                    "counter.js",
+                    "let n = 0; exports.next = function() { n = n + 1; return n; };",
+                ),
+                ("empty.js", ""),
+            ],
+        );
+        let next = "require('./counter').next();";
+
+        // each context loads its own modules, and creating one leaves the others alone
+        let mut a = context(&fixture.root);
+        run(&mut a, next).unwrap();
+        let mut b = context(&fixture.root);
+        assert_eq!(
+            run(&mut b, next).unwrap(),
+            RuntimeObject::Number(JSNumber::new(1.0))
+        );
+        assert_eq!(
+            run(&mut a, next).unwrap(),
+            RuntimeObject::Number(JSNumber::new(2.0))
+        );
+
+        // the `module` and `exports` objects are on the heap of the context
+        let mut c = context(&fixture.root);
+        let objects = c.heap_statistics().objects;
+        run(&mut c, "require('./empty');").unwrap();
+        assert_eq!(c.heap_statistics().objects, objects + 2);
+        let mut d = context(&fixture.root);
+        d.set_limits(Limits::new().max_heap_objects(1));
+        let e = run(&mut d, "require('./empty');").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Uncaught RangeError: Out of memory: heap limit exceeded"
+        );
+    }
+}
@@ -1,21 +1,28 @@
-use super::{host::HostJSRuntime, vm::VMRuntime};
+use super::{
+    host::HostJSRuntime,
+    vm::{VMFlags, VMRuntime},
+};
 
 pub trait JSRuntime {
     fn run(&mut self, source: String);
+
+    /// run the entry script located at `path`.
+    fn run_main(&mut self, _path: &str, source: String) {
+        self.run(source);
+    }
 }
 
 pub struct JSRuntimeBuilder;
 impl JSRuntimeBuilder {
-    pub fn build(vm: bool) -> Box<dyn JSRuntime> {
-        if vm {
-            Box::new(Self::build_vm())
-        } else {
-            Box::new(Self::build_host())
+    pub fn build(vm: Option<VMFlags>) -> Box<dyn JSRuntime> {
+        match vm {
+            Some(flags) => Box::new(Self::build_vm(flags)),
+            None => Box::new(Self::build_host()),
         }
     }
 
-    fn build_vm() -> impl JSRuntime {
-        VMRuntime::new()
+    fn build_vm(flags: VMFlags) -> impl JSRuntime {
+        VMRuntime::new(flags)
     }
 
     fn build_host() -> impl JSRuntime {
@@ -1,16 +1,39 @@
-use crate::engine::{core::vm::VirtualMachine, parsing::BuiltinParser};
+use crate::engine::{
+    core::vm::{code_cache::CodeCache, debugger::Debugger, optimizer::Passes, VirtualMachine},
+    parsing::BuiltinParser,
+};
 
 use super::interface::JSRuntime;
 
+/// the options of vm mode.
+#[derive(Clone, Copy)]
+pub struct VMFlags {
+    /// compile hot functions to machine code
+    pub jit: bool,
+    /// keep compiled scripts on disk
+    pub code_cache: bool,
+    /// the optimizations of the bytecode
+    pub passes: Passes,
+    /// stop at the debugger prompt, which turns the jit and the code cache off
+    pub debug: bool,
+}
+
 pub struct VMRuntime {
     vm: VirtualMachine,
+    code_cache: Option<CodeCache>,
 }
 
 impl VMRuntime {
-    pub fn new() -> Self {
+    pub fn new(flags: VMFlags) -> Self {
         let parser = Box::new(BuiltinParser);
-        let vm = VirtualMachine::new(parser);
-        VMRuntime { vm }
+        let mut vm = VirtualMachine::new(parser);
+        vm.set_jit(flags.jit);
+        vm.set_passes(flags.passes);
+        if flags.debug {
+            vm.set_debugger(Debugger::stdio());
+        }
+        let code_cache = (flags.code_cache && !flags.debug).then(CodeCache::new);
+        VMRuntime { vm, code_cache }
     }
 }
 
@@ -18,4 +41,11 @@ impl JSRuntime for VMRuntime {
     fn run(&mut self, source: String) {
         self.vm.run(source);
     }
+
+    fn run_main(&mut self, _path: &str, source: String) {
+        match &self.code_cache {
+            Some(cache) => self.vm.run_cached(source, cache),
+            None => self.vm.run(source),
+        }
+    }
 }
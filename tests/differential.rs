@@ -0,0 +1,203 @@
+//! runs every script of the corpus with the host interpreter and with the vm, and compares what
+//! they print: the console output, the result of the script and the kind of an uncaught error.
+//! the scripts the engines are known to disagree on are listed in the allowlist, with the
+//! reason.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    io::Read,
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// the directories of the scripts, relative to the crate.
+const CORPUS: &[&str] = &["example"];
+
+const ALLOWLIST: &str = "tests/differential_allowlist.txt";
+
+/// a script that runs longer is reported as a difference.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn test_host_and_vm_agree() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut allowed = allowlist(&root.join(ALLOWLIST));
+    let mut failures = vec![];
+
+    for script in corpus(root) {
+        let host = run(root, &[], &script);
+        let vm = run(root, &["--vm", "--no-code-cache"], &script);
+        let difference = first_difference(&host, &vm);
+        match (allowed.remove(&script), difference) {
+            (None, Some(difference)) => failures.push(format!("{}: {}", script, difference)),
+            (Some(reason), None) => failures.push(format!(
+                "{}: the engines agree now ({}), remove it from {}",
+                script, reason, ALLOWLIST
+            )),
+            _ => {}
+        }
+    }
+    for script in allowed.keys() {
+        failures.push(format!(
+            "{}: in {} but not in the corpus",
+            script, ALLOWLIST
+        ));
+    }
+
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
+
+/// the scripts by their path relative to the crate, like `example/for.js`.
+fn corpus(root: &Path) -> Vec<String> {
+    let mut scripts = vec![];
+    for dir in CORPUS {
+        for entry in fs::read_dir(root.join(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() == Some(OsStr::new("js")) {
+                let name = path.file_name().unwrap().to_string_lossy();
+                scripts.push(format!("{}/{}", dir, name));
+            }
+        }
+    }
+    scripts.sort();
+    scripts
+}
+
+/// the scripts of the allowlist and why they differ. a line is a path and an optional
+/// `# reason`, blank lines and lines starting with `#` are skipped.
+fn allowlist(path: &Path) -> BTreeMap<String, String> {
+    let text = fs::read_to_string(path).unwrap();
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (script, reason) = line.split_once('#').unwrap_or((line, ""));
+            (script.trim().to_string(), reason.trim().to_string())
+        })
+        .collect()
+}
+
+/// the normalized output of `gls <args> <script>`. it runs in the crate, where the host finds
+/// its builtin modules.
+fn run(root: &Path, args: &[&str], script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gls"))
+        .args(args)
+        .arg(script)
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut bytes = vec![];
+        stdout.read_to_end(&mut bytes).map(|_| bytes)
+    });
+
+    let status = wait(&mut child);
+    let bytes = reader.join().unwrap().unwrap();
+    let mut output = normalize(&String::from_utf8_lossy(&bytes));
+    match status {
+        Some(0) => {}
+        Some(code) => output.push_str(&format!("<exit {}>\n", code)),
+        None => output.push_str("<timed out or killed>\n"),
+    }
+    output
+}
+
+/// the exit code of `child`, `None` if it was killed by a signal or ran out of time.
+fn wait(child: &mut Child) -> Option<i32> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code();
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// the output without colors, and of an uncaught error only its kind: the host prefixes it
+/// with `Uncaught` and the messages of the engines differ.
+fn normalize(output: &str) -> String {
+    let mut text = String::new();
+    let mut chars = output.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // `ESC [ <parameters> m`
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            text.push(c);
+        }
+    }
+
+    let mut output = String::new();
+    for line in text.lines() {
+        let error = line.strip_prefix("Uncaught ").unwrap_or(line);
+        match error.split_once(": ") {
+            Some((kind, _))
+                if kind.ends_with("Error") && kind.chars().all(|c| c.is_ascii_alphabetic()) =>
+            {
+                output.push_str(&format!("Uncaught {}\n", kind));
+            }
+            _ => {
+                output.push_str(line.trim_end());
+                output.push('\n');
+            }
+        }
+    }
+    output
+}
+
+/// the first line the outputs differ in.
+fn first_difference(host: &str, vm: &str) -> Option<String> {
+    let mut host_lines = host.lines();
+    let mut vm_lines = vm.lines();
+    for line in 1.. {
+        match (host_lines.next(), vm_lines.next()) {
+            (None, None) => return None,
+            (h, v) if h == v => {}
+            (h, v) => {
+                return Some(format!(
+                    "line {} differs, host {:?}, vm {:?}",
+                    line,
+                    h.unwrap_or("<end>"),
+                    v.unwrap_or("<end>")
+                ))
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn test_normalize() {
+    let host = "\x1b[33m1\x1b[0m \nUncaught TypeError: Cannot read properties of null\n";
+    let vm = "\x1b[33m1\x1b[0m\nTypeError: Cannot read properties of Null\n";
+    assert_eq!(normalize(host), "1\nUncaught TypeError\n");
+    assert_eq!(normalize(host), normalize(vm));
+    // thrown values and console output are kept
+    assert_eq!(
+        normalize("Uncaught 1\nkey: value\n"),
+        "Uncaught 1\nkey: value\n"
+    );
+
+    assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+    assert_eq!(
+        first_difference("a\nb\n", "a\n").unwrap(),
+        "line 2 differs, host \"b\", vm \"<end>\""
+    );
+}